@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use libuhdr::{FloatPixel, GainMapFormulation, GainMapMetadata, UhdrBoostComputer};
+
+/// 1M pixels worth of linear base pixels and gain map recovery values, ramping across the range.
+fn ramp() -> Vec<(FloatPixel, FloatPixel)> {
+    const COUNT: usize = 1 << 20;
+    (0..COUNT)
+        .map(|i| {
+            let value = i as f32 / COUNT as f32;
+            (FloatPixel::new(value, 1.0 - value, value * 0.5), FloatPixel::new(1.0 - value, value, 0.5))
+        })
+        .collect()
+}
+
+/// Applies a gain map with a gamma other than 1, so that `powf`, `exp2` and the multiplies are all on the path.
+/// Vectorized if the `simd` feature is enabled.
+fn compute_boosted(c: &mut Criterion) {
+    let metadata = GainMapMetadata {
+        base_rendition_is_hdr: false,
+        gain_map_min: [0.0; 3],
+        gain_map_max: [3.0, 2.5, 2.0],
+        gamma: [2.2; 3],
+        offset_sdr: [1.0 / 64.0; 3],
+        offset_hdr: [1.0 / 64.0; 3],
+        hdr_capacity_min: 0.0,
+        hdr_capacity_max: 3.0,
+        formulation: GainMapFormulation::Adobe,
+    };
+    let boost_computer = UhdrBoostComputer::new(&metadata, 2.0);
+    let pixels = ramp();
+
+    c.bench_function("compute_boosted", |b| {
+        b.iter(|| {
+            for &(base, recovery) in &pixels {
+                black_box(boost_computer.compute_boosted(base, recovery));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, compute_boosted);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use libuhdr::transfer::{st2084_oetf, st2084_oetf_in_place};
+
+/// 1M pixels worth of normalized linear RGB values, ramping across the range.
+fn ramp() -> Vec<f32> {
+    const COUNT: usize = 3 << 20;
+    (0..COUNT)
+        .map(|i| i as f32 / COUNT as f32)
+        .collect()
+}
+
+fn pq_oetf(c: &mut Criterion) {
+    let values = ramp();
+
+    c.bench_function("st2084_oetf", |b| {
+        b.iter(|| {
+            for &value in &values {
+                black_box(st2084_oetf(value));
+            }
+        })
+    });
+
+    // Vectorized if the `simd` feature is enabled.
+    c.bench_function("st2084_oetf_in_place", |b| {
+        let mut buffer = values.clone();
+        b.iter(|| {
+            buffer.copy_from_slice(&values);
+            st2084_oetf_in_place(black_box(&mut buffer));
+        })
+    });
+}
+
+criterion_group!(benches, pq_oetf);
+criterion_main!(benches);
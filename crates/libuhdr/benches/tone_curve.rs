@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use lcms2::{CIExyY, CIExyYTRIPLE, Profile, ToneCurve};
+
+use libuhdr::IccColorSpace;
+
+/// 1M pixels worth of non-linear RGB values, ramping across the range.
+fn ramp() -> Vec<[f32; 3]> {
+    const COUNT: usize = 1 << 20;
+    (0..COUNT)
+        .map(|i| {
+            let value = i as f32 / COUNT as f32;
+            [value, 1.0 - value, value * 0.5]
+        })
+        .collect()
+}
+
+/// Compares evaluating the tone curves of an ICC profile with `lcms2` for every sample, i.e. an FFI call each,
+/// to the tables `IccColorSpace` samples them into.
+fn tone_curve(c: &mut Criterion) {
+    // The sRGB EOTF, as a parametric curve of type 4.
+    let curve = ToneCurve::new_parametric(4, &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045]).unwrap();
+    let pixels = ramp();
+
+    c.bench_function("tone_curve_eval_lcms2", |b| {
+        b.iter(|| {
+            for pixel in &pixels {
+                black_box(pixel.map(|value| curve.eval(value)));
+            }
+        })
+    });
+
+    let white_point = CIExyY { x: 0.3127, y: 0.3290, Y: 1.0 };
+    let primaries = CIExyYTRIPLE {
+        Red: CIExyY { x: 0.64, y: 0.33, Y: 1.0 },
+        Green: CIExyY { x: 0.30, y: 0.60, Y: 1.0 },
+        Blue: CIExyY { x: 0.15, y: 0.06, Y: 1.0 },
+    };
+    let icc_profile = Profile::new_rgb(&white_point, &primaries, &[&curve, &curve, &curve]).unwrap().icc().unwrap();
+    let icc_color_space = IccColorSpace::from_icc_profile_bytes(&icc_profile).unwrap();
+
+    c.bench_function("transfer_characteristics_evaluate", |b| {
+        b.iter(|| {
+            for pixel in &pixels {
+                black_box(icc_color_space.transfer_characteristics.evaluate(pixel));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, tone_curve);
+criterion_main!(benches);
@@ -1,6 +1,9 @@
 
 use derive_more::Debug;
-use lcms2::{Profile, TagSignature, Tag, CIEXYZ, CIExyY, ToneCurve};
+use log::warn;
+use lcms2::{Profile, TagSignature, Tag, CIEXYZ, CIExyY, ToneCurveRef};
+
+use crate::transfer::{hlg_inverse_oetf, hlg_ootf, srgb_eotf};
 
 #[derive(Debug, Clone)]
 pub struct IccColorSpace {
@@ -24,14 +27,46 @@ pub struct ColorPrimaries {
     blue: CIExyY,
 }
 
+/// Coding-independent code points, as in _Rec. ITU-T H.273_, from the `cicp` tag of an ICC profile (ICC.1:2022).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cicp {
+    pub color_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub full_range: bool,
+}
+
+/// How the samples of a source image are linearized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceTransfer {
+    /// The tone curves of the ICC profile.
+    Icc,
+    /// The piecewise sRGB EOTF of _IEC 61966-2-1_, assumed in the absence of an ICC profile.
+    Srgb,
+    /// A pure 2.2 gamma, only where signalled by the `cicp` tag of an ICC profile.
+    Gamma22,
+    /// BT.2100 HLG, signalled by the `cicp` tag of the ICC profile.
+    /// The primary image is then already HDR.
+    Hlg,
+}
+
+/// The tone curves of an ICC profile, sampled into tables when the profile is parsed.
+///
+/// `lcms2`'s `ToneCurve` isn't kept around because it isn't safe to share across threads:
+/// `cmsEvalToneCurveFloat` writes to the curve when evaluating sampled segments, even through a `const` pointer.
+/// The tables make this type `Send + Sync` without any `unsafe`.
 #[derive(Clone)]
 pub struct TransferCharacteristics {
-    red: Option<ToneCurve>,
-    green: Option<ToneCurve>,
-    blue: Option<ToneCurve>,
+    red: Option<SampledToneCurve>,
+    green: Option<SampledToneCurve>,
+    blue: Option<SampledToneCurve>,
+}
+
+/// A tone curve sampled at evenly spaced inputs in [0, 1], and linearly interpolated in between.
+#[derive(Clone)]
+struct SampledToneCurve {
+    samples: Box<[f32]>,
 }
-unsafe impl Send for TransferCharacteristics {}
-unsafe impl Sync for TransferCharacteristics {}
 
 impl IccColorSpace {
     pub fn from_icc_profile_bytes(icc_profile_bytes: &[u8]) -> Option<Self> {
@@ -56,9 +91,124 @@ impl IccColorSpace {
     }
 }
 
+impl Cicp {
+    /// `ColourPrimaries` value for BT.709, i.e. sRGB.
+    pub const COLOR_PRIMARIES_BT709: u8 = 1;
+    /// `ColourPrimaries` value for BT.2020 and BT.2100.
+    pub const COLOR_PRIMARIES_BT2020: u8 = 9;
+    /// `ColourPrimaries` value for SMPTE RP 431-2, i.e. DCI-P3.
+    pub const COLOR_PRIMARIES_SMPTE431: u8 = 11;
+    /// `ColourPrimaries` value for SMPTE EG 432-1, i.e. Display P3.
+    pub const COLOR_PRIMARIES_SMPTE432: u8 = 12;
+
+    /// `TransferCharacteristics` value for an assumed display gamma of 2.2.
+    pub const TRANSFER_CHARACTERISTICS_GAMMA22: u8 = 4;
+    /// `TransferCharacteristics` value for the sRGB EOTF of IEC 61966-2-1.
+    pub const TRANSFER_CHARACTERISTICS_SRGB: u8 = 13;
+    /// `TransferCharacteristics` value for BT.2100 PQ.
+    pub const TRANSFER_CHARACTERISTICS_PQ: u8 = 16;
+    /// `TransferCharacteristics` value for BT.2100 HLG.
+    pub const TRANSFER_CHARACTERISTICS_HLG: u8 = 18;
+
+    /// The color gamut of `color_primaries`, if it is one of `ColorGamut::CICP_COLOR_PRIMARIES`.
+    pub fn color_gamut(&self) -> Option<ColorGamut> {
+        ColorGamut::from_cicp_primaries(self.color_primaries)
+    }
+
+    /// How `transfer_characteristics` linearizes samples, if it is one of `SourceTransfer::CICP_TRANSFER_CHARACTERISTICS`.
+    pub fn source_transfer(&self) -> Option<SourceTransfer> {
+        SourceTransfer::from_cicp_transfer_characteristics(self.transfer_characteristics)
+    }
+
+    /// Reads the `cicp` tag from raw ICC profile bytes, since `lcms2` doesn't expose it.
+    pub fn from_icc_profile_bytes(icc_profile_bytes: &[u8]) -> Option<Self> {
+        const TAG_TABLE_OFFSET: usize = 128;
+        const TAG_SIGNATURE: &[u8] = b"cicp";
+
+        let read_u32 = |offset: usize| -> Option<usize> {
+            let bytes = icc_profile_bytes.get(offset..offset + 4)?;
+            Some(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+        };
+
+        let tag_count = read_u32(TAG_TABLE_OFFSET)?;
+        (0..tag_count)
+            .map(|index| TAG_TABLE_OFFSET + 4 + index * 12)
+            .find(|&entry_offset| icc_profile_bytes.get(entry_offset..entry_offset + 4) == Some(TAG_SIGNATURE))
+            .and_then(|entry_offset| {
+                let data_offset = read_u32(entry_offset + 4)?;
+                // Type signature, reserved, then the 4 code points.
+                let data = icc_profile_bytes.get(data_offset..data_offset + 12)?;
+                if &data[0..4] != TAG_SIGNATURE {
+                    return None;
+                }
+                Some(Self {
+                    color_primaries: data[8],
+                    transfer_characteristics: data[9],
+                    matrix_coefficients: data[10],
+                    full_range: data[11] != 0,
+                })
+            })
+    }
+}
+
+impl SourceTransfer {
+    /// The H.273 `TransferCharacteristics` code points that map to a `SourceTransfer`.
+    /// `Icc` has none, and neither has PQ, which no source image is encoded with.
+    pub const CICP_TRANSFER_CHARACTERISTICS: &[(u8, SourceTransfer)] = &[
+        (Cicp::TRANSFER_CHARACTERISTICS_GAMMA22, SourceTransfer::Gamma22),
+        (Cicp::TRANSFER_CHARACTERISTICS_SRGB, SourceTransfer::Srgb),
+        (Cicp::TRANSFER_CHARACTERISTICS_HLG, SourceTransfer::Hlg),
+    ];
+
+    /// Looks up `code` in `CICP_TRANSFER_CHARACTERISTICS`.
+    pub fn from_cicp_transfer_characteristics(code: u8) -> Option<Self> {
+        Self::CICP_TRANSFER_CHARACTERISTICS.iter()
+            .find(|(known_code, _)| *known_code == code)
+            .map(|&(_, source_transfer)| source_transfer)
+    }
+
+    /// Nominal peak luminance in nits of the HLG reference display, for which the system gamma is `1.2`.
+    pub const HLG_NOMINAL_PEAK_LUMINANCE: f32 = 1000.0;
+    /// Luminance in nits of HLG reference white, i.e. a 75% signal, per _Rec. ITU-R BT.2408_.
+    pub const HLG_REFERENCE_WHITE_LUMINANCE: f32 = 203.0;
+
+    /// Linearizes `rgb` in [0, 1].
+    ///
+    /// For `Hlg`, this applies the BT.2100 HLG EOTF, i.e. the inverse OETF followed by the OOTF with the system gamma of `1.2`,
+    /// and normalizes the display light so that HLG reference white becomes `1.0` like SDR white.
+    pub fn to_linear(self, rgb: [f32; 3], icc_color_space: Option<&IccColorSpace>) -> [f32; 3] {
+        match (self, icc_color_space) {
+            (SourceTransfer::Icc, Some(icc_color_space)) => icc_color_space.transfer_characteristics.evaluate(&rgb),
+            (SourceTransfer::Icc, None) | (SourceTransfer::Srgb, _) => rgb.map(srgb_eotf),
+            (SourceTransfer::Gamma22, _) => rgb.map(|value| value.powf(2.2)),
+            (SourceTransfer::Hlg, _) => {
+                let display = hlg_ootf(rgb.map(hlg_inverse_oetf));
+                display.map(|value| value * (Self::HLG_NOMINAL_PEAK_LUMINANCE / Self::HLG_REFERENCE_WHITE_LUMINANCE))
+            },
+        }
+    }
+}
+
 impl ColorGamut {
     const WHITE_POINT_D50: CIExyY = CIExyY { x: 0.3457, y: 0.3585, Y: 1.0000 };
     const WHITE_POINT_D65: CIExyY = CIExyY { x: 0.3127, y: 0.3290, Y: 1.0000 };
+    const WHITE_POINT_DCI: CIExyY = CIExyY { x: 0.3140, y: 0.3510, Y: 1.0000 };
+
+    /// The H.273 `ColourPrimaries` code points with a known color gamut, e.g. for the `cicp` tag of an ICC profile.
+    pub const CICP_COLOR_PRIMARIES: &[(u8, ColorGamut)] = &[
+        (Cicp::COLOR_PRIMARIES_BT709, Self::srgb()),
+        (Cicp::COLOR_PRIMARIES_BT2020, Self::bt2020()),
+        (Cicp::COLOR_PRIMARIES_SMPTE431, Self::dci_p3()),
+        (Cicp::COLOR_PRIMARIES_SMPTE432, Self::display_p3()),
+    ];
+
+    /// The largest distance in xy between a primary of the Chromaticity tag of an ICC profile and the corresponding colorant
+    /// tag, adapted back from D50, for them to be considered to agree. Far more than the fixed point rounding of the tags.
+    const MAX_CHROMATICITY_DISAGREEMENT: f64 = 0.005;
+
+    /// The largest distance in xy of a primary or the white point for `cicp_color_primaries` to consider them the same.
+    /// Far more than the fixed point rounding of ICC profiles, but far less than the white points of DCI-P3 and Display P3 differ.
+    const MAX_CICP_XY_DISTANCE: f64 = 0.005;
 
     /// [sRGB](https://en.wikipedia.org/wiki/SRGB) color gamut, same color primaries and white point as the ITU-R Recommendation BT.709 or [Rec.709](https://en.wikipedia.org/wiki/Rec._709) standard.
     pub const fn srgb() -> Self {
@@ -80,6 +230,41 @@ impl ColorGamut {
         }
     }
 
+    /// Color gamut of [Display P3](https://en.wikipedia.org/wiki/DCI-P3#Display_P3): The DCI-P3 primaries with a D65 white point.
+    pub const fn display_p3() -> Self {
+        Self {
+            primaries: ColorPrimaries::p3(&[0.2290, 0.6917, 0.0793]),
+            white_point: Self::WHITE_POINT_D65,
+        }
+    }
+
+    /// Color gamut of [DCI-P3](https://en.wikipedia.org/wiki/DCI-P3) as in digital cinema, with its greenish white point.
+    pub const fn dci_p3() -> Self {
+        Self {
+            primaries: ColorPrimaries::p3(&[0.2095, 0.7216, 0.0689]),
+            white_point: Self::WHITE_POINT_DCI,
+        }
+    }
+
+    /// Looks up `code` in `CICP_COLOR_PRIMARIES`.
+    pub fn from_cicp_primaries(code: u8) -> Option<Self> {
+        Self::CICP_COLOR_PRIMARIES.iter()
+            .find(|(known_code, _)| *known_code == code)
+            .map(|&(_, color_gamut)| color_gamut)
+    }
+
+    /// The code point in `CICP_COLOR_PRIMARIES` whose primaries and white point are all within `MAX_CICP_XY_DISTANCE`
+    /// of those of `self`, e.g. of a color gamut read from an ICC profile. `None` for gamuts without one, e.g. Adobe RGB.
+    pub fn cicp_color_primaries(&self) -> Option<u8> {
+        Self::CICP_COLOR_PRIMARIES.iter()
+            .find(|(_, color_gamut)| {
+                let [x, y] = color_gamut.white_point_xy();
+                let [white_x, white_y] = self.white_point_xy();
+                self.primaries.max_xy_distance(&color_gamut.primaries).max((x - white_x).hypot(y - white_y)) <= Self::MAX_CICP_XY_DISTANCE
+            })
+            .map(|&(code, _)| code)
+    }
+
     /// Color gamut used by the [ProPhoto RGB color space](https://en.wikipedia.org/wiki/ProPhoto_RGB_color_space) developed by Kodak.
     pub const fn prophoto_rgb() -> Self {
         Self {
@@ -88,6 +273,40 @@ impl ColorGamut {
         }
     }
 
+    /// Color gamut of [Adobe RGB (1998)](https://en.wikipedia.org/wiki/Adobe_RGB_color_space), common for photographs meant for print:
+    /// The red and blue primaries of sRGB with a more saturated green, and a D65 white point.
+    pub const fn adobe_rgb() -> Self {
+        Self {
+            primaries: ColorPrimaries::adobe_rgb(),
+            white_point: Self::WHITE_POINT_D65,
+        }
+    }
+
+    /// The color gamut of the chromaticities of the red, green and blue `primaries` and of `white_point`, as x and y,
+    /// e.g. from the `PRIMARIES` line of a Radiance `.hdr`. `None` if the primaries span no gamut.
+    #[allow(non_snake_case)]
+    pub fn from_xy(primaries: [[f64; 2]; 3], white_point: [f64; 2]) -> Option<Self> {
+        // A chromaticity with a y of 0 has no luminance to scale.
+        if primaries.iter().chain([&white_point]).any(|&[_, y]| y.is_nan() || y <= 0.0) {
+            return None;
+        }
+        let [red, green, blue] = primaries.map(|[x, y]| CIExyY { x, y, Y: 1.0 });
+        let [x, y] = white_point;
+        // Primaries that span no gamut can't be converted from.
+        invert_matrix([red, green, blue].map(xyY_to_XYZ))?;
+        let mut color_gamut = Self {
+            primaries: ColorPrimaries { red, green, blue },
+            white_point: CIExyY { x, y, Y: 1.0 },
+        };
+
+        // The luminances of the primaries are those that add up to the white point.
+        let rgb_to_XYZ = color_gamut.rgb_to_XYZ();
+        color_gamut.primaries.red.Y = rgb_to_XYZ[0][1];
+        color_gamut.primaries.green.Y = rgb_to_XYZ[1][1];
+        color_gamut.primaries.blue.Y = rgb_to_XYZ[2][1];
+        Some(color_gamut)
+    }
+
     pub fn from_icc_profile_bytes(icc_profile_bytes: &[u8]) -> Option<Self> {
         let icc_profile = Profile::new_icc(icc_profile_bytes).ok()?;
         Self::from_icc_profile(&icc_profile)
@@ -108,7 +327,7 @@ impl ColorGamut {
                         Some(invert_matrix(to_d50)?)
                     },
                     _ => {
-                        eprintln!("Expected CIExyYTRIPLE tag for Chromatic Adaptation, but got {:?}", tag);
+                        warn!("Expected CIExyYTRIPLE tag for Chromatic Adaptation, but got {:?}", tag);
                         return None;
                     },
                 }
@@ -131,35 +350,55 @@ impl ColorGamut {
         
         let white_point = lcms2::XYZ2xyY(&white_point);
 
-        // Single Chromaticity tag present ?
-        if let Some(tag) = read_tag(icc_profile, TagSignature::ChromaticityTag) {
-            match tag {
-                Tag::CIExyYTRIPLE(primaries) => {
-                    return Some(Self {
-                        primaries: ColorPrimaries {
-                            red: primaries.Red,
-                            green: primaries.Green,
-                            blue: primaries.Blue,
-                        },
-                        white_point,
-                    });
-                },
-                _ => panic!("Expected CIExyYTRIPLE tag for ChromaticityTag but got {:?}", tag),
-            }
-        }
+        let chromaticity = read_tag(icc_profile, TagSignature::ChromaticityTag).and_then(|tag| match tag {
+            Tag::CIExyYTRIPLE(primaries) => Some(ColorPrimaries {
+                red: primaries.Red,
+                green: primaries.Green,
+                blue: primaries.Blue,
+            }),
+            _ => {
+                warn!("Expected CIExyYTRIPLE tag for ChromaticityTag, but got {:?}; ignoring it", tag);
+                None
+            },
+        });
+
+        // The three primary colorant tags, adapted to D50 like the white point.
+        let colorants = [TagSignature::RedColorantTag, TagSignature::GreenColorantTag, TagSignature::BlueColorantTag]
+            .map(|sig| read_CIEXYZ_tag(icc_profile, sig));
+        let colorants = match colorants {
+            [Some(red), Some(green), Some(blue)] => Some([red, green, blue]),
+            _ => None,
+        };
 
-        // Otherwise, read the three primary colorant tags.
+        // The colorants define the transform, whereas the Chromaticity tag is informational, so they take precedence.
+        // If both agree, which they do for a well-formed profile, the Chromaticity tag is used though,
+        // since it has the exact primaries rather than ones adapted to D50 and rounded to fixed point.
+        let primaries = match (chromaticity, colorants) {
+            (Some(chromaticity), Some(colorants)) => {
+                let disagreement = chromaticity.max_xy_distance(&ColorPrimaries::from_colorants(&colorants, from_d50.as_ref()));
+                if disagreement > Self::MAX_CHROMATICITY_DISAGREEMENT {
+                    warn!(
+                        "The Chromaticity tag of the ICC profile disagrees with its colorant tags by {:.4} in xy; using the colorants",
+                        disagreement,
+                    );
+                    ColorPrimaries::from_colorants(&colorants, from_d50.as_ref())
+                } else {
+                    chromaticity
+                }
+            },
+            (Some(chromaticity), None) => chromaticity,
+            (None, Some(colorants)) => ColorPrimaries::from_colorants(&colorants, from_d50.as_ref()),
+            (None, None) => return None,
+        };
 
-        let red_primary = read_CIEXYZ_tag_as_CIExyY(icc_profile, TagSignature::RedColorantTag)?;
-        let green_primary = read_CIEXYZ_tag_as_CIExyY(icc_profile, TagSignature::GreenColorantTag)?;
-        let blue_primary = read_CIEXYZ_tag_as_CIExyY(icc_profile, TagSignature::BlueColorantTag)?;
+        // Primaries that span no gamut, e.g. all at the same chromaticity, can't be converted from.
+        if invert_matrix([primaries.red, primaries.green, primaries.blue].map(xyY_to_XYZ)).is_none() {
+            warn!("The primaries of the ICC profile are degenerate: {:?}", primaries);
+            return None;
+        }
 
         Some(Self {
-            primaries: ColorPrimaries {
-                red: red_primary,
-                green: green_primary,
-                blue: blue_primary,
-            },
+            primaries,
             white_point,
         })
     }
@@ -178,88 +417,55 @@ impl ColorGamut {
     }
 
     /// Converts a color value represented in the `src` `ColorGamut` primaries to one represented in the `dst` `ColorGamut` primaries.
+    ///
+    /// This derives the conversion matrix on each call. Use a `ColorGamutConverter` to convert many values.
     pub fn convert(value: &[f32; 3], src: &Self, dst: &Self) -> [f32; 3] {
-        // https://physics.stackexchange.com/questions/487763/how-are-the-matrices-for-the-rgb-to-from-cie-xyz-conversions-generated
-
-        // FIXME: Much of this stuff could be precomputed and cached.
+        ColorGamutConverter::new(src, dst).convert(value)
+    }
 
-        #![allow(non_snake_case)]
+    /// The row-major 3x3 matrix to right-multiply to RGB row vectors to convert them to CIEXYZ,
+    /// such that `[1, 1, 1]` becomes the white point.
+    #[allow(non_snake_case)]
+    fn rgb_to_XYZ(&self) -> [[f64; 3]; 3] {
+        // https://physics.stackexchange.com/questions/487763/how-are-the-matrices-for-the-rgb-to-from-cie-xyz-conversions-generated
 
-        let src_p = &src.primaries;
-
-        // This matrix converts RGB values to a relative XYZ space, but not yet scaled to match the white point.
-        let src_rgb_to_XYZ = {
-            // CIEXYZ coordinates of each RGB primary.
-            let r_X = src_p.red.x * src_p.red.Y / src_p.red.y;
-            let r_Y = src_p.red.Y;
-            let r_Z = (1.0 - src_p.red.x - src_p.red.y) * r_Y / src_p.red.y;
-            let g_X = src_p.green.x * src_p.green.Y / src_p.green.y;
-            let g_Y = src_p.green.Y;
-            let g_Z = (1.0 - src_p.green.x - src_p.green.y) * g_Y / src_p.green.y;
-            let b_X = src_p.blue.x * src_p.blue.Y / src_p.blue.y;
-            let b_Y = src_p.blue.Y;
-            let b_Z = (1.0 - src_p.blue.x - src_p.blue.y) * b_Y / src_p.blue.y;
-
-            [
-                [r_X, r_Y, r_Z],
-                [g_X, g_Y, g_Z],
-                [b_X, b_Y, b_Z],
-            ]
-        };
+        // CIEXYZ coordinates of each RGB primary, not yet scaled to match the white point.
+        let p = &self.primaries;
+        let unscaled_rgb_to_XYZ = [p.red, p.green, p.blue].map(xyY_to_XYZ);
 
-        // UnscaledXYZ is not correctly scaled to the destitnation gamut white point:
+        // In order to scale it to the white point, we need to scale the RGB values by a factor [a, b, c]:
         // ```
-        // UnscaledXYZ = RGB * src_rgb_to_XYZ
+        // WhitePointXYZ = [a, b, c] * [1, 1, 1] * unscaled_rgb_to_XYZ
+        // [a, b, c] = WhitePointXYZ * unscaled_rgb_to_XYZ^-1
         // ```
-        //
-        // In order to scale it to the destination white point, we need to scale it by a factor [a, b, c]:
-        // ```
-        // WhitePointXYZ = [a, b, c] * [1, 1, 1] * src_rgb_to_XYZ
-        // [a, b, c] = WhitePointXYZ * src_rgb_to_XYZ^-1
-        let chromatic_adaptation = {
-            let dst_white_point_XYZ = {
-                let w_X = dst.white_point.x * dst.white_point.Y / dst.white_point.y;
-                let w_Y = dst.white_point.Y;
-                let w_Z = (1.0 - dst.white_point.x - dst.white_point.y) * w_Y / dst.white_point.y;
-    
-                [w_X, w_Y, w_Z]
-            };
+        let scale = transform_right(&xyY_to_XYZ(self.white_point), &invert_matrix(unscaled_rgb_to_XYZ).unwrap());
 
-            transform_right(&dst_white_point_XYZ, &invert_matrix(src_rgb_to_XYZ).unwrap())
-        };
+        // The scale applies to the RGB values, i.e. the rows.
+        std::array::from_fn(|row| unscaled_rgb_to_XYZ[row].map(|value| value * scale[row]))
+    }
+}
 
-        let XYZ_to_dst_rgb= {
-            let dst_p = &dst.primaries;
-
-            let dst_rgb_to_XYZ = {
-                let r_X = dst_p.red.x * dst_p.red.Y / dst_p.red.y;
-                let r_Y = dst_p.red.Y;
-                let r_Z = (1.0 - dst_p.red.x - dst_p.red.y) * r_Y / dst_p.red.y;
-                let g_X = dst_p.green.x * dst_p.green.Y / dst_p.green.y;
-                let g_Y = dst_p.green.Y;
-                let g_Z = (1.0 - dst_p.green.x - dst_p.green.y) * g_Y / dst_p.green.y;
-                let b_X = dst_p.blue.x * dst_p.blue.Y / dst_p.blue.y;
-                let b_Y = dst_p.blue.Y;
-                let b_Z = (1.0 - dst_p.blue.x - dst_p.blue.y) * b_Y / dst_p.blue.y;
-    
-                [
-                    [r_X, r_Y, r_Z],
-                    [g_X, g_Y, g_Z],
-                    [b_X, b_Y, b_Z],
-                ]
-            };
+/// Converts color values from one `ColorGamut` to another, like `ColorGamut::convert`, with the matrix derived once.
+#[derive(Debug, Clone, Copy)]
+pub struct ColorGamutConverter {
+    /// Right-multiplied by RGB row vectors.
+    matrix: [[f64; 3]; 3],
+}
 
-            invert_matrix(dst_rgb_to_XYZ).unwrap()
-        };
+impl ColorGamutConverter {
+    pub fn new(src: &ColorGamut, dst: &ColorGamut) -> Self {
+        #![allow(non_snake_case)]
 
-        let value_XYZ = transform_right(&[value[0] as f64, value[1] as f64, value[2] as f64], &src_rgb_to_XYZ);        
-        let value_XYZ = [
-            value_XYZ[0] * chromatic_adaptation[0],
-            value_XYZ[1] * chromatic_adaptation[1],
-            value_XYZ[2] * chromatic_adaptation[2],
-        ];
+        // RGB to XYZ relative to the source white point, then adapted to the destination white point, then to RGB.
+        let src_rgb_to_XYZ = src.rgb_to_XYZ();
+        let adaptation = bradford_adaptation(src.white_point, dst.white_point);
+        let XYZ_to_dst_rgb = invert_matrix(dst.rgb_to_XYZ()).unwrap();
+
+        Self { matrix: multiply(&multiply(&src_rgb_to_XYZ, &adaptation), &XYZ_to_dst_rgb) }
+    }
 
-        let result_rgb = transform_right(&value_XYZ, &XYZ_to_dst_rgb);
+    pub fn convert(&self, value: &[f32; 3]) -> [f32; 3] {
+        let result_rgb = transform_right(&value.map(f64::from), &self.matrix);
 
         [
             result_rgb[0] as f32,
@@ -278,6 +484,23 @@ impl ColorPrimaries {
         }
     }
 
+    /// The primaries of DCI-P3 and Display P3, with the luminances of the primaries relative to the white point.
+    const fn p3(luminances: &[f64; 3]) -> Self {
+        Self {
+            red: CIExyY { x: 0.6800, y: 0.3200, Y: luminances[0] },
+            green: CIExyY { x: 0.2650, y: 0.6900, Y: luminances[1] },
+            blue: CIExyY { x: 0.1500, y: 0.0600, Y: luminances[2] },
+        }
+    }
+
+    pub const fn adobe_rgb() -> Self {
+        Self {
+            red: CIExyY { x: 0.6400, y: 0.3300, Y: 0.2974 },
+            green: CIExyY { x: 0.2100, y: 0.7100, Y: 0.6273 },
+            blue: CIExyY { x: 0.1500, y: 0.0600, Y: 0.0753 },
+        }
+    }
+
     pub const fn prophoto_rgb() -> Self {
         Self {
             red: CIExyY { x: 0.7347, y: 0.2653, Y: 0.28804  },
@@ -286,6 +509,29 @@ impl ColorPrimaries {
         }
     }
 
+    /// From the CIEXYZ colorant tags of an ICC profile, in the order red, green, blue, adapted with `from_d50` if specified.
+    fn from_colorants(colorants: &[CIEXYZ; 3], from_d50: Option<&[[f64; 3]; 3]>) -> Self {
+        let [red, green, blue] = colorants.map(|colorant| {
+            let colorant = match from_d50 {
+                Some(from_d50) => {
+                    let [x, y, z] = transform_right(&[colorant.X, colorant.Y, colorant.Z], from_d50);
+                    CIEXYZ { X: x, Y: y, Z: z }
+                },
+                None => colorant,
+            };
+            lcms2::XYZ2xyY(&colorant)
+        });
+        Self { red, green, blue }
+    }
+
+    /// The largest distance in xy between the corresponding primaries of `self` and `other`.
+    fn max_xy_distance(&self, other: &Self) -> f64 {
+        [(self.red, other.red), (self.green, other.green), (self.blue, other.blue)]
+            .iter()
+            .map(|(a, b)| (a.x - b.x).hypot(a.y - b.y))
+            .fold(0.0, f64::max)
+    }
+
     /// The red primary in CIExyY format.
     pub fn red(&self) -> [f64; 3] {
         [self.red.x, self.red.y, self.red.Y]
@@ -330,54 +576,86 @@ impl TransferCharacteristics {
     }
 
     fn from_icc_profile(icc_profile: &Profile) -> Option<Self> {
-        let red = read_tag(icc_profile, TagSignature::RedTRCTag).and_then(|tag| {
-            if let Tag::ToneCurve(curve) = tag {
-                Some(curve.to_owned())
-            } else {
-                None
-            }
-        });
-
-        let green = read_tag(icc_profile, TagSignature::GreenTRCTag).and_then(|tag| {
-            if let Tag::ToneCurve(curve) = tag {
-                Some(curve.to_owned())
-            } else {
-                None
-            }
-        });
+        // `lcms2` reads both the `curv` and the parametric `para` tag types, e.g. the sRGB piecewise EOTF, as a `ToneCurve`.
+        let read_curve = |sig: TagSignature| {
+            read_tag(icc_profile, sig).and_then(|tag| {
+                if let Tag::ToneCurve(curve) = tag {
+                    Some(SampledToneCurve::new(curve))
+                } else {
+                    None
+                }
+            })
+        };
 
-        let blue = read_tag(icc_profile, TagSignature::BlueTRCTag).and_then(|tag| {
-            if let Tag::ToneCurve(curve) = tag {
-                Some(curve.to_owned())
+        let red = read_curve(TagSignature::RedTRCTag);
+        let green = read_curve(TagSignature::GreenTRCTag);
+        let blue = read_curve(TagSignature::BlueTRCTag);
+
+        // Without any TRC, the samples would pass through as if already linear.
+        // Assume the sRGB EOTF if the profile is described as sRGB, and a display gamma of 2.2 otherwise,
+        // as for an image without an ICC profile.
+        if red.is_none() && green.is_none() && blue.is_none() {
+            let curve = if describes_srgb(icc_profile) {
+                warn!("The sRGB ICC profile has no TRC tags, assuming the sRGB EOTF");
+                SampledToneCurve::from_fn(srgb_eotf)
             } else {
-                None
-            }
-        });
+                warn!("The ICC profile has no TRC tags, assuming a gamma of 2.2");
+                SampledToneCurve::from_fn(|value| value.powf(2.2))
+            };
+            return Some(Self { red: Some(curve.clone()), green: Some(curve.clone()), blue: Some(curve) });
+        }
 
         Some(Self { red, green, blue })
     }
 }
 
+impl SampledToneCurve {
+    /// Enough for the interpolation error of typical EOTFs to be far below what an 8-bit input can resolve.
+    const SAMPLE_COUNT: usize = 4096;
+
+    fn new(curve: &ToneCurveRef) -> Self {
+        Self::from_fn(|value| curve.eval(value))
+    }
+
+    fn from_fn(f: impl Fn(f32) -> f32) -> Self {
+        let max_index = (Self::SAMPLE_COUNT - 1) as f32;
+        let samples = (0..Self::SAMPLE_COUNT)
+            .map(|index| f(index as f32 / max_index))
+            .collect();
+        Self { samples }
+    }
+
+    /// Inputs outside of [0, 1] are clamped.
+    fn eval(&self, value: f32) -> f32 {
+        let max_index = self.samples.len() - 1;
+        let position = value.clamp(0.0, 1.0) * max_index as f32;
+        let index = (position as usize).min(max_index - 1);
+        let fraction = position - index as f32;
+        self.samples[index] + (self.samples[index + 1] - self.samples[index]) * fraction
+    }
+}
+
+/// Whether the description of `icc_profile` names sRGB, e.g. "sRGB IEC61966-2.1".
+fn describes_srgb(icc_profile: &Profile) -> bool {
+    read_mlu_tag(icc_profile, TagSignature::ProfileDescriptionTag)
+        .is_some_and(|description| description.to_ascii_lowercase().contains("srgb"))
+}
+
 fn read_mlu_tag(icc_profile: &Profile, sig: TagSignature) -> Option<String> {
     let tag = read_tag(icc_profile, sig)?;
     match tag {
         Tag::MLU(mlu) => {
-            assert!(!mlu.tanslations().is_empty());
-
-            let locale = mlu.tanslations()[0];
+            let locale = *mlu.tanslations().first()?;
 
-            return Some(mlu.text(locale).unwrap())
+            mlu.text(locale).ok()
+        },
+        _ => {
+            warn!("Expected MLU tag for {:?}, but got {:?}", sig, tag);
+            None
         },
-        _ => panic!("Expected MLU tag"),
     }
 }
 
-#[allow(non_snake_case)]
-fn read_CIEXYZ_tag_as_CIExyY(icc_profile: &Profile, sig: TagSignature) -> Option<CIExyY> {
-    let ciexyz = read_CIEXYZ_tag(icc_profile, sig)?;
-    Some(lcms2::XYZ2xyY(&ciexyz))
-}
-
 #[allow(non_snake_case)]
 fn read_CIEXYZ_tag(icc_profile: &Profile, sig: TagSignature) -> Option<CIEXYZ> {
     let tag = read_tag(icc_profile, sig)?;
@@ -385,7 +663,10 @@ fn read_CIEXYZ_tag(icc_profile: &Profile, sig: TagSignature) -> Option<CIEXYZ> {
         Tag::CIEXYZ(xyz) => {
             return Some(*xyz)
         },
-        _ => panic!("Expected CIEXYZ tag"),
+        _ => {
+            warn!("Expected CIEXYZ tag for {:?}, but got {:?}", sig, tag);
+            None
+        },
     }
 }
 
@@ -396,6 +677,33 @@ fn read_tag(icc_profile: &Profile, sig: TagSignature) -> Option<Tag<'_>> {
     None
 }
 
+/// The Bradford matrix, to convert CIEXYZ column vectors to the cone response domain.
+const BRADFORD: [[f64; 3]; 3] = [
+    [ 0.8951,  0.2664, -0.1614],
+    [-0.7502,  1.7135,  0.0367],
+    [ 0.0389, -0.0685,  1.0296],
+];
+
+/// The row-major 3x3 matrix to right-multiply to CIEXYZ row vectors to adapt them from the white point `src_white_point`
+/// to `dst_white_point`, both in CIExyY, with the Bradford transform: The cone responses are scaled by the ratios of those
+/// of the white points.
+fn bradford_adaptation(src_white_point: CIExyY, dst_white_point: CIExyY) -> [[f64; 3]; 3] {
+    // Transposed for row vectors.
+    let to_cone = std::array::from_fn(|row| std::array::from_fn(|column| BRADFORD[column][row]));
+    let from_cone = invert_matrix(to_cone).unwrap();
+
+    let cone_response = |white_point: CIExyY| transform_right(&xyY_to_XYZ(white_point), &to_cone);
+    let (src_cone, dst_cone) = (cone_response(src_white_point), cone_response(dst_white_point));
+    let scaled_from_cone = std::array::from_fn(|row| from_cone[row].map(|value| value * dst_cone[row] / src_cone[row]));
+
+    multiply(&to_cone, &scaled_from_cone)
+}
+
+#[allow(non_snake_case)]
+fn xyY_to_XYZ(xyY: CIExyY) -> [f64; 3] {
+    [xyY.x * xyY.Y / xyY.y, xyY.Y, (1.0 - xyY.x - xyY.y) * xyY.Y / xyY.y]
+}
+
 /// Transform a row vector by right-multiplying a row-major 3x3 matrix.
 fn transform_right(row_vector: &[f64; 3], matrix: &[[f64; 3]; 3]) -> [f64; 3] {
     let mut result = [0.0; 3];
@@ -451,3 +759,242 @@ fn invert_matrix(matrix: [[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
 
     Some(inverse)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lcms2::{CIExyYTRIPLE, ToneCurve};
+
+    use crate::testutil;
+
+    #[test]
+    fn sampled_tone_curve_matches_lcms2() {
+        // The sRGB EOTF, as a parametric curve of type 4.
+        let srgb = ToneCurve::new_parametric(4, &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045]).unwrap();
+        let tabulated = ToneCurve::new_tabulated_float(
+            &(0..1024).map(|index| (index as f32 / 1023.0).powf(2.6)).collect::<Vec<_>>(),
+        );
+
+        for curve in [&srgb, &tabulated] {
+            let sampled = SampledToneCurve::new(curve);
+            for index in 0..=255 {
+                let value = index as f32 / 255.0;
+                let expected = curve.eval(value);
+                assert!((sampled.eval(value) - expected).abs() < 1e-5, "{}: {} != {}", value, sampled.eval(value), expected);
+            }
+            // Between the samples too, e.g. for 16-bit inputs.
+            let max_error = (0..=65535)
+                .map(|index| index as f32 / 65535.0)
+                .map(|value| (sampled.eval(value) - curve.eval(value)).abs())
+                .fold(0.0f32, f32::max);
+            assert!(max_error < 1e-5, "Max error of {}", max_error);
+            assert_eq!(sampled.eval(-0.5), sampled.eval(0.0));
+            assert_eq!(sampled.eval(1.5), sampled.eval(1.0));
+        }
+    }
+
+    #[test]
+    fn colorants_take_precedence_over_a_disagreeing_chromaticity_tag() {
+        let gamma = ToneCurve::new(2.2);
+        // With both the Chromaticity tag and the colorant tags for BT.2020, which agree.
+        let mut icc_profile = Profile::new_icc(&testutil::bt2020_icc_profile([&gamma, &gamma, &gamma])).unwrap();
+        let colorants = [TagSignature::RedColorantTag, TagSignature::GreenColorantTag, TagSignature::BlueColorantTag]
+            .map(|sig| read_CIEXYZ_tag(&icc_profile, sig).unwrap());
+        // As stored, i.e. adapted to D50, which is far off the primaries until adapted back to the D65 white point.
+        let unadapted_colorant_primaries = ColorPrimaries::from_colorants(&colorants, None);
+
+        let agreeing = ColorGamut::from_icc_profile(&icc_profile).unwrap();
+        // The Chromaticity tag, up to its fixed point rounding.
+        assert!(agreeing.primaries.max_xy_distance(&ColorGamut::bt2020().primaries) < 1e-4);
+        assert!(unadapted_colorant_primaries.max_xy_distance(&ColorGamut::bt2020().primaries) > 1e-3);
+
+        // An authoring bug: sRGB chromaticities for the BT.2020 colorants, which are far enough apart to be warned about.
+        let srgb = ColorPrimaries::srgb();
+        assert!(srgb.max_xy_distance(&agreeing.primaries) > ColorGamut::MAX_CHROMATICITY_DISAGREEMENT);
+        let srgb = CIExyYTRIPLE { Red: srgb.red, Green: srgb.green, Blue: srgb.blue };
+        assert!(icc_profile.write_tag(TagSignature::ChromaticityTag, Tag::CIExyYTRIPLE(&srgb)));
+        let icc_profile = Profile::new_icc(&icc_profile.icc().unwrap()).unwrap();
+
+        // The BT.2020 primaries of the colorants, adapted back to D65, up to their fixed point rounding.
+        let disagreeing = ColorGamut::from_icc_profile(&icc_profile).unwrap();
+        assert!(disagreeing.primaries.max_xy_distance(&ColorGamut::bt2020().primaries) < 1e-3, "{:?}", disagreeing.primaries);
+        assert_eq!(disagreeing.white_point_xy(), agreeing.white_point_xy());
+    }
+
+    #[test]
+    fn tags_of_an_unexpected_type_are_ignored() {
+        let gamma = ToneCurve::new(2.2);
+        let icc_profile_bytes = testutil::bt2020_icc_profile([&gamma, &gamma, &gamma]);
+        // Overwrites the type signature of a tag's data, which lcms2 then refuses to read.
+        let with_tag_type = |sig: &[u8; 4], tag_type: &[u8; 4]| {
+            let mut bytes = icc_profile_bytes.clone();
+            let tag_count = u32::from_be_bytes(bytes[128..132].try_into().unwrap()) as usize;
+            let entry = (0..tag_count).map(|index| 132 + index * 12).find(|&entry| &bytes[entry..entry + 4] == sig).unwrap();
+            let offset = u32::from_be_bytes(bytes[entry + 4..entry + 8].try_into().unwrap()) as usize;
+            bytes[offset..offset + 4].copy_from_slice(tag_type);
+            bytes
+        };
+
+        // Without a usable Chromaticity tag, the colorants still define the primaries.
+        let icc_color_space = IccColorSpace::from_icc_profile_bytes(&with_tag_type(b"chrm", b"curv")).unwrap();
+        assert!(icc_color_space.color_gamut.primaries.max_xy_distance(&ColorGamut::bt2020().primaries) < 1e-3);
+        // Nor is there one without a white point.
+        let icc_profile = Profile::new_icc(&with_tag_type(b"wtpt", b"mluc")).unwrap();
+        assert!(ColorGamut::from_icc_profile(&icc_profile).is_none());
+        let icc_profile = Profile::new_icc(&with_tag_type(b"desc", b"XYZ ")).unwrap();
+        assert!(!describes_srgb(&icc_profile));
+    }
+
+    #[test]
+    fn profile_without_trc_is_linearized_with_gamma_22() {
+        let gamma = ToneCurve::new(1.8);
+        let mut icc_profile = Profile::new_icc(&testutil::bt2020_icc_profile([&gamma, &gamma, &gamma])).unwrap();
+        for sig in [TagSignature::RedTRCTag, TagSignature::GreenTRCTag, TagSignature::BlueTRCTag] {
+            assert!(icc_profile.remove_tag(sig));
+        }
+        let icc_color_space = IccColorSpace::from_icc_profile_bytes(&icc_profile.icc().unwrap()).unwrap();
+
+        // The primaries are still used.
+        assert!(icc_color_space.color_gamut.primaries.max_xy_distance(&ColorGamut::bt2020().primaries) < 1e-4);
+        let linear = SourceTransfer::Icc.to_linear([0.5, 0.25, 1.0], Some(&icc_color_space));
+        for (linear, value) in linear.iter().zip([0.5f32, 0.25, 1.0]) {
+            assert!((linear - value.powf(2.2)).abs() < 1e-4, "{} linearized as {}", value, linear);
+        }
+    }
+
+    #[test]
+    fn parametric_trc_and_srgb_profile_without_trc_use_the_srgb_eotf() {
+        // The sRGB EOTF, as a parametric curve of type 4, i.e. a `para` tag.
+        let srgb = ToneCurve::new_parametric(4, &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045]).unwrap();
+        let icc_profile_bytes = testutil::bt2020_icc_profile([&srgb, &srgb, &srgb]);
+        let assert_srgb = |icc_color_space: &IccColorSpace| {
+            // Including the shadows, where the linear segment differs most from a 2.2 gamma.
+            for value in [0.01f32, 0.03, 0.2, 0.5, 1.0] {
+                let [linear, _, _] = SourceTransfer::Icc.to_linear([value; 3], Some(icc_color_space));
+                assert!((linear - srgb_eotf(value)).abs() < 1e-5, "{} linearized as {}", value, linear);
+            }
+        };
+        assert_srgb(&IccColorSpace::from_icc_profile_bytes(&icc_profile_bytes).unwrap());
+
+        let mut icc_profile = Profile::new_icc(&icc_profile_bytes).unwrap();
+        for sig in [TagSignature::RedTRCTag, TagSignature::GreenTRCTag, TagSignature::BlueTRCTag] {
+            assert!(icc_profile.remove_tag(sig));
+        }
+        let mut description = lcms2::MLU::new(1);
+        description.set_text_ascii("sRGB IEC61966-2.1", lcms2::Locale::none());
+        assert!(icc_profile.write_tag(TagSignature::ProfileDescriptionTag, Tag::MLU(&description)));
+        assert_srgb(&IccColorSpace::from_icc_profile_bytes(&icc_profile.icc().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn common_cicp_code_points_are_mapped() {
+        let xy = |color_gamut: ColorGamut| {
+            let primaries = color_gamut.primaries();
+            [primaries.red_xy(), primaries.green_xy(), primaries.blue_xy(), color_gamut.white_point_xy()]
+        };
+        for (code, expected) in [
+            (1, ColorGamut::srgb()),
+            (9, ColorGamut::bt2020()),
+            (11, ColorGamut::dci_p3()),
+            (12, ColorGamut::display_p3()),
+        ] {
+            assert_eq!(ColorGamut::from_cicp_primaries(code).map(xy), Some(xy(expected)), "{}", code);
+        }
+        // Reserved, unspecified, and ones without a `ColorGamut`.
+        for code in [0, 2, 3, 22, 255] {
+            assert!(ColorGamut::from_cicp_primaries(code).is_none(), "{}", code);
+        }
+        for &(code, color_gamut) in ColorGamut::CICP_COLOR_PRIMARIES {
+            assert_eq!(color_gamut.cicp_color_primaries(), Some(code));
+        }
+        assert_eq!(ColorGamut::adobe_rgb().cicp_color_primaries(), None);
+        assert_eq!(ColorGamut::prophoto_rgb().cicp_color_primaries(), None);
+        assert_eq!(ColorGamut::display_p3().white_point_xy(), ColorGamut::srgb().white_point_xy());
+
+        // Display P3 red in BT.2020, per the well-known conversion matrix.
+        let red = ColorGamut::convert(&[1.0, 0.0, 0.0], &ColorGamut::display_p3(), &ColorGamut::bt2020());
+        for (actual, expected) in red.iter().zip([0.7538, 0.0457, -0.0012]) {
+            assert!((actual - expected).abs() < 1e-3, "{:?}", red);
+        }
+
+        // A converter round trips through its inverse, and is the identity for the same gamut.
+        let to_bt2020 = ColorGamutConverter::new(&ColorGamut::display_p3(), &ColorGamut::bt2020());
+        let from_bt2020 = ColorGamutConverter::new(&ColorGamut::bt2020(), &ColorGamut::display_p3());
+        for value in [[1.0, 0.0, 0.0], [0.25, 0.5, 0.75], [4.0, 2.0, 1.0]] {
+            for (actual, expected) in from_bt2020.convert(&to_bt2020.convert(&value)).iter().zip(value) {
+                assert!((actual - expected).abs() < 1e-5 * expected.max(1.0), "{:?}", value);
+            }
+            let identity = ColorGamutConverter::new(&ColorGamut::bt2020(), &ColorGamut::bt2020()).convert(&value);
+            for (actual, expected) in identity.iter().zip(value) {
+                assert!((actual - expected).abs() < 1e-5 * expected.max(1.0), "{:?}", value);
+            }
+        }
+
+        // The luminances of gamuts from chromaticities alone are derived from the white point.
+        let bt2020 = ColorGamut::bt2020();
+        let primaries = bt2020.primaries();
+        let from_xy = ColorGamut::from_xy([primaries.red_xy(), primaries.green_xy(), primaries.blue_xy()], bt2020.white_point_xy()).unwrap();
+        assert_eq!(from_xy.cicp_color_primaries(), Some(Cicp::COLOR_PRIMARIES_BT2020));
+        for (actual, expected) in [from_xy.primaries().red(), from_xy.primaries().green(), from_xy.primaries().blue()].iter().zip([0.2627, 0.6780, 0.0593]) {
+            assert!((actual[2] - expected).abs() < 1e-3, "{:?}", actual);
+        }
+        assert!(ColorGamut::from_xy([[0.3, 0.6]; 3], bt2020.white_point_xy()).is_none());
+        assert!(ColorGamut::from_xy([primaries.red_xy(), primaries.green_xy(), primaries.blue_xy()], [0.3, 0.0]).is_none());
+
+        assert_eq!(SourceTransfer::from_cicp_transfer_characteristics(18), Some(SourceTransfer::Hlg));
+        assert_eq!(SourceTransfer::from_cicp_transfer_characteristics(4), Some(SourceTransfer::Gamma22));
+        assert_eq!(SourceTransfer::from_cicp_transfer_characteristics(13), Some(SourceTransfer::Srgb));
+        assert_eq!(SourceTransfer::from_cicp_transfer_characteristics(16), None);
+        let cicp = Cicp { color_primaries: 12, transfer_characteristics: 18, matrix_coefficients: 0, full_range: true };
+        assert_eq!(cicp.color_gamut().map(xy), Some(xy(ColorGamut::display_p3())));
+        assert_eq!(cicp.source_transfer(), Some(SourceTransfer::Hlg));
+    }
+
+    #[test]
+    fn white_points_are_adapted_with_bradford() {
+        let assert_close = |actual: [f32; 3], expected: [f32; 3]| {
+            for (actual, expected) in actual.iter().zip(expected) {
+                assert!((actual - expected).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+            }
+        };
+
+        // Rec. ITU-R BT.2087, the conversion from BT.709 to BT.2020, both D65.
+        let to_bt2020 = ColorGamutConverter::new(&ColorGamut::srgb(), &ColorGamut::bt2020());
+        assert_close(to_bt2020.convert(&[1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+        assert_close(to_bt2020.convert(&[1.0, 0.0, 0.0]), [0.6274, 0.0691, 0.0164]);
+        assert_close(to_bt2020.convert(&[0.0, 1.0, 0.0]), [0.3293, 0.9195, 0.0880]);
+        assert_close(to_bt2020.convert(&[0.0, 0.0, 1.0]), [0.0433, 0.0114, 0.8956]);
+
+        // D50 to D65 per Bruce Lindbloom's table of Bradford matrices, which are for column vectors.
+        let adaptation = bradford_adaptation(ColorGamut::WHITE_POINT_D50, ColorGamut::WHITE_POINT_D65);
+        let expected = [
+            [ 0.9555766, -0.0230393,  0.0631636],
+            [-0.0282895,  1.0099416,  0.0210077],
+            [ 0.0122982, -0.0204830,  1.3299098],
+        ];
+        for (row, expected_column) in expected.iter().enumerate() {
+            for (column, expected) in expected_column.iter().enumerate() {
+                assert!((adaptation[column][row] - expected).abs() < 1e-3, "{:?}", adaptation);
+            }
+        }
+
+        // D50 white stays white in a D65 gamut, and neutrals stay neutral.
+        let to_srgb = ColorGamutConverter::new(&ColorGamut::prophoto_rgb(), &ColorGamut::srgb());
+        assert_close(to_srgb.convert(&[1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+        assert_close(to_srgb.convert(&[0.2, 0.2, 0.2]), [0.2, 0.2, 0.2]);
+
+        // Adobe RGB shares red and blue with sRGB but not their luminances, per the well-known conversion matrix.
+        let to_srgb = ColorGamutConverter::new(&ColorGamut::adobe_rgb(), &ColorGamut::srgb());
+        assert_close(to_srgb.convert(&[1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+        for (value, expected) in [([1.0, 0.0, 0.0], [1.3982, 0.0, 0.0]), ([0.0, 1.0, 0.0], [-0.3982, 1.0, -0.0429]), ([0.0, 0.0, 1.0], [0.0, 0.0, 1.0429])] {
+            let actual = to_srgb.convert(&value);
+            assert!(actual.iter().zip(expected).all(|(actual, expected)| (actual - expected).abs() < 1e-3), "{:?} != {:?}", actual, expected);
+        }
+
+        // The DCI white point, which DCI-P3 white is adapted from.
+        assert_eq!(ColorGamut::dci_p3().white_point_xy(), [0.3140, 0.3510]);
+        let to_bt2020 = ColorGamutConverter::new(&ColorGamut::dci_p3(), &ColorGamut::bt2020());
+        assert_close(to_bt2020.convert(&[1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,241 @@
+//! Extracting the rendered preview of a DNG and its gain map as an Ultra HDR JPEG, so that DNGs go through the same pipeline.
+//!
+//! The raw sensor data itself is not developed. Instead, the JPEG previews a DNG carries in its IFDs and `SubIFDs` are used:
+//! Either a preview that already is an Ultra HDR JPEG, as some phones embed, or the largest preview and a separate
+//! gain map JPEG with `hdrgm` XMP, which are muxed into one.
+//!
+//! See: https://helpx.adobe.com/camera-raw/digital-negative.html
+
+#![cfg(feature = "dng")]
+
+use std::io::Cursor;
+
+use crate::mux;
+use crate::segments;
+use crate::tiff::{Tiff, TiffFieldValue, TiffIfd};
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_SUB_IFDS: u16 = 330;
+const TAG_JPEG_INTERCHANGE_FORMAT: u16 = 513;
+const TAG_JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 514;
+const TAG_DNG_VERSION: u16 = 50706;
+
+/// "Old-style" JPEG, located by `JPEGInterchangeFormat`, which DNG uses for some thumbnails.
+const COMPRESSION_OLD_JPEG: u16 = 6;
+/// JPEG in strips. A preview is a complete JPEG in a single strip.
+const COMPRESSION_JPEG: u16 = 7;
+
+/// More than the raw image, previews and thumbnails a DNG has in practice.
+const MAX_SUB_IFD_COUNT: usize = 16;
+
+/// Whether `bytes` start like a TIFF, and hence possibly a DNG, rather than a JPEG.
+pub fn is_tiff(bytes: &[u8]) -> bool {
+    matches!(bytes.get(0..4), Some(b"II*\0" | b"MM\0*"))
+}
+
+/// Extracts an Ultra HDR JPEG from the JPEG previews of the DNG `dng_bytes`, to be converted like any other.
+///
+/// A preview that already has an MPF block is taken as is, the largest one first.
+/// Otherwise, a preview with `hdrgm` gain map metadata in its XMP is taken as the gain map of the largest other preview.
+pub fn extract_uhdr_jpeg_from_dng(dng_bytes: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut reader = Cursor::new(dng_bytes);
+    let tiff = Tiff::from_reader(&mut reader).map_err(|e| format!("Failed to parse the DNG's TIFF structure: {}", e))?;
+    if tiff.ifds.first().and_then(|ifd| ifd.entry_with_tag(TAG_DNG_VERSION)).is_none() {
+        return Err("Not a DNG: IFD0 has no `DNGVersion`".into());
+    }
+
+    // DNG places the raw image and the previews in `SubIFDs` of IFD0, and sometimes a thumbnail in IFD0 itself.
+    let mut ifds: Vec<TiffIfd> = Vec::new();
+    for ifd in &tiff.ifds {
+        for offset in unsigned_values(ifd, TAG_SUB_IFDS) {
+            if ifds.len() >= MAX_SUB_IFD_COUNT {
+                return Err("Too many SubIFDs".into());
+            }
+            ifds.push(tiff.read_ifd_at(&mut reader, offset).map_err(|e| format!("Failed to read SubIFD: {}", e))?);
+        }
+    }
+    ifds.extend(tiff.ifds.iter().cloned());
+
+    let mut previews: Vec<(u64, &[u8])> = ifds.iter()
+        .filter_map(|ifd| Some((pixel_count(ifd), jpeg_bytes(ifd, dng_bytes)?)))
+        .collect();
+    previews.sort_by_key(|&(pixel_count, _)| std::cmp::Reverse(pixel_count));
+
+    if let Some(&(_, uhdr_jpeg)) = previews.iter().find(|(_, jpeg)| has_mpf(jpeg)) {
+        return Ok(uhdr_jpeg.to_vec());
+    }
+
+    let gain_map_index = previews.iter()
+        .position(|(_, jpeg)| has_gain_map_metadata(jpeg))
+        .ok_or("The DNG has no JPEG preview with a gain map")?;
+    let (_, gain_map_jpeg) = previews.remove(gain_map_index);
+    let &(_, primary_jpeg) = previews.first().ok_or("The DNG has no JPEG preview besides the gain map")?;
+
+    let mut uhdr_jpeg = Vec::new();
+    mux::write_uhdr_jpeg(&mut uhdr_jpeg, primary_jpeg, gain_map_jpeg, None)?;
+    Ok(uhdr_jpeg)
+}
+
+/// The values of an entry that is either `SHORT` or `LONG`, as most offsets, counts and dimensions may be.
+fn unsigned_values(ifd: &TiffIfd, tag: u16) -> Vec<u64> {
+    match ifd.entry_with_tag(tag).map(|entry| &entry.field_value) {
+        Some(TiffFieldValue::SHORT(values)) => values.iter().map(|&value| value.into()).collect(),
+        Some(TiffFieldValue::LONG(values)) => values.iter().map(|&value| value.into()).collect(),
+        Some(TiffFieldValue::LONG8(values)) => values.clone(),
+        _ => Vec::new(),
+    }
+}
+
+fn unsigned_value(ifd: &TiffIfd, tag: u16) -> Option<u64> {
+    unsigned_values(ifd, tag).first().copied()
+}
+
+/// 0 if the dimensions are missing, so that such previews are only used as a last resort.
+fn pixel_count(ifd: &TiffIfd) -> u64 {
+    unsigned_value(ifd, TAG_IMAGE_WIDTH).unwrap_or(0).saturating_mul(unsigned_value(ifd, TAG_IMAGE_LENGTH).unwrap_or(0))
+}
+
+/// The complete JPEG of `ifd` within `dng_bytes`, if it is a JPEG preview. `None` for any other image, e.g. the raw one.
+fn jpeg_bytes<'a>(ifd: &TiffIfd, dng_bytes: &'a [u8]) -> Option<&'a [u8]> {
+    let (offset, length) = match unsigned_value(ifd, TAG_COMPRESSION)? as u16 {
+        COMPRESSION_OLD_JPEG => (
+            unsigned_value(ifd, TAG_JPEG_INTERCHANGE_FORMAT)?,
+            unsigned_value(ifd, TAG_JPEG_INTERCHANGE_FORMAT_LENGTH)?,
+        ),
+        COMPRESSION_JPEG => match (&unsigned_values(ifd, TAG_STRIP_OFFSETS)[..], &unsigned_values(ifd, TAG_STRIP_BYTE_COUNTS)[..]) {
+            // Multiple strips are the tiles of a lossless raw image rather than a preview.
+            ([offset], [length]) => (*offset, *length),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(length).ok()?)?;
+    dng_bytes.get(start..end).filter(|jpeg| jpeg.starts_with(&[0xFF, 0xD8]))
+}
+
+/// Whether `jpeg` has an MPF block, i.e. secondary images such as a gain map.
+fn has_mpf(jpeg: &[u8]) -> bool {
+    segments::header_segments(jpeg).any(|segment| segment.marker == 0xE2 && segment.payload.starts_with(b"MPF\0"))
+}
+
+fn has_gain_map_metadata(jpeg: &[u8]) -> bool {
+    mux::find_xmp(jpeg).is_some_and(|xmp| xmp.windows(b"hdrgm:HDRCapacityMax".len()).any(|window| window == b"hdrgm:HDRCapacityMax"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::{self, TestGainMapParams, TestImage};
+    use crate::tiff::{Endianness, TiffIfdEntry, TiffWriter};
+    use crate::UhdrConverter;
+
+    /// A DNG with a raw image and the `jpegs` as previews in `SubIFDs`, and a small uncompressed thumbnail in IFD0.
+    fn build_dng(jpegs: &[(&TestImage, Vec<u8>)]) -> Vec<u8> {
+        let short = |tag: u16, value: u16| TiffIfdEntry::new(tag, TiffFieldValue::SHORT(vec![value]));
+        let long = |tag: u16, value: u32| TiffIfdEntry::new(tag, TiffFieldValue::LONG(vec![value]));
+
+        let mut writer = TiffWriter::new(Endianness::LittleEndian);
+        let mut sub_ifd_offsets = Vec::new();
+
+        // Uncompressed 16-bit raw data.
+        let raw_offset = writer.append_data(&[0; 4 * 4 * 2]);
+        sub_ifd_offsets.push(writer.append_unchained_ifd(&[
+            long(254, 0),
+            long(TAG_IMAGE_WIDTH, 4),
+            long(TAG_IMAGE_LENGTH, 4),
+            short(TAG_COMPRESSION, 1),
+            long(TAG_STRIP_OFFSETS, raw_offset),
+            long(TAG_STRIP_BYTE_COUNTS, 4 * 4 * 2),
+        ]).unwrap());
+
+        for (image, jpeg) in jpegs {
+            let offset = writer.append_data(jpeg);
+            sub_ifd_offsets.push(writer.append_unchained_ifd(&[
+                long(254, 1),
+                long(TAG_IMAGE_WIDTH, image.width as u32),
+                long(TAG_IMAGE_LENGTH, image.height as u32),
+                short(TAG_COMPRESSION, COMPRESSION_JPEG),
+                long(TAG_STRIP_OFFSETS, offset),
+                long(TAG_STRIP_BYTE_COUNTS, jpeg.len() as u32),
+            ]).unwrap());
+        }
+
+        let thumbnail_offset = writer.append_data(&[128; 2 * 2 * 3]);
+        writer.append_ifd(&[
+            long(254, 1),
+            long(TAG_IMAGE_WIDTH, 2),
+            long(TAG_IMAGE_LENGTH, 2),
+            short(TAG_COMPRESSION, 1),
+            long(TAG_STRIP_OFFSETS, thumbnail_offset),
+            long(TAG_STRIP_BYTE_COUNTS, 2 * 2 * 3),
+            TiffIfdEntry::new(TAG_SUB_IFDS, TiffFieldValue::LONG(sub_ifd_offsets)),
+            TiffIfdEntry::new(TAG_DNG_VERSION, TiffFieldValue::BYTE(vec![1, 7, 0, 0])),
+        ]).unwrap();
+        writer.finish()
+    }
+
+    fn convert_to_avif(uhdr_jpeg: &[u8]) -> Vec<u8> {
+        let converter = UhdrConverter::new(&mut &uhdr_jpeg[..], 4.0).unwrap();
+        assert_eq!(converter.info().gain_maps.len(), 1);
+        let mut avif_bytes = Vec::new();
+        converter.convert_to_avif(&mut avif_bytes, 80.0).unwrap();
+        avif_bytes
+    }
+
+    #[test]
+    fn ultra_hdr_preview_is_extracted_and_converted() {
+        let primary = TestImage::from_fn(16, 8, |x, _| [(x * 16) as u8, 128, 64]);
+        let small_preview = TestImage::from_fn(8, 4, |_, _| [128; 3]);
+        let uhdr_jpeg = testutil::gradient_uhdr_jpeg(16, 8, 4, 2);
+        let dng_bytes = build_dng(&[(&small_preview, testutil::encode_jpeg(&small_preview, &[])), (&primary, uhdr_jpeg.clone())]);
+
+        assert!(is_tiff(&dng_bytes));
+        assert_eq!(extract_uhdr_jpeg_from_dng(&dng_bytes).unwrap(), uhdr_jpeg);
+
+        // Transparently, through the converter.
+        let avif_bytes = convert_to_avif(&dng_bytes);
+        assert_eq!(avif_bytes, convert_to_avif(&uhdr_jpeg));
+    }
+
+    #[test]
+    fn separate_gain_map_preview_is_muxed_with_largest_preview() {
+        let primary = TestImage::from_fn(16, 8, |x, _| [(x * 16) as u8, 128, 64]);
+        let small_preview = TestImage::from_fn(8, 4, |_, _| [128; 3]);
+        let gain_map = TestImage::from_fn(4, 2, |_, _| [192; 3]);
+        let gain_map_jpeg = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&TestGainMapParams::default().to_xmp())]);
+        let primary_jpeg = testutil::encode_jpeg(&primary, &[]);
+        let dng_bytes = build_dng(&[
+            (&small_preview, testutil::encode_jpeg(&small_preview, &[])),
+            (&gain_map, gain_map_jpeg.clone()),
+            (&primary, primary_jpeg.clone()),
+        ]);
+
+        let mut expected = Vec::new();
+        mux::write_uhdr_jpeg(&mut expected, &primary_jpeg, &gain_map_jpeg, None).unwrap();
+        assert_eq!(extract_uhdr_jpeg_from_dng(&dng_bytes).unwrap(), expected);
+
+        let avif_bytes = convert_to_avif(&dng_bytes);
+        assert!(testutil::find(&avif_bytes, b"ftypavif").is_some());
+    }
+
+    #[test]
+    fn dng_without_gain_map_or_tiff_without_dng_version_is_rejected() {
+        let preview = TestImage::from_fn(8, 4, |_, _| [128; 3]);
+        let dng_bytes = build_dng(&[(&preview, testutil::encode_jpeg(&preview, &[]))]);
+        assert!(extract_uhdr_jpeg_from_dng(&dng_bytes).is_err());
+        assert!(UhdrConverter::new(&mut &dng_bytes[..], 4.0).is_err());
+
+        let mut writer = TiffWriter::new(Endianness::BigEndian);
+        writer.append_ifd(&[TiffIfdEntry::new(TAG_IMAGE_WIDTH, TiffFieldValue::SHORT(vec![1]))]).unwrap();
+        let tiff_bytes = writer.finish();
+        assert!(is_tiff(&tiff_bytes));
+        assert!(extract_uhdr_jpeg_from_dng(&tiff_bytes).unwrap_err().to_string().contains("DNGVersion"));
+    }
+}
@@ -0,0 +1,313 @@
+use std::io::Write;
+
+use crate::{ConversionTimings, TiffTransfer, UhdrConverter};
+
+/// Format-specific parameters passed to `HdrEncoder::encode`. Encoders ignore the ones that don't apply to them.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodeOptions {
+    /// The luminance level in nits to scale the SDR white (1, 1, 1) of the _HDR rendition_ to.
+    pub target_sdr_white_level: f32,
+    /// How the samples of a TIFF are encoded.
+    pub tiff_transfer: TiffTransfer,
+    /// How the samples of a PNG are encoded.
+    pub png_transfer: PngTransfer,
+}
+
+/// How the linear values are encoded into the 16-bit samples of a PNG, which is always available for `EncodeOptions`
+/// although writing PNG requires the `png` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum PngTransfer {
+    /// SMPTE ST 2084 PQ of absolute luminance, where the maximum sample value is 10,000 nits, as in HDR10.
+    #[default]
+    Pq,
+    /// Linear in light, scaled so that the maximum sample value is the nominal peak of the _HDR rendition_, as for TIFF.
+    Linear,
+}
+
+/// An output format that `UhdrConverter` can encode to, looked up by name or file extension through an `EncoderRegistry`.
+pub trait HdrEncoder {
+    /// The unique name of the format, e.g. `"avif"`, as selected by the CLI's `--format`.
+    fn name(&self) -> &'static str;
+
+    /// A one-line description for help texts, without a trailing period.
+    fn description(&self) -> &'static str;
+
+    /// The lowercase file extensions of the format, without the dot.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Encodes the output of `converter` to `writer`, returning how long each phase took if the encoder measures it.
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        writer: &mut dyn Write,
+        options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>>;
+}
+
+/// The available `HdrEncoder`s, in registration order.
+///
+/// `EncoderRegistry::default()` has the built-in encoders of the enabled features.
+pub struct EncoderRegistry {
+    encoders: Vec<Box<dyn HdrEncoder>>,
+}
+
+impl Default for EncoderRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        #[cfg(feature = "avif")]
+        registry.register(Box::new(AvifEncoder));
+        registry.register(Box::new(TiffEncoder));
+        registry.register(Box::new(UhdrJpegEncoder));
+        #[cfg(feature = "webp")]
+        registry.register(Box::new(WebpEncoder));
+        #[cfg(feature = "exr")]
+        registry.register(Box::new(ExrEncoder));
+        #[cfg(feature = "hdr")]
+        registry.register(Box::new(RadianceEncoder));
+        #[cfg(feature = "png")]
+        registry.register(Box::new(PngEncoder));
+        #[cfg(all(feature = "heif", feature = "avif"))]
+        registry.register(Box::new(HeifEncoder));
+        registry
+    }
+}
+
+impl EncoderRegistry {
+    /// A registry without any encoders, not even the built-in ones.
+    pub fn empty() -> Self {
+        Self { encoders: Vec::new() }
+    }
+
+    /// Adds `encoder`, replacing any encoder of the same name.
+    /// Where extensions overlap, the encoder registered first takes precedence.
+    pub fn register(&mut self, encoder: Box<dyn HdrEncoder>) {
+        self.encoders.retain(|registered| registered.name() != encoder.name());
+        self.encoders.push(encoder);
+    }
+
+    pub fn encoders(&self) -> impl Iterator<Item = &dyn HdrEncoder> {
+        self.encoders.iter().map(|encoder| encoder.as_ref())
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&dyn HdrEncoder> {
+        self.encoders().find(|encoder| encoder.name() == name)
+    }
+
+    /// Looks up an encoder by file extension, without the dot, ignoring case.
+    pub fn by_extension(&self, extension: &str) -> Option<&dyn HdrEncoder> {
+        let extension = extension.to_ascii_lowercase();
+        self.encoders().find(|encoder| encoder.extensions().contains(&extension.as_str()))
+    }
+
+    /// Looks up an encoder by the extension of `path`.
+    pub fn by_path(&self, path: &std::path::Path) -> Option<&dyn HdrEncoder> {
+        self.by_extension(path.extension()?.to_str()?)
+    }
+}
+
+#[cfg(feature = "avif")]
+struct AvifEncoder;
+
+#[cfg(feature = "avif")]
+impl HdrEncoder for AvifEncoder {
+    fn name(&self) -> &'static str { "avif" }
+    fn description(&self) -> &'static str { "10-bit HDR10 (BT.2020 PQ) AVIF" }
+    fn extensions(&self) -> &'static [&'static str] { &["avif"] }
+
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        mut writer: &mut dyn Write,
+        options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+        Ok(Some(converter.convert_to_avif_timed(&mut writer, options.target_sdr_white_level)?))
+    }
+}
+
+struct TiffEncoder;
+
+impl HdrEncoder for TiffEncoder {
+    fn name(&self) -> &'static str { "tiff" }
+    fn description(&self) -> &'static str { "Uncompressed 16-bit BT.2020 TIFF without PQ" }
+    fn extensions(&self) -> &'static [&'static str] { &["tif", "tiff"] }
+
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        mut writer: &mut dyn Write,
+        options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+        converter.convert_to_tiff(&mut writer, options.target_sdr_white_level, options.tiff_transfer)?;
+        Ok(None)
+    }
+}
+
+struct UhdrJpegEncoder;
+
+impl HdrEncoder for UhdrJpegEncoder {
+    fn name(&self) -> &'static str { "uhdr-jpeg" }
+    fn description(&self) -> &'static str {
+        "Ultra HDR JPEG with the input's primary image and gain map copied without re-encoding, and fresh MPF and XMP metadata"
+    }
+    fn extensions(&self) -> &'static [&'static str] { &["jpg", "jpeg"] }
+
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        mut writer: &mut dyn Write,
+        _options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+        converter.convert_to_uhdr_jpeg(&mut writer)?;
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "webp")]
+struct WebpEncoder;
+
+#[cfg(feature = "webp")]
+impl HdrEncoder for WebpEncoder {
+    fn name(&self) -> &'static str { "webp" }
+    fn description(&self) -> &'static str { "Lossy 8-bit sRGB WebP of the primary image, i.e. the SDR rendition" }
+    fn extensions(&self) -> &'static [&'static str] { &["webp"] }
+
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        mut writer: &mut dyn Write,
+        _options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+        converter.convert_to_webp(&mut writer)?;
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "exr")]
+struct ExrEncoder;
+
+#[cfg(feature = "exr")]
+impl HdrEncoder for ExrEncoder {
+    fn name(&self) -> &'static str { "exr" }
+    fn description(&self) -> &'static str { "32-bit float linear BT.2020 OpenEXR, where 1.0 is SDR white" }
+    fn extensions(&self) -> &'static [&'static str] { &["exr"] }
+
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        mut writer: &mut dyn Write,
+        _options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+        converter.convert_to_exr(&mut writer, &crate::ColorGamut::bt2020())?;
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "hdr")]
+struct RadianceEncoder;
+
+#[cfg(feature = "hdr")]
+impl HdrEncoder for RadianceEncoder {
+    fn name(&self) -> &'static str { "hdr" }
+    fn description(&self) -> &'static str { "Radiance RGBE linear BT.2020, where 1.0 is SDR white" }
+    fn extensions(&self) -> &'static [&'static str] { &["hdr"] }
+
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        mut writer: &mut dyn Write,
+        _options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+        converter.convert_to_hdr(&mut writer, &crate::ColorGamut::bt2020())?;
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "png")]
+struct PngEncoder;
+
+#[cfg(feature = "png")]
+impl HdrEncoder for PngEncoder {
+    fn name(&self) -> &'static str { "png" }
+    fn description(&self) -> &'static str { "16-bit BT.2020 PNG, PQ-encoded or linear" }
+    fn extensions(&self) -> &'static [&'static str] { &["png"] }
+
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        mut writer: &mut dyn Write,
+        options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+        converter.convert_to_png(&mut writer, options.target_sdr_white_level, options.png_transfer)?;
+        Ok(None)
+    }
+}
+
+#[cfg(all(feature = "heif", feature = "avif"))]
+struct HeifEncoder;
+
+#[cfg(all(feature = "heif", feature = "avif"))]
+impl HdrEncoder for HeifEncoder {
+    fn name(&self) -> &'static str { "heif" }
+    fn description(&self) -> &'static str { "10-bit HDR10 (BT.2020 PQ) HEVC HEIF" }
+    fn extensions(&self) -> &'static [&'static str] { &["heic", "heif"] }
+
+    fn encode(
+        &self,
+        converter: &UhdrConverter,
+        mut writer: &mut dyn Write,
+        options: &EncodeOptions,
+    ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+        converter.convert_to_heif(&mut writer, options.target_sdr_white_level)?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil;
+
+    /// Writes the output dimensions as text.
+    struct DummyEncoder;
+
+    impl HdrEncoder for DummyEncoder {
+        fn name(&self) -> &'static str { "dummy" }
+        fn description(&self) -> &'static str { "Dimensions as text" }
+        fn extensions(&self) -> &'static [&'static str] { &["txt", "tiff"] }
+
+        fn encode(
+            &self,
+            converter: &UhdrConverter,
+            writer: &mut dyn Write,
+            _options: &EncodeOptions,
+        ) -> Result<Option<ConversionTimings>, Box<dyn std::error::Error>> {
+            let (width, height) = converter.output_extent();
+            write!(writer, "{}x{}", width, height)?;
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn registered_encoder_is_resolved_by_name_and_extension() {
+        let mut registry = EncoderRegistry::default();
+        registry.register(Box::new(DummyEncoder));
+
+        assert_eq!(registry.by_name("dummy").unwrap().name(), "dummy");
+        assert_eq!(registry.by_extension("TXT").unwrap().name(), "dummy");
+        assert_eq!(registry.by_path(std::path::Path::new("out/image.txt")).unwrap().name(), "dummy");
+        // The built-in encoder registered first keeps the extension.
+        assert_eq!(registry.by_extension("tiff").unwrap().name(), "tiff");
+        assert!(registry.by_name("txt").is_none());
+        assert!(registry.by_path(std::path::Path::new("image")).is_none());
+        assert!(EncoderRegistry::empty().by_extension("tif").is_none());
+
+        let converter = UhdrConverter::new(&mut testutil::gradient_uhdr_jpeg(16, 8, 4, 2).as_slice(), 4.0).unwrap();
+        let options = EncodeOptions { target_sdr_white_level: 80.0, tiff_transfer: TiffTransfer::Linear, png_transfer: PngTransfer::Pq };
+        let mut bytes = Vec::new();
+        let timings = registry.by_name("dummy").unwrap().encode(&converter, &mut bytes, &options).unwrap();
+        assert_eq!(bytes, b"16x8");
+        assert!(timings.is_none());
+    }
+}
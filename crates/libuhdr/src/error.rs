@@ -0,0 +1,78 @@
+use crate::gainmap::GainMapError;
+use crate::jpeg::JpegDecodeError;
+
+/// Why `UhdrConverter` failed to decode its input or to convert it.
+///
+/// Functions returning `Box<dyn std::error::Error>` can still use `?` on it, and the messages are those of before.
+#[derive(Debug)]
+pub enum UhdrError {
+    /// Reading the input or writing the output failed.
+    Io(std::io::Error),
+    /// The input, or an MPF image selected from it, couldn't be decoded as a JPEG, e.g. because it is in another format.
+    NotUhdrJpeg(String),
+    /// The input has no gain map image, or its gain map image has neither XMP nor ISO 21496-1 gain map metadata.
+    MissingGainMap(String),
+    /// The gain map metadata of the gain map image couldn't be parsed.
+    GainMapMetadata(GainMapError),
+    /// The ICC profile of the primary image or a gain map couldn't be parsed.
+    Icc(String),
+    /// The options can't be applied to this input, e.g. a crop outside of the image.
+    InvalidOptions(String),
+    /// The output is wider or taller than `max_dimension`, the largest the format or its encoder supports,
+    /// e.g. for AVIF output of a huge panorama without `AvifOversize::Downscale`.
+    OutputTooLarge { width: usize, height: usize, max_dimension: usize },
+    /// Encoding or writing the output failed.
+    Encode(String),
+}
+
+impl std::fmt::Display for UhdrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::GainMapMetadata(e) => write!(f, "Failed to parse gain map metadata: {}", e),
+            Self::OutputTooLarge { width, height, max_dimension } => write!(
+                f,
+                "The output is too large: {}x{} exceeds the maximum dimensions of {}x{}",
+                width, height, max_dimension, max_dimension,
+            ),
+            Self::NotUhdrJpeg(message)
+            | Self::MissingGainMap(message)
+            | Self::Icc(message)
+            | Self::InvalidOptions(message)
+            | Self::Encode(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for UhdrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::GainMapMetadata(e) => Some(e),
+            Self::NotUhdrJpeg(_) | Self::MissingGainMap(_) | Self::Icc(_) | Self::InvalidOptions(_) | Self::OutputTooLarge { .. } | Self::Encode(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for UhdrError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<GainMapError> for UhdrError {
+    fn from(e: GainMapError) -> Self {
+        Self::GainMapMetadata(e)
+    }
+}
+
+impl UhdrError {
+    /// Prefixes the message of `e` with `context`, e.g. which image failed to decode.
+    pub(crate) fn from_jpeg_decode_error(context: &str, e: JpegDecodeError) -> Self {
+        let message = format!("{}: {}", context, e);
+        match e {
+            JpegDecodeError::Image(_) => Self::NotUhdrJpeg(message),
+            JpegDecodeError::Icc(_) => Self::Icc(message),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::orientation::Orientation;
+use crate::resolution::{self, Resolution, TAG_RESOLUTION_UNIT, TAG_X_RESOLUTION, TAG_Y_RESOLUTION};
+use crate::tiff::Endianness;
+
+const TAG_ORIENTATION: u16 = 274;
+
+/// TIFF field types of the IFD0 entries read and patched.
+const FIELD_TYPE_SHORT: u16 = 3;
+const FIELD_TYPE_RATIONAL: u16 = 5;
+
+/// An entry of IFD0 of an EXIF TIFF block, with where its value field is.
+struct RawIfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    /// The offset of the 4-byte value field, which holds the value itself if it fits, or else the offset of the value.
+    value_field_offset: usize,
+}
+
+/// The orientation in IFD0 of an EXIF TIFF block. `None` if it is missing or invalid.
+pub(crate) fn orientation(tiff_bytes: &[u8]) -> Option<Orientation> {
+    let (endianness, entries) = ifd0_entries(tiff_bytes)?;
+    let entry = entries.iter()
+        .find(|entry| entry.tag == TAG_ORIENTATION && entry.field_type == FIELD_TYPE_SHORT && entry.count == 1)?;
+    Orientation::from_exif_value(read_u16(tiff_bytes, endianness, entry.value_field_offset)?)
+}
+
+/// Prepares the EXIF TIFF block of the source JPEG, e.g. with the capture date, camera model and GPS position,
+/// for output whose pixels are stored in display orientation:
+/// - The orientation is reset to 1, since the pixels have already been rotated and mirrored by `Orientation::apply`,
+///   so that readers which honor the tag don't do so again.
+/// - The resolution is replaced by `resolution` where IFD0 has it, e.g. after stretching to square pixels.
+///
+/// Everything is patched in place, so that the offsets within the block, e.g. of the EXIF and GPS IFDs and of
+/// maker notes, stay valid. Tags that are missing are not added.
+pub(crate) fn carry_forward_exif_tiff(tiff_bytes: &[u8], resolution: Option<Resolution>) -> Vec<u8> {
+    let mut tiff_bytes = tiff_bytes.to_vec();
+    let Some((endianness, entries)) = ifd0_entries(&tiff_bytes) else {
+        return tiff_bytes;
+    };
+
+    for entry in entries {
+        if entry.count != 1 {
+            continue;
+        }
+        match (entry.tag, entry.field_type) {
+            (TAG_ORIENTATION, FIELD_TYPE_SHORT) => write_u16(&mut tiff_bytes, endianness, entry.value_field_offset, 1),
+            (TAG_RESOLUTION_UNIT, FIELD_TYPE_SHORT) => if let Some(resolution) = resolution {
+                write_u16(&mut tiff_bytes, endianness, entry.value_field_offset, resolution.unit.exif_value());
+            },
+            (TAG_X_RESOLUTION | TAG_Y_RESOLUTION, FIELD_TYPE_RATIONAL) => if let Some(resolution) = resolution {
+                let density = if entry.tag == TAG_X_RESOLUTION { resolution.x } else { resolution.y };
+                let (numerator, denominator) = resolution::exif_rational(density);
+                let Some(offset) = read_u32(&tiff_bytes, endianness, entry.value_field_offset) else {
+                    continue;
+                };
+                let offset = offset as usize;
+                if tiff_bytes.len() >= offset + 8 {
+                    write_u32(&mut tiff_bytes, endianness, offset, numerator);
+                    write_u32(&mut tiff_bytes, endianness, offset + 4, denominator);
+                }
+            },
+            _ => {},
+        }
+    }
+    tiff_bytes
+}
+
+/// `None` if `tiff_bytes` is not a classic TIFF, or if IFD0 is truncated.
+fn ifd0_entries(tiff_bytes: &[u8]) -> Option<(Endianness, Vec<RawIfdEntry>)> {
+    let endianness = match tiff_bytes.get(0..2)? {
+        b"II" => Endianness::LittleEndian,
+        b"MM" => Endianness::BigEndian,
+        _ => return None,
+    };
+    if read_u16(tiff_bytes, endianness, 2)? != 42 {
+        return None;
+    }
+
+    let ifd0_offset = read_u32(tiff_bytes, endianness, 4)? as usize;
+    let entry_count = read_u16(tiff_bytes, endianness, ifd0_offset)? as usize;
+    (0..entry_count)
+        .map(|index| {
+            let offset = ifd0_offset + 2 + index * 12;
+            Some(RawIfdEntry {
+                tag: read_u16(tiff_bytes, endianness, offset)?,
+                field_type: read_u16(tiff_bytes, endianness, offset + 2)?,
+                count: read_u32(tiff_bytes, endianness, offset + 4)?,
+                value_field_offset: offset + 8,
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|entries| (endianness, entries))
+}
+
+fn read_u16(bytes: &[u8], endianness: Endianness, offset: usize) -> Option<u16> {
+    endianness.read_u16(&mut bytes.get(offset..)?).ok()
+}
+
+fn read_u32(bytes: &[u8], endianness: Endianness, offset: usize) -> Option<u32> {
+    endianness.read_u32(&mut bytes.get(offset..)?).ok()
+}
+
+/// `offset` must be in bounds, as the value fields of `ifd0_entries` are.
+fn write_u16(bytes: &mut [u8], endianness: Endianness, offset: usize, value: u16) {
+    endianness.write_u16(&mut &mut bytes[offset..offset + 2], value).expect("The value field has room for a SHORT");
+}
+
+/// `offset` must be in bounds.
+fn write_u32(bytes: &mut [u8], endianness: Endianness, offset: usize, value: u32) {
+    endianness.write_u32(&mut &mut bytes[offset..offset + 4], value).expect("The bounds were checked");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::resolution::ResolutionUnit;
+    use crate::tiff::{Tiff, TiffFieldValue, TiffIfdEntry, TiffWriter};
+
+    #[test]
+    fn orientation_and_resolution_are_patched_and_the_rest_is_kept() {
+        let make = TiffFieldValue::ASCII("Camera\0".to_string());
+        for endianness in [Endianness::LittleEndian, Endianness::BigEndian] {
+            let mut writer = TiffWriter::new(endianness);
+            writer.append_ifd(&[
+                TiffIfdEntry::new(271, make.clone()),
+                TiffIfdEntry::new(TAG_ORIENTATION, TiffFieldValue::SHORT(vec![6])),
+                TiffIfdEntry::new(TAG_X_RESOLUTION, TiffFieldValue::RATIONAL(vec![(72, 1)])),
+                TiffIfdEntry::new(TAG_Y_RESOLUTION, TiffFieldValue::RATIONAL(vec![(72, 1)])),
+                TiffIfdEntry::new(TAG_RESOLUTION_UNIT, TiffFieldValue::SHORT(vec![2])),
+            ]).unwrap();
+            let tiff_bytes = writer.finish();
+
+            let resolution = Resolution { x: 150.0, y: 300.0, unit: ResolutionUnit::Centimeter };
+            let carried = carry_forward_exif_tiff(&tiff_bytes, Some(resolution));
+            assert_eq!(carried.len(), tiff_bytes.len());
+            assert_eq!(orientation(&tiff_bytes), Some(Orientation::Rotate90));
+            assert_eq!(orientation(&carried), Some(Orientation::Normal));
+            assert_eq!(Resolution::from_exif_tiff(&carried), Some(resolution));
+
+            let tiff = Tiff::from_reader(&mut std::io::Cursor::new(&carried)).unwrap();
+            let ifd0 = &tiff.ifds[0];
+            assert!(matches!(&ifd0.entry_with_tag(TAG_ORIENTATION).unwrap().field_value, TiffFieldValue::SHORT(values) if values == &[1]));
+            assert!(matches!(&ifd0.entry_with_tag(271).unwrap().field_value, TiffFieldValue::ASCII(value) if value.starts_with("Camera")));
+
+            // Without a resolution, only the orientation changes.
+            let carried = carry_forward_exif_tiff(&tiff_bytes, None);
+            assert_eq!(Resolution::from_exif_tiff(&carried), Resolution::from_exif_tiff(&tiff_bytes));
+        }
+
+        // Not a TIFF, kept as is.
+        assert_eq!(carry_forward_exif_tiff(b"garbage", None), b"garbage");
+    }
+}
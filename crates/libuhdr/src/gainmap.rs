@@ -1,6 +1,8 @@
+use log::warn;
 
 /// See: https://developer.android.com/media/platform/hdr-image-format
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct GainMapMetadata {
     /// Indicates the dynamic range of the primary image. It is required to be set to `false`: https://developer.android.com/media/platform/hdr-image-format#HDR_gain_map_metadata
     /// 
@@ -21,12 +23,176 @@ pub struct GainMapMetadata {
     pub hdr_capacity_min: f32,
     /// `hdr_capacity_max`. `log2` of the maximum display boost value for which the map is applied completely.
     pub hdr_capacity_max: f32,
+    /// How the gain map is applied with these values.
+    pub formulation: GainMapFormulation,
+}
+
+/// The formulation of gain map application that metadata is written for. Both agree for an SDR base rendition.
+///
+/// For an HDR base rendition, the Adobe formulation keeps the meaning of each value tied to the SDR and HDR renditions,
+/// whereas ISO 21496-1 ties them to the base and the alternate rendition:
+/// - Adobe: The gains are `log2` of HDR over SDR, and `offset_sdr` and `offset_hdr` are those of the SDR and HDR renditions.
+///   `hdr_capacity_min` must be less than `hdr_capacity_max`, and `base_rendition_is_hdr` inverts the weight factor,
+///   with the gains divided out of the HDR base instead of multiplied into an SDR base.
+/// - ISO 21496-1: The gains are `log2` of alternate over base, i.e. negative to derive SDR from an HDR base,
+///   `offset_sdr` and `offset_hdr` are those of the base and the alternate rendition, and `hdr_capacity_min` and
+///   `hdr_capacity_max` are the HDR headroom of the base and the alternate rendition, in either order.
+///   `base_rendition_is_hdr` is implied by that order, and ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum GainMapFormulation {
+    /// Adobe's Gain Map specification 1.0, which the `hdrgm` XMP namespace of Ultra HDR is from.
+    #[default]
+    #[cfg_attr(feature = "serde", serde(rename = "adobe"))]
+    Adobe,
+    /// _ISO 21496-1_, signalled by its `APP2` segment in the gain map image of an Ultra HDR 1.1 JPEG,
+    /// which also carries the values in binary.
+    #[cfg_attr(feature = "serde", serde(rename = "iso_21496_1"))]
+    Iso21496,
+}
+
+/// The identifier of the `APP2` segment of ISO 21496-1 gain map metadata.
+pub const ISO_21496_1_IDENTIFIER: &[u8] = b"urn:iso:std:iso:ts:21496:-1\0";
+
+/// ISO 21496-1 flags: Whether there are values for each of the 3 channels, instead of one for all of them.
+const ISO_21496_1_FLAG_MULTI_CHANNEL: u8 = 1 << 7;
+/// ISO 21496-1 flags: Whether the gain map is applied in the colour space of the base rendition.
+const ISO_21496_1_FLAG_USE_BASE_COLOUR_SPACE: u8 = 1 << 6;
+/// ISO 21496-1 flags: Whether all fractions share one denominator, written once.
+const ISO_21496_1_FLAG_COMMON_DENOMINATOR: u8 = 1 << 3;
+
+/// The payload of the ISO 21496-1 `APP2` segment of the JPEG `jpeg_bytes` after `ISO_21496_1_IDENTIFIER`.
+pub fn iso_21496_1_payload(jpeg_bytes: &[u8]) -> Option<&[u8]> {
+    crate::segments::header_segments(jpeg_bytes)
+        .find(|segment| segment.marker == 0xE2 && segment.payload.starts_with(ISO_21496_1_IDENTIFIER))
+        .map(|segment| &segment.payload[ISO_21496_1_IDENTIFIER.len()..])
+}
+
+/// Why `GainMapMetadata::new_from_xmp_bytes` failed.
+#[derive(Debug)]
+pub enum GainMapError {
+    /// The XMP isn't UTF-8.
+    InvalidUtf8(std::str::Utf8Error),
+    /// The XMP isn't well-formed XML.
+    InvalidXml(roxmltree::Error),
+    /// The XMP has no `rdf:Description`, i.e. it isn't RDF.
+    MissingDescription,
+    /// A property without a default is missing or unparseable, e.g. `HDRCapacityMax` of XMP that isn't gain map metadata.
+    MissingField(&'static str),
+    /// The binary ISO 21496-1 metadata has a minimum version other than 0, which this reader doesn't understand.
+    UnsupportedIsoVersion(u16),
+    /// The binary ISO 21496-1 metadata ends early, or only has the version, as in the primary image, or has a zero denominator.
+    InvalidIso,
+}
+
+impl std::fmt::Display for GainMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUtf8(e) => write!(f, "The gain map XMP is not valid UTF-8: {}", e),
+            Self::InvalidXml(e) => write!(f, "The gain map XMP is not valid XML: {}", e),
+            Self::MissingDescription => write!(f, "The gain map XMP has no rdf:Description"),
+            Self::MissingField(name) => write!(f, "The gain map XMP has no valid hdrgm:{}", name),
+            Self::UnsupportedIsoVersion(version) => write!(f, "The ISO 21496-1 gain map metadata has the unsupported minimum version {}", version),
+            Self::InvalidIso => write!(f, "The ISO 21496-1 gain map metadata is truncated or invalid"),
+        }
+    }
+}
+
+impl std::error::Error for GainMapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidUtf8(e) => Some(e),
+            Self::InvalidXml(e) => Some(e),
+            Self::MissingDescription | Self::MissingField(_) | Self::UnsupportedIsoVersion(_) | Self::InvalidIso => None,
+        }
+    }
 }
 
 impl GainMapMetadata {
-    pub fn new_from_xmp_bytes(xmp_bytes: &[u8]) -> Option<Self> {
-        let doc = roxmltree::Document::parse(std::str::from_utf8(xmp_bytes).unwrap()).unwrap();
-        let description_element_node = doc.descendants().find(|node| node.tag_name().name() == "Description").unwrap();
+    /// Reads the metadata of the gain map image `jpeg_bytes`, with the XMP `xmp_bytes` if it has any:
+    /// From its binary ISO 21496-1 metadata if it has any, as `GainMapFormulation::Iso21496`, otherwise from the `hdrgm` XMP,
+    /// as `GainMapFormulation::Adobe`. `None` if it has neither.
+    ///
+    /// If the ISO 21496-1 metadata is invalid, the XMP is read instead if there is any. Its values are still those of
+    /// the Adobe formulation, whatever the `APP2` segment signals.
+    pub fn new_from_gain_map_jpeg_bytes(jpeg_bytes: &[u8], xmp_bytes: Option<&[u8]>) -> Option<Result<Self, GainMapError>> {
+        if let Some(payload) = iso_21496_1_payload(jpeg_bytes) {
+            match Self::new_from_iso_21496_1_bytes(payload) {
+                Ok(metadata) => return Some(Ok(metadata)),
+                Err(e) if xmp_bytes.is_some() => warn!("{}, reading the XMP instead", e),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        xmp_bytes.map(Self::new_from_xmp_bytes)
+    }
+
+    /// Reads the binary ISO 21496-1 metadata `bytes`, i.e. the payload of the `APP2` segment after `ISO_21496_1_IDENTIFIER`,
+    /// as `GainMapFormulation::Iso21496`. Single-channel values apply to all 3 channels.
+    ///
+    /// Gain maps applied in the colour space of the alternate rendition are applied in that of the base rendition anyway.
+    pub fn new_from_iso_21496_1_bytes(bytes: &[u8]) -> Result<Self, GainMapError> {
+        let mut reader = BigEndianReader(bytes);
+
+        let min_version = reader.u16()?;
+        if min_version != 0 {
+            return Err(GainMapError::UnsupportedIsoVersion(min_version));
+        }
+        let _writer_version = reader.u16()?;
+
+        let flags = reader.u8()?;
+        let channel_count = if flags & ISO_21496_1_FLAG_MULTI_CHANNEL != 0 { 3 } else { 1 };
+        if flags & ISO_21496_1_FLAG_USE_BASE_COLOUR_SPACE == 0 {
+            warn!("The gain map is for the colour space of the alternate rendition, applying it in that of the base rendition");
+        }
+
+        let common_denominator = if flags & ISO_21496_1_FLAG_COMMON_DENOMINATOR != 0 { Some(reader.u32()?) } else { None };
+        // Each fraction is written as its numerator, and its denominator unless it is the common one.
+        let read_fraction = |reader: &mut BigEndianReader, signed: bool| -> Result<f32, GainMapError> {
+            let numerator = if signed { reader.i32()? as f64 } else { reader.u32()? as f64 };
+            let denominator = match common_denominator {
+                Some(denominator) => denominator,
+                None => reader.u32()?,
+            };
+            if denominator == 0 {
+                return Err(GainMapError::InvalidIso);
+            }
+            Ok((numerator / denominator as f64) as f32)
+        };
+
+        let base_hdr_headroom = read_fraction(&mut reader, false)?;
+        let alternate_hdr_headroom = read_fraction(&mut reader, false)?;
+        // The gain map min and max, the gamma, and the base and alternate offsets of each channel.
+        let mut channels = [[0.0f32; 5]; 3];
+        for channel in channels.iter_mut().take(channel_count) {
+            for (index, value) in channel.iter_mut().enumerate() {
+                // Only the gamma is unsigned.
+                *value = read_fraction(&mut reader, index != 2)?;
+            }
+        }
+        if channel_count == 1 {
+            channels = [channels[0]; 3];
+        }
+        let per_channel = |index: usize| channels.map(|channel| channel[index]);
+
+        Ok(Self {
+            // Implied by the headrooms for this formulation.
+            base_rendition_is_hdr: base_hdr_headroom > alternate_hdr_headroom,
+            gain_map_min: per_channel(0),
+            gain_map_max: per_channel(1),
+            gamma: per_channel(2),
+            offset_sdr: per_channel(3),
+            offset_hdr: per_channel(4),
+            hdr_capacity_min: base_hdr_headroom,
+            hdr_capacity_max: alternate_hdr_headroom,
+            formulation: GainMapFormulation::Iso21496,
+        })
+    }
+
+    pub fn new_from_xmp_bytes(xmp_bytes: &[u8]) -> Result<Self, GainMapError> {
+        let xmp = std::str::from_utf8(xmp_bytes).map_err(GainMapError::InvalidUtf8)?;
+        let doc = roxmltree::Document::parse(xmp).map_err(GainMapError::InvalidXml)?;
+        let description_element_node = Self::find_container(&doc).ok_or(GainMapError::MissingDescription)?;
 
         let base_rendition_is_hdr = Self::read_single_bool_value(&description_element_node, "BaseRenditionIsHDR").unwrap_or(false);
         let gain_map_min = Self::read_rgb_f32_value(&description_element_node, "GainMapMin").unwrap_or([0.0; 3]);
@@ -35,9 +201,16 @@ impl GainMapMetadata {
         let offset_sdr = Self::read_rgb_f32_value(&description_element_node, "OffsetSDR").unwrap_or([0.015625; 3]);
         let offset_hdr = Self::read_rgb_f32_value(&description_element_node, "OffsetHDR").unwrap_or([0.015625; 3]);
         let hdr_capacity_min = Self::read_single_f32_value(&description_element_node, "HDRCapacityMin").unwrap_or(0.0);
-        let hdr_capacity_max = Self::read_single_f32_value(&description_element_node, "HDRCapacityMax")?;
+        let hdr_capacity_max = Self::read_single_f32_value(&description_element_node, "HDRCapacityMax")
+            .ok_or(GainMapError::MissingField("HDRCapacityMax"))?;
 
-        Some(Self {
+        if let Some(version) = Self::read_single_text(&description_element_node, "Version")
+            && version != "1.0"
+        {
+            warn!("Unknown gain map metadata version {:?}, applying it as version 1.0", version);
+        }
+
+        Ok(Self {
             base_rendition_is_hdr,
             gain_map_min,
             gain_map_max,
@@ -46,60 +219,203 @@ impl GainMapMetadata {
             offset_hdr,
             hdr_capacity_min,
             hdr_capacity_max,
+            formulation: GainMapFormulation::Adobe,
         })
     }
 
+    /// Serializes as the `hdrgm` XMP of the gain map image of an Ultra HDR JPEG, which `new_from_xmp_bytes` can read back.
+    pub fn to_xmp(&self) -> String {
+        let rgb_element = |name: &str, values: &[f32; 3]| {
+            format!(
+                "<hdrgm:{name}><rdf:Seq><rdf:li>{}</rdf:li><rdf:li>{}</rdf:li><rdf:li>{}</rdf:li></rdf:Seq></hdrgm:{name}>",
+                values[0], values[1], values[2],
+            )
+        };
+
+        format!(
+            concat!(
+                r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">"#,
+                r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">"#,
+                r#"<rdf:Description rdf:about="" xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/" hdrgm:Version="1.0" hdrgm:BaseRenditionIsHDR="{}" hdrgm:HDRCapacityMin="{}" hdrgm:HDRCapacityMax="{}">"#,
+                "{}{}{}{}{}",
+                "</rdf:Description>",
+                "</rdf:RDF>",
+                "</x:xmpmeta>",
+            ),
+            // XMP booleans are capitalized.
+            if self.base_rendition_is_hdr { "True" } else { "False" },
+            self.hdr_capacity_min,
+            self.hdr_capacity_max,
+            rgb_element("GainMapMin", &self.gain_map_min),
+            rgb_element("GainMapMax", &self.gain_map_max),
+            rgb_element("Gamma", &self.gamma),
+            rgb_element("OffsetSDR", &self.offset_sdr),
+            rgb_element("OffsetHDR", &self.offset_hdr),
+        )
+    }
+
+    /// Computes how much of the gain map to apply for a display with the given `log2` of the maximum available boost,
+    /// ramping linearly from none at `hdr_capacity_min` to all of it at `hdr_capacity_max`.
+    ///
+    /// If `hdr_capacity_max` isn't greater than `hdr_capacity_min`, which only malformed metadata has, there is no ramp to
+    /// interpolate along. The weight factor is then a step function instead: All of the gain map is applied from
+    /// `hdr_capacity_max` on, and none of it below.
+    ///
+    /// For `GainMapFormulation::Iso21496`, the ramp is from the base headroom `hdr_capacity_min` to the alternate headroom
+    /// `hdr_capacity_max`, which may also be less, and is a step only if they are equal.
     pub fn compute_weight_factor(&self, log2_max_display_boost: f32) -> f32 {
-        let unclamped_weight_factor = (log2_max_display_boost - self.hdr_capacity_min) / (self.hdr_capacity_max - self.hdr_capacity_min);
-        if !self.base_rendition_is_hdr {
+        if self.formulation == GainMapFormulation::Iso21496 {
+            let (base_headroom, alternate_headroom) = (self.hdr_capacity_min, self.hdr_capacity_max);
+            return if alternate_headroom != base_headroom {
+                ((log2_max_display_boost - base_headroom) / (alternate_headroom - base_headroom)).clamp(0.0, 1.0)
+            } else if log2_max_display_boost >= alternate_headroom {
+                1.0
+            } else {
+                0.0
+            };
+        }
+
+        let weight_factor = if self.hdr_capacity_max > self.hdr_capacity_min {
+            let unclamped_weight_factor = (log2_max_display_boost - self.hdr_capacity_min) / (self.hdr_capacity_max - self.hdr_capacity_min);
             unclamped_weight_factor.clamp(0.0, 1.0)
+        } else if log2_max_display_boost >= self.hdr_capacity_max {
+            1.0
+        } else {
+            0.0
+        };
+
+        if !self.base_rendition_is_hdr {
+            weight_factor
         }
         else {
-            1.0 - unclamped_weight_factor.clamp(0.0, 1.0)
+            1.0 - weight_factor
         }
     }
 }
 
+/// Reads big-endian integers from the front of a slice, failing with `GainMapError::InvalidIso` at its end.
+struct BigEndianReader<'a>(&'a [u8]);
+
+impl BigEndianReader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], GainMapError> {
+        let (bytes, rest) = self.0.split_first_chunk::<N>().ok_or(GainMapError::InvalidIso)?;
+        self.0 = rest;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, GainMapError> {
+        self.bytes().map(u8::from_be_bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, GainMapError> {
+        self.bytes().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, GainMapError> {
+        self.bytes().map(u32::from_be_bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, GainMapError> {
+        self.bytes().map(i32::from_be_bytes)
+    }
+}
+
+/// The namespace of the gain map metadata, conventionally prefixed `hdrgm`.
+const HDRGM_NAMESPACE: &str = "http://ns.adobe.com/hdr-gain-map/1.0/";
+
+/// Where the value of an XMP property was found.
+enum XmpValue<'a, 'input> {
+    /// An attribute, or an `rdf:value` attribute of a qualified property.
+    Text(&'a str),
+    /// An element, holding the value as text or as an `rdf:Seq`.
+    Element(roxmltree::Node<'a, 'input>),
+}
+
 impl GainMapMetadata{
-    fn read_single_bool_value(description_node: &roxmltree::Node<'_, '_>, name: &str) -> Option<bool> {
-        let attr = description_node.attributes()
-            .find(|attr| attr.name() == name);
-        if let Some(attr) = attr {
-            return attr.value().parse::<bool>().ok();
-        }
+    /// The `rdf:Description` with the gain map metadata: The first one with an attribute or a descendant in the `hdrgm`
+    /// namespace, or else the first one, for XMP that uses a different namespace URI.
+    fn find_container<'a, 'input>(doc: &'a roxmltree::Document<'input>) -> Option<roxmltree::Node<'a, 'input>> {
+        let is_description = |node: &roxmltree::Node| node.is_element() && node.tag_name().name() == "Description";
+        let has_hdrgm = |node: &roxmltree::Node| node.descendants().any(|descendant| {
+            descendant.tag_name().namespace() == Some(HDRGM_NAMESPACE)
+                || descendant.attributes().any(|attr| attr.namespace() == Some(HDRGM_NAMESPACE))
+        });
 
-        let value_element_node = description_node.children().find(|node| node.tag_name().name() == name)?;
-        let text = value_element_node.text()?;
-        text.parse::<bool>().ok()
+        doc.descendants().filter(is_description).find(has_hdrgm)
+            .or_else(|| doc.descendants().find(is_description))
     }
 
-    fn read_single_f32_value(description_node: &roxmltree::Node<'_, '_>, name: &str) -> Option<f32> {
-        let attr = description_node.attributes()
-            .find(|attr| attr.name() == name);
-        if let Some(attr) = attr {
-            return attr.value().parse::<f32>().ok();
+    /// Finds the property `name` by local name, as an attribute of `container` or as an element at any depth under it.
+    ///
+    /// Besides plain properties, this accepts qualified ones, whose value is in an `rdf:value` element or attribute
+    /// nested in the property element, e.g. `<hdrgm:Gamma rdf:parseType="Resource"><rdf:value>1</rdf:value></hdrgm:Gamma>`,
+    /// as some serializers write them.
+    fn find_property<'a, 'input>(container: &roxmltree::Node<'a, 'input>, name: &str) -> Option<XmpValue<'a, 'input>> {
+        if let Some(attr) = container.attributes().find(|attr| attr.name() == name) {
+            return Some(XmpValue::Text(attr.value()));
         }
 
-        let value_element_node = description_node.children().find(|node| node.tag_name().name() == name)?;
-        let text = value_element_node.text()?;
-        text.parse::<f32>().ok()
+        let property_node = container.descendants().find(|node| node.is_element() && node.tag_name().name() == name)?;
+        // Not the qualifiers of the items of an array, which `read_seq_rgb_value` reads.
+        let is_in_array = |node: &roxmltree::Node| node.ancestors()
+            .take_while(|ancestor| *ancestor != property_node)
+            .any(|ancestor| matches!(ancestor.tag_name().name(), "Seq" | "Bag" | "Alt"));
+        for node in property_node.descendants().filter(|node| node.is_element() && !is_in_array(node)) {
+            if let Some(attr) = node.attributes().find(|attr| attr.name() == "value") {
+                return Some(XmpValue::Text(attr.value()));
+            }
+            if node.tag_name().name() == "value" {
+                return Some(XmpValue::Element(node));
+            }
+        }
+        Some(XmpValue::Element(property_node))
     }
 
-    fn read_rgb_f32_value(description_node: &roxmltree::Node<'_, '_>, name: &str) -> Option<[f32; 3]> {
-        let attr = description_node.attributes()
-            .find(|attr| attr.name() == name);
-        if let Some(attr) = attr {
-            let value = attr.value().parse::<f32>().ok()?;
-            return Some([value, value, value]);
+    /// The text of a single-valued property, trimmed.
+    fn read_single_text(container: &roxmltree::Node<'_, '_>, name: &str) -> Option<String> {
+        let text = match Self::find_property(container, name)? {
+            XmpValue::Text(text) => text,
+            XmpValue::Element(node) => node.text().unwrap_or_default(),
+        };
+        Some(text.trim().to_string())
+    }
+
+    /// Reads the property `name`, accepting `true`/`false` in any case, and `1`/`0`.
+    /// `None` if absent, and `Some(false)` with a warning if present but unparseable.
+    fn read_single_bool_value(container: &roxmltree::Node<'_, '_>, name: &str) -> Option<bool> {
+        let value = Self::read_single_text(container, name)?;
+        if value.eq_ignore_ascii_case("true") || value == "1" {
+            Some(true)
+        } else if value.eq_ignore_ascii_case("false") || value == "0" {
+            Some(false)
+        } else {
+            warn!("Unparseable {} value {:?}, assuming false", name, value);
+            Some(false)
         }
+    }
 
-        let value_element_node = description_node.children().find(|node| node.tag_name().name() == name)?;
+    fn read_single_f32_value(container: &roxmltree::Node<'_, '_>, name: &str) -> Option<f32> {
+        Self::read_single_text(container, name)?.parse::<f32>().ok()
+    }
+
+    fn read_rgb_f32_value(container: &roxmltree::Node<'_, '_>, name: &str) -> Option<[f32; 3]> {
+        let value_element_node = match Self::find_property(container, name)? {
+            XmpValue::Text(text) => {
+                let value = text.trim().parse::<f32>().ok()?;
+                return Some([value, value, value]);
+            },
+            XmpValue::Element(node) => node,
+        };
 
-        Self::read_seq_rgb_value(&value_element_node)
+        // A single value for all channels may also be written as the text of the element.
+        Self::read_seq_rgb_value(&value_element_node).or_else(|| {
+            let value = value_element_node.text()?.trim().parse::<f32>().ok()?;
+            Some([value, value, value])
+        })
     }
 
     fn read_seq_rgb_value(value_element_node: &roxmltree::Node<'_, '_>) -> Option<[f32; 3]> {
-        let seq_element_node = value_element_node.children().find(|node| node.tag_name().name() == "Seq")?;
+        let seq_element_node = value_element_node.descendants().find(|node| node.tag_name().name() == "Seq")?;
 
         let mut values = [0.0; 3];
         let mut index = 0;
@@ -109,11 +425,19 @@ impl GainMapMetadata{
                 break; // Ensure we only read up to 3 values
             }
 
-            if let Some(text) = li_node.text() {
-                if let Ok(parsed_value) = text.parse::<f32>() {
-                    values[index] = parsed_value;
-                    index += 1;
-                }
+            // Items may be qualified too.
+            let text = match li_node.text().filter(|text| !text.trim().is_empty()) {
+                Some(text) => Some(text),
+                None => li_node.descendants().find_map(|node| {
+                    node.attributes().find(|attr| attr.name() == "value").map(|attr| attr.value())
+                        .or_else(|| (node.tag_name().name() == "value").then(|| node.text()).flatten())
+                }),
+            };
+            if let Some(text) = text
+                && let Ok(parsed_value) = text.trim().parse::<f32>()
+            {
+                values[index] = parsed_value;
+                index += 1;
             }
         }
 
@@ -124,3 +448,221 @@ impl GainMapMetadata{
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weight_factor_is_a_step_for_empty_capacity_range() {
+        let metadata = GainMapMetadata {
+            base_rendition_is_hdr: false,
+            gain_map_min: [0.0; 3],
+            gain_map_max: [2.0; 3],
+            gamma: [1.0; 3],
+            offset_sdr: [0.015625; 3],
+            offset_hdr: [0.015625; 3],
+            hdr_capacity_min: 1.0,
+            hdr_capacity_max: 1.0,
+            formulation: GainMapFormulation::Adobe,
+        };
+        let inverted = GainMapMetadata { hdr_capacity_min: 2.0, ..metadata };
+        let hdr_base = GainMapMetadata { base_rendition_is_hdr: true, ..metadata };
+
+        for log2_max_display_boost in [-1.0, 0.0, 0.5, 0.999, 1.0, 1.5, 10.0] {
+            let expected = if log2_max_display_boost >= 1.0 { 1.0 } else { 0.0 };
+            assert_eq!(metadata.compute_weight_factor(log2_max_display_boost), expected, "{}", log2_max_display_boost);
+            assert_eq!(inverted.compute_weight_factor(log2_max_display_boost), expected, "{}", log2_max_display_boost);
+            assert_eq!(hdr_base.compute_weight_factor(log2_max_display_boost), 1.0 - expected, "{}", log2_max_display_boost);
+        }
+
+        // A regular range still ramps.
+        let ramp = GainMapMetadata { hdr_capacity_max: 3.0, ..metadata };
+        assert_eq!(ramp.compute_weight_factor(2.0), 0.5);
+    }
+
+    #[test]
+    fn base_rendition_is_hdr_accepts_case_insensitive_and_numeric_values() {
+        let attribute_xmp = |value: &str| format!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/" hdrgm:BaseRenditionIsHDR="{}" hdrgm:HDRCapacityMax="2"/></rdf:RDF></x:xmpmeta>"#,
+            value,
+        );
+        let element_xmp = |value: &str| format!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/" hdrgm:HDRCapacityMax="2"><hdrgm:BaseRenditionIsHDR>{}</hdrgm:BaseRenditionIsHDR></rdf:Description></rdf:RDF></x:xmpmeta>"#,
+            value,
+        );
+
+        for xmp in [attribute_xmp, element_xmp] {
+            let base_rendition_is_hdr = |value: &str| GainMapMetadata::new_from_xmp_bytes(xmp(value).as_bytes()).unwrap().base_rendition_is_hdr;
+
+            for value in ["true", "True", "TRUE", "1", " true "] {
+                assert!(base_rendition_is_hdr(value), "{:?}", value);
+            }
+            for value in ["false", "False", "0"] {
+                assert!(!base_rendition_is_hdr(value), "{:?}", value);
+            }
+            // Garbage is treated as the required `false`.
+            for value in ["yes", "2", ""] {
+                assert!(!base_rendition_is_hdr(value), "{:?}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn nested_and_qualified_properties_are_read() {
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+            <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+                <rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:CreatorTool="Editor"/>
+                <rdf:Description xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/">
+                    <hdrgm:Version>1.0</hdrgm:Version>
+                    <hdrgm:HDRCapacityMax rdf:parseType="Resource">
+                        <rdf:value> 2.5 </rdf:value>
+                    </hdrgm:HDRCapacityMax>
+                    <hdrgm:BaseRenditionIsHDR>
+                        <rdf:Description rdf:value="False"/>
+                    </hdrgm:BaseRenditionIsHDR>
+                    <hdrgm:Gamma rdf:value="2.2"/>
+                    <hdrgm:GainMap rdf:parseType="Resource">
+                        <hdrgm:GainMapMax>
+                            <rdf:Description>
+                                <rdf:value>
+                                    <rdf:Seq>
+                                        <rdf:li>1</rdf:li>
+                                        <rdf:li rdf:parseType="Resource"><rdf:value>2</rdf:value></rdf:li>
+                                        <rdf:li>3</rdf:li>
+                                    </rdf:Seq>
+                                </rdf:value>
+                            </rdf:Description>
+                        </hdrgm:GainMapMax>
+                        <hdrgm:OffsetSDR><rdf:value>0.5</rdf:value></hdrgm:OffsetSDR>
+                        <hdrgm:OffsetHDR>
+                            <rdf:Seq>
+                                <rdf:li>0.25</rdf:li>
+                                <rdf:li rdf:value="0.5"/>
+                                <rdf:li>0.75</rdf:li>
+                            </rdf:Seq>
+                        </hdrgm:OffsetHDR>
+                    </hdrgm:GainMap>
+                </rdf:Description>
+            </rdf:RDF>
+        </x:xmpmeta>"#;
+
+        let metadata = GainMapMetadata::new_from_xmp_bytes(xmp.as_bytes()).unwrap();
+        assert_eq!(metadata.hdr_capacity_max, 2.5);
+        assert!(!metadata.base_rendition_is_hdr);
+        assert_eq!(metadata.gamma, [2.2; 3]);
+        assert_eq!(metadata.gain_map_max, [1.0, 2.0, 3.0]);
+        assert_eq!(metadata.offset_sdr, [0.5; 3]);
+        assert_eq!(metadata.offset_hdr, [0.25, 0.5, 0.75]);
+        // Absent properties still default.
+        assert_eq!(metadata.gain_map_min, [0.0; 3]);
+    }
+    #[test]
+    fn malformed_xmp_is_an_error() {
+        assert!(matches!(GainMapMetadata::new_from_xmp_bytes(b"<x:xmpmeta \xFF/>"), Err(GainMapError::InvalidUtf8(_))));
+        assert!(matches!(GainMapMetadata::new_from_xmp_bytes(b"<x:xmpmeta"), Err(GainMapError::InvalidXml(_))));
+        assert!(matches!(GainMapMetadata::new_from_xmp_bytes(b"<xmpmeta/>"), Err(GainMapError::MissingDescription)));
+
+        // E.g. the XMP of the primary image.
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/" hdrgm:Version="1.0"/></rdf:RDF></x:xmpmeta>"#;
+        assert!(matches!(GainMapMetadata::new_from_xmp_bytes(xmp.as_bytes()), Err(GainMapError::MissingField("HDRCapacityMax"))));
+    }
+
+    /// ISO 21496-1 metadata with a common denominator of 4 and a single channel: Headrooms 0 and 2.5, gains 0 to 3,
+    /// a gamma of 1 and offsets of 1/4.
+    fn single_channel_iso_bytes() -> Vec<u8> {
+        let mut bytes = [0u16.to_be_bytes(), 0u16.to_be_bytes()].concat();
+        bytes.push(ISO_21496_1_FLAG_USE_BASE_COLOUR_SPACE | ISO_21496_1_FLAG_COMMON_DENOMINATOR);
+        for value in [4u32, 0, 10] {
+            bytes.extend(value.to_be_bytes());
+        }
+        for value in [0i32, 12, 4, 1, 1] {
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn iso_21496_1_metadata_is_read() {
+        let metadata = GainMapMetadata::new_from_iso_21496_1_bytes(&single_channel_iso_bytes()).unwrap();
+        assert_eq!(metadata.formulation, GainMapFormulation::Iso21496);
+        assert_eq!((metadata.hdr_capacity_min, metadata.hdr_capacity_max), (0.0, 2.5));
+        assert!(!metadata.base_rendition_is_hdr);
+        assert_eq!(metadata.gain_map_min, [0.0; 3]);
+        assert_eq!(metadata.gain_map_max, [3.0; 3]);
+        assert_eq!(metadata.gamma, [1.0; 3]);
+        assert_eq!((metadata.offset_sdr, metadata.offset_hdr), ([0.25; 3], [0.25; 3]));
+
+        // An HDR base with values for each channel, each with its own denominator.
+        let mut bytes = [0u16.to_be_bytes(), 0u16.to_be_bytes()].concat();
+        bytes.push(ISO_21496_1_FLAG_MULTI_CHANNEL | ISO_21496_1_FLAG_USE_BASE_COLOUR_SPACE);
+        for value in [3u32, 1, 0, 1] {
+            bytes.extend(value.to_be_bytes());
+        }
+        for channel in 0..3 {
+            for (numerator, denominator) in [(-3 - channel, 1), (0, 1), (1, 2), (1, 64), (1, 32)] {
+                bytes.extend((numerator as i32).to_be_bytes());
+                bytes.extend((denominator as u32).to_be_bytes());
+            }
+        }
+        let metadata = GainMapMetadata::new_from_iso_21496_1_bytes(&bytes).unwrap();
+        assert_eq!((metadata.hdr_capacity_min, metadata.hdr_capacity_max), (3.0, 0.0));
+        assert!(metadata.base_rendition_is_hdr);
+        assert_eq!(metadata.gain_map_min, [-3.0, -4.0, -5.0]);
+        assert_eq!(metadata.gamma, [0.5; 3]);
+        assert_eq!((metadata.offset_sdr, metadata.offset_hdr), ([0.015625; 3], [0.03125; 3]));
+
+        // The primary image only has the version.
+        assert!(matches!(GainMapMetadata::new_from_iso_21496_1_bytes(&[0, 0, 0, 0]), Err(GainMapError::InvalidIso)));
+        let mut zero_denominator = single_channel_iso_bytes();
+        zero_denominator[5..9].fill(0);
+        assert!(matches!(GainMapMetadata::new_from_iso_21496_1_bytes(&zero_denominator), Err(GainMapError::InvalidIso)));
+        let mut unsupported = single_channel_iso_bytes();
+        unsupported[1] = 1;
+        assert!(matches!(GainMapMetadata::new_from_iso_21496_1_bytes(&unsupported), Err(GainMapError::UnsupportedIsoVersion(1))));
+    }
+
+    #[test]
+    fn gain_map_metadata_is_read_from_iso_21496_1_before_xmp() {
+        let image = crate::testutil::TestImage::from_fn(8, 8, |_, _| [128; 3]);
+        let iso_jpeg = |payload: &[u8]| crate::testutil::encode_jpeg(&image, &[(2, [ISO_21496_1_IDENTIFIER, payload].concat())]);
+        let xmp = GainMapMetadata {
+            base_rendition_is_hdr: false,
+            gain_map_min: [0.0; 3],
+            gain_map_max: [1.0; 3],
+            gamma: [1.0; 3],
+            offset_sdr: [0.015625; 3],
+            offset_hdr: [0.015625; 3],
+            hdr_capacity_min: 0.0,
+            hdr_capacity_max: 1.0,
+            formulation: GainMapFormulation::Adobe,
+        }.to_xmp();
+
+        let read = |jpeg_bytes: &[u8], xmp: Option<&str>| {
+            GainMapMetadata::new_from_gain_map_jpeg_bytes(jpeg_bytes, xmp.map(str::as_bytes)).map(Result::unwrap)
+        };
+        let from_iso = read(&iso_jpeg(&single_channel_iso_bytes()), Some(&xmp)).unwrap();
+        assert_eq!((from_iso.gain_map_max, from_iso.formulation), ([3.0; 3], GainMapFormulation::Iso21496));
+        assert_eq!(read(&iso_jpeg(&single_channel_iso_bytes()), None).unwrap().gain_map_max, [3.0; 3]);
+
+        // The XMP if the binary metadata is invalid, which is of the Adobe formulation whatever the segment signals.
+        let from_xmp = read(&iso_jpeg(&[0, 0, 0, 0]), Some(&xmp)).unwrap();
+        assert_eq!((from_xmp.gain_map_max, from_xmp.formulation), ([1.0; 3], GainMapFormulation::Adobe));
+        assert!(GainMapMetadata::new_from_gain_map_jpeg_bytes(&iso_jpeg(&[0, 0, 0, 0]), None).unwrap().is_err());
+
+        // An HDR base in the XMP still has its gains divided out for an SDR display, as ISO 21496-1 would instead
+        // multiply them in.
+        let hdr_base_xmp = xmp.replace("hdrgm:BaseRenditionIsHDR=\"False\"", "hdrgm:BaseRenditionIsHDR=\"True\"");
+        assert_ne!(hdr_base_xmp, xmp);
+        let hdr_base = read(&iso_jpeg(&[0, 0, 0, 0]), Some(&hdr_base_xmp)).unwrap();
+        assert!(hdr_base.base_rendition_is_hdr);
+        assert_eq!(hdr_base.formulation, GainMapFormulation::Adobe);
+        let base = crate::pixel::FloatPixel::new(0.5, 0.5, 0.5);
+        let boosted = crate::uhdr::UhdrBoostComputer::new(&hdr_base, 0.0).compute_boosted(base, crate::pixel::FloatPixel::one());
+        assert!(boosted.g() < 0.3, "{:?}", boosted);
+
+        let plain_jpeg = crate::testutil::encode_jpeg(&image, &[]);
+        assert_eq!(read(&plain_jpeg, Some(&xmp)).unwrap().formulation, GainMapFormulation::Adobe);
+        assert!(read(&plain_jpeg, None).is_none());
+    }
+}
@@ -0,0 +1,207 @@
+#![cfg(feature = "hdr")]
+
+use crate::pixel::{FloatImageContent, FloatPixel};
+
+/// The largest width or height read, to fail early on corrupt headers rather than allocate for them.
+const MAX_EXTENT: usize = 1 << 16;
+
+/// A Radiance RGBE `.hdr` as read by `read_hdr`.
+#[derive(Clone)]
+pub struct RadianceImage {
+    /// The linear pixels, divided by the product of any `EXPOSURE` lines, so that they are as before the exposure was applied.
+    pub content: FloatImageContent,
+    /// The chromaticities of the red, green and blue primaries and of the white point, as x and y,
+    /// from the `PRIMARIES` line if there is one.
+    pub primaries: Option<[[f64; 2]; 4]>,
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads a Radiance RGBE `.hdr`, like those `write_linear_pixels_to_hdr` writes.
+///
+/// Scanlines may be flat, run-length encoded adaptively, or run-length encoded the old way. Only the standard orientation
+/// of `-Y <height> +X <width>`, i.e. top to bottom, left to right, is supported, and only the `32-bit_rle_rgbe` format.
+pub fn read_hdr(hdr_bytes: &[u8]) -> std::io::Result<RadianceImage> {
+    let mut rest = hdr_bytes;
+    let mut next_line = || {
+        let end = rest.iter().position(|&byte| byte == b'\n').ok_or_else(|| invalid_data("Truncated Radiance header"))?;
+        let line = std::str::from_utf8(&rest[..end]).map_err(|_| invalid_data("Radiance header is not UTF-8"))?;
+        rest = &rest[end + 1..];
+        Ok::<_, std::io::Error>(line.trim_end_matches('\r'))
+    };
+
+    let signature = next_line()?;
+    if signature != "#?RADIANCE" && signature != "#?RGBE" {
+        return Err(invalid_data("Not a Radiance RGBE file"));
+    }
+
+    let mut exposure = 1.0f32;
+    let mut primaries = None;
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err(invalid_data(format!("Unsupported Radiance format {:?}", format)));
+            }
+        } else if let Some(value) = line.strip_prefix("EXPOSURE=") {
+            exposure *= value.trim().parse::<f32>().map_err(|_| invalid_data(format!("Invalid exposure {:?}", value)))?;
+        } else if let Some(values) = line.strip_prefix("PRIMARIES=") {
+            let values: Vec<f64> = values.split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid_data(format!("Invalid primaries {:?}", values)))?;
+            let values: [f64; 8] = values.try_into().map_err(|_| invalid_data("Expected 8 values for the primaries"))?;
+            primaries = Some(std::array::from_fn(|index| [values[index * 2], values[index * 2 + 1]]));
+        }
+    }
+
+    let resolution = next_line()?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["-Y", height, "+X", width] => (height.parse::<usize>(), width.parse::<usize>()),
+        _ => return Err(invalid_data(format!("Unsupported Radiance resolution {:?}", resolution))),
+    };
+    let (Ok(height), Ok(width)) = (height, width) else {
+        return Err(invalid_data(format!("Invalid Radiance resolution {:?}", resolution)));
+    };
+    if width == 0 || height == 0 || width > MAX_EXTENT || height > MAX_EXTENT {
+        return Err(invalid_data(format!("Unsupported Radiance extent {}x{}", width, height)));
+    }
+    if !(exposure.is_finite() && exposure > 0.0) {
+        return Err(invalid_data(format!("Invalid exposure {}", exposure)));
+    }
+
+    let mut content = FloatImageContent::with_extent(width, height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for y in 0..height {
+        rest = read_scanline(rest, &mut scanline)?;
+        for (x, &rgbe) in scanline.iter().enumerate() {
+            let [r, g, b] = from_rgbe(rgbe).map(|value| value / exposure);
+            content.set_at(x, y, FloatPixel::new(r, g, b));
+        }
+    }
+
+    Ok(RadianceImage { content, primaries })
+}
+
+/// Decodes an RGBE pixel to the center of the range of values that encode to it.
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> [f32; 3] {
+    if e == 0 {
+        return [0.0; 3];
+    }
+    let scale = 2.0f64.powi(e as i32 - 136);
+    [r, g, b].map(|mantissa| ((mantissa as f64 + 0.5) * scale) as f32)
+}
+
+/// Reads a scanline into `scanline`, returning the bytes after it.
+fn read_scanline<'a>(mut bytes: &'a [u8], scanline: &mut [[u8; 4]]) -> std::io::Result<&'a [u8]> {
+    let truncated = || invalid_data("Truncated Radiance pixels");
+    let width = scanline.len();
+
+    // The marker of the adaptive run-length encoding, which a flat pixel can't start with since its red is normalized.
+    if let [2, 2, high, low, ..] = *bytes && high & 0x80 == 0 {
+        if (high as usize) << 8 | low as usize != width {
+            return Err(invalid_data("Radiance scanline of the wrong width"));
+        }
+        bytes = &bytes[4..];
+        for component in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let (&count, rest) = bytes.split_first().ok_or_else(truncated)?;
+                if count > 128 {
+                    let (&value, rest) = rest.split_first().ok_or_else(truncated)?;
+                    let run = scanline.get_mut(x..x + (count - 128) as usize).ok_or_else(|| invalid_data("Radiance run overruns its scanline"))?;
+                    run.iter_mut().for_each(|rgbe| rgbe[component] = value);
+                    (x, bytes) = (x + (count - 128) as usize, rest);
+                } else {
+                    let count = count as usize;
+                    let literal = rest.get(..count).ok_or_else(truncated)?;
+                    let pixels = scanline.get_mut(x..x + count).ok_or_else(|| invalid_data("Radiance literal overruns its scanline"))?;
+                    pixels.iter_mut().zip(literal).for_each(|(rgbe, &value)| rgbe[component] = value);
+                    // A count of 0 would never advance.
+                    if count == 0 {
+                        return Err(invalid_data("Empty Radiance literal"));
+                    }
+                    (x, bytes) = (x + count, &rest[count..]);
+                }
+            }
+        }
+        return Ok(bytes);
+    }
+
+    // Flat pixels, where the old run-length encoding repeats the previous pixel for `[1, 1, 1, count]`,
+    // with the counts of consecutive repeats as successively more significant bytes.
+    let mut x = 0;
+    let mut shift = 0;
+    while x < width {
+        let (&rgbe, rest) = bytes.split_first_chunk::<4>().ok_or_else(truncated)?;
+        bytes = rest;
+        if let [1, 1, 1, count] = rgbe && x > 0 {
+            let count = (count as usize).checked_shl(shift).filter(|&count| count <= width - x)
+                .ok_or_else(|| invalid_data("Radiance run overruns its scanline"))?;
+            let previous = scanline[x - 1];
+            scanline[x..x + count].fill(previous);
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x] = rgbe;
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colorspace::ColorGamut;
+
+    #[test]
+    fn written_hdr_reads_back() {
+        // Wide enough for the adaptive run-length encoding, with runs and literals.
+        let mut content = FloatImageContent::with_extent(40, 3);
+        for y in 0..3 {
+            for x in 0..40 {
+                let value = if x < 20 { 0.5 } else { (x * (y + 1)) as f32 * 0.37 };
+                content.set_at(x, y, FloatPixel::new(value, value * 0.25, 1000.0));
+            }
+        }
+
+        let mut hdr_bytes = Vec::new();
+        crate::outradiance::write_linear_pixels_to_hdr(&mut hdr_bytes, &content, &ColorGamut::bt2020()).unwrap();
+        let image = read_hdr(&hdr_bytes).unwrap();
+
+        assert_eq!(image.content.extent(), (40, 3));
+        let [[red_x, red_y], _, _, [white_x, white_y]] = image.primaries.unwrap();
+        assert_eq!([red_x, red_y, white_x, white_y], [0.708, 0.292, 0.3127, 0.329]);
+        for (read, written) in image.content.pixels().iter().zip(content.pixels()) {
+            for (read, written) in read.rgb().iter().zip(written.rgb()) {
+                // Within the precision of an 8-bit mantissa of the brightest channel.
+                assert!((read - written).abs() <= 1000.0 / 128.0, "{} vs {}", read, written);
+            }
+        }
+
+        for truncated in [&hdr_bytes[..hdr_bytes.len() - 1], &hdr_bytes[..20], b"#?RADIANCE\n\n-Y 1 +X 1\n"] {
+            assert!(read_hdr(truncated).is_err());
+        }
+    }
+
+    #[test]
+    fn flat_and_old_style_run_length_encoded_scanlines_are_read() {
+        let mut hdr_bytes = b"#?RGBE\nEXPOSURE=2\n\n-Y 1 +X 5\n".to_vec();
+        // 1.0 and 0.5, the former repeated 3 times the old way.
+        hdr_bytes.extend([128, 128, 128, 129, 1, 1, 1, 3, 128, 64, 0, 129]);
+        let image = read_hdr(&hdr_bytes).unwrap();
+
+        let greens: Vec<f32> = image.content.pixels().iter().map(|pixel| pixel.g()).collect();
+        let expected = [128.5 / 256.0, 128.5 / 256.0, 128.5 / 256.0, 128.5 / 256.0, 64.5 / 256.0];
+        assert_eq!(greens, expected);
+        assert_eq!(image.primaries, None);
+    }
+}
@@ -0,0 +1,616 @@
+//! Reading the base image and the gain map of an ISO base media file format (ISOBMFF) container,
+//! where they are separate items rather than JPEGs concatenated and located through MPF,
+//! and adding item properties to one, such as the ICC profile of an AVIF.
+//!
+//! Per ISO 21496-1, the gain map is described by a derived image item of type `tmap`, whose `dimg` references are the
+//! base image and the gain map, in that order, and whose payload is the binary gain map metadata.
+//! Only JPEG-coded items are supported, since HEVC or AV1 coded ones would need a decoder of their own.
+//!
+//! See: ISO/IEC 14496-12 and ISO/IEC 23008-12 (HEIF)
+
+use std::io;
+use std::ops::Range;
+
+const ITEM_TYPE_TMAP: &[u8; 4] = b"tmap";
+const ITEM_TYPE_JPEG: &[u8; 4] = b"jpeg";
+const REFERENCE_TYPE_DIMG: &[u8; 4] = b"dimg";
+
+/// The only version of the `tmap` item payload there is, which precedes the ISO 21496-1 metadata.
+const TMAP_VERSION: u8 = 0;
+
+/// `iloc` construction methods: From an offset in the file, or from an offset in the `idat` box.
+const CONSTRUCTION_METHOD_FILE: u8 = 0;
+const CONSTRUCTION_METHOD_IDAT: u8 = 1;
+
+/// The items of an ISOBMFF container that make up an image with a gain map.
+#[derive(Debug, Clone)]
+pub(crate) struct GainMapItems {
+    pub base_jpeg: Vec<u8>,
+    pub gain_map_jpeg: Vec<u8>,
+    /// The ISO 21496-1 metadata, in the same format as in the `APP2` segment of a JPEG gain map.
+    pub metadata: Vec<u8>,
+}
+
+/// Whether `bytes` start with an ISOBMFF `ftyp` box, of whichever brand.
+pub(crate) fn is_isobmff(bytes: &[u8]) -> bool {
+    bytes.get(4..8) == Some(b"ftyp")
+}
+
+/// Locates the `tmap` item of the ISOBMFF `bytes` and extracts the items it derives from and its metadata.
+///
+/// Fails if the boxes are malformed, if there is no `tmap` item, or if the base image or the gain map isn't JPEG-coded.
+pub(crate) fn extract_gain_map_items(bytes: &[u8]) -> io::Result<GainMapItems> {
+    let mut meta = Reader(find_box(&boxes(bytes)?, b"meta")?);
+    meta.full_box_version()?;
+    let meta_boxes = boxes(meta.0)?;
+
+    let item_types = item_types(find_box(&meta_boxes, b"iinf")?)?;
+    let locations = item_locations(find_box(&meta_boxes, b"iloc")?)?;
+    let idat = meta_boxes.iter().find(|(box_type, _)| box_type == b"idat").map(|&(_, payload)| payload);
+
+    let (tmap_id, _) = item_types.iter()
+        .find(|(_, item_type)| item_type == ITEM_TYPE_TMAP)
+        .ok_or_else(|| invalid_data("There is no `tmap` item, i.e. no gain map"))?;
+    let references = dimg_references(find_box(&meta_boxes, b"iref")?)?;
+    let Some([base_id, gain_map_id]) = references.iter()
+        .find(|(from_id, _)| from_id == tmap_id)
+        .and_then(|(_, to_ids)| <[u32; 2]>::try_from(to_ids.as_slice()).ok())
+    else {
+        return Err(invalid_data("The `tmap` item must derive from exactly a base image and a gain map"));
+    };
+
+    let item_data = |id: u32| {
+        let location = locations.iter()
+            .find(|(location_id, _)| *location_id == id)
+            .map(|(_, location)| location)
+            .ok_or_else(|| invalid_data(format!("Item {} has no location", id)))?;
+        location.data(bytes, idat)
+    };
+    let jpeg_item_data = |id: u32, name: &str| {
+        match item_types.iter().find(|(item_id, _)| *item_id == id) {
+            Some((_, item_type)) if item_type == ITEM_TYPE_JPEG => item_data(id),
+            Some((_, item_type)) => Err(invalid_data(format!(
+                "The {} is a `{}` item, but only JPEG-coded items are supported", name, String::from_utf8_lossy(item_type),
+            ))),
+            None => Err(invalid_data(format!("The {} item {} doesn't exist", name, id))),
+        }
+    };
+
+    let tmap = item_data(*tmap_id)?;
+    let metadata = match tmap.split_first() {
+        Some((&TMAP_VERSION, metadata)) => metadata.to_vec(),
+        Some((version, _)) => return Err(invalid_data(format!("Unsupported `tmap` version {}", version))),
+        None => return Err(invalid_data("The `tmap` item is empty")),
+    };
+
+    Ok(GainMapItems {
+        base_jpeg: jpeg_item_data(base_id, "base image")?,
+        gain_map_jpeg: jpeg_item_data(gain_map_id, "gain map")?,
+        metadata,
+    })
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads big-endian values, as all of ISOBMFF is.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < count {
+            return Err(invalid_data("Truncated box"));
+        }
+        let (bytes, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    /// An unsigned integer of `size` bytes, where 0 bytes are the value 0, as for the optional fields of `iloc`.
+    fn uint(&mut self, size: usize) -> io::Result<u64> {
+        Ok(self.bytes(size)?.iter().fold(0, |value, &byte| value << 8 | u64::from(byte)))
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.uint(1)? as u8)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(self.uint(4)? as u32)
+    }
+
+    /// The version of a `FullBox`, skipping its flags.
+    fn full_box_version(&mut self) -> io::Result<u8> {
+        let version = self.u8()?;
+        self.bytes(3)?;
+        Ok(version)
+    }
+
+    /// An item ID, which is 16-bit in version 0 of most boxes and 32-bit in later versions.
+    fn item_id(&mut self, is_32_bit: bool) -> io::Result<u32> {
+        if is_32_bit { self.u32() } else { self.u16().map(u32::from) }
+    }
+}
+
+/// The types and payloads of the boxes `bytes` consists of.
+fn boxes(bytes: &[u8]) -> io::Result<Vec<([u8; 4], &[u8])>> {
+    Ok(box_spans(bytes)?.into_iter().map(|(box_type, _, payload)| (box_type, &bytes[payload])).collect())
+}
+
+/// The type of a box, and the ranges of the whole box and of its payload in the bytes it is in.
+type BoxSpan = ([u8; 4], Range<usize>, Range<usize>);
+
+/// The boxes `bytes` consists of.
+fn box_spans(bytes: &[u8]) -> io::Result<Vec<BoxSpan>> {
+    let mut reader = Reader(bytes);
+    let mut boxes = Vec::new();
+    while !reader.0.is_empty() {
+        let start = bytes.len() - reader.0.len();
+        let size = reader.u32()?;
+        let box_type: [u8; 4] = reader.bytes(4)?.try_into().expect("4 bytes were read");
+        let payload_size = match size {
+            // Extends to the end of the enclosing data.
+            0 => reader.0.len() as u64,
+            1 => reader.uint(8)?.checked_sub(16).ok_or_else(|| invalid_data("Invalid box size"))?,
+            size => u64::from(size).checked_sub(8).ok_or_else(|| invalid_data("Invalid box size"))?,
+        };
+        let payload_start = bytes.len() - reader.0.len();
+        reader.bytes(usize::try_from(payload_size).unwrap_or(usize::MAX))?;
+        let end = bytes.len() - reader.0.len();
+        boxes.push((box_type, start..end, payload_start..end));
+    }
+    Ok(boxes)
+}
+
+#[cfg(any(feature = "avif", test))]
+fn write_box(bytes: &mut Vec<u8>, box_type: &[u8; 4], payload: &[u8]) {
+    bytes.extend((8 + payload.len() as u32).to_be_bytes());
+    bytes.extend(box_type);
+    bytes.extend(payload);
+}
+
+/// Adds the whole box `property`, e.g. a `colr` box, to the item properties of the ISOBMFF `bytes`, associated with
+/// the item `item_id` as non-essential, and moves the offsets `iloc` has into the file after the `meta` box by however
+/// much the `meta` box grows, so that the items in the `mdat` box after it, as in every AVIF `avif_serialize` writes,
+/// are still found.
+#[cfg(feature = "avif")]
+pub(crate) fn add_item_property(bytes: &[u8], item_id: u32, property: &[u8]) -> io::Result<Vec<u8>> {
+    let top_boxes = box_spans(bytes)?;
+    let (_, meta_span, meta_payload) = top_boxes.into_iter()
+        .find(|(box_type, ..)| box_type == b"meta")
+        .ok_or_else(|| invalid_data("There is no `meta` box"))?;
+    let (version_and_flags, meta_boxes) = bytes[meta_payload].split_at_checked(4).ok_or_else(|| invalid_data("Truncated box"))?;
+
+    let mut children = Vec::new();
+    for (box_type, payload) in boxes(meta_boxes)? {
+        if &box_type != b"iprp" {
+            children.push((box_type, payload.to_vec()));
+            continue;
+        }
+        let iprp_boxes = boxes(payload)?;
+        // 1-based.
+        let property_index = boxes(find_box(&iprp_boxes, b"ipco")?)?.len() + 1;
+        let mut iprp = Vec::new();
+        for (child_type, child) in iprp_boxes {
+            match &child_type {
+                b"ipco" => write_box(&mut iprp, b"ipco", &[child, property].concat()),
+                b"ipma" => write_box(&mut iprp, b"ipma", &associate_property(child, item_id, property_index)?),
+                _ => write_box(&mut iprp, &child_type, child),
+            }
+        }
+        children.push((box_type, iprp));
+    }
+
+    let meta_length = 8 + version_and_flags.len() + children.iter().map(|(_, payload)| 8 + payload.len()).sum::<usize>();
+    let growth = meta_length as i64 - meta_span.len() as i64;
+    let mut meta = version_and_flags.to_vec();
+    for (box_type, mut payload) in children {
+        if &box_type == b"iloc" {
+            move_file_offsets(&mut payload, meta_span.end as u64, growth)?;
+        }
+        write_box(&mut meta, &box_type, &payload);
+    }
+
+    let mut moved = bytes[..meta_span.start].to_vec();
+    write_box(&mut moved, b"meta", &meta);
+    moved.extend_from_slice(&bytes[meta_span.end..]);
+    Ok(moved)
+}
+
+/// The `ipma` payload `ipma` with the 1-based `property_index` associated with the item `item_id` as well.
+#[cfg(feature = "avif")]
+fn associate_property(ipma: &[u8], item_id: u32, property_index: usize) -> io::Result<Vec<u8>> {
+    let mut reader = Reader(ipma);
+    let version_and_flags: [u8; 4] = reader.bytes(4)?.try_into().expect("4 bytes were read");
+    let (is_32_bit, large_indices) = (version_and_flags[0] != 0, version_and_flags[3] & 1 != 0);
+
+    let entry_count = reader.u32()?;
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        let id = reader.item_id(is_32_bit)?;
+        let association_count = reader.u8()?;
+        let associations = (0..association_count)
+            .map(|_| if large_indices { reader.u16() } else { reader.u8().map(u16::from) })
+            .collect::<io::Result<Vec<u16>>>()?;
+        entries.push((id, associations));
+    }
+
+    // Without the `essential` flag in the most significant bit.
+    let max_index = if large_indices { 0x7FFF } else { 0x7F };
+    let property_index = u16::try_from(property_index).ok()
+        .filter(|&index| index <= max_index)
+        .ok_or_else(|| invalid_data("There are too many item properties to associate another one"))?;
+    match entries.iter_mut().find(|(id, _)| *id == item_id) {
+        Some((_, associations)) => associations.push(property_index),
+        None => entries.push((item_id, vec![property_index])),
+    }
+
+    let mut associated = version_and_flags.to_vec();
+    associated.extend((entries.len() as u32).to_be_bytes());
+    for (id, associations) in entries {
+        match is_32_bit {
+            true => associated.extend(id.to_be_bytes()),
+            false => associated.extend((id as u16).to_be_bytes()),
+        }
+        associated.push(u8::try_from(associations.len()).map_err(|_| invalid_data("An item has too many properties"))?);
+        for association in associations {
+            match large_indices {
+                true => associated.extend(association.to_be_bytes()),
+                false => associated.push(association as u8),
+            }
+        }
+    }
+    Ok(associated)
+}
+
+/// Adds `distance` to the offsets into the file of the `iloc` payload `iloc` that are at or after `from`,
+/// either to the base offset of an item or to the offsets of its extents.
+#[cfg(feature = "avif")]
+fn move_file_offsets(iloc: &mut [u8], from: u64, distance: i64) -> io::Result<()> {
+    // The positions and sizes of the fields to move.
+    let mut fields = Vec::new();
+    {
+        let mut reader = Reader(iloc);
+        let position = |reader: &Reader| iloc.len() - reader.0.len();
+        let version = reader.full_box_version()?;
+        if version > 2 {
+            return Err(invalid_data(format!("Unsupported `iloc` version {}", version)));
+        }
+        let sizes = reader.u8()?;
+        let (offset_size, length_size) = (usize::from(sizes >> 4), usize::from(sizes & 0xF));
+        let sizes = reader.u8()?;
+        let base_offset_size = usize::from(sizes >> 4);
+        let index_size = if version == 0 { 0 } else { usize::from(sizes & 0xF) };
+
+        let item_count = reader.item_id(version == 2)?;
+        for _ in 0..item_count {
+            reader.item_id(version == 2)?;
+            let construction_method = if version == 0 { CONSTRUCTION_METHOD_FILE } else { (reader.u16()? & 0xF) as u8 };
+            // `data_reference_index`
+            reader.u16()?;
+            let base_offset_position = position(&reader);
+            let base_offset = reader.uint(base_offset_size)?;
+            let moves_base_offset = base_offset >= from;
+            if construction_method == CONSTRUCTION_METHOD_FILE && moves_base_offset {
+                fields.push((base_offset_position, base_offset_size));
+            }
+
+            let extent_count = reader.u16()?;
+            for _ in 0..extent_count {
+                reader.uint(index_size)?;
+                let offset_position = position(&reader);
+                let offset = reader.uint(offset_size)?;
+                reader.uint(length_size)?;
+                if construction_method == CONSTRUCTION_METHOD_FILE && !moves_base_offset && base_offset.saturating_add(offset) >= from {
+                    fields.push((offset_position, offset_size));
+                }
+            }
+        }
+    }
+
+    for (position, size) in fields {
+        let value = Reader(&iloc[position..]).uint(size)?;
+        let moved = value.checked_add_signed(distance)
+            .filter(|&moved| size == 8 || moved >> (size * 8) == 0)
+            .ok_or_else(|| invalid_data("An item offset doesn't fit into its `iloc` field once moved"))?;
+        iloc[position..position + size].copy_from_slice(&moved.to_be_bytes()[8 - size..]);
+    }
+    Ok(())
+}
+
+fn find_box<'a>(boxes: &[([u8; 4], &'a [u8])], box_type: &[u8; 4]) -> io::Result<&'a [u8]> {
+    boxes.iter()
+        .find(|(candidate, _)| candidate == box_type)
+        .map(|&(_, payload)| payload)
+        .ok_or_else(|| invalid_data(format!("There is no `{}` box", String::from_utf8_lossy(box_type))))
+}
+
+/// The IDs and types of the items of the `iinf` payload `iinf`.
+fn item_types(iinf: &[u8]) -> io::Result<Vec<(u32, [u8; 4])>> {
+    let mut reader = Reader(iinf);
+    let version = reader.full_box_version()?;
+    // The entry count, which the `infe` boxes that follow tell as well.
+    reader.item_id(version != 0)?;
+
+    boxes(reader.0)?.into_iter()
+        .filter(|(box_type, _)| box_type == b"infe")
+        .map(|(_, infe)| {
+            let mut reader = Reader(infe);
+            // Only versions 2 and 3 have an item type.
+            let item_id = match reader.full_box_version()? {
+                2 => reader.item_id(false)?,
+                3 => reader.item_id(true)?,
+                version => return Err(invalid_data(format!("Unsupported `infe` version {}", version))),
+            };
+            // `item_protection_index`
+            reader.u16()?;
+            let item_type = reader.bytes(4)?.try_into().expect("4 bytes were read");
+            Ok((item_id, item_type))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct ItemLocation {
+    construction_method: u8,
+    base_offset: u64,
+    /// Offsets relative to `base_offset`, and lengths, where a length of 0 extends to the end of the data.
+    extents: Vec<(u64, u64)>,
+}
+
+impl ItemLocation {
+    /// The data of the item, concatenated from its extents.
+    fn data(&self, file: &[u8], idat: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let source = match self.construction_method {
+            CONSTRUCTION_METHOD_FILE => file,
+            CONSTRUCTION_METHOD_IDAT => idat.ok_or_else(|| invalid_data("An item is in the `idat` box, but there is none"))?,
+            method => return Err(invalid_data(format!("Unsupported `iloc` construction method {}", method))),
+        };
+
+        let mut data = Vec::new();
+        for &(offset, length) in &self.extents {
+            let start = self.base_offset.checked_add(offset)
+                .and_then(|start| usize::try_from(start).ok())
+                .filter(|&start| start <= source.len())
+                .ok_or_else(|| invalid_data("An item extent starts past the end of the data"))?;
+            let end = match length {
+                0 => source.len(),
+                length => usize::try_from(length).ok()
+                    .and_then(|length| start.checked_add(length))
+                    .filter(|&end| end <= source.len())
+                    .ok_or_else(|| invalid_data("An item extent ends past the end of the data"))?,
+            };
+            data.extend_from_slice(&source[start..end]);
+        }
+        Ok(data)
+    }
+}
+
+/// The locations of the items of the `iloc` payload `iloc`, by item ID.
+fn item_locations(iloc: &[u8]) -> io::Result<Vec<(u32, ItemLocation)>> {
+    let mut reader = Reader(iloc);
+    let version = reader.full_box_version()?;
+    if version > 2 {
+        return Err(invalid_data(format!("Unsupported `iloc` version {}", version)));
+    }
+
+    let sizes = reader.u8()?;
+    let (offset_size, length_size) = (usize::from(sizes >> 4), usize::from(sizes & 0xF));
+    let sizes = reader.u8()?;
+    let base_offset_size = usize::from(sizes >> 4);
+    // Reserved in version 0.
+    let index_size = if version == 0 { 0 } else { usize::from(sizes & 0xF) };
+
+    let item_count = reader.item_id(version == 2)?;
+    let mut locations = Vec::new();
+    for _ in 0..item_count {
+        let item_id = reader.item_id(version == 2)?;
+        let construction_method = if version == 0 { CONSTRUCTION_METHOD_FILE } else { (reader.u16()? & 0xF) as u8 };
+        // `data_reference_index`, where 0 is this file, which is all that is supported.
+        if reader.u16()? != 0 {
+            return Err(invalid_data(format!("Item {} is in another file", item_id)));
+        }
+        let base_offset = reader.uint(base_offset_size)?;
+
+        let extent_count = reader.u16()?;
+        let extents = (0..extent_count)
+            .map(|_| {
+                // `extent_index`, only used by the construction method of another item's data.
+                reader.uint(index_size)?;
+                Ok((reader.uint(offset_size)?, reader.uint(length_size)?))
+            })
+            .collect::<io::Result<_>>()?;
+        locations.push((item_id, ItemLocation { construction_method, base_offset, extents }));
+    }
+    Ok(locations)
+}
+
+/// The `dimg` references of the `iref` payload `iref`, as the ID of the derived item and the IDs of the items it derives from.
+fn dimg_references(iref: &[u8]) -> io::Result<Vec<(u32, Vec<u32>)>> {
+    let mut reader = Reader(iref);
+    let is_32_bit = reader.full_box_version()? != 0;
+
+    boxes(reader.0)?.into_iter()
+        .filter(|(box_type, _)| box_type == REFERENCE_TYPE_DIMG)
+        .map(|(_, payload)| {
+            let mut reader = Reader(payload);
+            let from_id = reader.item_id(is_32_bit)?;
+            let reference_count = reader.u16()?;
+            let to_ids = (0..reference_count).map(|_| reader.item_id(is_32_bit)).collect::<io::Result<_>>()?;
+            Ok((from_id, to_ids))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::gainmap::GainMapFormulation;
+    use crate::testutil::{self, TestImage};
+    use crate::UhdrConverter;
+
+    fn full_box(version: u8, content: &[u8]) -> Vec<u8> {
+        [&[version, 0, 0, 0], content].concat()
+    }
+
+    /// A HEIF with the items `(type, data)`, with IDs from 1, where item 1 is the primary one.
+    /// The first item is stored in `idat`, and the others in `mdat`.
+    /// `dimg` references the others from the first item.
+    fn build_container(items: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut iinf = (items.len() as u16).to_be_bytes().to_vec();
+        for (index, (item_type, _)) in items.iter().enumerate() {
+            let infe = full_box(2, &[&(index as u16 + 1).to_be_bytes()[..], &[0, 0], &item_type[..], b"\0"].concat());
+            write_box(&mut iinf, b"infe", &infe);
+        }
+
+        let mut iref = Vec::new();
+        let mut dimg = 1u16.to_be_bytes().to_vec();
+        dimg.extend((items.len() as u16 - 1).to_be_bytes());
+        for id in 2..=items.len() as u16 {
+            dimg.extend(id.to_be_bytes());
+        }
+        write_box(&mut iref, b"dimg", &dimg);
+
+        // The `iloc` has a fixed size, so the `mdat` payload starts at a known offset once the rest is laid out.
+        let build_meta = |mdat_offset: u32| {
+            // 4-byte offsets and lengths, no base offset, 16-bit extent indices.
+            let mut iloc = vec![0x44, 0x02];
+            iloc.extend((items.len() as u16).to_be_bytes());
+            let mut offset = mdat_offset;
+            for (index, (_, data)) in items.iter().enumerate() {
+                let construction_method = if index == 0 { CONSTRUCTION_METHOD_IDAT } else { CONSTRUCTION_METHOD_FILE };
+                iloc.extend((index as u16 + 1).to_be_bytes());
+                iloc.extend(u16::from(construction_method).to_be_bytes());
+                iloc.extend([0, 0, 0, 1, 0, 0]);
+                if index == 0 {
+                    iloc.extend(0u32.to_be_bytes());
+                } else {
+                    iloc.extend(offset.to_be_bytes());
+                    offset += data.len() as u32;
+                }
+                iloc.extend((data.len() as u32).to_be_bytes());
+            }
+
+            let mut meta = full_box(0, &[]);
+            write_box(&mut meta, b"hdlr", &full_box(0, &[&[0; 4], &b"pict"[..], &[0; 13]].concat()));
+            write_box(&mut meta, b"pitm", &full_box(0, &1u16.to_be_bytes()));
+            write_box(&mut meta, b"iinf", &full_box(0, &iinf));
+            write_box(&mut meta, b"iref", &full_box(0, &iref));
+            write_box(&mut meta, b"iloc", &full_box(1, &iloc));
+            write_box(&mut meta, b"idat", items[0].1);
+            meta
+        };
+
+        let mut bytes = Vec::new();
+        write_box(&mut bytes, b"ftyp", b"mif1\0\0\0\0mif1tmap");
+        let mdat_offset = (bytes.len() + 8 + build_meta(0).len() + 8) as u32;
+        write_box(&mut bytes, b"meta", &build_meta(mdat_offset));
+        let mdat: Vec<u8> = items[1..].iter().flat_map(|(_, data)| data.iter().copied()).collect();
+        write_box(&mut bytes, b"mdat", &mdat);
+        bytes
+    }
+
+    /// Single-channel ISO 21496-1 metadata: An SDR base, a headroom of 2.5 for the alternate rendition and gains up to 3.
+    fn iso_metadata() -> Vec<u8> {
+        let mut bytes = [0u16.to_be_bytes(), 0u16.to_be_bytes()].concat();
+        // Base colour space, common denominator.
+        bytes.push((1 << 6) | (1 << 3));
+        for value in [4u32, 0, 10] {
+            bytes.extend(value.to_be_bytes());
+        }
+        for value in [0i32, 12, 4, 1, 1] {
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn jpeg_items_of_a_tmap_item_are_converted() {
+        let primary = TestImage::from_fn(8, 8, |x, y| [(x * 32) as u8, (y * 32) as u8, 128]);
+        let gain_map = TestImage::grayscale_from_fn(4, 4, |x, _| (x * 64) as u8);
+        let base_jpeg = testutil::encode_jpeg(&primary, &[]);
+        let gain_map_jpeg = testutil::encode_jpeg(&gain_map, &[]);
+        let tmap = [&[TMAP_VERSION][..], &iso_metadata()].concat();
+        let container = build_container(&[(ITEM_TYPE_TMAP, &tmap), (ITEM_TYPE_JPEG, &base_jpeg), (ITEM_TYPE_JPEG, &gain_map_jpeg)]);
+
+        assert!(is_isobmff(&container));
+        assert!(!is_isobmff(&base_jpeg));
+        let items = extract_gain_map_items(&container).unwrap();
+        assert_eq!(items.base_jpeg, base_jpeg);
+        assert_eq!(items.gain_map_jpeg, gain_map_jpeg);
+        assert_eq!(items.metadata, iso_metadata());
+
+        let converter = UhdrConverter::new(&mut &container[..], 4.0).unwrap();
+        assert_eq!((converter.info().width, converter.info().height), (8, 8));
+        let metadata = converter.gain_map_metadata(0).unwrap();
+        assert_eq!((metadata.formulation, metadata.gain_map_max), (GainMapFormulation::Iso21496, [3.0; 3]));
+        assert_eq!(converter.gain_map_preview(0).unwrap().extent(), (4, 4));
+    }
+
+    #[test]
+    fn unsupported_or_malformed_containers_are_errors() {
+        let jpeg = testutil::encode_jpeg(&TestImage::from_fn(4, 4, |_, _| [128; 3]), &[]);
+        let tmap = [&[TMAP_VERSION][..], &iso_metadata()].concat();
+        let message = |container: &[u8]| extract_gain_map_items(container).unwrap_err().to_string();
+
+        let hevc = build_container(&[(ITEM_TYPE_TMAP, &tmap), (b"hvc1", b"hevc"), (ITEM_TYPE_JPEG, &jpeg)]);
+        assert!(message(&hevc).contains("`hvc1`"), "{}", message(&hevc));
+        let without_gain_map = build_container(&[(ITEM_TYPE_TMAP, &tmap), (ITEM_TYPE_JPEG, &jpeg)]);
+        assert!(message(&without_gain_map).contains("exactly"), "{}", message(&without_gain_map));
+        let without_tmap = build_container(&[(b"grid", b"grid"), (ITEM_TYPE_JPEG, &jpeg)]);
+        assert!(message(&without_tmap).contains("no `tmap` item"), "{}", message(&without_tmap));
+        let future_tmap = build_container(&[(ITEM_TYPE_TMAP, &[1]), (ITEM_TYPE_JPEG, &jpeg), (ITEM_TYPE_JPEG, &jpeg)]);
+        assert!(message(&future_tmap).contains("version 1"), "{}", message(&future_tmap));
+
+        // Every truncation is an error rather than a panic.
+        let container = build_container(&[(ITEM_TYPE_TMAP, &tmap), (ITEM_TYPE_JPEG, &jpeg), (ITEM_TYPE_JPEG, &jpeg)]);
+        for length in 0..container.len() - jpeg.len() {
+            assert!(extract_gain_map_items(&container[..length]).is_err(), "{}", length);
+        }
+
+        let error = UhdrConverter::new(&mut &hevc[..], 4.0).err().unwrap();
+        assert!(matches!(error, crate::UhdrError::NotUhdrJpeg(_)), "{:?}", error);
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn added_item_property_is_associated_and_the_items_are_still_found() {
+        fn meta_boxes(file: &[u8]) -> Vec<([u8; 4], &[u8])> {
+            boxes(&find_box(&boxes(file).unwrap(), b"meta").unwrap()[4..]).unwrap()
+        }
+        // The data of the items by ID.
+        fn items(file: &[u8]) -> Vec<(u32, Vec<u8>)> {
+            item_locations(find_box(&meta_boxes(file), b"iloc").unwrap()).unwrap().into_iter()
+                .map(|(item_id, location)| (item_id, location.data(file, None).unwrap()))
+                .collect()
+        }
+
+        let mut aviffy = avif_serialize::Aviffy::new();
+        aviffy.set_exif(b"II*\0".to_vec());
+        let avif_file = aviffy.to_vec(b"AV1 data", None, 8, 8, 10);
+        let mut property = Vec::new();
+        write_box(&mut property, b"colr", b"profICC profile");
+        let with_property = add_item_property(&avif_file, 1, &property).unwrap();
+
+        assert_eq!(items(&avif_file).len(), 2);
+        assert_eq!(items(&with_property), items(&avif_file));
+        let meta_boxes = meta_boxes(&with_property);
+
+        let iprp_boxes = boxes(find_box(&meta_boxes, b"iprp").unwrap()).unwrap();
+        let properties = boxes(find_box(&iprp_boxes, b"ipco").unwrap()).unwrap();
+        assert_eq!(properties.last(), Some(&(*b"colr", &b"profICC profile"[..])));
+        // Version 0 `ipma` with 16-bit item IDs and 7-bit indices, whose first entry is of item 1.
+        let mut ipma = Reader(&find_box(&iprp_boxes, b"ipma").unwrap()[8..]);
+        assert_eq!(ipma.u16().unwrap(), 1);
+        let associations: Vec<u8> = (0..ipma.u8().unwrap()).map(|_| ipma.u8().unwrap() & 0x7F).collect();
+        assert_eq!(associations.last(), Some(&(properties.len() as u8)));
+
+        assert!(add_item_property(&avif_file[..avif_file.len() / 2], 1, &property).is_err());
+    }
+}
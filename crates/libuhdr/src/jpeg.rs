@@ -1,10 +1,48 @@
 
+use std::time::{Duration, Instant};
+
 use log::{trace, warn, error};
 use zune_jpeg::ImageInfo as JpegImageInfo;
+use zune_jpeg::JpegDecoder;
+use zune_jpeg::zune_core::bytestream::ZCursor;
+use zune_jpeg::zune_core::options::DecoderOptions;
 use zune_jpeg::zune_core::colorspace::ColorSpace as JpegColorSpace;
 
-use crate::colorspace::{IccColorSpace, ColorGamut};
+use crate::colorspace::{Cicp, IccColorSpace, ColorGamut, SourceTransfer};
 use crate::mpf::MpfInfo;
+use crate::orientation::Orientation;
+use crate::resolution::Resolution;
+use crate::segments;
+
+type ZuneJpegDecoder<'a> = JpegDecoder<ZCursor<&'a [u8]>>;
+
+/// The start of image marker every JPEG begins with.
+const JPEG_SOI: &[u8] = &[0xFF, 0xD8];
+/// The signatures of the other formats `new_secondary_from_bytes_with_settings` sniffs.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const HEIF_BRANDS: [&[u8]; 6] = [b"heic", b"heix", b"heim", b"heis", b"mif1", b"msf1"];
+
+/// Why decoding a `UhdrJpeg` failed, telling an unparseable ICC profile apart for `UhdrError::Icc`.
+#[derive(Debug)]
+pub(crate) enum JpegDecodeError {
+    /// The headers or the image data.
+    Image(String),
+    Icc(String),
+}
+
+impl std::fmt::Display for JpegDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Image(message) | Self::Icc(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for JpegDecodeError {
+    fn from(message: String) -> Self {
+        Self::Image(message)
+    }
+}
 
 /// Represents a JPEG image, potentially with Ultra HDR metadata and gain map information.
 #[derive(Clone)]
@@ -12,11 +50,82 @@ pub struct UhdrJpeg {
     jpeg_info: JpegImageInfo,
     xmp_bytes: Option<Vec<u8>>,
     content: JpegImageContent,
+    /// How long `new_from_bytes` spent parsing the ICC profile, for `ConversionTimings`.
+    icc_parse_duration: Duration,
+    /// The bytes this JPEG was decoded from, excluding anything appended to it.
+    coded_bytes: Vec<u8>,
+    /// How this JPEG was decoded, which extracted images are decoded with as well.
+    settings: JpegDecodeSettings,
+}
+
+/// Options passed through to the JPEG decoder, `zune-jpeg`.
+///
+/// It has no DCT-domain downscaling, so images are always decoded at their full size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JpegDecodeOptions {
+    /// Rejects JPEGs wider than this, as declared by their headers, before decoding the image data.
+    pub max_width: usize,
+    /// Rejects JPEGs taller than this, as declared by their headers, before decoding the image data.
+    pub max_height: usize,
+    /// Rejects progressive JPEGs with more scans than this, which bounds the decoding time of crafted files.
+    pub max_scans: usize,
+    /// Whether to use the decoder's platform-specific SIMD code paths, which are faster but `unsafe`.
+    pub use_simd: bool,
+    /// Requests RGB output, whatever the colorspace of the JPEG.
+    /// The decoder then converts YCbCr, grayscale and CMYK to RGB itself, upsampling chroma as it does.
+    ///
+    /// Otherwise, grayscale JPEGs are decoded to a single channel, taking a third of the memory,
+    /// and all others to RGB.
+    pub force_rgb: bool,
+}
+
+impl Default for JpegDecodeOptions {
+    /// The defaults of `zune-jpeg`, e.g. at most 16,536 pixels wide and 100 scans.
+    fn default() -> Self {
+        let options = DecoderOptions::default();
+        Self {
+            max_width: options.max_width(),
+            max_height: options.max_height(),
+            max_scans: options.jpeg_get_max_scans(),
+            use_simd: options.use_unsafe(),
+            force_rgb: false,
+        }
+    }
+}
+
+impl JpegDecodeOptions {
+    fn to_decoder_options(self) -> DecoderOptions {
+        let options = DecoderOptions::default()
+            .set_max_width(self.max_width)
+            .set_max_height(self.max_height)
+            .jpeg_set_max_scans(self.max_scans)
+            .set_use_unsafe(self.use_simd);
+        if self.force_rgb {
+            options.jpeg_set_out_colorspace(JpegColorSpace::RGB)
+        } else {
+            options
+        }
+    }
+}
+
+/// Settings for decoding a `UhdrJpeg` beyond what the public constructors offer, derived from `DecodeOptions`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct JpegDecodeSettings {
+    /// Whether to ignore the ICC profile, as `new_from_bytes_ignoring_icc` does.
+    pub ignore_icc: bool,
+    /// The maximum number of pixels, checked against the dimensions in the frame header before decoding the image data.
+    pub max_pixels: Option<usize>,
+    /// Whether to accept truncated or otherwise damaged image data, keeping whatever could be decoded.
+    pub allow_partial: bool,
+    /// Passed through to the decoder.
+    pub jpeg: JpegDecodeOptions,
 }
 
 #[derive(Clone)]
 struct JpegImageContent {
     icc_color_space: Option<IccColorSpace>,
+    /// From the `cicp` tag of the ICC profile, which takes precedence over its tone curves.
+    cicp: Option<Cicp>,
     jpeg_color_space: JpegColorSpace,
     pixels: Vec<u8>,
 }
@@ -26,32 +135,56 @@ impl UhdrJpeg {
     /// This function decodes the JPEG image, extracts the XMP metadata, ICC profile, and pixel data.
     /// Despite the struct's name, the JPEG does not need to be in an Ultra HDR JPEG format for this function to succeed.
     pub fn new_from_bytes(jpeg_bytes: &[u8]) -> Result<Self, String> {
-        use zune_jpeg::JpegDecoder;
-        use zune_jpeg::zune_core::bytestream::ZCursor;
+        Self::new_from_bytes_with_settings(jpeg_bytes, JpegDecodeSettings::default()).map_err(|e| e.to_string())
+    }
 
-        let mut jpeg_decoder = JpegDecoder::new(ZCursor::new(jpeg_bytes));
-        jpeg_decoder.decode_headers()
-            .map_err(|e| format!("Failed to decode JPEG headers: {}", e))
-            ?;
+    /// Same as `new_from_bytes`, but ignores any ICC profile as if there were none, without even parsing it.
+    /// The samples are then assumed to be sRGB, with the sRGB EOTF.
+    /// Images extracted from the returned `UhdrJpeg`, such as the gain map, ignore their ICC profiles as well.
+    pub fn new_from_bytes_ignoring_icc(jpeg_bytes: &[u8]) -> Result<Self, String> {
+        Self::new_from_bytes_with_settings(jpeg_bytes, JpegDecodeSettings { ignore_icc: true, ..Default::default() })
+            .map_err(|e| e.to_string())
+    }
 
-        let jpeg_info = jpeg_decoder.info().unwrap();
+    /// Same as `new_from_bytes`, but decodes with `options`.
+    /// Images extracted from the returned `UhdrJpeg`, such as the gain map, are decoded with them as well.
+    pub fn new_from_bytes_with_options(jpeg_bytes: &[u8], options: &JpegDecodeOptions) -> Result<Self, String> {
+        Self::new_from_bytes_with_settings(jpeg_bytes, JpegDecodeSettings { jpeg: *options, ..Default::default() })
+            .map_err(|e| e.to_string())
+    }
 
+    pub(crate) fn new_from_bytes_with_settings(jpeg_bytes: &[u8], settings: JpegDecodeSettings) -> Result<Self, JpegDecodeError> {
+        let (jpeg_decoder, pixels) = match Self::decode(jpeg_bytes, &settings, true) {
+            Ok(decoded) => decoded,
+            // Not strictly decodable, but the lenient decoder stops at the damage instead and fills the rest of the image.
+            Err(e) if settings.allow_partial => {
+                warn!("{}; using the partially decoded image", e);
+                Self::decode(jpeg_bytes, &settings, false)?
+            },
+            Err(e) => return Err(e.into()),
+        };
+        let jpeg_info = jpeg_decoder.info().unwrap();
         let xmp_bytes = jpeg_decoder.xmp().cloned();
 
         let jpeg_output_color_space = jpeg_decoder.output_colorspace()
-            .ok_or_else(|| "Failed to get JPEG output ColorSpace")
+            .ok_or_else(|| "Failed to get JPEG output ColorSpace".to_string())
             ?;
         trace!("Output color space: {:?}", jpeg_output_color_space);
+        check_output_color_space(jpeg_output_color_space)?;
 
-        let pixels = jpeg_decoder.decode()
-            .map_err(|e| format!("Failed to decode JPEG image: {}", e))
-            ?;
         trace!("Decoded JPEG: {}x{} with {} bytes", jpeg_info.width, jpeg_info.height, pixels.len());
 
-        let icc_profile_bytes = jpeg_decoder.icc_profile();
+        let icc_parse_start = Instant::now();
+
+        let icc_profile_bytes = if settings.ignore_icc {
+            trace!("Ignoring ICC profile");
+            None
+        } else {
+            Self::read_icc_profile(jpeg_bytes, jpeg_decoder.icc_profile())
+        };
         let icc_profile = if let Some(icc_profile_bytes) = &icc_profile_bytes {
             let icc_profile = lcms2::Profile::new_icc(&icc_profile_bytes)
-                .map_err(|e| format!("Failed to parse ICC profile: {}", e))
+                .map_err(|e| JpegDecodeError::Icc(format!("Failed to parse ICC profile: {}", e)))
                 ?;
             Some(icc_profile)
         } else {
@@ -65,17 +198,241 @@ impl UhdrJpeg {
             });
         trace!("ICC Color space: {:?}", icc_color_space);
 
+        let cicp = icc_profile_bytes
+            .as_ref()
+            .and_then(|icc_profile_bytes| Cicp::from_icc_profile_bytes(icc_profile_bytes));
+        trace!("CICP: {:?}", cicp);
+
+        let icc_parse_duration = icc_parse_start.elapsed();
+
+        let coded_bytes = match segments::image_length(jpeg_bytes) {
+            Some(length) => jpeg_bytes[..length].to_vec(),
+            None => {
+                warn!("JPEG has no end of image marker");
+                jpeg_bytes.to_vec()
+            },
+        };
+
         Ok(Self {
             jpeg_info,
             xmp_bytes,
+            icc_parse_duration,
+            coded_bytes,
+            settings,
             content: JpegImageContent {
                 icc_color_space,
+                cicp,
                 jpeg_color_space: jpeg_output_color_space,
                 pixels,
             },
         })
     }
 
+    /// Decodes the headers and then the image data, in the decoder's strict mode unless `strict` is `false`.
+    /// Strict mode rejects truncated or damaged scans, which the lenient mode decodes up to the damage.
+    fn decode<'a>(jpeg_bytes: &'a [u8], settings: &JpegDecodeSettings, strict: bool) -> Result<(ZuneJpegDecoder<'a>, Vec<u8>), String> {
+        let options = settings.jpeg.to_decoder_options().set_strict_mode(strict);
+        let mut jpeg_decoder = JpegDecoder::new_with_options(ZCursor::new(jpeg_bytes), options);
+        jpeg_decoder.decode_headers()
+            .map_err(|e| format!("Failed to decode JPEG headers: {}", e))
+            ?;
+
+        if !settings.jpeg.force_rgb && jpeg_decoder.input_colorspace() == Some(JpegColorSpace::Luma) {
+            jpeg_decoder.set_options(options.jpeg_set_out_colorspace(JpegColorSpace::Luma));
+        }
+
+        let jpeg_info = jpeg_decoder.info().unwrap();
+
+        // Before decoding, so that a small file declaring huge dimensions can't exhaust memory.
+        check_pixel_count(jpeg_info.width as usize, jpeg_info.height as usize, settings.max_pixels)?;
+
+        let pixels = jpeg_decoder.decode()
+            .map_err(|e| format!("Failed to decode JPEG image: {}", e))
+            ?;
+        Ok((jpeg_decoder, pixels))
+    }
+
+    /// Same as `new_from_bytes_with_settings`, but for an MPF image other than the primary image, such as a gain map,
+    /// which some non-standard files embed as a PNG or HEIC instead of a JPEG. The format is sniffed from the magic bytes.
+    ///
+    /// Images in other formats are decoded to 8-bit RGB, with the XMP metadata but without any ICC profile.
+    /// PNG requires the `png` feature, and HEIC the `heif` feature.
+    pub(crate) fn new_secondary_from_bytes_with_settings(bytes: &[u8], settings: JpegDecodeSettings) -> Result<Self, JpegDecodeError> {
+        if bytes.starts_with(PNG_SIGNATURE) {
+            #[cfg(feature = "png")]
+            return Self::new_from_png_bytes(bytes, settings).map_err(JpegDecodeError::Image);
+            #[cfg(not(feature = "png"))]
+            return Err(JpegDecodeError::Image("The image is a PNG, which requires the `png` feature".to_string()));
+        }
+        if is_heif(bytes) {
+            #[cfg(feature = "heif")]
+            return Self::new_from_heif_bytes(bytes, settings).map_err(JpegDecodeError::Image);
+            #[cfg(not(feature = "heif"))]
+            return Err(JpegDecodeError::Image("The image is a HEIC, which requires the `heif` feature".to_string()));
+        }
+        Self::new_from_bytes_with_settings(bytes, settings)
+    }
+
+    #[cfg(feature = "png")]
+    fn new_from_png_bytes(png_bytes: &[u8], settings: JpegDecodeSettings) -> Result<Self, String> {
+        const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+        let mut decoder = png::Decoder::new(png_bytes);
+        // 8-bit, with palettes expanded.
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        decoder.set_ignore_text_chunk(false);
+        let mut reader = decoder.read_info()
+            .map_err(|e| format!("Failed to decode PNG headers: {}", e))
+            ?;
+
+        let (width, height) = (reader.info().width as usize, reader.info().height as usize);
+        check_pixel_count(width, height, settings.max_pixels)?;
+
+        // Only text chunks before the image data are read by now, which is where XMP is conventionally placed.
+        let xmp_bytes = reader.info().utf8_text.iter()
+            .find(|chunk| chunk.keyword == XMP_KEYWORD)
+            .and_then(|chunk| chunk.get_text().ok())
+            .map(String::into_bytes);
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let output_info = reader.next_frame(&mut buffer)
+            .map_err(|e| format!("Failed to decode PNG image: {}", e))
+            ?;
+        let samples = &buffer[..output_info.buffer_size()];
+
+        let pixels = match output_info.color_type {
+            png::ColorType::Rgb => samples.to_vec(),
+            png::ColorType::Rgba => samples.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect(),
+            png::ColorType::Grayscale => samples.iter().flat_map(|&value| [value; 3]).collect(),
+            png::ColorType::GrayscaleAlpha => samples.chunks_exact(2).flat_map(|pixel| [pixel[0]; 3]).collect(),
+            png::ColorType::Indexed => return Err("Failed to expand the PNG palette".to_string()),
+        };
+        trace!("Decoded PNG: {}x{} with {} bytes", width, height, pixels.len());
+
+        Self::new_from_rgb8_pixels(width, height, pixels, xmp_bytes, png_bytes, settings)
+    }
+
+    #[cfg(feature = "heif")]
+    fn new_from_heif_bytes(heif_bytes: &[u8], settings: JpegDecodeSettings) -> Result<Self, String> {
+        use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+        let context = HeifContext::read_from_bytes(heif_bytes)
+            .map_err(|e| format!("Failed to read HEIF: {}", e))
+            ?;
+        let handle = context.primary_image_handle()
+            .map_err(|e| format!("Failed to get the HEIF primary image: {}", e))
+            ?;
+
+        let (width, height) = (handle.width() as usize, handle.height() as usize);
+        check_pixel_count(width, height, settings.max_pixels)?;
+
+        let xmp_bytes = {
+            let mut item_ids = vec![0; handle.number_of_metadata_blocks(b"mime") as usize];
+            handle.metadata_block_ids(&mut item_ids, b"mime");
+            item_ids.into_iter()
+                .find(|&item_id| handle.metadata_content_type(item_id) == Some("application/rdf+xml"))
+                .and_then(|item_id| handle.metadata(item_id).ok())
+        };
+
+        let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+            .map_err(|e| format!("Failed to decode HEIF image: {}", e))
+            ?;
+        let planes = image.planes();
+        let plane = planes.interleaved
+            .ok_or_else(|| "Failed to get the interleaved HEIF plane".to_string())
+            ?;
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            pixels.extend_from_slice(&plane.data[y * plane.stride..][..width * 3]);
+        }
+        trace!("Decoded HEIF: {}x{} with {} bytes", width, height, pixels.len());
+
+        Self::new_from_rgb8_pixels(width, height, pixels, xmp_bytes, heif_bytes, settings)
+    }
+
+    /// For images decoded from formats other than JPEG, as 8-bit RGB `pixels` without an ICC profile.
+    #[cfg(any(feature = "png", feature = "heif"))]
+    fn new_from_rgb8_pixels(
+        width: usize,
+        height: usize,
+        pixels: Vec<u8>,
+        xmp_bytes: Option<Vec<u8>>,
+        coded_bytes: &[u8],
+        settings: JpegDecodeSettings,
+    ) -> Result<Self, String> {
+        let (Ok(jpeg_width), Ok(jpeg_height)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(format!("Image too large: {}x{} exceeds the JPEG limit of 65535x65535", width, height));
+        };
+
+        Ok(Self {
+            jpeg_info: JpegImageInfo { width: jpeg_width, height: jpeg_height, ..Default::default() },
+            xmp_bytes,
+            icc_parse_duration: Duration::ZERO,
+            coded_bytes: coded_bytes.to_vec(),
+            settings,
+            content: JpegImageContent {
+                icc_color_space: None,
+                cicp: None,
+                jpeg_color_space: JpegColorSpace::RGB,
+                pixels,
+            },
+        })
+    }
+
+    /// Validates the ICC profile reassembled by `zune-jpeg` from its `APP2` chunks against the profile size in its header.
+    ///
+    /// `zune-jpeg` gives up on chunks with inconsistent sequence numbers or chunk counts, e.g. those numbered from `0`,
+    /// in which case the chunks are reassembled here in the order of their sequence numbers instead.
+    /// Returns `None`, as if there were no ICC profile, if that doesn't result in a complete profile either.
+    fn read_icc_profile(jpeg_bytes: &[u8], zune_icc_profile: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        const ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+
+        // The profile size, which is the first field of the ICC header, may be less than the actual size due to padding.
+        let is_complete = |icc_profile: &[u8]| {
+            icc_profile.get(0..4)
+                .is_some_and(|size| u32::from_be_bytes(size.try_into().unwrap()) as usize <= icc_profile.len())
+        };
+
+        if let Some(icc_profile) = zune_icc_profile {
+            if is_complete(&icc_profile) {
+                return Some(icc_profile);
+            }
+            warn!("The ICC profile reassembled by zune-jpeg is incomplete at {} bytes", icc_profile.len());
+        }
+
+        // (sequence number, chunk count, data)
+        let mut chunks: Vec<(u8, u8, &[u8])> = segments::header_segments(jpeg_bytes)
+            .filter(|segment| segment.marker == 0xE2)
+            .filter_map(|segment| segment.payload.strip_prefix(ICC_SIGNATURE))
+            .filter_map(|payload| match payload {
+                [sequence_number, chunk_count, data @ ..] => Some((*sequence_number, *chunk_count, data)),
+                _ => None,
+            })
+            .collect();
+        if chunks.is_empty() {
+            return None;
+        }
+
+        warn!(
+            "Reassembling the ICC profile from {} APP2 chunks with sequence numbers {:?} and chunk counts {:?}",
+            chunks.len(),
+            chunks.iter().map(|chunk| chunk.0).collect::<Vec<_>>(),
+            chunks.iter().map(|chunk| chunk.1).collect::<Vec<_>>(),
+        );
+
+        // Stable, so that chunks with the same sequence number stay in the order they appear in.
+        chunks.sort_by_key(|chunk| chunk.0);
+        let icc_profile: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.2).copied().collect();
+
+        if is_complete(&icc_profile) {
+            Some(icc_profile)
+        } else {
+            warn!("The ICC profile reassembled from APP2 chunks is incomplete at {} bytes, ignoring it", icc_profile.len());
+            None
+        }
+    }
+
     pub fn extent(&self) -> (usize, usize) {
         (self.jpeg_info.width as usize, self.jpeg_info.height as usize)
     }
@@ -84,10 +441,59 @@ impl UhdrJpeg {
         self.xmp_bytes.as_deref()
     }
 
+    /// The TIFF block of the EXIF `APP1` segment, i.e. after the `Exif\0\0` identifier, if any.
+    pub fn exif_bytes(&self) -> Option<&[u8]> {
+        crate::resolution::exif_tiff(&self.coded_bytes)
+    }
+
+    /// How the pixels are to be displayed, from the EXIF metadata. `Orientation::Normal` if it has none.
+    pub fn orientation(&self) -> Orientation {
+        self.exif_bytes().and_then(crate::exif::orientation).unwrap_or_default()
+    }
+
+    /// The physical resolution from the JFIF or EXIF metadata, see `Resolution::from_jpeg_bytes`.
+    pub fn resolution(&self) -> Option<Resolution> {
+        Resolution::from_jpeg_bytes(&self.coded_bytes)
+    }
+
+    /// The width of a pixel relative to its height, see `resolution::pixel_aspect_ratio_from_jpeg_bytes`.
+    pub fn pixel_aspect_ratio(&self) -> f32 {
+        crate::resolution::pixel_aspect_ratio_from_jpeg_bytes(&self.coded_bytes)
+    }
+
     pub fn icc_color_space(&self) -> Option<&IccColorSpace> {
         self.content.icc_color_space.as_ref()
     }
 
+    /// How long it took to parse the ICC profile while decoding.
+    pub fn icc_parse_duration(&self) -> Duration {
+        self.icc_parse_duration
+    }
+
+    /// The coded bytes of this JPEG alone, without any MPF images appended to it,
+    /// e.g. for embedding it into another file without re-encoding.
+    pub fn coded_bytes(&self) -> &[u8] {
+        &self.coded_bytes
+    }
+
+    pub fn cicp(&self) -> Option<Cicp> {
+        self.content.cicp
+    }
+
+    /// Whether the pixels were decoded as a single luma channel, i.e. the JPEG is grayscale.
+    pub fn is_grayscale(&self) -> bool {
+        self.content.jpeg_color_space == JpegColorSpace::Luma
+    }
+
+    /// How `fetch_pixel_linear` linearizes the samples. The sampling functions return them as they are.
+    pub fn source_transfer(&self) -> SourceTransfer {
+        match (&self.content.cicp, &self.content.icc_color_space) {
+            (Some(cicp), _) if cicp.source_transfer() == Some(SourceTransfer::Hlg) => SourceTransfer::Hlg,
+            (_, Some(_)) => SourceTransfer::Icc,
+            (_, None) => SourceTransfer::Srgb,
+        }
+    }
+
     pub fn color_gamut(&self) -> Option<ColorGamut> {
         self.icc_color_space()
             .map(|icc| icc.color_gamut)
@@ -106,6 +512,7 @@ impl UhdrJpeg {
             let mpf_bytes = self.mpf_bytes()?;
 
             MpfInfo::new_from_bytes(mpf_bytes)
+                .map_err(|e| warn!("Failed to parse the MPF information: {}", e))
                 .ok()
                 ?
         };
@@ -115,11 +522,20 @@ impl UhdrJpeg {
             return None;
         }
 
-        let first_mp_entry = &mpf_info.mp_entries()[0];
-        let offset = first_mp_entry.individual_image_size;
+        // Located by its data offset relative to the MP endian field, rather than assumed to follow the primary image directly.
+        let Some(gain_map_jpeg_bytes) = self.mpf_image_bytes(original_bytes, 1) else {
+            warn!("Failed to locate the gain map: Its MP entry points outside of the file");
+            return None;
+        };
+        if !(gain_map_jpeg_bytes.starts_with(JPEG_SOI) || gain_map_jpeg_bytes.starts_with(PNG_SIGNATURE) || is_heif(gain_map_jpeg_bytes)) {
+            warn!(
+                "Failed to locate the gain map: The image at MPF data offset {} doesn't start with a JPEG SOI marker",
+                mpf_info.mp_entries()[1].individual_image_data_offset,
+            );
+            return None;
+        }
 
-        let gain_map_jpeg_bytes = &original_bytes[offset as usize..original_bytes.len() - 1];
-        let gain_map_jpeg = UhdrJpeg::new_from_bytes(gain_map_jpeg_bytes)
+        let gain_map_jpeg = UhdrJpeg::new_secondary_from_bytes_with_settings(gain_map_jpeg_bytes, self.settings)
             .map_err(|e| {
                 error!("Failed to extract gain map JPEG: {}", e);
                 e
@@ -128,23 +544,70 @@ impl UhdrJpeg {
         Some(gain_map_jpeg)
     }
 
+    /// Returns the bytes of the MPF image at `index`, located by its MP entry, or `None` if it can't be located.
+    ///
+    /// This JPEG must be the first image in `original_bytes`, which contains the MPF information.
+    /// The first image is at the start of `original_bytes`, and the others are at offsets relative to the MP endian field.
+    pub fn mpf_image_bytes<'a>(&self, original_bytes: &'a [u8], index: usize) -> Option<&'a [u8]> {
+        let mpf_info = MpfInfo::new_from_bytes(self.mpf_bytes()?).ok()?;
+        let mp_entry = mpf_info.mp_entries().get(index)?;
+
+        let start = if index == 0 {
+            0
+        } else {
+            MpfInfo::find_mp_endian_offset(original_bytes)? + mp_entry.individual_image_data_offset as usize
+        };
+        let end = start + mp_entry.individual_image_size as usize;
+        original_bytes.get(start..end)
+    }
+
+    /// Extracts the gain map JPEG as `extract_gain_map_jpeg` does, followed by any further MPF images,
+    /// which experimental multi-layer files use for additional gain maps.
+    /// The further images are located by their MP entry data offsets; those that fail to decode are skipped.
+    /// Whether any of them actually carries gain map metadata is up to the caller to check.
+    pub fn extract_gain_map_jpegs(&self, original_bytes: &[u8]) -> Vec<Self> {
+        let Some(gain_map_jpeg) = self.extract_gain_map_jpeg(original_bytes) else {
+            return Vec::new();
+        };
+        let mut gain_map_jpegs = vec![gain_map_jpeg];
+
+        let Some(mpf_info) = self.mpf_bytes().and_then(|mpf_bytes| MpfInfo::new_from_bytes(mpf_bytes).ok()) else {
+            return gain_map_jpegs;
+        };
+
+        for index in 2..mpf_info.mp_entries().len() {
+            let Some(jpeg_bytes) = self.mpf_image_bytes(original_bytes, index) else {
+                warn!("Failed to locate MPF image {}, skipping", index);
+                continue;
+            };
+
+            match UhdrJpeg::new_secondary_from_bytes_with_settings(jpeg_bytes, self.settings) {
+                Ok(jpeg) => gain_map_jpegs.push(jpeg),
+                Err(e) => warn!("Failed to decode MPF image {}, skipping: {}", index, e),
+            }
+        }
+
+        gain_map_jpegs
+    }
+
     /// Fetches a pixel at the given coordinates (x, y), which is typically in a non-linear color space (i.e. after OETF).
+    /// A grayscale pixel is returned as `R = G = B`.
+    ///
+    /// Panics if the coordinates are out of bounds.
     pub fn fetch_pixel(
         &self,
         x: usize,
         y: usize,
     ) -> [f32; 3] {
-        let pixel_index = (y * self.jpeg_info.width as usize + x) * 3;
-
-        let r = self.content.pixels[pixel_index + 0] as f32 / 255.0;
-        let g = self.content.pixels[pixel_index + 1] as f32 / 255.0;
-        let b = self.content.pixels[pixel_index + 2] as f32 / 255.0;
+        assert!(x < self.jpeg_info.width as usize && y < self.jpeg_info.height as usize, "Pixel ({}, {}) is out of bounds", x, y);
 
-        [r, g, b]
+        let rgb = self.get_pixel_as_rgb888(x, y)
+            .expect("The output color space is checked to be supported when decoding");
+        rgb.map(|value| value as f32 / 255.0)
     }
 
     /// Fetches a pixel at the given coordinates (x, y) and applies the EOTF according the `IccColorSpace` if available.
-    /// If no `IccColorSpace` is available, the EOTF is assumed to be gamma of `2.2`.
+    /// If no `IccColorSpace` is available, the EOTF is assumed to be the piecewise sRGB EOTF.
     pub fn fetch_pixel_linear(
         &self,
         x: usize,
@@ -156,48 +619,25 @@ impl UhdrJpeg {
 
     /// Samples a pixel coordinate using bilinear filtering and clamp addressing.
     /// The U and V coordinates are in the range [0, 1].
-    /// The function returns the RGB values in the range [0, 1].
-    /// If the coordinates are out of bounds, it returns None.
+    /// The function returns the RGB values in the range [0, 1], or None if the color space is unsupported.
+    /// They are the stored values, not linearized, as the recovery values of a gain map are only raised to its gamma.
+    ///
+    /// Texel centers are at half-integer coordinates, so the image can be of any size relative to the one it is being
+    /// mapped onto, e.g. a gain map of half or a quarter of the resolution of the primary image, which is then
+    /// interpolated smoothly between its texels rather than in steps.
     pub fn sample_bilinear(
         &self,
         u: f32,
         v: f32,
     ) -> Option<[f32; 3]> {
-        // U and V are in the range [0, 1]
-        let width = self.jpeg_info.width as f32;
-        let height = self.jpeg_info.height as f32;
+        // Both axes are derived the same way.
+        let (x0, x1, s) = bilinear_taps(u, self.jpeg_info.width as usize);
+        let (y0, y1, t) = bilinear_taps(v, self.jpeg_info.height as usize);
 
-        let x = u * width;
-        let y = v * height;
-
-        let base_x = if x.fract() < 0.5 {
-            x.floor() - 1.0
-        }
-        else {
-            x.floor()
-        };
-        let base_y = if y.fract() < 0.5 {
-            y - 1.0
-        }
-        else {
-            y.floor()
-        };
-
-        let base_x = (base_x as usize).clamp(0, self.jpeg_info.width as usize - 1);
-        let base_y = (base_y as usize).clamp(0, self.jpeg_info.height as usize - 1);
-
-        let p00 = self.get_pixel_as_rgb888_unorm_linear(base_x, base_y);
-        let p01 = self.get_pixel_as_rgb888_unorm_linear(base_x, base_y + 1);
-        let p10 = self.get_pixel_as_rgb888_unorm_linear(base_x + 1, base_y);
-        let p11 = self.get_pixel_as_rgb888_unorm_linear(base_x + 1, base_y + 1);
-
-        let p00 = p00.unwrap_or([0.0, 0.0, 0.0]);
-        let p01 = p01.unwrap_or([0.0, 0.0, 0.0]);
-        let p10 = p10.unwrap_or([0.0, 0.0, 0.0]);
-        let p11 = p11.unwrap_or([0.0, 0.0, 0.0]);
-
-        let s = (x - base_x as f32).clamp(0.0, 1.0);
-        let t = (y - base_y as f32).clamp(0.0, 1.0);
+        let p00 = self.get_pixel_as_rgb888_unorm(x0, y0)?;
+        let p01 = self.get_pixel_as_rgb888_unorm(x0, y1)?;
+        let p10 = self.get_pixel_as_rgb888_unorm(x1, y0)?;
+        let p11 = self.get_pixel_as_rgb888_unorm(x1, y1)?;
 
         fn lerp(a: f32, b: f32, t: f32) -> f32 {
             a + (b - a) * t
@@ -218,18 +658,83 @@ impl UhdrJpeg {
     }
 }
 
+/// The two texels to interpolate between along an axis of `size` texels at the normalized coordinate `coordinate`,
+/// clamped to the edges, and the weight of the second one.
+fn bilinear_taps(coordinate: f32, size: usize) -> (usize, usize, f32) {
+    // Relative to the texel centers.
+    let position = coordinate * size as f32 - 0.5;
+    let base = position.floor();
+
+    let clamp = |texel: f32| (texel.max(0.0) as usize).min(size - 1);
+    (clamp(base), clamp(base + 1.0), position - base)
+}
+
 impl UhdrJpeg {
-    fn get_pixel_as_rgb888_unorm_linear(&self, x: usize, y: usize) -> Option<[f32; 3]> {
+    /// Samples the average over a box footprint centered at the pixel coordinate, using clamp addressing.
+    /// The U and V coordinates and the footprint extent `footprint_u` x `footprint_v` are in the range [0, 1].
+    /// Texels partially covered by the footprint are weighted by their coverage.
+    ///
+    /// Unlike `sample_bilinear`, this doesn't skip texels when the footprint spans more than one texel,
+    /// which is the case when the image is larger than the one it is being mapped onto.
+    /// The function returns the stored RGB values in the range [0, 1], like `sample_bilinear`.
+    pub fn sample_area(
+        &self,
+        u: f32,
+        v: f32,
+        footprint_u: f32,
+        footprint_v: f32,
+    ) -> Option<[f32; 3]> {
+        let width = self.jpeg_info.width as usize;
+        let height = self.jpeg_info.height as usize;
+
+        let x0 = (u - footprint_u * 0.5) * width as f32;
+        let x1 = (u + footprint_u * 0.5) * width as f32;
+        let y0 = (v - footprint_v * 0.5) * height as f32;
+        let y1 = (v + footprint_v * 0.5) * height as f32;
+
+        let mut sum = [0.0f32; 3];
+        let mut total_weight = 0.0f32;
+
+        for texel_y in (y0.floor() as i64)..(y1.ceil() as i64) {
+            let weight_y = (y1.min(texel_y as f32 + 1.0) - y0.max(texel_y as f32)).max(0.0);
+            let clamped_y = texel_y.clamp(0, height as i64 - 1) as usize;
+
+            for texel_x in (x0.floor() as i64)..(x1.ceil() as i64) {
+                let weight_x = (x1.min(texel_x as f32 + 1.0) - x0.max(texel_x as f32)).max(0.0);
+                let clamped_x = texel_x.clamp(0, width as i64 - 1) as usize;
+
+                let weight = weight_x * weight_y;
+                let texel = self.get_pixel_as_rgb888_unorm(clamped_x, clamped_y)?;
+                for (sum, texel) in sum.iter_mut().zip(texel) {
+                    *sum += texel * weight;
+                }
+                total_weight += weight;
+            }
+        }
+
+        if total_weight <= 0.0 {
+            return None;
+        }
+
+        Some(sum.map(|sum| sum / total_weight))
+    }
+}
+
+impl UhdrJpeg {
+    /// The stored values, without any transfer applied.
+    fn get_pixel_as_rgb888_unorm(&self, x: usize, y: usize) -> Option<[f32; 3]> {
         let [r, g, b] = self.get_pixel_as_rgb888(x, y)?;
         let r = r as f32 / 255.0;
         let g = g as f32 / 255.0;
         let b = b as f32 / 255.0;
-        Some(self.to_linear([r, g, b]))
+        Some([r, g, b])
     }
 
+    /// YCbCr is converted to RGB, e.g. if the decoder is ever configured to output it rather than converting it itself.
+    /// `None` for the color spaces `check_output_color_space` rejects.
     fn get_pixel_as_rgb888(&self, x: usize, y: usize) -> Option<[u8; 3]> {
         let pixel_index = match self.content.jpeg_color_space {
-            JpegColorSpace::RGB => (y * self.jpeg_info.width as usize + x) * 3,
+            JpegColorSpace::RGB | JpegColorSpace::YCbCr => (y * self.jpeg_info.width as usize + x) * 3,
             JpegColorSpace::Luma => (y * self.jpeg_info.width as usize + x) * 1,
             _ => return None,
         };
@@ -237,6 +742,10 @@ impl UhdrJpeg {
         if pixel_index < self.content.pixels.len() {
             let (r, g, b) = match self.content.jpeg_color_space {
                 JpegColorSpace::RGB => (self.content.pixels[pixel_index], self.content.pixels[pixel_index + 1], self.content.pixels[pixel_index + 2]),
+                JpegColorSpace::YCbCr => {
+                    let [r, g, b] = ycbcr_to_rgb888([self.content.pixels[pixel_index], self.content.pixels[pixel_index + 1], self.content.pixels[pixel_index + 2]]);
+                    (r, g, b)
+                },
                 JpegColorSpace::Luma => (self.content.pixels[pixel_index], self.content.pixels[pixel_index], self.content.pixels[pixel_index]),
                 _ => return None,
             };
@@ -246,17 +755,256 @@ impl UhdrJpeg {
         }
     }
 
-    /// Applies the EOTF according the `IccColorSpace` if available.
-    /// If no `IccColorSpace` is available, the EOTF is assumed to be gamma of `2.2`.
-    fn to_linear(&self, mut rgb: [f32; 3]) -> [f32; 3] {
-        if let Some(icc_color_space) = &self.content.icc_color_space {
-            rgb = icc_color_space.transfer_characteristics.evaluate(&rgb);
-        } else {
-            // Assume 2.2 gamma, which is the default for most JPEGs and is the best we can do without an ICC profile.
-            rgb[0] = rgb[0].powf(2.2);
-            rgb[1] = rgb[1].powf(2.2);
-            rgb[2] = rgb[2].powf(2.2);
+    /// Applies the EOTF according to `source_transfer`:
+    /// HLG if signalled by CICP, otherwise the `IccColorSpace` if available.
+    /// If no `IccColorSpace` is available, the EOTF is assumed to be the piecewise sRGB EOTF.
+    fn to_linear(&self, rgb: [f32; 3]) -> [f32; 3] {
+        self.source_transfer().to_linear(rgb, self.content.icc_color_space.as_ref())
+    }
+}
+
+/// Converts a full-range BT.601 YCbCr pixel, which JFIF specifies for JPEG, to RGB.
+fn ycbcr_to_rgb888([y, cb, cr]: [u8; 3]) -> [u8; 3] {
+    let y = y as f32;
+    let cb = cb as f32 - 128.0;
+    let cr = cr as f32 - 128.0;
+    [
+        y + 1.402 * cr,
+        y - 0.344_136 * cb - 0.714_136 * cr,
+        y + 1.772 * cb,
+    ].map(|value| value.round().clamp(0.0, 255.0) as u8)
+}
+
+/// Whether `bytes` starts with an ISO BMFF `ftyp` box of one of `HEIF_BRANDS`.
+fn is_heif(bytes: &[u8]) -> bool {
+    bytes.get(4..8) == Some(b"ftyp") && bytes.get(8..12).is_some_and(|brand| HEIF_BRANDS.contains(&brand))
+}
+
+/// Fails for a decoder output other than RGB, YCbCr or grayscale, which `get_pixel_as_rgb888` can't read pixels of.
+fn check_output_color_space(color_space: JpegColorSpace) -> Result<(), String> {
+    match color_space {
+        JpegColorSpace::RGB | JpegColorSpace::YCbCr | JpegColorSpace::Luma => Ok(()),
+        _ => Err(format!("Unsupported JPEG output color space {:?}", color_space)),
+    }
+}
+
+/// Fails if `width` × `height` exceeds `max_pixels`, before anything is decoded.
+fn check_pixel_count(width: usize, height: usize, max_pixels: Option<usize>) -> Result<(), String> {
+    let pixel_count = width * height;
+    if let Some(max_pixels) = max_pixels && pixel_count > max_pixels {
+        return Err(format!(
+            "Image too large: {}x{} is {} pixels, exceeding the limit of {}",
+            width, height, pixel_count, max_pixels,
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil;
+
+    #[test]
+    fn max_dimensions_are_honored() {
+        let image = testutil::TestImage::from_fn(16, 8, |_, _| [255, 0, 0]);
+        let jpeg_bytes = testutil::encode_jpeg(&image, &[]);
+
+        let options = JpegDecodeOptions { max_width: 15, ..Default::default() };
+        assert!(UhdrJpeg::new_from_bytes_with_options(&jpeg_bytes, &options).is_err());
+        let options = JpegDecodeOptions { max_height: 7, ..Default::default() };
+        assert!(UhdrJpeg::new_from_bytes_with_options(&jpeg_bytes, &options).is_err());
+
+        let options = JpegDecodeOptions { max_width: 16, max_height: 8, use_simd: false, ..Default::default() };
+        let jpeg = UhdrJpeg::new_from_bytes_with_options(&jpeg_bytes, &options).unwrap();
+        assert_eq!(jpeg.extent(), (16, 8));
+        assert_eq!(jpeg.fetch_pixel(3, 3), UhdrJpeg::new_from_bytes(&jpeg_bytes).unwrap().fetch_pixel(3, 3));
+    }
+
+    #[test]
+    fn bilinear_sampling_of_a_checkerboard_matches_hand_computed_values() {
+        // A 2x2 checkerboard, black at the top left, set exactly rather than through lossy JPEG compression.
+        // 0 and 1 are the same in linear light whatever the EOTF.
+        let image = testutil::TestImage::from_fn(2, 2, |_, _| [0; 3]);
+        let options = JpegDecodeOptions { force_rgb: true, ..Default::default() };
+        let mut jpeg = UhdrJpeg::new_from_bytes_with_options(&testutil::encode_jpeg(&image, &[]), &options).unwrap();
+        jpeg.content.pixels = [[0; 3], [255; 3], [255; 3], [0; 3]].concat();
+
+        // Texel centers are at 0.25 and 0.75.
+        let expected = [
+            ((0.25, 0.25), 0.0),
+            ((0.75, 0.25), 1.0),
+            ((0.25, 0.75), 1.0),
+            ((0.5, 0.5), 0.5),
+            ((0.5, 0.25), 0.5),
+            ((0.25, 0.5), 0.5),
+            // A quarter of the way from one texel center to the next, horizontally and vertically alike.
+            ((0.375, 0.25), 0.25),
+            ((0.25, 0.375), 0.25),
+            // lerp(lerp(0, 1, 0.25), lerp(1, 0, 0.25), 0.25)
+            ((0.375, 0.375), 0.375),
+            // lerp(lerp(0, 1, 0.7), lerp(1, 0, 0.7), 0.3)
+            ((0.6, 0.4), 0.58),
+            // Clamped to the outermost texels.
+            ((0.0, 0.0), 0.0),
+            ((1.0, 0.0), 1.0),
+            ((1.0, 1.0), 0.0),
+        ];
+        for ((u, v), value) in expected {
+            let sampled = jpeg.sample_bilinear(u, v).unwrap();
+            assert!(sampled.iter().all(|sampled| (sampled - value).abs() < 1e-5), "({}, {}): {:?} != {}", u, v, sampled, value);
+        }
+    }
+
+    #[test]
+    fn ycbcr_jpeg_is_decoded_to_rgb_if_forced() {
+        // `jpeg-encoder` writes YCbCr.
+        let image = testutil::TestImage::from_fn(16, 16, |x, _| if x < 8 { [200, 40, 60] } else { [30, 90, 220] });
+        let jpeg_bytes = testutil::encode_jpeg(&image, &[]);
+
+        let options = JpegDecodeOptions { force_rgb: true, ..Default::default() };
+        let jpeg = UhdrJpeg::new_from_bytes_with_options(&jpeg_bytes, &options).unwrap();
+        assert_eq!(jpeg.content.jpeg_color_space, JpegColorSpace::RGB);
+        assert_eq!(jpeg.content.pixels.len(), 16 * 16 * 3);
+
+        for (x, expected) in [(2, [200, 40, 60]), (13, [30, 90, 220])] {
+            let rgb = jpeg.get_pixel_as_rgb888(x, 8).unwrap();
+            assert!(rgb.iter().zip(expected).all(|(&actual, expected)| actual.abs_diff(expected) <= 4), "{:?}", rgb);
+            // Same as without the option.
+            assert_eq!(rgb, UhdrJpeg::new_from_bytes(&jpeg_bytes).unwrap().get_pixel_as_rgb888(x, 8).unwrap());
         }
-        rgb
+    }
+
+    #[test]
+    fn ycbcr_output_is_converted_to_rgb_when_sampling() {
+        let gain_map = testutil::TestImage::from_fn(8, 8, |x, y| [(x * 32) as u8, 160, (y * 32) as u8]);
+        let jpeg_bytes = testutil::encode_jpeg(&gain_map, &[]);
+        let rgb_jpeg = UhdrJpeg::new_from_bytes(&jpeg_bytes).unwrap();
+
+        // The same image with the decoder's YCbCr output, as if it didn't convert to RGB itself.
+        let options = DecoderOptions::default().jpeg_set_out_colorspace(JpegColorSpace::YCbCr);
+        let mut decoder = JpegDecoder::new_with_options(ZCursor::new(&jpeg_bytes[..]), options);
+        let mut ycbcr_jpeg = rgb_jpeg.clone();
+        ycbcr_jpeg.content.pixels = decoder.decode().unwrap();
+        assert_eq!(decoder.output_colorspace(), Some(JpegColorSpace::YCbCr));
+        ycbcr_jpeg.content.jpeg_color_space = JpegColorSpace::YCbCr;
+
+        for (u, v) in [(0.1, 0.1), (0.5, 0.3), (0.9, 0.8)] {
+            let sampled = ycbcr_jpeg.sample_bilinear(u, v).unwrap();
+            let expected = rgb_jpeg.sample_bilinear(u, v).unwrap();
+            assert!(sampled[1] > 0.3, "{:?}", sampled);
+            assert!(sampled.iter().zip(expected).all(|(a, b)| (a - b).abs() < 0.02), "{:?} vs {:?}", sampled, expected);
+        }
+    }
+
+    #[test]
+    fn jpeg_without_icc_profile_is_linearized_with_the_srgb_eotf() {
+        // Mid-gray and near-black, set exactly rather than through lossy JPEG compression.
+        let image = testutil::TestImage::from_fn(2, 1, |_, _| [0; 3]);
+        let mut jpeg = UhdrJpeg::new_from_bytes(&testutil::encode_jpeg(&image, &[])).unwrap();
+        assert_eq!(jpeg.source_transfer(), SourceTransfer::Srgb);
+        jpeg.content.pixels = vec![128, 128, 128, 10, 10, 10];
+
+        let [mid_gray, _, _] = jpeg.fetch_pixel_linear(0, 0);
+        let [near_black, _, _] = jpeg.fetch_pixel_linear(1, 0);
+        assert!((mid_gray - crate::transfer::srgb_eotf(128.0 / 255.0)).abs() < 1e-6, "{}", mid_gray);
+        assert!((near_black - crate::transfer::srgb_eotf(10.0 / 255.0)).abs() < 1e-6, "{}", near_black);
+
+        // The curves nearly agree at mid-gray, but the linear segment of sRGB lifts the shadows well above a pure 2.2 gamma,
+        // which is only used where a profile signals it.
+        let [gamma_mid_gray, _, _] = SourceTransfer::Gamma22.to_linear([128.0 / 255.0; 3], None);
+        let [gamma_near_black, _, _] = SourceTransfer::Gamma22.to_linear([10.0 / 255.0; 3], None);
+        assert!((mid_gray / gamma_mid_gray - 1.0).abs() < 0.02, "{} vs {}", mid_gray, gamma_mid_gray);
+        assert!(near_black / gamma_near_black > 3.0, "{} vs {}", near_black, gamma_near_black);
+    }
+
+    #[test]
+    fn gain_map_is_located_by_its_data_offset_and_checked_for_soi() {
+        let primary = testutil::TestImage::from_fn(8, 8, |_, _| [128; 3]);
+        let gain_map_bytes = testutil::encode_jpeg(&testutil::TestImage::from_fn(4, 2, |_, _| [64; 3]), &[]);
+        const PADDING: usize = 16;
+
+        // The MPF payload has the same size regardless of the values.
+        let encode = |gain_map_offset: usize| {
+            let image_sizes = [0, gain_map_bytes.len() as u32];
+            let mut bytes = testutil::encode_jpeg(&primary, &[testutil::mpf_app_segment(&image_sizes, &[0, gain_map_offset as u32])]);
+            bytes.extend([0; PADDING]);
+            bytes.extend(&gain_map_bytes);
+            bytes
+        };
+        let placeholder = encode(0);
+        let mp_endian_offset = MpfInfo::find_mp_endian_offset(&placeholder).unwrap();
+        let gain_map_offset = placeholder.len() - gain_map_bytes.len() - mp_endian_offset;
+
+        // Behind padding.
+        let bytes = encode(gain_map_offset);
+        let jpeg = UhdrJpeg::new_from_bytes(&bytes).unwrap();
+        assert_eq!(jpeg.extract_gain_map_jpeg(&bytes).unwrap().extent(), (4, 2));
+
+        // Pointing into the padding, or past the end of the file.
+        for offset in [gain_map_offset - PADDING / 2, gain_map_offset + 1] {
+            let bytes = encode(offset);
+            assert!(UhdrJpeg::new_from_bytes(&bytes).unwrap().extract_gain_map_jpeg(&bytes).is_none(), "{}", offset);
+        }
+    }
+
+    #[test]
+    fn grayscale_jpeg_is_decoded_to_a_single_channel_unless_rgb_is_forced() {
+        let image = testutil::TestImage::grayscale_from_fn(16, 8, |x, y| (x * 16 + y) as u8);
+        let jpeg_bytes = testutil::encode_jpeg(&image, &[]);
+
+        let jpeg = UhdrJpeg::new_from_bytes(&jpeg_bytes).unwrap();
+        assert_eq!(jpeg.content.jpeg_color_space, JpegColorSpace::Luma);
+        assert_eq!(jpeg.content.pixels.len(), 16 * 8);
+
+        let options = JpegDecodeOptions { force_rgb: true, ..Default::default() };
+        let rgb_jpeg = UhdrJpeg::new_from_bytes_with_options(&jpeg_bytes, &options).unwrap();
+        assert_eq!(rgb_jpeg.content.jpeg_color_space, JpegColorSpace::RGB);
+
+        // Every pixel, including the last one, which reading 3 bytes per pixel would run past the end for.
+        for y in 0..8 {
+            for x in 0..16 {
+                let [r, g, b] = jpeg.fetch_pixel(x, y);
+                assert!(r == g && g == b, "{:?}", [r, g, b]);
+                assert!((r * 255.0 - (x * 16 + y) as f32).abs() <= 2.0, "({}, {}): {}", x, y, r * 255.0);
+                assert_eq!([r, g, b], rgb_jpeg.fetch_pixel(x, y), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn only_color_spaces_with_readable_pixels_are_accepted() {
+        for color_space in [JpegColorSpace::RGB, JpegColorSpace::YCbCr, JpegColorSpace::Luma] {
+            assert_eq!(check_output_color_space(color_space), Ok(()));
+        }
+        for color_space in [JpegColorSpace::CMYK, JpegColorSpace::YCCK, JpegColorSpace::RGBA, JpegColorSpace::LumaA] {
+            assert_eq!(check_output_color_space(color_space), Err(format!("Unsupported JPEG output color space {:?}", color_space)));
+        }
+    }
+
+    #[test]
+    fn truncated_jpeg_is_partially_decoded_only_if_allowed() {
+        let image = testutil::TestImage::from_fn(64, 64, |_, _| [255, 0, 0]);
+        let jpeg_bytes = testutil::encode_jpeg(&image, &[]);
+        let header_end = segments::header_segments(&jpeg_bytes)
+            .last()
+            .map(|segment| segment.payload_offset + segment.payload.len())
+            .unwrap();
+        // Halfway through the scan.
+        let truncated_bytes = &jpeg_bytes[..(header_end + jpeg_bytes.len()) / 2];
+
+        let error = UhdrJpeg::new_from_bytes(truncated_bytes).err().unwrap();
+        assert!(error.contains("Failed to decode JPEG image"), "{}", error);
+
+        let settings = JpegDecodeSettings { allow_partial: true, ..Default::default() };
+        let jpeg = UhdrJpeg::new_from_bytes_with_settings(truncated_bytes, settings).unwrap();
+        assert_eq!((jpeg.jpeg_info.width, jpeg.jpeg_info.height), (64, 64));
+
+        // The top is decoded, the bottom is filled.
+        let [r, g, b] = jpeg.get_pixel_as_rgb888(0, 0).unwrap();
+        assert!(r > 250 && g < 5 && b < 5, "{:?}", [r, g, b]);
+        let [r, g, b] = jpeg.get_pixel_as_rgb888(63, 63).unwrap();
+        assert!(r == g && g == b, "{:?}", [r, g, b]);
     }
 }
@@ -1,182 +1,2867 @@
 
-pub use crate::colorspace::{IccColorSpace, ColorGamut};
-pub use crate::gainmap::GainMapMetadata;
-pub use crate::jpeg::UhdrJpeg;
-pub use crate::uhdr::UhdrBoostComputer;
+pub use crate::colorspace::{Cicp, IccColorSpace, ColorGamut, ColorGamutConverter, SourceTransfer};
+#[cfg(feature = "dng")]
+pub use crate::dng::extract_uhdr_jpeg_from_dng;
+pub use crate::encoder::{EncodeOptions, EncoderRegistry, HdrEncoder, PngTransfer};
+pub use crate::error::UhdrError;
+pub use crate::gainmap::{GainMapError, GainMapFormulation, GainMapMetadata};
+pub use crate::jpeg::{JpegDecodeOptions, UhdrJpeg};
+#[cfg(feature = "avif")]
+pub use crate::lut::CubeLut;
+#[cfg(feature = "avif")]
+pub use crate::outavif::{AvifBitDepth, AvifChroma, AvifColorPrimaries, AvifColorSpace, AvifEncodeOptions, AvifEncodingError, AvifMetadata, AvifOversize, AvifRange, AvifTransfer, Hdr10LightLevels, MasteringDisplay, Rounding};
+#[cfg(feature = "hdr")]
+pub use crate::inradiance::{RadianceImage, read_hdr};
+pub use crate::mux::{write_uhdr_jpeg, write_uhdr_jpeg_from_hdr};
+pub use crate::orientation::Orientation;
+pub use crate::outtiff::TiffTransfer;
+pub use crate::pixel::{FloatImageContent, FloatPixel};
+pub use crate::resolution::{Resolution, ResolutionUnit};
+#[cfg(feature = "half")]
+pub use crate::pixel::HalfImageContent;
+pub use crate::stats::{AUTO_WHITE_MEDIAN_LUMINANCE, LuminanceStats, MAX_AUTO_WHITE_LEVEL, MIN_AUTO_WHITE_LEVEL};
+pub use crate::timings::ConversionTimings;
+pub use crate::tonemap::ToneMapOperator;
+pub use crate::uhdr::{UhdrBoostComputer, compute_gain_map};
 
 pub mod colorspace;
 pub mod gainmap;
 pub mod jpeg;
+pub mod mux;
+pub mod transfer;
 pub mod uhdr;
 
 #[cfg(feature = "avif")]
 pub mod outavif;
+#[cfg(feature = "avif")]
+mod lut;
+pub mod outtiff;
 
+#[cfg(feature = "dng")]
+mod dng;
+mod encoder;
+mod error;
+mod exif;
+#[cfg(feature = "hdr")]
+mod inradiance;
+mod isobmff;
 mod mpf;
+mod orientation;
 #[cfg(feature = "exr")]
 mod outexr;
 #[cfg(feature = "heif")]
 mod outheif;
+#[cfg(feature = "png")]
+mod outpng;
+#[cfg(feature = "hdr")]
+mod outradiance;
+#[cfg(feature = "webp")]
+mod outwebp;
 mod pixel;
+mod resample;
+mod resolution;
+mod segments;
+mod stats;
+#[cfg(test)]
+mod testutil;
 mod tiff;
+mod timings;
+mod tonemap;
 
 use std::io::{Read, Write};
+use std::time::Instant;
 
-use log::warn;
+use log::{debug, warn};
 
-use crate::pixel::{FloatImageContent, FloatPixel};
+use crate::jpeg::{JpegDecodeError, JpegDecodeSettings};
+use crate::pixel::LinearImageContent;
 
+/// Converts a decoded Ultra HDR JPEG.
+///
+/// This is `Send + Sync`, so converters can be moved to worker threads, and a shared converter can run several
+/// conversions at once. Nothing in it refers to `lcms2` objects after decoding; see `TransferCharacteristics`.
 #[derive(Clone)]
 pub struct UhdrConverter {
     uhdr_jpeg: UhdrJpeg,
-    gain_map_jpeg: UhdrJpeg,
+    /// Applied in order. Ultra HDR JPEGs have exactly one.
+    /// Empty if the primary image is HLG-encoded, since it is already HDR.
+    gain_maps: Vec<GainMapLayer>,
     src_color_gamut: ColorGamut,
-    uhdr_boost_computer: UhdrBoostComputer,
+    max_display_boost: f32,
+    crop: Option<CropRect>,
+    /// Applied to the output; see `with_orientation`.
+    orientation: Orientation,
+    exposure_ev: f32,
+    /// The decoding phases, measured by `new_with_options`.
+    decode_timings: ConversionTimings,
+    /// Whether to store the linear pixels as `f16`; see `with_low_memory`.
+    #[cfg(feature = "half")]
+    low_memory: bool,
+    /// The AV1 base quantizer of HDR AVIF output; see `with_avif_quantizer`.
+    #[cfg(feature = "avif")]
+    avif_quantizer: Option<u8>,
+    /// The quality and speed of HDR AVIF output; see `with_avif_encode_options`.
+    #[cfg(feature = "avif")]
+    avif_encode_options: AvifEncodeOptions,
+    /// Applied to HDR AVIF output; see `with_lut`.
+    #[cfg(feature = "avif")]
+    lut: Option<CubeLut>,
+    /// How HDR AVIF output is quantized; see `with_rounding`.
+    #[cfg(feature = "avif")]
+    rounding: Rounding,
+    /// The bit depth of HDR AVIF output; see `with_avif_bit_depth`.
+    #[cfg(feature = "avif")]
+    avif_bit_depth: AvifBitDepth,
+    /// Whether HDR AVIF output is bounded by and tagged with the authored peak; see `with_derived_peak`.
+    #[cfg(feature = "avif")]
+    derive_peak: bool,
+    /// What AVIF output too large for AV1 does; see `with_avif_oversize`.
+    #[cfg(feature = "avif")]
+    avif_oversize: AvifOversize,
+    /// The content light levels HDR AVIF output is tagged with instead of the measured; see `with_avif_light_levels`.
+    #[cfg(feature = "avif")]
+    avif_light_levels: Option<Hdr10LightLevels>,
+    /// The mastering display HDR AVIF output is tagged with; see `with_avif_mastering_display`.
+    #[cfg(feature = "avif")]
+    avif_mastering_display: Option<MasteringDisplay>,
+    /// The transfer characteristics of HDR AVIF output; see `with_avif_transfer`.
+    #[cfg(feature = "avif")]
+    avif_transfer: AvifTransfer,
+    /// The primaries of HDR AVIF output; see `with_avif_color_primaries`.
+    #[cfg(feature = "avif")]
+    avif_color_primaries: AvifColorPrimaries,
+    /// Whether HDR AVIF output is Y'C'bC'r or ICtCp; see `with_avif_color_space`.
+    #[cfg(feature = "avif")]
+    avif_color_space: AvifColorSpace,
+    /// Whether HDR AVIF output has no `Exif` item; see `with_strip_metadata`.
+    #[cfg(feature = "avif")]
+    strip_metadata: bool,
+    /// The number of rows HDR AVIF output is converted in at a time, if in bands; see `with_avif_band_rows`.
+    #[cfg(feature = "avif")]
+    avif_band_rows: Option<usize>,
+}
+
+#[derive(Clone)]
+struct GainMapLayer {
+    jpeg: UhdrJpeg,
+    metadata: GainMapMetadata,
+    boost_computer: UhdrBoostComputer,
+    filter: GainMapFilter,
+}
+
+/// How the gain map is resampled onto the pixels of the primary image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum GainMapFilter {
+    /// Bilinear filtering, suited to gain maps no larger than the primary image, which is the common case.
+    Bilinear,
+    /// Averages the gain map texels covered by each primary image pixel.
+    /// Avoids aliasing when the gain map is larger than the primary image.
+    Area,
+}
+
+impl GainMapFilter {
+    /// Selects `Area` if the gain map is larger than the primary image in either dimension, and `Bilinear` otherwise.
+    pub fn for_extents(primary_extent: (usize, usize), gain_map_extent: (usize, usize)) -> Self {
+        if gain_map_extent.0 > primary_extent.0 || gain_map_extent.1 > primary_extent.1 {
+            GainMapFilter::Area
+        } else {
+            GainMapFilter::Bilinear
+        }
+    }
+}
+
+/// The default of `DecodeOptions::min_gain_map_scale`. Ultra HDR JPEGs from cameras commonly have gain maps of a quarter of
+/// the primary image's width and height, which is detailed enough.
+pub const DEFAULT_MIN_GAIN_MAP_SCALE: f32 = 0.25;
+
+/// The number of pixels `UhdrConverter::suggest_target_sdr_white_level` samples at most, which is plenty for a histogram.
+pub const AUTO_WHITE_MAX_SAMPLES: usize = 1 << 20;
+
+/// How much the differences in the diff image of `UhdrConverter::convert_to_avif_with_diff_image` are amplified,
+/// so that one 10-bit code value is a visible 1/16 of full scale, and 16 code values or more are white.
+#[cfg(feature = "avif")]
+pub const DIFF_IMAGE_AMPLIFICATION: f32 = 1023.0 / 16.0;
+
+/// A warning that the HDR detail will be limited by the resolution of the gain map, if its width or height is less than
+/// `min_scale` times the primary image's.
+fn low_gain_map_resolution_warning(primary_extent: (usize, usize), gain_map_extent: (usize, usize), min_scale: f32) -> Option<String> {
+    let scale = f32::min(
+        gain_map_extent.0 as f32 / primary_extent.0 as f32,
+        gain_map_extent.1 as f32 / primary_extent.1 as f32,
+    );
+    (scale < min_scale).then(|| format!(
+        "The gain map ({:?}) is only {:.3} times the resolution of the primary image ({:?}), below {}: \
+         It is upsampled bilinearly, so the HDR detail will be limited and may look soft or blocky",
+        gain_map_extent, scale, primary_extent, min_scale,
+    ))
+}
+
+/// How much the horizontal and vertical scales of a gain map relative to the primary image may differ, beyond rounding
+/// of the dimensions of small gain maps, before `mismatched_gain_map_warning` warns.
+const MAX_GAIN_MAP_SCALE_MISMATCH: f32 = 1.5;
+
+/// A warning that the gain map is scaled very differently horizontally and vertically relative to the primary image,
+/// e.g. because it is rotated or belongs to another image. It is still stretched over the whole primary image.
+fn mismatched_gain_map_warning(primary_extent: (usize, usize), gain_map_extent: (usize, usize)) -> Option<String> {
+    let scale_x = gain_map_extent.0 as f32 / primary_extent.0 as f32;
+    let scale_y = gain_map_extent.1 as f32 / primary_extent.1 as f32;
+    let mismatch = scale_x.max(scale_y) / scale_x.min(scale_y);
+    (mismatch > MAX_GAIN_MAP_SCALE_MISMATCH).then(|| format!(
+        "The gain map ({:?}) is scaled by {:.3} horizontally but by {:.3} vertically relative to the primary image ({:?}): \
+         It is stretched to fit, but may not belong to it",
+        gain_map_extent, scale_x, scale_y, primary_extent,
+    ))
+}
+
+/// A region of interest in pixels of the primary image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CropRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Options for reading the input of `UhdrConverter::new_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// The MPF image to use as the primary image, overriding the assumption that it is the first one.
+    /// For recovering files with incorrect MPF information.
+    /// If only `gain_map_index` is specified, this defaults to the first image other than the gain map.
+    pub primary_index: Option<usize>,
+    /// The MPF image to use as the gain map, overriding the assumption that it is the second one.
+    /// If only `primary_index` is specified, this defaults to the first image other than the primary image.
+    /// Only this single gain map is applied if specified.
+    pub gain_map_index: Option<usize>,
+    /// Ignores any ICC profiles without even parsing them, as if there were none: The primary image is then assumed to be
+    /// sRGB, with the sRGB EOTF. For diagnosing whether an ICC profile is causing wrong colors.
+    pub ignore_icc: bool,
+    /// Rejects any image, i.e. the primary image or a gain map, with more pixels than this, as declared by its JPEG headers
+    /// before anything is decoded. Guards against inputs declaring huge dimensions, e.g. in server contexts.
+    pub max_pixels: Option<usize>,
+    /// Accepts images with truncated or otherwise damaged image data, which are rejected by default, keeping whatever could be
+    /// decoded up to the damage. The rest of such an image is filled with gray, and a warning is logged.
+    pub allow_partial: bool,
+    /// Passed through to the JPEG decoder for every image, i.e. the primary image and the gain maps.
+    pub jpeg: JpegDecodeOptions,
+    /// Warns if the width or height of a gain map is less than this fraction of the primary image's, since the HDR detail
+    /// is then limited by the gain map. Defaults to `DEFAULT_MIN_GAIN_MAP_SCALE` if unspecified. `0.0` disables the warning.
+    pub min_gain_map_scale: Option<f32>,
+}
+
+/// What was found in the input Ultra HDR JPEG, for diagnostics.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct UhdrInfo {
+    pub width: usize,
+    pub height: usize,
+    /// The description of the primary image's ICC profile, if it has one with a description.
+    pub icc_description: Option<String>,
+    pub has_icc_profile: bool,
+    /// In the order they are applied.
+    pub gain_maps: Vec<GainMapInfo>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GainMapInfo {
+    pub width: usize,
+    pub height: usize,
+    pub metadata: GainMapMetadata,
+    pub filter: GainMapFilter,
 }
 
 impl UhdrConverter {
     pub fn new<R: Read>(
         reader: &mut R,
         max_display_boost: f32,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+    ) -> Result<Self, UhdrError> {
+        Self::new_with_options(reader, max_display_boost, &DecodeOptions::default())
+    }
+
+    /// `reader` may also provide an ISOBMFF container, e.g. a HEIF, whose gain map is a `tmap` item deriving from
+    /// JPEG-coded base image and gain map items, per ISO 21496-1.
+    ///
+    /// With the `dng` feature, `reader` may also provide a DNG, which is converted through its previews,
+    /// see `extract_uhdr_jpeg_from_dng`.
+    pub fn new_with_options<R: Read>(
+        reader: &mut R,
+        max_display_boost: f32,
+        options: &DecodeOptions,
+    ) -> Result<Self, UhdrError> {
         let jpeg_bytes = {
             let mut bytes = Vec::new();
             reader.read_to_end(&mut bytes)?;
             bytes
         };
-        let uhdr_jpeg = UhdrJpeg::new_from_bytes(&jpeg_bytes)
-            .map_err(|e| format!("Failed to parse JPEG: {}", e))?;
 
-        let gain_map_jpeg = uhdr_jpeg.extract_gain_map_jpeg(&jpeg_bytes)
-            .ok_or_else(|| "Failed to extract gain map JPEG".to_string())?;
-        let gain_map_jpeg_xmp_bytes = gain_map_jpeg.xmp_bytes()
-            .ok_or_else(|| "Gain Map JPEG does not contain XMP metadata".to_string())?;
-        let gain_map_metadata = GainMapMetadata::new_from_xmp_bytes(&gain_map_jpeg_xmp_bytes)
-            .ok_or_else(|| "Failed to parse gain map metadata from XMP".to_string())?;
+        // A DNG is converted through the Ultra HDR JPEG among its previews.
+        #[cfg(feature = "dng")]
+        let jpeg_bytes = if crate::dng::is_tiff(&jpeg_bytes) {
+            crate::dng::extract_uhdr_jpeg_from_dng(&jpeg_bytes)
+                .map_err(|e| UhdrError::NotUhdrJpeg(format!("Failed to extract from DNG: {}", e)))?
+        } else {
+            jpeg_bytes
+        };
+
+        let decode_start = Instant::now();
+
+        let (uhdr_jpeg, gain_maps) = if crate::isobmff::is_isobmff(&jpeg_bytes) {
+            Self::decode_isobmff(&jpeg_bytes, options)?
+        } else {
+            let first_jpeg = Self::decode_jpeg(&jpeg_bytes, options)
+                .map_err(|e| UhdrError::from_jpeg_decode_error("Failed to parse JPEG", e))?;
+
+            let (uhdr_jpeg, gain_map_jpegs) = if options.primary_index.is_none() && options.gain_map_index.is_none() {
+                let gain_map_jpegs = first_jpeg.extract_gain_map_jpegs(&jpeg_bytes);
+                (first_jpeg, gain_map_jpegs)
+            } else {
+                let (primary_jpeg, gain_map_jpeg) = Self::extract_jpegs_by_index(first_jpeg, &jpeg_bytes, options)?;
+                (primary_jpeg, vec![gain_map_jpeg])
+            };
+
+            let gain_maps = if uhdr_jpeg.source_transfer() == SourceTransfer::Hlg {
+                // The HDR rendition is the primary image itself, so gain maps, if any, are only good for deriving an SDR rendition.
+                if !gain_map_jpegs.is_empty() {
+                    warn!("The primary image is HLG-encoded, ignoring gain maps");
+                }
+                Vec::new()
+            } else {
+                Self::read_gain_map_metadata(gain_map_jpegs)?
+            };
+            (uhdr_jpeg, gain_maps)
+        };
+
+        let decode_timings = {
+            let icc_parse = gain_maps.iter()
+                .map(|(jpeg, _)| jpeg.icc_parse_duration())
+                .sum::<std::time::Duration>()
+                + uhdr_jpeg.icc_parse_duration();
+            ConversionTimings {
+                jpeg_decode: decode_start.elapsed().saturating_sub(icc_parse),
+                icc_parse,
+                ..Default::default()
+            }
+        };
 
         let src_color_gamut = uhdr_jpeg.icc_color_space()
             .as_ref()
             .map(|icc| icc.color_gamut)
             .unwrap_or_else(|| {
-                warn!("No ICC profile found, using default sRGB color gamut");
+                if options.ignore_icc {
+                    debug!("Ignoring ICC profiles, using default sRGB color gamut");
+                } else {
+                    warn!("No ICC profile found, using default sRGB color gamut");
+                }
                 ColorGamut::srgb()
             });
-        
-        let uhdr_boost_computer = UhdrBoostComputer::new(&gain_map_metadata, max_display_boost.log2());
+
+        let gain_maps = gain_maps.into_iter()
+            .map(|(jpeg, metadata)| {
+                let boost_computer = UhdrBoostComputer::new(&metadata, max_display_boost.log2());
+
+                let min_scale = options.min_gain_map_scale.unwrap_or(DEFAULT_MIN_GAIN_MAP_SCALE);
+                if let Some(warning) = low_gain_map_resolution_warning(uhdr_jpeg.extent(), jpeg.extent(), min_scale) {
+                    warn!("{}", warning);
+                }
+                if let Some(warning) = mismatched_gain_map_warning(uhdr_jpeg.extent(), jpeg.extent()) {
+                    warn!("{}", warning);
+                }
+
+                let filter = GainMapFilter::for_extents(uhdr_jpeg.extent(), jpeg.extent());
+                if filter == GainMapFilter::Area {
+                    debug!(
+                        "Gain map ({:?}) is larger than the primary image ({:?}), using area filtering",
+                        jpeg.extent(), uhdr_jpeg.extent(),
+                    );
+                }
+
+                GainMapLayer { jpeg, metadata, boost_computer, filter }
+            })
+            .collect();
+
+        let orientation = uhdr_jpeg.orientation();
+        if orientation != Orientation::Normal {
+            debug!("Applying the EXIF orientation {:?}", orientation);
+        }
 
         Ok(Self {
             uhdr_jpeg,
-            gain_map_jpeg,
+            gain_maps,
             src_color_gamut,
-            uhdr_boost_computer,
+            max_display_boost,
+            crop: None,
+            orientation,
+            exposure_ev: 0.0,
+            decode_timings,
+            #[cfg(feature = "half")]
+            low_memory: false,
+            #[cfg(feature = "avif")]
+            avif_quantizer: None,
+            #[cfg(feature = "avif")]
+            avif_encode_options: AvifEncodeOptions::default(),
+            #[cfg(feature = "avif")]
+            lut: None,
+            #[cfg(feature = "avif")]
+            rounding: Rounding::Round,
+            #[cfg(feature = "avif")]
+            avif_bit_depth: AvifBitDepth::Ten,
+            #[cfg(feature = "avif")]
+            derive_peak: false,
+            #[cfg(feature = "avif")]
+            avif_oversize: AvifOversize::Fail,
+            #[cfg(feature = "avif")]
+            avif_light_levels: None,
+            #[cfg(feature = "avif")]
+            avif_mastering_display: None,
+            #[cfg(feature = "avif")]
+            avif_transfer: AvifTransfer::Pq,
+            #[cfg(feature = "avif")]
+            avif_color_primaries: AvifColorPrimaries::Bt2020,
+            #[cfg(feature = "avif")]
+            avif_color_space: AvifColorSpace::Ycbcr,
+            #[cfg(feature = "avif")]
+            strip_metadata: false,
+            #[cfg(feature = "avif")]
+            avif_band_rows: None,
         })
     }
 
+    /// Decodes the MPF images at `options.primary_index` and `options.gain_map_index`, where unspecified indices default to
+    /// the first image that isn't the other one.
+    fn extract_jpegs_by_index(
+        first_jpeg: UhdrJpeg,
+        jpeg_bytes: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<(UhdrJpeg, UhdrJpeg), UhdrError> {
+        let primary_index = options.primary_index
+            .unwrap_or(if options.gain_map_index == Some(0) { 1 } else { 0 });
+        let gain_map_index = options.gain_map_index
+            .unwrap_or(if primary_index == 0 { 1 } else { 0 });
+        if primary_index == gain_map_index {
+            return Err(UhdrError::InvalidOptions(format!("The primary image and the gain map can't both be MPF image {}", primary_index)));
+        }
+        debug!("Using MPF image {} as the primary image and MPF image {} as the gain map", primary_index, gain_map_index);
+
+        // The first image is already decoded, so only the others need to be located.
+        let locate = |index: usize| {
+            first_jpeg.mpf_image_bytes(jpeg_bytes, index)
+                .ok_or_else(|| UhdrError::InvalidOptions(format!("Failed to locate MPF image {}", index)))
+        };
+        let primary_bytes = (primary_index != 0).then(|| locate(primary_index)).transpose()?;
+        let gain_map_bytes = (gain_map_index != 0).then(|| locate(gain_map_index)).transpose()?;
+
+        let mut first_jpeg = Some(first_jpeg);
+        let mut decode = |index: usize, bytes: Option<&[u8]>| match bytes {
+            Some(bytes) => UhdrJpeg::new_secondary_from_bytes_with_settings(bytes, Self::decode_settings(options))
+                .map_err(|e| UhdrError::from_jpeg_decode_error(&format!("Failed to decode MPF image {}", index), e)),
+            None => Ok(first_jpeg.take().unwrap()),
+        };
+
+        let primary_jpeg = decode(primary_index, primary_bytes)?;
+        let gain_map_jpeg = decode(gain_map_index, gain_map_bytes)?;
+        Ok((primary_jpeg, gain_map_jpeg))
+    }
+
+    /// Decodes the base image and the gain map items of the ISOBMFF container `bytes`, with the metadata of their `tmap` item.
+    fn decode_isobmff(bytes: &[u8], options: &DecodeOptions) -> Result<(UhdrJpeg, Vec<(UhdrJpeg, GainMapMetadata)>), UhdrError> {
+        if options.primary_index.is_some() || options.gain_map_index.is_some() {
+            return Err(UhdrError::InvalidOptions("An ISOBMFF container has no MPF images to select by index".to_string()));
+        }
+
+        let items = crate::isobmff::extract_gain_map_items(bytes)
+            .map_err(|e| UhdrError::NotUhdrJpeg(format!("Failed to extract from ISOBMFF container: {}", e)))?;
+        debug!("Using the JPEG items of the `tmap` item of an ISOBMFF container");
+
+        let base_jpeg = UhdrJpeg::new_from_bytes_with_settings(&items.base_jpeg, Self::decode_settings(options))
+            .map_err(|e| UhdrError::from_jpeg_decode_error("Failed to parse base image JPEG", e))?;
+        if base_jpeg.source_transfer() == SourceTransfer::Hlg {
+            warn!("The primary image is HLG-encoded, ignoring gain maps");
+            return Ok((base_jpeg, Vec::new()));
+        }
+
+        let gain_map_jpeg = UhdrJpeg::new_secondary_from_bytes_with_settings(&items.gain_map_jpeg, Self::decode_settings(options))
+            .map_err(|e| UhdrError::from_jpeg_decode_error("Failed to decode gain map JPEG", e))?;
+        let gain_map_metadata = GainMapMetadata::new_from_iso_21496_1_bytes(&items.metadata)?;
+        Ok((base_jpeg, vec![(gain_map_jpeg, gain_map_metadata)]))
+    }
+
+    fn decode_jpeg(jpeg_bytes: &[u8], options: &DecodeOptions) -> Result<UhdrJpeg, JpegDecodeError> {
+        UhdrJpeg::new_from_bytes_with_settings(jpeg_bytes, Self::decode_settings(options))
+    }
+
+    fn decode_settings(options: &DecodeOptions) -> JpegDecodeSettings {
+        JpegDecodeSettings {
+            ignore_icc: options.ignore_icc,
+            max_pixels: options.max_pixels,
+            allow_partial: options.allow_partial,
+            jpeg: options.jpeg,
+        }
+    }
+
+    /// Parses the gain map metadata of `gain_map_jpegs`, which is required of the first one.
+    /// The others are ignored unless they have gain map metadata.
+    fn read_gain_map_metadata(gain_map_jpegs: Vec<UhdrJpeg>) -> Result<Vec<(UhdrJpeg, GainMapMetadata)>, UhdrError> {
+        let mut gain_map_jpegs = gain_map_jpegs.into_iter();
+
+        let gain_map_jpeg = gain_map_jpegs.next()
+            .ok_or_else(|| UhdrError::MissingGainMap("Failed to extract gain map JPEG".to_string()))?;
+        let gain_map_metadata = GainMapMetadata::new_from_gain_map_jpeg_bytes(gain_map_jpeg.coded_bytes(), gain_map_jpeg.xmp_bytes())
+            .ok_or_else(|| UhdrError::MissingGainMap("Gain Map JPEG does not contain XMP or ISO 21496-1 metadata".to_string()))?
+            .map_err(UhdrError::GainMapMetadata)?;
+        debug!("Gain map formulation: {:?}", gain_map_metadata.formulation);
+
+        let mut gain_maps = vec![(gain_map_jpeg, gain_map_metadata)];
+
+        // Further MPF images are additional gain maps only if they carry gain map metadata; they may be e.g. depth maps instead.
+        for (index, jpeg) in gain_map_jpegs.enumerate() {
+            match GainMapMetadata::new_from_gain_map_jpeg_bytes(jpeg.coded_bytes(), jpeg.xmp_bytes()) {
+                Some(Ok(metadata)) => {
+                    debug!("Found additional gain map {}: {:?}", index + 1, metadata);
+                    gain_maps.push((jpeg, metadata));
+                },
+                Some(Err(e)) => debug!("MPF image {} is not a gain map ({}), ignoring", index + 2, e),
+                None => debug!("MPF image {} is not a gain map, ignoring", index + 2),
+            }
+        }
+
+        Ok(gain_maps)
+    }
+
+    /// Replaces the `max_display_boost` given to `new`, e.g. with `authored_max_display_boost` once the gain maps are known.
+    pub fn with_max_display_boost(mut self, max_display_boost: f32) -> Self {
+        self.max_display_boost = max_display_boost;
+        for gain_map in &mut self.gain_maps {
+            gain_map.boost_computer = UhdrBoostComputer::new(&gain_map.metadata, max_display_boost.log2());
+        }
+        self
+    }
+
+    /// Applies exposure compensation of `exposure_ev` stops, multiplying the linear _HDR rendition_ by `2^exposure_ev`.
+    ///
+    /// This scales everything uniformly, including highlights already boosted by the gain map.
+    /// In contrast, `max_display_boost` only changes how much of the gain map is applied, leaving the SDR rendition as is,
+    /// and `target_sdr_white_level` only changes the absolute luminance SDR white maps to.
+    /// In effect, the exposure compensation is equivalent to multiplying `target_sdr_white_level` by `2^exposure_ev`.
+    pub fn with_exposure_ev(mut self, exposure_ev: f32) -> Self {
+        self.exposure_ev = exposure_ev;
+        self
+    }
+
+    /// Overrides the filter of all gain maps, which is otherwise selected by `GainMapFilter::for_extents`.
+    pub fn with_gain_map_filter(mut self, gain_map_filter: GainMapFilter) -> Self {
+        for gain_map in &mut self.gain_maps {
+            gain_map.filter = gain_map_filter;
+        }
+        self
+    }
+
+    /// Stores the linear pixels of the _HDR rendition_ as `f16` instead of `f32` between computing and encoding them,
+    /// reducing that intermediate from 16 to 6 bytes per pixel, for huge images.
+    ///
+    /// The computation itself is still done in `f32`, so the only loss of precision is the rounding to `f16`,
+    /// which is mostly absorbed by the 10-bit quantization of HDR10 output.
+    /// Doesn't apply to `convert_to_avif_with_hook`, since the hook operates on a `FloatImageContent`.
+    #[cfg(feature = "half")]
+    pub fn with_low_memory(mut self, low_memory: bool) -> Self {
+        self.low_memory = low_memory;
+        self
+    }
+
+    /// Encodes HDR AVIF output with the AV1 base quantizer `quantizer` in [0, 255], where lower is higher quality,
+    /// instead of the quality of `with_avif_encode_options`, for precise rate control.
+    /// Doesn't apply to `convert_to_sdr_avif`.
     #[cfg(feature = "avif")]
-    pub fn convert_to_avif<W: Write>(
-        &self,
-        writer: &mut W,
-        target_sdr_white_level: f32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        const DST_COLOR_GAMUT: ColorGamut = ColorGamut::bt2020();
+    pub fn with_avif_quantizer(mut self, quantizer: u8) -> Self {
+        self.avif_quantizer = Some(quantizer);
+        self
+    }
+
+    /// Encodes HDR AVIF output with the quality, speed, range and chroma subsampling of `options` instead of a quality of 100
+    /// at speed 4 in full range and 4:4:4, e.g. faster and smaller for large batches, or at speed 0 for archival.
+    /// The conversion fails if they are out of range, see `AvifEncodeOptions::validate`. Doesn't apply to `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_encode_options(mut self, options: AvifEncodeOptions) -> Self {
+        self.avif_encode_options = options;
+        self
+    }
+
+    /// Rounds to code values with `rounding` when quantizing HDR AVIF output, instead of to the nearest one.
+    /// Doesn't apply to `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Encodes HDR AVIF output with `bit_depth` instead of 10 bits, or picks it from the content with `AvifBitDepth::Auto`.
+    /// 12 bits are always encoded with rav1e directly, as with `with_avif_quantizer`. Doesn't apply to `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_bit_depth(mut self, bit_depth: AvifBitDepth) -> Self {
+        self.avif_bit_depth = bit_depth;
+        self
+    }
+
+    /// Clamps HDR AVIF output to `authored_peak_luminance` instead of the 10,000 nits of PQ, and tags it with that peak
+    /// as the mastering display's in an `mdcv` box. PQ output is tagged with its content light levels in a `clli` box either way.
+    /// Has no effect if there is no gain map to derive the peak from. Doesn't apply to `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_derived_peak(mut self, derive_peak: bool) -> Self {
+        self.derive_peak = derive_peak;
+        self
+    }
+
+    /// Omits the `Exif` item of HDR AVIF output, which otherwise carries the EXIF metadata of the primary image forward,
+    /// e.g. the capture date, camera model and GPS position, or at least the resolution; see `exif::carry_forward_exif_tiff`.
+    #[cfg(feature = "avif")]
+    pub fn with_strip_metadata(mut self, strip_metadata: bool) -> Self {
+        self.strip_metadata = strip_metadata;
+        self
+    }
+
+    /// Downscales HDR and SDR AVIF output that is wider or taller than `RAV1E_MAX_DIMENSION` to fit with `AvifOversize::Downscale`,
+    /// instead of failing. See `avif_extent`.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_oversize(mut self, oversize: AvifOversize) -> Self {
+        self.avif_oversize = oversize;
+        self
+    }
+
+    /// Tags PQ AVIF output with `light_levels`, e.g. the values known from the mastering, instead of the content light levels
+    /// measured from the output. Doesn't apply to HLG or `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_light_levels(mut self, light_levels: Hdr10LightLevels) -> Self {
+        self.avif_light_levels = Some(light_levels);
+        self
+    }
+
+    /// Tags PQ AVIF output with the primaries, white point and luminance range of the display it was mastered on
+    /// in an `mdcv` box, instead of the default `MasteringDisplay` with the peak of `with_derived_peak`, if any.
+    /// Doesn't apply to HLG or `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_mastering_display(mut self, mastering_display: MasteringDisplay) -> Self {
+        self.avif_mastering_display = Some(mastering_display);
+        self
+    }
+
+    /// Encodes HDR AVIF output with HLG instead of PQ with `AvifTransfer::Hlg`, keeping the primaries and matrix
+    /// of `with_avif_color_primaries`.
+    ///
+    /// HLG is relative to the display's peak, so the `target_sdr_white_level` passed to the conversion is ignored for it:
+    /// SDR white is always mapped to `HLG_REFERENCE_WHITE_LUMINANCE`, as BT.2408 recommends, on the reference display
+    /// of `HLG_NOMINAL_PEAK_LUMINANCE`, above which highlights are clipped. Doesn't apply to `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_transfer(mut self, transfer: AvifTransfer) -> Self {
+        self.avif_transfer = transfer;
+        self
+    }
+
+    /// Encodes HDR AVIF output in the color gamut of `primaries` instead of BT.2020, e.g. Display P3 for P3 displays,
+    /// clipping colors outside of it. `AvifColorPrimaries::from_color_gamut` maps a `ColorGamut` to them, as `Custom` primaries
+    /// signalled by an ICC profile if they have no H.273 code point.
+    /// Doesn't apply to `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_color_primaries(mut self, primaries: AvifColorPrimaries) -> Self {
+        self.avif_color_primaries = primaries;
+        self
+    }
+
+    /// Encodes HDR AVIF output as ICtCp instead of Y'C'bC'r with `AvifColorSpace::Ictcp`, tagged with the matrix
+    /// coefficients 14. The conversion fails unless the output is PQ and BT.2020. Doesn't apply to `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_color_space(mut self, color_space: AvifColorSpace) -> Self {
+        self.avif_color_space = color_space;
+        self
+    }
+
+    /// Converts HDR AVIF output in bands of `band_rows` rows, rounded up to an even number, if specified, for huge images:
+    /// Each band is computed, converted to Y'CbCr and written into the AV1 frame before the next one is computed.
+    ///
+    /// This bounds the memory of the conversion itself: Besides the decoded JPEGs, at 3 bytes per pixel of an RGB primary
+    /// image, it takes a band of 22 bytes per pixel, and the AV1 frame of 2 bytes per sample, i.e. 6 bytes per pixel
+    /// for 4:4:4, whereas at once, the linear pixels and the Y'CbCr code values take 22 bytes per pixel of the whole image,
+    /// or 12 with `with_low_memory`. It doesn't bound rav1e's working set while it encodes the frame, which is of
+    /// the whole image either way and depends on the speed and the bit depth.
+    ///
+    /// The pixels are never all known before encoding, so `AvifBitDepth::Auto` is 10 bits, and grayscale input is only
+    /// encoded as monochrome if it can't become colored. The AVIF is always encoded by rav1e directly, as for 12 bits.
+    /// Fails for images with non-square pixels, for output that would have to be downscaled to fit into an AV1 frame,
+    /// and for `convert_to_avif_with_diff_image`. Doesn't apply to `convert_to_avif_with_hook`,
+    /// since the hook operates on the whole image.
+    #[cfg(feature = "avif")]
+    pub fn with_avif_band_rows(mut self, band_rows: Option<usize>) -> Self {
+        self.avif_band_rows = band_rows;
+        self
+    }
+
+    /// Applies the 3D LUT `lut` to HDR AVIF output, after the gamut conversion to BT.2020 and before the PQ encoding.
+    /// See `CubeLut` for the encoding it operates in. The conversion fails unless `with_avif_color_primaries` is BT.2020,
+    /// since the LUT is graded for it. Doesn't apply to `convert_to_sdr_avif`.
+    #[cfg(feature = "avif")]
+    pub fn with_lut(mut self, lut: CubeLut) -> Self {
+        self.lut = Some(lut);
+        self
+    }
+
+    /// Limits the conversion, and hence the output dimensions, to `crop`, in pixels of the primary image as stored,
+    /// i.e. before any stretching to square pixels and before the orientation is applied. Fails if `crop` is empty or doesn't fit within the primary image.
+    pub fn with_crop(mut self, crop: CropRect) -> Result<Self, UhdrError> {
+        let (width, height) = self.uhdr_jpeg.extent();
+        if crop.width == 0
+            || crop.height == 0
+            || crop.x.checked_add(crop.width).is_none_or(|end| end > width)
+            || crop.y.checked_add(crop.height).is_none_or(|end| end > height)
+        {
+            return Err(UhdrError::InvalidOptions(format!("Crop {:?} is outside of the {}x{} image", crop, width, height)));
+        }
+        self.crop = Some(crop);
+        Ok(self)
+    }
 
+    /// Rotates and mirrors the output by `orientation` instead of the EXIF orientation of the primary image,
+    /// e.g. `Orientation::Normal` to keep the pixels as stored.
+    ///
+    /// The orientation applies to the _HDR rendition_ after the gain maps, which are aligned to the stored pixels,
+    /// and to the _SDR rendition_ alike.
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// Describes the input Ultra HDR JPEG and the gain maps to be applied.
+    pub fn info(&self) -> UhdrInfo {
         let (width, height) = self.uhdr_jpeg.extent();
+        let icc_color_space = self.uhdr_jpeg.icc_color_space();
+
+        UhdrInfo {
+            width,
+            height,
+            icc_description: icc_color_space.and_then(|icc| icc.description.clone()),
+            has_icc_profile: icc_color_space.is_some(),
+            gain_maps: self.gain_maps.iter()
+                .map(|gain_map| {
+                    let (width, height) = gain_map.jpeg.extent();
+                    GainMapInfo {
+                        width,
+                        height,
+                        metadata: gain_map.metadata,
+                        filter: gain_map.filter,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// The metadata of the gain map at `index` in `info().gain_maps`, i.e. `0` for the only one of an Ultra HDR JPEG.
+    /// `None` if there is no such gain map, e.g. for an HLG-encoded primary image.
+    pub fn gain_map_metadata(&self, index: usize) -> Option<&GainMapMetadata> {
+        self.gain_maps.get(index).map(|gain_map| &gain_map.metadata)
+    }
+
+    /// The color gamut of the primary image: that of its ICC profile, or sRGB without one.
+    pub fn src_color_gamut(&self) -> ColorGamut {
+        self.src_color_gamut
+    }
+
+    /// The parsed ICC profile of the primary image. `None` if it has none, or if ICC profiles are ignored.
+    pub fn source_icc(&self) -> Option<&IccColorSpace> {
+        self.uhdr_jpeg.icc_color_space()
+    }
 
-        let mut linear_pixels = FloatImageContent::with_extent(width, height);
+    /// The gain map at `index` in `info().gain_maps` as decoded, for inspecting whether it is parsed correctly,
+    /// e.g. by writing it out as an 8-bit image. `None` if there is no such gain map.
+    ///
+    /// The pixels are the encoded recovery values in [0, 1] at the gain map's own resolution, in stored orientation and
+    /// uncropped. Single-channel gain maps have the same value in R, G and B. When converting, these are sampled as they are,
+    /// without the transfer of the gain map JPEG, and only raised to the gain map gamma.
+    pub fn gain_map_preview(&self, index: usize) -> Option<FloatImageContent> {
+        let gain_map = &self.gain_maps.get(index)?.jpeg;
+        let (width, height) = gain_map.extent();
+        let mut content = FloatImageContent::with_extent(width, height);
         for y in 0..height {
             for x in 0..width {
-                // RGB value after EOTF.
-                let in_rgb: FloatPixel = self.uhdr_jpeg.fetch_pixel_linear(x, y).into();
-
-                let gain_map_rgb: FloatPixel = {
-                    let (u, v) = {
-                        let texel_width = 1.0 / width as f32;
-                        let texel_height = 1.0 / height as f32;
-
-                        // Use texel center.
-                        let u_offset = texel_width * 0.5;
-                        let v_offset = texel_height * 0.5;
-                        let u = texel_width * x as f32 + u_offset;
-                        let v = texel_height * y as f32 + v_offset;
-
-                        (u, v)
-                    };
-
-                    self.gain_map_jpeg.sample_bilinear(u, v)
-                        .unwrap_or_else(|| panic!("Failed to sample gain map at ({}, {})", u, v))
-                        .into()
-                };
+                content.set_at(x, y, gain_map.fetch_pixel(x, y).into());
+            }
+        }
+        Some(content)
+    }
 
-                let boosted = self.uhdr_boost_computer.compute_boosted(in_rgb, gain_map_rgb);
+    /// How long decoding took, with the other phases zero.
+    pub fn decode_timings(&self) -> ConversionTimings {
+        self.decode_timings
+    }
 
-                // Map 1 to `target_sdr_white_level` nits.
-                let scaled_boosted = boosted * target_sdr_white_level;
+    /// The dimensions of the output image, which is the crop if any,
+    /// stretched to square pixels if the primary image has non-square ones, see `UhdrJpeg::pixel_aspect_ratio`,
+    /// and with the width and height swapped if the orientation rotates by 90 or 270 degrees.
+    pub fn output_extent(&self) -> (usize, usize) {
+        self.orientation.apply_to_extent(self.square_extent())
+    }
 
-                let [r, g , b] = ColorGamut::convert(scaled_boosted.rgb(), &self.src_color_gamut, &DST_COLOR_GAMUT);
+    /// `output_extent` before the orientation is applied.
+    fn square_extent(&self) -> (usize, usize) {
+        let (width, height) = self.native_extent();
+        let pixel_aspect_ratio = self.uhdr_jpeg.pixel_aspect_ratio();
+        // Only ever upsampled, so that no detail is lost.
+        if pixel_aspect_ratio > 1.0 {
+            (((width as f32 * pixel_aspect_ratio).round() as usize).max(width), height)
+        } else {
+            (width, ((height as f32 / pixel_aspect_ratio).round() as usize).max(height))
+        }
+    }
 
-                linear_pixels.set_at(x, y, FloatPixel::from([r, g, b]));
+    /// The dimensions of AVIF output: `output_extent`, downscaled to fit into `RAV1E_MAX_DIMENSION` with a warning
+    /// for `AvifOversize::Downscale`. Fails with `UhdrError::OutputTooLarge` if it doesn't fit with `AvifOversize::Fail`.
+    #[cfg(feature = "avif")]
+    pub fn avif_extent(&self) -> Result<(usize, usize), UhdrError> {
+        let (width, height) = self.output_extent();
+        if crate::outavif::check_av1_dimensions(width, height).is_err() {
+            match self.avif_oversize {
+                AvifOversize::Fail => {
+                    return Err(UhdrError::OutputTooLarge { width, height, max_dimension: crate::outavif::RAV1E_MAX_DIMENSION });
+                },
+                AvifOversize::Downscale => {
+                    let fitted = crate::outavif::fit_av1_dimensions(width, height);
+                    warn!("Downscaling the output from {}x{} to {:?} to fit into an AV1 frame", width, height, fitted);
+                    return Ok(fitted);
+                },
             }
         }
+        Ok((width, height))
+    }
 
-        crate::outavif::write_hdr10_linear_pixels_to_avif(
-            writer,
-            width as usize,
-            height as usize,
-            &linear_pixels,
-        ).map_err(|e| format!("Failed to write AVIF: {}", e))?;
+    /// The dimensions of the crop if any, or else of the primary image, in its possibly non-square pixels.
+    fn native_extent(&self) -> (usize, usize) {
+        match &self.crop {
+            Some(crop) => (crop.width, crop.height),
+            None => self.uhdr_jpeg.extent(),
+        }
+    }
 
+    #[cfg(feature = "avif")]
+    pub fn convert_to_avif<W: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+    ) -> Result<(), UhdrError> {
+        writer.write_all(&self.convert_to_avif_bytes(target_sdr_white_level)?)?;
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::path::Path;
+    /// Same as `convert_to_avif`, but returns the encoded AVIF file in memory, e.g. for an HTTP response.
+    #[cfg(feature = "avif")]
+    pub fn convert_to_avif_bytes(
+        &self,
+        target_sdr_white_level: f32,
+    ) -> Result<Vec<u8>, UhdrError> {
+        // The AVIF is muxed into a single buffer either way, so this is the only copy.
+        let mut avif_bytes = Vec::new();
+        self.convert_to_avif_timed(&mut avif_bytes, target_sdr_white_level)?;
+        Ok(avif_bytes)
+    }
 
-    #[test]
-    fn it_works() {
-        /// Luminance level in nits for sRGB (1, 1, 1) by Windows convention.
-        const WINDOWS_SDR_WHITE_LEVEL: f32 = 80.0f32;
+    /// Same as `convert_to_avif`, but calls `hook` on the computed pixels right before they are encoded,
+    /// e.g. for custom grading.
+    ///
+    /// The pixels `hook` sees are linear, in nits, and represented in the destination color gamut of `with_avif_color_primaries`,
+    /// i.e. after the gain map boost, gamut conversion and any LUT, but before the PQ encoding.
+    #[cfg(feature = "avif")]
+    pub fn convert_to_avif_with_hook<W: Write, F: FnMut(&mut FloatImageContent)>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+        hook: F,
+    ) -> Result<(), UhdrError> {
+        self.convert_to_avif_with_hook_timed::<FloatImageContent, _, _>(writer, target_sdr_white_level, hook, false)?;
+        Ok(())
+    }
 
-        // FIXME: The maximum brightness of the display in nits.
-        const ASSUMED_DISPLAY_MAX_BRIGHTNESS :f32 = 930.0f32;
+    /// Same as `convert_to_avif`, but also returns how long each phase of the conversion took,
+    /// including the decoding done by `new`.
+    #[cfg(feature = "avif")]
+    pub fn convert_to_avif_timed<W: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+    ) -> Result<ConversionTimings, UhdrError> {
+        self.convert_to_avif_measured(writer, target_sdr_white_level, false).map(|(timings, _)| timings)
+    }
 
-        // FIXME: The maximum available boost supported by a display, at a given point in time.
-        const MAX_DISPLAY_BOOST: f32 = ASSUMED_DISPLAY_MAX_BRIGHTNESS / WINDOWS_SDR_WHITE_LEVEL;
+    /// Same as `convert_to_avif_timed`, but also writes an image of the encoding error to `diff_writer` for QA,
+    /// e.g. to tune the quantizer and the bit depth, and returns the error.
+    ///
+    /// The image is an uncompressed 16-bit TIFF of the absolute differences between the PQ-encoded R'G'B' of the HDR pixels
+    /// and of the decoded AVIF, amplified by `DIFF_IMAGE_AMPLIFICATION`. Black means lossless.
+    /// See `write_hdr10_linear_pixels_to_avif_with_error` about how the AVIF is encoded and decoded.
+    #[cfg(feature = "avif")]
+    pub fn convert_to_avif_with_diff_image<W: Write, D: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+        diff_writer: &mut D,
+    ) -> Result<(ConversionTimings, AvifEncodingError), UhdrError> {
+        let (timings, error) = self.convert_to_avif_measured(writer, target_sdr_white_level, true)?;
+        let error = error.expect("The error is measured if requested");
 
-        let manifest_dir = env!("CARGO_MANIFEST_DIR");
+        crate::outtiff::write_rgb_image_to_tiff(
+            diff_writer,
+            error.width,
+            error.height,
+            &ColorGamut::bt2020(),
+            TiffTransfer::Linear,
+            |x, y| {
+                let [r, g, b] = error.pixels[y * error.width + x].map(|value| value * DIFF_IMAGE_AMPLIFICATION);
+                (r, g, b)
+            },
+        ).map_err(|e| UhdrError::Encode(format!("Failed to write the diff image: {}", e)))?;
 
-        let test_dir_path = Path::new(manifest_dir).join("..").join("..").join("test");
+        Ok((timings, error))
+    }
 
-        let jpeg_file_paths: Vec<_> = std::fs::read_dir(test_dir_path)
-            .unwrap()
-            .filter_map(|entry| {
-                let entry = entry.unwrap();
-                if entry.path().extension().map_or(false, |ext| ext == "jpg" || ext == "jpeg") {
-                    Some(entry.path())
-                } else {
-                    None
-                }
-            })
-            .collect();
+    #[cfg(feature = "avif")]
+    fn convert_to_avif_measured<W: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+        measure_error: bool,
+    ) -> Result<(ConversionTimings, Option<AvifEncodingError>), UhdrError> {
+        if let Some(band_rows) = self.avif_band_rows {
+            if measure_error {
+                return Err(UhdrError::InvalidOptions("The encoding error can't be measured in bands".to_string()));
+            }
+            return self.convert_to_avif_in_bands(writer, target_sdr_white_level, band_rows).map(|timings| (timings, None));
+        }
+        #[cfg(feature = "half")]
+        if self.low_memory {
+            return self.convert_to_avif_with_hook_timed::<HalfImageContent, _, _>(writer, target_sdr_white_level, |_| {}, measure_error);
+        }
+        self.convert_to_avif_with_hook_timed::<FloatImageContent, _, _>(writer, target_sdr_white_level, |_| {}, measure_error)
+    }
 
-        println!("JPEG files found: {:?}", jpeg_file_paths);
+    /// Also returns the encoding error if `measure_error`.
+    #[cfg(feature = "avif")]
+    fn convert_to_avif_with_hook_timed<C: LinearImageContent, W: Write, F: FnMut(&mut C)>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+        mut hook: F,
+        measure_error: bool,
+    ) -> Result<(ConversionTimings, Option<AvifEncodingError>), UhdrError> {
+        let output_extent = self.output_extent();
+        let (width, height) = self.avif_extent()?;
+        let target_sdr_white_level = self.avif_target_sdr_white_level(target_sdr_white_level);
+        // Before the pixel pass, which can take a while.
+        self.check_lut_primaries()?;
+        self.hdr_avif_options(target_sdr_white_level, self.avif_encode_options, None)
+            .validate()
+            .map_err(|e| UhdrError::InvalidOptions(e.to_string()))?;
+        let dst_color_gamut = self.avif_color_primaries.color_gamut();
 
-        for file_path in &jpeg_file_paths {
-            let mut in_file = std::fs::File::open(file_path).unwrap();
+        let pixel_pass_start = Instant::now();
 
-            let uhdr_converter = crate::UhdrConverter::new(&mut in_file, MAX_DISPLAY_BOOST)
-                .expect("Failed to create UHDR converter");
+        let mut linear_pixels: C = self.compute_linear_pixels_as(target_sdr_white_level, &dst_color_gamut);
+        if (width, height) != output_extent {
+            linear_pixels = crate::resample::downsample_bilinear(&linear_pixels, output_extent, (width, height));
+        }
 
-            let mut out_file = {
-                let output_file_name = file_path.file_stem().unwrap().to_str().unwrap();
-                let output_file_name = format!("{}.avif", output_file_name);
+        if let Some(lut) = &self.lut {
+            lut.apply(&mut linear_pixels, width, height);
+        }
 
-                std::fs::File::create(&output_file_name).unwrap()
+        hook(&mut linear_pixels);
+
+        // A grayscale JPEG converts to neutral pixels, unless a color gain map, the LUT or the hook adds color,
+        // so C'b and C'r would all be centered, and only Y' needs to be encoded.
+        let mut encode_options = self.avif_encode_options;
+        if self.uhdr_jpeg.is_grayscale() && is_neutral(&linear_pixels, width, height) {
+            debug!("Encoding the grayscale primary image as a monochrome AVIF");
+            encode_options.chroma = AvifChroma::Monochrome;
+        }
+
+        let pixel_pass = pixel_pass_start.elapsed();
+        let encode_start = Instant::now();
+
+        let exif = self.avif_exif((width, height));
+        let options = self.hdr_avif_options(target_sdr_white_level, encode_options, exif.as_deref());
+        let error = if measure_error {
+            crate::outavif::write_hdr10_linear_pixels_to_avif_with_error(writer, width, height, &linear_pixels, &options)
+                .map(Some)
+        } else {
+            crate::outavif::write_hdr10_linear_pixels_to_avif_with_options(writer, width, height, &linear_pixels, &options)
+                .map(|()| None)
+        }.map_err(|e| UhdrError::Encode(format!("Failed to write AVIF: {}", e)))?;
+
+        let timings = ConversionTimings {
+            pixel_pass,
+            encode: encode_start.elapsed(),
+            ..self.decode_timings()
+        };
+        timings.log();
+
+        Ok((timings, error))
+    }
+
+    /// Fails if a LUT is to be applied to primaries other than the BT.2020 ones it is graded for; see `with_lut`.
+    #[cfg(feature = "avif")]
+    fn check_lut_primaries(&self) -> Result<(), UhdrError> {
+        if self.lut.is_some() && self.avif_color_primaries != AvifColorPrimaries::Bt2020 {
+            return Err(UhdrError::InvalidOptions(format!(
+                "The LUT is graded for BT.2020, but the output primaries are {:?}",
+                self.avif_color_primaries,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Same as `convert_to_avif_timed`, but computes and encodes `band_rows` rows of the output at a time; see `with_avif_band_rows`.
+    #[cfg(feature = "avif")]
+    fn convert_to_avif_in_bands<W: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+        band_rows: usize,
+    ) -> Result<ConversionTimings, UhdrError> {
+        let native_extent = self.native_extent();
+        if native_extent != self.square_extent() {
+            return Err(UhdrError::InvalidOptions("Non-square pixels can't be stretched to square ones in bands".to_string()));
+        }
+        let (width, height) = self.avif_extent()?;
+        if (width, height) != self.output_extent() {
+            return Err(UhdrError::InvalidOptions("The output can't be downscaled to fit into an AV1 frame in bands".to_string()));
+        }
+        self.check_lut_primaries()?;
+        let target_sdr_white_level = self.avif_target_sdr_white_level(target_sdr_white_level);
+
+        // In bands, the pixels aren't known before encoding starts, so whether they are neutral is decided from the inputs.
+        let mut encode_options = self.avif_encode_options;
+        if self.converts_to_neutral() {
+            debug!("Encoding the grayscale primary image as a monochrome AVIF");
+            encode_options.chroma = AvifChroma::Monochrome;
+        }
+        let exif = self.avif_exif((width, height));
+        let options = self.hdr_avif_options(target_sdr_white_level, encode_options, exif.as_deref());
+        let to_encode_error = |e: std::io::Error| UhdrError::Encode(format!("Failed to write AVIF: {}", e));
+        let mut encoder = crate::outavif::Hdr10AvifBandEncoder::new(width, height, &options)
+            .map_err(|e| UhdrError::InvalidOptions(e.to_string()))?;
+        let gamut_converter = ColorGamutConverter::new(&self.src_color_gamut, &self.avif_color_primaries.color_gamut());
+
+        let pixel_pass_start = Instant::now();
+
+        // Even, so that no block of 2x2 pixels of 4:2:0 straddles two bands.
+        let band_rows = band_rows.max(1).next_multiple_of(2).min(height);
+        let mut band = FloatImageContent::with_extent(width, band_rows);
+        for first_row in (0..height).step_by(band_rows) {
+            let rows = band_rows.min(height - first_row);
+            for y in 0..rows {
+                for x in 0..width {
+                    let native = self.orientation.stored_coordinates((x, first_row + y), native_extent);
+                    band.set_at(x, y, self.compute_linear_pixel(native, target_sdr_white_level, &gamut_converter));
+                }
+            }
+            if let Some(lut) = &self.lut {
+                lut.apply(&mut band, width, rows);
+            }
+            encoder.write_band(&band, rows).map_err(to_encode_error)?;
+        }
+
+        let pixel_pass = pixel_pass_start.elapsed();
+        let encode_start = Instant::now();
+
+        encoder.finish(writer).map_err(to_encode_error)?;
+
+        let timings = ConversionTimings {
+            pixel_pass,
+            encode: encode_start.elapsed(),
+            ..self.decode_timings()
+        };
+        timings.log();
+
+        Ok(timings)
+    }
+
+    /// Whether every pixel converts to a neutral one: The primary image is grayscale, and so is every gain map,
+    /// with the same metadata for all channels, and there is no LUT, which could add color.
+    #[cfg(feature = "avif")]
+    fn converts_to_neutral(&self) -> bool {
+        let is_neutral = |values: [f32; 3]| values.iter().all(|&value| value == values[0]);
+        self.uhdr_jpeg.is_grayscale()
+            && self.lut.is_none()
+            && self.gain_maps.iter().all(|gain_map| {
+                let metadata = &gain_map.metadata;
+                gain_map.jpeg.is_grayscale()
+                    && [metadata.gain_map_min, metadata.gain_map_max, metadata.gamma, metadata.offset_sdr, metadata.offset_hdr].into_iter().all(is_neutral)
+            })
+    }
+
+    /// The luminance SDR white is mapped to in HDR AVIF output: `target_sdr_white_level` for PQ.
+    /// HLG is relative, so SDR white is at its reference white whatever the requested level.
+    #[cfg(feature = "avif")]
+    fn avif_target_sdr_white_level(&self, target_sdr_white_level: f32) -> f32 {
+        match self.avif_transfer {
+            AvifTransfer::Pq => target_sdr_white_level,
+            AvifTransfer::Hlg => crate::outavif::HLG_REFERENCE_WHITE_LUMINANCE,
+        }
+    }
+
+    /// The TIFF block of the `Exif` item of HDR AVIF output of `(width, height)`, if any.
+    #[cfg(feature = "avif")]
+    fn avif_exif(&self, (width, height): (usize, usize)) -> Option<Vec<u8>> {
+        // For print workflows. The crop doesn't change it, but stretching to square pixels does, and so does any rotation.
+        let resolution = self.uhdr_jpeg.resolution().map(|mut resolution| {
+            let (native_width, native_height) = self.native_extent();
+            let (stored_width, stored_height) = self.orientation.apply_to_extent((width, height));
+            resolution.x *= stored_width as f32 / native_width as f32;
+            resolution.y *= stored_height as f32 / native_height as f32;
+            if self.orientation.swaps_dimensions() {
+                std::mem::swap(&mut resolution.x, &mut resolution.y);
+            }
+            resolution
+        });
+        match self.uhdr_jpeg.exif_bytes() {
+            _ if self.strip_metadata => None,
+            Some(exif_bytes) => Some(crate::exif::carry_forward_exif_tiff(exif_bytes, resolution)),
+            None => resolution.map(|resolution| resolution.to_exif_tiff()),
+        }
+    }
+
+    /// The options HDR AVIF output is encoded with, with `encode_options` and `exif`.
+    #[cfg(feature = "avif")]
+    fn hdr_avif_options<'a>(
+        &self,
+        target_sdr_white_level: f32,
+        encode_options: AvifEncodeOptions,
+        exif: Option<&'a [u8]>,
+    ) -> crate::outavif::Hdr10AvifOptions<'a> {
+        crate::outavif::Hdr10AvifOptions {
+            quantizer: self.avif_quantizer,
+            encode: encode_options,
+            exif,
+            rounding: self.rounding,
+            bit_depth: self.avif_bit_depth,
+            peak_luminance: if self.derive_peak { self.authored_peak_luminance(target_sdr_white_level) } else { None },
+            light_levels: self.avif_light_levels,
+            mastering_display: self.avif_mastering_display,
+            transfer: self.avif_transfer,
+            primaries: self.avif_color_primaries,
+            color_space: self.avif_color_space,
+        }
+    }
+
+    /// The peak luminance in nits the _HDR rendition_ was authored for: SDR white at `target_sdr_white_level`,
+    /// boosted by `2^hdr_capacity_max` of each gain map, and by the exposure compensation.
+    /// `None` if there is no gain map, i.e. for an HLG-encoded primary image.
+    pub fn authored_peak_luminance(&self, target_sdr_white_level: f32) -> Option<f32> {
+        if self.gain_maps.is_empty() {
+            return None;
+        }
+        let log2_boost: f32 = self.gain_maps.iter().map(|gain_map| gain_map.metadata.hdr_capacity_max).sum();
+        Some((log2_boost + self.exposure_ev).exp2() * target_sdr_white_level)
+    }
+
+    /// The smallest `max_display_boost` at which every gain map is applied fully, i.e. for the whole _HDR rendition_ the author
+    /// intended: `2^hdr_capacity_max` of the gain map with the largest. `None` if there is no gain map, i.e. for an HLG-encoded
+    /// primary image.
+    pub fn authored_max_display_boost(&self) -> Option<f32> {
+        self.gain_maps.iter()
+            .map(|gain_map| gain_map.metadata.hdr_capacity_max)
+            .reduce(f32::max)
+            .map(f32::exp2)
+    }
+
+    /// Suggests a `target_sdr_white_level` for users who don't know which to use, from the luminance histogram of the primary image,
+    /// i.e. the _SDR rendition_, within the crop: The one that places its median luminance at `AUTO_WHITE_MEDIAN_LUMINANCE`,
+    /// clamped to [`MIN_AUTO_WHITE_LEVEL`, `MAX_AUTO_WHITE_LEVEL`]. Dark images get a higher white level than bright ones.
+    ///
+    /// Large images are sampled on a grid of at most `AUTO_WHITE_MAX_SAMPLES` pixels.
+    pub fn suggest_target_sdr_white_level(&self) -> f32 {
+        let (width, height) = self.native_extent();
+        let (x_offset, y_offset) = self.crop.map_or((0, 0), |crop| (crop.x, crop.y));
+        let step = ((width * height) as f64 / AUTO_WHITE_MAX_SAMPLES as f64).sqrt().ceil().max(1.0) as usize;
+
+        let to_bt2020 = ColorGamutConverter::new(&self.src_color_gamut, &ColorGamut::bt2020());
+        let mut histogram = crate::stats::LuminanceHistogram::new();
+        for y in (0..height).step_by(step) {
+            for x in (0..width).step_by(step) {
+                let rgb = self.uhdr_jpeg.fetch_pixel_linear(x + x_offset, y + y_offset);
+                let [r, g, b] = to_bt2020.convert(&rgb);
+                histogram.add(0.2627 * r + 0.6780 * g + 0.0593 * b);
+            }
+        }
+        histogram.suggest_target_sdr_white_level()
+    }
+
+    /// Computes the _HDR rendition_ as `convert_to_avif` does, but only gathers its luminance statistics instead of encoding it.
+    pub fn compute_luminance_stats(&self, target_sdr_white_level: f32) -> LuminanceStats {
+        let linear_pixels: FloatImageContent = self.compute_linear_pixels_as(target_sdr_white_level, &ColorGamut::bt2020());
+        LuminanceStats::from_linear_pixels(&linear_pixels, target_sdr_white_level)
+    }
+
+    /// Re-encodes the primary image, i.e. the authored _SDR rendition_, as an 8-bit sRGB AVIF without any of the HDR processing:
+    /// Neither the gain maps, `max_display_boost` nor the exposure compensation are applied. Only the crop is.
+    ///
+    /// If the primary image has no ICC profile, it is assumed to be sRGB and its decoded pixels are passed through as is.
+    /// Otherwise they are converted to sRGB, clipping colors outside of the sRGB gamut.
+    /// An HLG-encoded primary image is clipped at HLG reference white.
+    #[cfg(feature = "avif")]
+    pub fn convert_to_sdr_avif<W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), UhdrError> {
+        self.write_srgb8_avif(writer, self.compute_srgb8_pixels())
+    }
+
+    /// Tone maps the _HDR rendition_ with `operator` into an 8-bit sRGB AVIF, for SDR displays when the authored
+    /// _SDR rendition_ is not wanted; see `compute_tone_mapped_srgb8_pixels`.
+    #[cfg(feature = "avif")]
+    pub fn convert_to_tone_mapped_avif<W: Write>(
+        &self,
+        writer: &mut W,
+        operator: ToneMapOperator,
+    ) -> Result<(), UhdrError> {
+        self.write_srgb8_avif(writer, self.compute_tone_mapped_srgb8_pixels(operator))
+    }
+
+    /// Computes the _HDR rendition_ as `convert_to_avif` does, then tone maps it with `operator` into 8-bit sRGB.
+    ///
+    /// The pixels are converted to the sRGB gamut in linear light relative to SDR white, clipping colors outside of it,
+    /// and the brightest channel of the rendition is mapped to white before the sRGB OETF is applied.
+    /// Without any headroom, e.g. with a `max_display_boost` of 1, the peak is SDR white.
+    pub fn compute_tone_mapped_srgb8_pixels(&self, operator: ToneMapOperator) -> Vec<[u8; 3]> {
+        let linear_pixels: FloatImageContent = self.compute_linear_pixels_as(1.0, &ColorGamut::srgb());
+        let peak = linear_pixels.pixels().iter()
+            .flat_map(|pixel| *pixel.rgb())
+            .fold(1.0f32, f32::max);
+
+        linear_pixels.pixels().iter()
+            .map(|pixel| pixel.rgb().map(|value| {
+                let mapped = transfer::srgb_oetf(operator.apply(value, peak));
+                (mapped * 255.0).round() as u8
+            }))
+            .collect()
+    }
+
+    /// Writes pixels at `output_extent` as an 8-bit sRGB AVIF, downscaling them to `avif_extent` if needed.
+    #[cfg(feature = "avif")]
+    fn write_srgb8_avif<W: Write>(
+        &self,
+        writer: &mut W,
+        mut srgb_pixels: Vec<[u8; 3]>,
+    ) -> Result<(), UhdrError> {
+        let output_extent = self.output_extent();
+        let (width, height) = self.avif_extent()?;
+
+        if (width, height) != output_extent {
+            let content = srgb8_to_float_image_content(&srgb_pixels, output_extent);
+            srgb_pixels = float_image_content_to_srgb8(&crate::resample::downsample_bilinear(&content, output_extent, (width, height)));
+        }
+
+        crate::outavif::write_srgb8_pixels_to_avif(
+            writer,
+            width,
+            height,
+            &srgb_pixels,
+        ).map_err(|e| UhdrError::Encode(format!("Failed to write AVIF: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Re-encodes the primary image as a lossy 8-bit sRGB WebP, for targets that support neither AVIF nor Ultra HDR JPEGs.
+    ///
+    /// Like `convert_to_sdr_avif`, this is the authored _SDR rendition_, to which only the crop applies.
+    #[cfg(feature = "webp")]
+    pub fn convert_to_webp<W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), UhdrError> {
+        let (width, height) = self.output_extent();
+
+        let srgb_pixels = self.compute_srgb8_pixels();
+
+        crate::outwebp::write_srgb8_pixels_to_webp(
+            writer,
+            width,
+            height,
+            &srgb_pixels,
+        ).map_err(|e| UhdrError::Encode(format!("Failed to write WebP: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Writes an Ultra HDR JPEG with the primary image and the gain map as they were coded in the input, with freshly written
+    /// MPF and XMP metadata, e.g. to repair a file whose MPF image roles had to be overridden by `DecodeOptions`.
+    ///
+    /// The gain map isn't re-derived nor re-encoded, so its decoded values are exactly those of the input.
+    /// For the same reason, this fails if a crop is set, and the HDR parameters don't apply.
+    /// Fails unless there is exactly one gain map.
+    pub fn convert_to_uhdr_jpeg<W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), UhdrError> {
+        if self.crop.is_some() {
+            return Err(UhdrError::InvalidOptions("Cropping would require re-encoding the primary image and the gain map".to_string()));
+        }
+        let [gain_map] = self.gain_maps.as_slice() else {
+            return Err(UhdrError::InvalidOptions(format!("Expected exactly 1 gain map to write, but there are {}", self.gain_maps.len())));
+        };
+
+        crate::mux::write_uhdr_jpeg(
+            writer,
+            self.uhdr_jpeg.coded_bytes(),
+            gain_map.jpeg.coded_bytes(),
+            Some(&gain_map.metadata),
+        ).map_err(|e| UhdrError::Encode(format!("Failed to write Ultra HDR JPEG: {}", e)))
+    }
+
+    /// Converts to an uncompressed 16-bit BT.2020 TIFF encoded with `transfer` rather than PQ.
+    ///
+    /// Since integer samples can't exceed 1, the values are normalized so that the nominal peak of the HDR rendition,
+    /// `target_sdr_white_level * max_display_boost` nits, maps to the maximum sample value. Anything brighter is clipped.
+    pub fn convert_to_tiff<W: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+        transfer: TiffTransfer,
+    ) -> Result<(), UhdrError> {
+        #[cfg(feature = "half")]
+        if self.low_memory {
+            return self.convert_to_tiff_as::<HalfImageContent, _>(writer, target_sdr_white_level, transfer);
+        }
+        self.convert_to_tiff_as::<FloatImageContent, _>(writer, target_sdr_white_level, transfer)
+    }
+
+    fn convert_to_tiff_as<C: LinearImageContent, W: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+        transfer: TiffTransfer,
+    ) -> Result<(), UhdrError> {
+        const DST_COLOR_GAMUT: ColorGamut = ColorGamut::bt2020();
+
+        let (width, height) = self.output_extent();
+
+        let linear_pixels: C = self.compute_linear_pixels_as(target_sdr_white_level, &DST_COLOR_GAMUT);
+
+        let peak_nits = target_sdr_white_level * self.max_display_boost;
+
+        crate::outtiff::write_rgb_image_to_tiff(
+            writer,
+            width,
+            height,
+            &DST_COLOR_GAMUT,
+            transfer,
+            |x, y| {
+                let [r, g, b] = (linear_pixels.get_at(x, y) / peak_nits).to_serialized_rgb();
+                (r, g, b)
+            },
+        ).map_err(|e| UhdrError::Encode(format!("Failed to write TIFF: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Writes the _HDR rendition_ as a 16-bit BT.2020 PNG with `transfer`: PQ of the absolute luminance, like HDR10,
+    /// or linear with the nominal peak, `target_sdr_white_level` times the max display boost, as the maximum sample value,
+    /// like `convert_to_tiff`.
+    #[cfg(feature = "png")]
+    pub fn convert_to_png<W: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+        transfer: PngTransfer,
+    ) -> Result<(), UhdrError> {
+        const DST_COLOR_GAMUT: ColorGamut = ColorGamut::bt2020();
+
+        let (width, height) = self.output_extent();
+
+        let linear_pixels: FloatImageContent = self.compute_linear_pixels_as(target_sdr_white_level, &DST_COLOR_GAMUT);
+
+        let max_nits = match transfer {
+            PngTransfer::Pq => 10000.0,
+            PngTransfer::Linear => target_sdr_white_level * self.max_display_boost,
+        };
+
+        crate::outpng::write_bt2020_rgb_image_to_png(
+            writer,
+            width,
+            height,
+            transfer,
+            |x, y| {
+                let [r, g, b] = (linear_pixels.get_at(x, y) / max_nits).to_serialized_rgb();
+                (r, g, b)
+            },
+        ).map_err(|e| UhdrError::Encode(format!("Failed to write PNG: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Writes the _HDR rendition_ as a PIZ-compressed 32-bit float OpenEXR, linear and represented in `dst_color_gamut`,
+    /// whose chromaticities it is tagged with.
+    ///
+    /// The values are relative, with 1.0 being SDR white, as is customary for EXR, so there is no target SDR white level.
+    #[cfg(feature = "exr")]
+    pub fn convert_to_exr<W: Write>(
+        &self,
+        writer: &mut W,
+        dst_color_gamut: &ColorGamut,
+    ) -> Result<(), UhdrError> {
+        // Mapping SDR white to 1 "nit" leaves it at 1.
+        let linear_pixels: FloatImageContent = self.compute_linear_pixels_as(1.0, dst_color_gamut);
+
+        crate::outexr::write_linear_pixels_to_exr(writer, &linear_pixels, dst_color_gamut)
+            .map_err(|e| UhdrError::Encode(format!("Failed to write EXR: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Writes the _HDR rendition_ as a run-length encoded Radiance RGBE `.hdr`, linear and represented in `dst_color_gamut`,
+    /// whose chromaticities it is tagged with.
+    ///
+    /// The values are relative, with 1.0 being SDR white, like those of `convert_to_exr`.
+    #[cfg(feature = "hdr")]
+    pub fn convert_to_hdr<W: Write>(
+        &self,
+        writer: &mut W,
+        dst_color_gamut: &ColorGamut,
+    ) -> Result<(), UhdrError> {
+        let linear_pixels: FloatImageContent = self.compute_linear_pixels_as(1.0, dst_color_gamut);
+
+        crate::outradiance::write_linear_pixels_to_hdr(writer, &linear_pixels, dst_color_gamut)
+            .map_err(|e| UhdrError::Encode(format!("Failed to write HDR: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Writes the _HDR rendition_ as a 10-bit HEVC HEIF in BT.2020 PQ, the HDR10 encoding of `convert_to_avif`,
+    /// at full quality. The AVIF-specific settings don't apply.
+    #[cfg(all(feature = "heif", feature = "avif"))]
+    pub fn convert_to_heif<W: Write>(
+        &self,
+        writer: &mut W,
+        target_sdr_white_level: f32,
+    ) -> Result<(), UhdrError> {
+        const DST_COLOR_GAMUT: ColorGamut = ColorGamut::bt2020();
+
+        let (width, height) = self.output_extent();
+
+        let linear_pixels: FloatImageContent = self.compute_linear_pixels_as(target_sdr_white_level, &DST_COLOR_GAMUT);
+
+        crate::outheif::write_hdr10_rgb_image_to_heif(
+            writer,
+            width,
+            height,
+            |x, y| {
+                let [r, g, b] = linear_pixels.get_at(x, y).rgb().map(|nits| crate::transfer::st2084_oetf((nits / 10000.0).clamp(0.0, 1.0)));
+                (r, g, b)
+            },
+        ).map_err(|e| UhdrError::Encode(format!("Failed to write HEIF: {}", e)))?;
+
+        Ok(())
+    }
+}
+
+impl UhdrConverter {
+    /// Computes the boosted _HDR rendition_ in linear light, in nits, represented in `dst_color_gamut`.
+    /// Only the crop is computed if any, with `(0, 0)` being its top-left corner.
+    #[cfg(test)]
+    fn compute_linear_pixels(
+        &self,
+        target_sdr_white_level: f32,
+        dst_color_gamut: &ColorGamut,
+    ) -> FloatImageContent {
+        self.compute_linear_pixels_as(target_sdr_white_level, dst_color_gamut)
+    }
+
+    /// Same as `compute_linear_pixels`, but stores the pixels in `C`.
+    fn compute_linear_pixels_as<C: LinearImageContent>(
+        &self,
+        target_sdr_white_level: f32,
+        dst_color_gamut: &ColorGamut,
+    ) -> C {
+        let (width, height) = self.native_extent();
+        let gamut_converter = ColorGamutConverter::new(&self.src_color_gamut, dst_color_gamut);
+
+        let mut linear_pixels = C::with_extent(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                linear_pixels.set_at(x, y, self.compute_linear_pixel((x, y), target_sdr_white_level, &gamut_converter));
+            }
+        }
+
+        self.to_output_pixels(linear_pixels)
+    }
+
+    /// The linear pixel of the _HDR rendition_ at `(x, y)` of the native extent, i.e. relative to the crop if any,
+    /// converted by `gamut_converter`. See `compute_linear_pixels`.
+    fn compute_linear_pixel(&self, (x, y): (usize, usize), target_sdr_white_level: f32, gamut_converter: &ColorGamutConverter) -> FloatPixel {
+        let (full_width, full_height) = self.uhdr_jpeg.extent();
+        let (x_offset, y_offset) = self.crop.map_or((0, 0), |crop| (crop.x, crop.y));
+
+        // Coordinates in the primary image.
+        let (src_x, src_y) = (x + x_offset, y + y_offset);
+
+        // RGB value after EOTF.
+        let in_rgb: FloatPixel = self.uhdr_jpeg.fetch_pixel_linear(src_x, src_y).into();
+
+        let texel_width = 1.0 / full_width as f32;
+        let texel_height = 1.0 / full_height as f32;
+
+        // Normalized over the full primary image, so that a crop samples the matching region of the gain map.
+        let (u, v) = {
+            // Use texel center.
+            let u_offset = texel_width * 0.5;
+            let v_offset = texel_height * 0.5;
+            let u = texel_width * src_x as f32 + u_offset;
+            let v = texel_height * src_y as f32 + v_offset;
+
+            (u, v)
+        };
+
+        let mut boosted = in_rgb;
+        for gain_map in &self.gain_maps {
+            let sampled = match gain_map.filter {
+                GainMapFilter::Bilinear => gain_map.jpeg.sample_bilinear(u, v),
+                // The footprint of a primary image pixel.
+                GainMapFilter::Area => gain_map.jpeg.sample_area(u, v, texel_width, texel_height),
+            };
+            let gain_map_rgb: FloatPixel = sampled
+                .unwrap_or_else(|| panic!("Failed to sample gain map at ({}, {})", u, v))
+                .into();
+
+            boosted = gain_map.boost_computer.compute_boosted(boosted, gain_map_rgb);
+        }
+
+        // Apply exposure compensation, then map 1 to `target_sdr_white_level` nits.
+        let scaled_boosted = boosted * (self.exposure_ev.exp2() * target_sdr_white_level);
+
+        let [r, g , b] = gamut_converter.convert(scaled_boosted.rgb());
+
+        FloatPixel::from([r, g, b])
+    }
+
+    /// Stretches `content` of the native extent to square pixels if they aren't, then applies the orientation.
+    fn to_output_pixels<C: LinearImageContent>(&self, content: C) -> C {
+        let (native_extent, square_extent) = (self.native_extent(), self.square_extent());
+        let content = if native_extent == square_extent {
+            content
+        } else {
+            debug!("Stretching {:?} to {:?} for square pixels", native_extent, square_extent);
+            crate::resample::resample_bilinear(&content, native_extent, square_extent)
+        };
+        self.orientation.apply(content, square_extent)
+    }
+}
+
+impl UhdrConverter {
+    /// Computes the primary image in 8-bit sRGB. Only the crop is computed if any, like `compute_linear_pixels`.
+    #[cfg(any(feature = "avif", feature = "webp"))]
+    fn compute_srgb8_pixels(&self) -> Vec<[u8; 3]> {
+        const DST_COLOR_GAMUT: ColorGamut = ColorGamut::srgb();
+
+        let (width, height) = self.native_extent();
+        let (x_offset, y_offset) = self.crop.map_or((0, 0), |crop| (crop.x, crop.y));
+
+        // Without an ICC profile, the primary image is already sRGB.
+        let passthrough = self.uhdr_jpeg.icc_color_space().is_none();
+        let gamut_converter = ColorGamutConverter::new(&self.src_color_gamut, &DST_COLOR_GAMUT);
+
+        let mut srgb_pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x + x_offset, y + y_offset);
+
+                let rgb = if passthrough {
+                    self.uhdr_jpeg.fetch_pixel(src_x, src_y)
+                } else {
+                    let linear = self.uhdr_jpeg.fetch_pixel_linear(src_x, src_y);
+                    gamut_converter.convert(&linear)
+                        .map(|value| transfer::srgb_oetf(value.clamp(0.0, 1.0)))
+                };
+
+                srgb_pixels.push(rgb.map(|value| (value * 255.0).round() as u8));
+            }
+        }
+
+        if self.native_extent() == self.square_extent() && self.orientation == Orientation::Normal {
+            return srgb_pixels;
+        }
+        let content = srgb8_to_float_image_content(&srgb_pixels, (width, height));
+        float_image_content_to_srgb8(&self.to_output_pixels(content))
+    }
+}
+
+/// 8-bit pixels as values in [0, 255] in float pixels, to be resampled like `compute_linear_pixels` are.
+#[cfg(any(feature = "avif", feature = "webp"))]
+fn srgb8_to_float_image_content(srgb_pixels: &[[u8; 3]], (width, height): (usize, usize)) -> FloatImageContent {
+    let mut content = FloatImageContent::with_extent(width, height);
+    for (index, rgb) in srgb_pixels.iter().enumerate() {
+        content.set_at(index % width, index / width, rgb.map(f32::from).into());
+    }
+    content
+}
+
+/// The inverse of `srgb8_to_float_image_content`.
+#[cfg(any(feature = "avif", feature = "webp"))]
+fn float_image_content_to_srgb8(content: &FloatImageContent) -> Vec<[u8; 3]> {
+    content.pixels().iter()
+        .map(|pixel| pixel.rgb().map(|value| value.round().clamp(0.0, 255.0) as u8))
+        .collect()
+}
+
+/// The largest difference between the channels of a pixel, relative to its brightest one, for `is_neutral`.
+/// Below a code value of 12-bit PQ, so only rounding in the gamut conversion is tolerated.
+#[cfg(feature = "avif")]
+const NEUTRAL_TOLERANCE: f32 = 1e-3;
+
+/// Whether all pixels of `content` have equal R, G and B, up to `NEUTRAL_TOLERANCE`.
+#[cfg(feature = "avif")]
+fn is_neutral<C: LinearImageContent>(content: &C, width: usize, height: usize) -> bool {
+    (0..height).all(|y| (0..width).all(|x| {
+        let [r, g, b] = content.get_at(x, y).to_serialized_rgb();
+        let (min, max) = (r.min(g).min(b), r.max(g).max(b));
+        max - min <= max.abs() * NEUTRAL_TOLERANCE
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "avif")]
+    use std::path::Path;
+
+    use super::*;
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn it_works() {
+        /// Luminance level in nits for sRGB (1, 1, 1) by Windows convention.
+        const WINDOWS_SDR_WHITE_LEVEL: f32 = 80.0f32;
+
+        // FIXME: The maximum brightness of the display in nits.
+        const ASSUMED_DISPLAY_MAX_BRIGHTNESS :f32 = 930.0f32;
+
+        // FIXME: The maximum available boost supported by a display, at a given point in time.
+        const MAX_DISPLAY_BOOST: f32 = ASSUMED_DISPLAY_MAX_BRIGHTNESS / WINDOWS_SDR_WHITE_LEVEL;
+
+        let manifest_dir = env!("CARGO_MANIFEST_DIR");
+
+        let test_dir_path = Path::new(manifest_dir).join("..").join("..").join("test");
+
+        let jpeg_file_paths: Vec<_> = std::fs::read_dir(test_dir_path)
+            .unwrap()
+            .filter_map(|entry| {
+                let entry = entry.unwrap();
+                if entry.path().extension().map_or(false, |ext| ext == "jpg" || ext == "jpeg") {
+                    Some(entry.path())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        println!("JPEG files found: {:?}", jpeg_file_paths);
+
+        for file_path in &jpeg_file_paths {
+            let mut in_file = std::fs::File::open(file_path).unwrap();
+
+            let uhdr_converter = UhdrConverter::new(&mut in_file, MAX_DISPLAY_BOOST)
+                .expect("Failed to create UHDR converter");
+
+            let mut out_file = {
+                let output_file_name = file_path.file_stem().unwrap().to_str().unwrap();
+                let output_file_name = format!("{}.avif", output_file_name);
+
+                std::fs::File::create(&output_file_name).unwrap()
+            };
+            
+            uhdr_converter.convert_to_avif(&mut out_file, WINDOWS_SDR_WHITE_LEVEL)
+                .expect("Failed to convert UHDR JPEG to AVIF");
+        }
+    }
+
+    #[test]
+    fn crop_matches_region_of_full_conversion() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(32, 24, 8, 6);
+        let dst_color_gamut = ColorGamut::bt2020();
+
+        let full_converter = UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap();
+        let full = full_converter.compute_linear_pixels(80.0, &dst_color_gamut);
+
+        let crop = CropRect { x: 5, y: 3, width: 17, height: 11 };
+        let cropped_converter = full_converter.clone().with_crop(crop).unwrap();
+        assert_eq!(cropped_converter.output_extent(), (crop.width, crop.height));
+        let cropped = cropped_converter.compute_linear_pixels(80.0, &dst_color_gamut);
+
+        for y in 0..crop.height {
+            for x in 0..crop.width {
+                assert_eq!(cropped.get_at(x, y), full.get_at(crop.x + x, crop.y + y), "Mismatch at ({}, {})", x, y);
+            }
+        }
+
+        assert!(full_converter.clone().with_crop(CropRect { x: 20, y: 0, width: 13, height: 1 }).is_err());
+        assert!(full_converter.clone().with_crop(CropRect { x: 0, y: 0, width: 0, height: 1 }).is_err());
+        // The end would overflow.
+        assert!(full_converter.clone().with_crop(CropRect { x: usize::MAX, y: 0, width: 1, height: 1 }).is_err());
+        assert!(full_converter.with_crop(CropRect { x: 0, y: 1, width: 1, height: usize::MAX }).is_err());
+    }
+
+    #[test]
+    fn exposure_ev_scales_linear_luminance() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(16, 8, 8, 4);
+        let dst_color_gamut = ColorGamut::bt2020();
+
+        let converter = UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap();
+        let base = converter.clone().with_exposure_ev(0.0).compute_linear_pixels(80.0, &dst_color_gamut);
+        let plus_one = converter.with_exposure_ev(1.0).compute_linear_pixels(80.0, &dst_color_gamut);
+
+        for y in 0..8 {
+            for x in 0..16 {
+                let expected = base.get_at(x, y) * 2.0;
+                let actual = plus_one.get_at(x, y);
+                for channel in 0..3 {
+                    assert!((actual[channel] - expected[channel]).abs() <= expected[channel].abs() * 1e-5, "({}, {}): {:?} != {:?}", x, y, actual, expected);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn hook_modifies_pixels_before_encoding() {
+        let converter = UhdrConverter::new(&mut testutil::gradient_uhdr_jpeg(16, 8, 8, 4).as_slice(), 4.0).unwrap();
+        let other_converter = UhdrConverter::new(&mut testutil::gradient_uhdr_jpeg(16, 8, 4, 2).as_slice(), 2.0).unwrap();
+
+        let black_out = |content: &mut FloatImageContent| {
+            for pixel in content.pixels_mut() {
+                *pixel = *pixel * 0.0;
+            }
+        };
+
+        let mut unmodified = Vec::new();
+        converter.convert_to_avif(&mut unmodified, 80.0).unwrap();
+        assert_eq!(converter.convert_to_avif_bytes(80.0).unwrap(), unmodified);
+
+        let mut hooked = Vec::new();
+        converter.convert_to_avif_with_hook(&mut hooked, 80.0, black_out).unwrap();
+
+        let mut other_hooked = Vec::new();
+        other_converter.convert_to_avif_with_hook(&mut other_hooked, 80.0, black_out).unwrap();
+
+        assert_ne!(hooked, unmodified);
+        // Both are all black regardless of the source.
+        assert_eq!(hooked, other_hooked);
+    }
+
+    #[test]
+    fn multiple_gain_maps_are_applied_in_sequence() {
+        let primary = testutil::TestImage::from_fn(8, 8, |_, _| [128, 128, 128]);
+        let full_boost = testutil::TestImage::from_fn(4, 4, |_, _| [255, 255, 255]);
+        // Doubles the luminance at full boost.
+        let params = testutil::TestGainMapParams { gain_map_max: 1.0, ..Default::default() };
+        let gain_map_bytes = testutil::encode_jpeg(&full_boost, &[testutil::xmp_app_segment(&params.to_xmp())]);
+
+        let single = testutil::build_uhdr_jpeg_from_parts(&primary, &[gain_map_bytes.clone()]);
+        let double = testutil::build_uhdr_jpeg_from_parts(&primary, &[gain_map_bytes.clone(), gain_map_bytes.clone()]);
+        // A further image without gain map metadata, e.g. a depth map, is ignored.
+        let with_depth_map = testutil::build_uhdr_jpeg_from_parts(&primary, &[
+            gain_map_bytes.clone(),
+            testutil::encode_jpeg(&full_boost, &[]),
+        ]);
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        let convert = |jpeg_bytes: &[u8]| {
+            let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+            (converter.gain_maps.len(), converter.compute_linear_pixels(1.0, &dst_color_gamut).get_at(4, 4).g())
+        };
+
+        let (single_count, single_value) = convert(&single);
+        let (double_count, double_value) = convert(&double);
+        let (depth_count, depth_value) = convert(&with_depth_map);
+
+        assert_eq!(single_count, 1);
+        assert_eq!(double_count, 2);
+        assert_eq!(depth_count, 1);
+        assert_eq!(depth_value, single_value);
+
+        // `(x + offset) * 2 - offset` applied twice.
+        let offset = params.offset_sdr;
+        let sdr = (single_value + offset) / 2.0 - offset;
+        let expected = ((sdr + offset) * 2.0 - offset + offset) * 2.0 - offset;
+        assert!((double_value - expected).abs() < 1e-4, "{} != {}", double_value, expected);
+    }
+
+    #[test]
+    fn authored_max_display_boost_applies_the_gain_map_fully() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(16, 8, 8, 4);
+        let partial = UhdrConverter::new(&mut &jpeg_bytes[..], 1.5).unwrap();
+        // `hdr_capacity_max` is 2 stops.
+        assert_eq!(partial.authored_max_display_boost(), Some(4.0));
+
+        let full = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        let boosted = partial.clone().with_max_display_boost(4.0);
+        let dst_color_gamut = ColorGamut::bt2020();
+        assert!(boosted.compute_linear_pixels(80.0, &dst_color_gamut).pixels() == full.compute_linear_pixels(80.0, &dst_color_gamut).pixels());
+        assert!(partial.compute_linear_pixels(80.0, &dst_color_gamut).pixels() != full.compute_linear_pixels(80.0, &dst_color_gamut).pixels());
+    }
+
+    #[test]
+    fn hdr_base_rendition_is_mapped_down_to_sdr() {
+        // An HDR base at SDR white whose gain map takes it down 2 stops to the SDR rendition.
+        let white = testutil::TestImage::from_fn(8, 8, |_, _| [255; 3]);
+        let params = testutil::TestGainMapParams { base_rendition_is_hdr: true, ..Default::default() };
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&white, &white, params);
+
+        let offset = params.offset_sdr;
+        for (max_display_boost, stops) in [(4.0, 0.0), (2.0, 1.0), (1.0, 2.0)] {
+            let converter = UhdrConverter::new(&mut &jpeg_bytes[..], max_display_boost).unwrap();
+            assert!(converter.gain_maps[0].metadata.base_rendition_is_hdr);
+
+            // The HDR base itself with all of the headroom, and the SDR rendition without any.
+            let value = converter.compute_linear_pixels(1.0, &ColorGamut::bt2020()).get_at(4, 4).g();
+            let expected = (1.0 + offset) / 2.0f32.powf(stops) - offset;
+            assert!((value - expected).abs() < 1e-4, "{}: {} != {}", max_display_boost, value, expected);
+        }
+    }
+
+    #[test]
+    fn gain_map_recovery_values_are_only_raised_to_the_gamma() {
+        // SDR white, boosted by a mid-gray gain map, whose recovery values aren't linearized like the pixels of an image.
+        let white = testutil::TestImage::from_fn(8, 8, |_, _| [255; 3]);
+        let gain_map = testutil::TestImage::grayscale_from_fn(4, 4, |_, _| 128);
+        for gamma in [1.0, 2.0] {
+            let params = testutil::TestGainMapParams { gamma, ..Default::default() };
+            let jpeg_bytes = testutil::build_uhdr_jpeg(&white, &gain_map, params);
+            let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+
+            let offset = params.offset_sdr;
+            let value = converter.compute_linear_pixels(1.0, &ColorGamut::bt2020()).get_at(4, 4).g();
+            let log_recovery = ((value + offset) / (1.0 + offset)).log2() / params.gain_map_max;
+            let expected = (128.0f32 / 255.0).powf(1.0 / gamma);
+            assert!((log_recovery - expected).abs() < 0.01, "gamma {}: {} != {}", gamma, log_recovery, expected);
+        }
+    }
+
+    #[test]
+    fn tone_mapping_maps_the_peak_to_white() {
+        let primary = testutil::TestImage::from_fn(8, 8, |_, _| [200, 200, 200]);
+        let gain_map = testutil::TestImage::from_fn(4, 4, |x, _| if x < 2 { [0; 3] } else { [255; 3] });
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, Default::default());
+
+        for operator in [ToneMapOperator::Reinhard, ToneMapOperator::Hable, ToneMapOperator::Aces] {
+            let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+            let pixels = converter.compute_tone_mapped_srgb8_pixels(operator);
+            assert_eq!(pixels.len(), 8 * 8);
+
+            // The boosted half holds the peak, above the unboosted half.
+            assert_eq!(pixels.iter().flatten().max(), Some(&255), "{:?}", operator);
+            assert!(pixels[0][1] < pixels[5][1], "{:?}: {:?}", operator, &pixels[..8]);
+        }
+
+        // Extended Reinhard leaves the primary image as is without headroom.
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 1.0).unwrap();
+        for pixel in converter.compute_tone_mapped_srgb8_pixels(ToneMapOperator::Reinhard) {
+            assert!(pixel.iter().all(|&value| value.abs_diff(200) <= 1), "{:?}", pixel);
+        }
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png_gain_map_is_decoded_and_applied() {
+        let primary = testutil::TestImage::from_fn(8, 8, |_, _| [128, 128, 128]);
+        let gain_map = testutil::TestImage::from_fn(4, 4, |x, _| if x < 2 { [64; 3] } else { [255; 3] });
+        let params = testutil::TestGainMapParams { gain_map_max: 1.0, ..Default::default() };
+
+        let jpeg_gain_map_bytes = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&params.to_xmp())]);
+        let png_gain_map_bytes = {
+            let mut bytes = Vec::new();
+            let mut encoder = png::Encoder::new(&mut bytes, 4, 4);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.add_itxt_chunk("XML:com.adobe.xmp".to_string(), params.to_xmp()).unwrap();
+            encoder.write_header().unwrap().write_image_data(&gain_map.rgb).unwrap();
+            bytes
+        };
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        let convert = |gain_map_bytes: Vec<u8>| {
+            let jpeg_bytes = testutil::build_uhdr_jpeg_from_parts(&primary, &[gain_map_bytes]);
+            let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+            assert_eq!(converter.info().gain_maps[0].width, 4);
+            converter.compute_linear_pixels(80.0, &dst_color_gamut)
+        };
+        let from_jpeg = convert(jpeg_gain_map_bytes);
+        let from_png = convert(png_gain_map_bytes);
+
+        // The left half is boosted less than the right half, the same as with the JPEG gain map.
+        assert!(from_png.get_at(1, 4).g() < from_png.get_at(6, 4).g());
+        for (x, y) in [(1, 4), (6, 4)] {
+            let (png, jpeg) = (from_png.get_at(x, y).g(), from_jpeg.get_at(x, y).g());
+            assert!((png - jpeg).abs() < jpeg * 0.01, "({}, {}): {} != {}", x, y, png, jpeg);
+        }
+    }
+
+    #[test]
+    fn hlg_primary_is_linearized_with_hlg_eotf() {
+        // A 75% HLG signal, which is HLG reference white.
+        const SIGNAL: u8 = 191;
+
+        let primary = testutil::TestImage::from_fn(8, 8, |_, _| [SIGNAL; 3]);
+        let gain_map = testutil::TestImage::from_fn(4, 4, |_, _| [255; 3]);
+        let gain_map_bytes = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&testutil::TestGainMapParams::default().to_xmp())]);
+
+        let icc_profile = testutil::bt2020_icc_profile_with_cicp(Cicp::TRANSFER_CHARACTERISTICS_HLG);
+        let jpeg_bytes = testutil::build_mpf_jpeg(&primary, &[], Some(&icc_profile), &[gain_map_bytes]);
+
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        assert_eq!(converter.uhdr_jpeg.source_transfer(), SourceTransfer::Hlg);
+        // The primary image is already HDR.
+        assert!(converter.gain_maps.is_empty());
+
+        let value = converter.compute_linear_pixels(SourceTransfer::HLG_REFERENCE_WHITE_LUMINANCE, &ColorGamut::bt2020()).get_at(4, 4).g();
+
+        // For a gray, the OOTF reduces to `Lw * E^1.2` for the scene light `E` given by the HLG inverse OETF.
+        let signal = SIGNAL as f32 / 255.0;
+        let scene = ((signal - 0.55991073) / 0.17883277).exp() / 12.0 + (1.0 - 4.0 * 0.17883277) / 12.0;
+        let expected = SourceTransfer::HLG_NOMINAL_PEAK_LUMINANCE * scene.powf(1.2);
+        assert!((value - expected).abs() / expected < 0.01, "{} != {}", value, expected);
+        // Close to reference white, and far from the 2.2 gamma assumption.
+        assert!((value - SourceTransfer::HLG_REFERENCE_WHITE_LUMINANCE).abs() < 3.0, "{}", value);
+    }
+
+    #[test]
+    fn mpf_image_roles_can_be_overridden() {
+        let primary = testutil::TestImage::from_fn(8, 8, |x, y| [(x * 32) as u8, (y * 32) as u8, 128]);
+        let gain_map = testutil::TestImage::from_fn(4, 4, |x, _| [(x * 64) as u8; 3]);
+        let params = testutil::TestGainMapParams::default();
+
+        let expected_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, params);
+
+        // Mis-authored: The gain map comes first, and the primary image second.
+        let reversed_bytes = testutil::build_mpf_jpeg(
+            &gain_map,
+            &[testutil::xmp_app_segment(&params.to_xmp())],
+            None,
+            &[testutil::encode_jpeg(&primary, &[])],
+        );
+        // The primary image, taken for the gain map, doesn't have gain map metadata.
+        assert!(UhdrConverter::new(&mut &reversed_bytes[..], 4.0).is_err());
+
+        let options = DecodeOptions { primary_index: Some(1), gain_map_index: Some(0), ..Default::default() };
+        let recovered = UhdrConverter::new_with_options(&mut &reversed_bytes[..], 4.0, &options).unwrap();
+        // The gain map index defaults to the other image.
+        let options = DecodeOptions { primary_index: Some(1), gain_map_index: None, ..Default::default() };
+        let recovered_with_default = UhdrConverter::new_with_options(&mut &reversed_bytes[..], 4.0, &options).unwrap();
+        let expected = UhdrConverter::new(&mut &expected_bytes[..], 4.0).unwrap();
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        let expected_pixels = expected.compute_linear_pixels(80.0, &dst_color_gamut);
+        for converter in [recovered, recovered_with_default] {
+            assert_eq!(converter.uhdr_jpeg.extent(), (8, 8));
+            assert!(converter.compute_linear_pixels(80.0, &dst_color_gamut).pixels() == expected_pixels.pixels());
+        }
+
+        let options = DecodeOptions { primary_index: Some(1), gain_map_index: Some(1), ..Default::default() };
+        assert!(UhdrConverter::new_with_options(&mut &reversed_bytes[..], 4.0, &options).is_err());
+        let options = DecodeOptions { primary_index: Some(2), gain_map_index: None, ..Default::default() };
+        assert!(UhdrConverter::new_with_options(&mut &reversed_bytes[..], 4.0, &options).is_err());
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn timed_conversion_reports_each_phase() {
+        let primary = testutil::TestImage::from_fn(64, 64, |x, y| [(x * 4) as u8, (y * 4) as u8, 128]);
+        let gain_map = testutil::TestImage::from_fn(16, 16, |x, _| [(x * 16) as u8; 3]);
+        let gain_map_bytes = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&testutil::TestGainMapParams::default().to_xmp())]);
+        // BT.709 transfer characteristics, i.e. not HLG, so that the ICC profile is used.
+        let icc_profile = testutil::bt2020_icc_profile_with_cicp(1);
+        let jpeg_bytes = testutil::build_mpf_jpeg(&primary, &[], Some(&icc_profile), &[gain_map_bytes]);
+
+        let start = Instant::now();
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        let new_elapsed = start.elapsed();
+        let convert_start = Instant::now();
+        let timings = converter.convert_to_avif_timed(&mut Vec::new(), 80.0).unwrap();
+        let convert_elapsed = convert_start.elapsed();
+        let elapsed = start.elapsed();
+
+        assert!(!timings.jpeg_decode.is_zero(), "{:?}", timings);
+        assert!(!timings.icc_parse.is_zero(), "{:?}", timings);
+        assert!(!timings.pixel_pass.is_zero(), "{:?}", timings);
+        assert!(!timings.encode.is_zero(), "{:?}", timings);
+
+        // The decoding phases are those measured by `new`, within the time `new` took, and the others are within
+        // the time of the conversion itself.
+        let decode_timings = converter.decode_timings();
+        assert_eq!((timings.jpeg_decode, timings.icc_parse), (decode_timings.jpeg_decode, decode_timings.icc_parse));
+        assert!(timings.jpeg_decode + timings.icc_parse <= new_elapsed, "{:?} vs {:?}", timings, new_elapsed);
+        assert!(timings.pixel_pass + timings.encode <= convert_elapsed, "{:?} vs {:?}", timings, convert_elapsed);
+        // Reading the input and the bookkeeping in between aren't accounted for, but are a small part of the time.
+        assert!(timings.total() <= elapsed, "{:?} > {:?}", timings.total(), elapsed);
+        assert!(timings.total() >= elapsed.mul_f64(0.5), "{:?} < half of {:?}", timings.total(), elapsed);
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn derived_peak_bounds_and_tags_avif() {
+        let white = testutil::TestImage::from_fn(8, 8, |_, _| [255; 3]);
+        let params = testutil::TestGainMapParams { gain_map_max: 2.5, hdr_capacity_max: 2.5, ..Default::default() };
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&white, &white, params);
+
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 10.0).unwrap();
+        let peak = converter.authored_peak_luminance(100.0).unwrap();
+        assert!((peak - 2.5f32.exp2() * 100.0).abs() < 1e-3, "{}", peak);
+        assert_eq!(converter.clone().with_exposure_ev(1.0).authored_peak_luminance(100.0), Some(peak * 2.0));
+
+        let mut avif_bytes = Vec::new();
+        converter.clone().convert_to_avif(&mut avif_bytes, 100.0).unwrap();
+        // The content light levels are tagged without a derived peak, but not the mastering display.
+        assert!(testutil::find(&avif_bytes, b"clli").is_some());
+        assert!(testutil::find(&avif_bytes, b"mdcv").is_none());
+
+        let light_levels = Hdr10LightLevels { max_cll: 1000.0, max_fall: 200.0 };
+        let mastering_display = MasteringDisplay { max_luminance: 2000.0, ..Default::default() };
+        let mut avif_bytes = Vec::new();
+        converter.clone()
+            .with_derived_peak(true)
+            .with_avif_light_levels(light_levels)
+            .with_avif_mastering_display(mastering_display)
+            .convert_to_avif(&mut avif_bytes, 100.0)
+            .unwrap();
+        let clli = testutil::find(&avif_bytes, b"clli").unwrap() + 4;
+        assert_eq!(avif_bytes[clli..clli + 4], [0x03, 0xE8, 0x00, 0xC8]);
+        let mdcv = testutil::find(&avif_bytes, b"mdcv").unwrap() + 4 + 16;
+        assert_eq!(avif_bytes[mdcv..mdcv + 4], 20_000_000u32.to_be_bytes());
+
+        for quantizer in [None, Some(100)] {
+            let mut converter = converter.clone().with_derived_peak(true);
+            if let Some(quantizer) = quantizer {
+                converter = converter.with_avif_quantizer(quantizer);
+            }
+            let mut avif_bytes = Vec::new();
+            converter.convert_to_avif(&mut avif_bytes, 100.0).unwrap();
+
+            let clli = testutil::find(&avif_bytes, b"clli").unwrap() + 4;
+            let max_cll = u16::from_be_bytes([avif_bytes[clli], avif_bytes[clli + 1]]);
+            let max_fall = u16::from_be_bytes([avif_bytes[clli + 2], avif_bytes[clli + 3]]);
+            // SDR white boosted by the full `2^2.5`, give or take the JPEG and gain map rounding.
+            assert!((max_cll as f32 - peak).abs() <= 2.0, "{} vs {}", max_cll, peak);
+            assert!(max_fall > 0 && max_fall <= max_cll, "{} vs {}", max_fall, max_cll);
+
+            // After the chromaticities of the 3 primaries and the white point.
+            let mdcv = testutil::find(&avif_bytes, b"mdcv").unwrap() + 4 + 16;
+            let max_luminance = u32::from_be_bytes(avif_bytes[mdcv..mdcv + 4].try_into().unwrap());
+            assert_eq!(max_luminance, (peak * 10000.0).round() as u32);
+        }
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn diff_image_shows_the_error_of_low_quality_encodes() {
+        // Fine detail, which a coarse quantizer loses.
+        let primary = testutil::TestImage::from_fn(32, 32, |x, y| {
+            let value = ((x * 7 + y * 13) % 17 * 15) as u8;
+            [value, 255 - value, if (x + y) % 2 == 0 { 64 } else { 192 }]
+        });
+        let gain_map = testutil::TestImage::from_fn(8, 8, |_, _| [192; 3]);
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, testutil::TestGainMapParams::default());
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+
+        let encode = |quantizer: u8| {
+            let (mut avif_bytes, mut diff_bytes) = (Vec::new(), Vec::new());
+            let (_, error) = converter.clone().with_avif_quantizer(quantizer)
+                .convert_to_avif_with_diff_image(&mut avif_bytes, 80.0, &mut diff_bytes)
+                .unwrap();
+
+            // The same AVIF as without the diff image.
+            let mut plain_avif_bytes = Vec::new();
+            converter.clone().with_avif_quantizer(quantizer).convert_to_avif(&mut plain_avif_bytes, 80.0).unwrap();
+            assert_eq!(avif_bytes, plain_avif_bytes);
+
+            assert_eq!((error.width, error.height), (32, 32));
+            assert!(diff_bytes.starts_with(b"II*\0"));
+            // The brightest sample of the diff image is the largest error, amplified.
+            let brightest = diff_bytes[8..8 + 32 * 32 * 6].chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .max()
+                .unwrap();
+            let expected = (error.max() * DIFF_IMAGE_AMPLIFICATION).min(1.0) * 65535.0;
+            assert!((brightest as f32 - expected).abs() <= 1.0, "{} vs {}", brightest, expected);
+            error
+        };
+
+        // Near black: Within a few 10-bit code values, and mostly within one.
+        // Even the finest quantizer isn't lossless, and the R'G'B' derived from Y'CbCr magnifies the error of G'.
+        let high_quality = encode(0);
+        assert!(high_quality.max() * 1023.0 < 8.0, "{}", high_quality.max() * 1023.0);
+        assert!(high_quality.mean() * 1023.0 < 1.0, "{}", high_quality.mean() * 1023.0);
+
+        let low_quality = encode(255);
+        assert!(low_quality.max() * DIFF_IMAGE_AMPLIFICATION >= 1.0, "{}", low_quality.max() * 1023.0);
+        assert!(low_quality.mean() > high_quality.mean() * 10.0, "{} vs {}", low_quality.mean(), high_quality.mean());
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn oversize_avif_fails_or_is_downscaled() {
+        // The widest JPEG the decoder accepts by default, stretched to square pixels by the largest accepted ratio.
+        let mut jpeg_bytes = testutil::gradient_uhdr_jpeg(16384, 2, 4, 1);
+        let units = testutil::find(&jpeg_bytes, b"JFIF\0").unwrap() + 7;
+        jpeg_bytes[units..units + 5].copy_from_slice(&[0, 0, 1, 0, 4]);
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        assert_eq!(converter.output_extent(), (65536, 2));
+
+        let error = converter.convert_to_avif(&mut Vec::new(), 80.0).unwrap_err();
+        assert!(matches!(error, UhdrError::OutputTooLarge { width: 65536, height: 2, max_dimension: 65535 }), "{}", error);
+        assert!(error.to_string().contains("65536x2 exceeds the maximum dimensions of 65535x65535"), "{}", error);
+        assert!(converter.convert_to_sdr_avif(&mut Vec::new()).is_err());
+        assert!(matches!(converter.avif_extent(), Err(UhdrError::OutputTooLarge { .. })));
+
+        // Not encoded, which would take minutes in a debug build.
+        let converter = converter.with_avif_oversize(AvifOversize::Downscale);
+        assert_eq!(converter.avif_extent().unwrap(), (65535, 2));
+
+        // Never for output that fits.
+        assert_eq!(crate::outavif::fit_av1_dimensions(65535, 100), (65535, 100));
+        assert_eq!(crate::outavif::fit_av1_dimensions(100, 131070), (50, 65535));
+        assert_eq!(crate::outavif::fit_av1_dimensions(1, 1_000_000), (1, 65535));
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn resolution_is_carried_into_avif() {
+        let mut jpeg_bytes = testutil::gradient_uhdr_jpeg(16, 8, 4, 2);
+        let no_resolution_avif_bytes = {
+            let mut avif_bytes = Vec::new();
+            UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap().convert_to_avif(&mut avif_bytes, 80.0).unwrap();
+            avif_bytes
+        };
+        assert!(testutil::find(&no_resolution_avif_bytes, b"Exif").is_none());
+
+        // 300 DPI in the JFIF segment of the primary image, which has the same size as the one with an aspect ratio only.
+        let units = testutil::find(&jpeg_bytes, b"JFIF\0").unwrap() + 7;
+        jpeg_bytes[units..units + 5].copy_from_slice(&[1, 0x01, 0x2C, 0x01, 0x2C]);
+
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        let expected = Resolution { x: 300.0, y: 300.0, unit: ResolutionUnit::Inch };
+        assert_eq!(converter.uhdr_jpeg.resolution(), Some(expected));
+
+        // Both through `ravif` and through rav1e directly.
+        for quantizer in [None, Some(100)] {
+            let mut avif_bytes = Vec::new();
+            let converter = match quantizer {
+                Some(quantizer) => UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap().with_avif_quantizer(quantizer),
+                None => UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap(),
             };
-            
-            uhdr_converter.convert_to_avif(&mut out_file)
-                .expect("Failed to convert UHDR JPEG to AVIF");
+            converter.convert_to_avif(&mut avif_bytes, 80.0).unwrap();
+
+            assert!(testutil::find(&avif_bytes, b"Exif").is_some());
+            let tiff_start = testutil::find(&avif_bytes, b"MM\0*").unwrap();
+            assert_eq!(Resolution::from_exif_tiff(&avif_bytes[tiff_start..]), Some(expected), "{:?}", quantizer);
+            // Still a decodable HDR10 AVIF with the same AV1 data.
+            assert!(testutil::find(&avif_bytes, b"colrnclx").is_some());
+            if quantizer.is_none() {
+                assert!(avif_bytes.len() > no_resolution_avif_bytes.len());
+            }
+        }
+    }
+
+    #[test]
+    fn exif_orientation_is_applied_to_the_output() {
+        use crate::tiff::{Endianness, TiffFieldValue, TiffIfdEntry, TiffWriter};
+
+        // 32x16 with the top-left 8x8 block white, and a uniform gain map.
+        let primary = testutil::TestImage::from_fn(32, 16, |x, y| if x < 8 && y < 8 { [255; 3] } else { [0; 3] });
+        let gain_map = testutil::TestImage::from_fn(8, 4, |_, _| [128; 3]);
+        let gain_map_bytes = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&testutil::TestGainMapParams::default().to_xmp())]);
+
+        // The corner the stored top-left corner is displayed at, as (right, bottom).
+        let expected_corners = [(false, false), (true, false), (true, true), (false, true), (false, false), (true, false), (true, true), (false, true)];
+        for (value, (right, bottom)) in (1..=8).zip(expected_corners) {
+            let mut writer = TiffWriter::new(Endianness::LittleEndian);
+            writer.append_ifd(&[TiffIfdEntry::new(274, TiffFieldValue::SHORT(vec![value]))]).unwrap();
+            let exif_segment = (1, [b"Exif\0\0".as_slice(), &writer.finish()].concat());
+            let jpeg_bytes = testutil::build_mpf_jpeg(&primary, &[exif_segment], None, &[gain_map_bytes.clone()]);
+
+            let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+            assert_eq!(converter.uhdr_jpeg.orientation(), Orientation::from_exif_value(value).unwrap());
+
+            // The width and height are swapped for 90 and 270 degrees, i.e. 5 to 8.
+            let (width, height) = converter.output_extent();
+            assert_eq!((width, height), if value >= 5 { (16, 32) } else { (32, 16) }, "{}", value);
+
+            let linear_pixels = converter.compute_linear_pixels(80.0, &ColorGamut::bt2020());
+            for (x, y) in [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)] {
+                let is_expected_corner = (x > 0) == right && (y > 0) == bottom;
+                let value_at_corner = linear_pixels.get_at(x, y).g();
+                assert_eq!(value_at_corner > 40.0, is_expected_corner, "{} at ({}, {}): {}", value, x, y, value_at_corner);
+            }
+
+            // The stored pixels as is.
+            assert_eq!(converter.with_orientation(Orientation::Normal).output_extent(), (32, 16));
+        }
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn exif_is_carried_into_avif_unless_stripped() {
+        use crate::tiff::{Endianness, Tiff, TiffFieldValue, TiffIfdEntry, TiffWriter};
+
+        let mut writer = TiffWriter::new(Endianness::BigEndian);
+        writer.append_ifd(&[
+            // Make, and Orientation rotated 90 degrees clockwise.
+            TiffIfdEntry::new(271, TiffFieldValue::ASCII("Camera\0".to_string())),
+            TiffIfdEntry::new(274, TiffFieldValue::SHORT(vec![6])),
+        ]).unwrap();
+        let exif_segment = (1, [b"Exif\0\0".as_slice(), &writer.finish()].concat());
+
+        let image = testutil::TestImage::from_fn(8, 8, |_, _| [128; 3]);
+        let gain_map_bytes = testutil::encode_jpeg(&image, &[testutil::xmp_app_segment(&testutil::TestGainMapParams::default().to_xmp())]);
+        let jpeg_bytes = testutil::build_mpf_jpeg(&image, &[exif_segment], None, &[gain_map_bytes]);
+
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        assert!(converter.uhdr_jpeg.exif_bytes().is_some());
+
+        let avif_bytes = converter.convert_to_avif_bytes(80.0).unwrap();
+        let tiff_start = testutil::find(&avif_bytes, b"MM\0*").unwrap();
+        let tiff = Tiff::from_reader(&mut std::io::Cursor::new(&avif_bytes[tiff_start..])).unwrap();
+        assert!(matches!(&tiff.ifds[0].entry_with_tag(271).unwrap().field_value, TiffFieldValue::ASCII(make) if make.starts_with("Camera")));
+        // The pixels are not rotated, so neither must readers that honor the orientation.
+        assert!(matches!(&tiff.ifds[0].entry_with_tag(274).unwrap().field_value, TiffFieldValue::SHORT(values) if values == &[1]));
+
+        let stripped_avif_bytes = converter.with_strip_metadata(true).convert_to_avif_bytes(80.0).unwrap();
+        assert!(testutil::find(&stripped_avif_bytes, b"Exif").is_none());
+    }
+
+    #[test]
+    fn non_square_pixels_are_stretched_to_square() {
+        let square_bytes = testutil::gradient_uhdr_jpeg(16, 8, 4, 2);
+        // Pixels half as wide as tall, as an aspect ratio without units in the JFIF segment of the primary image.
+        let mut jpeg_bytes = square_bytes.clone();
+        let units = testutil::find(&jpeg_bytes, b"JFIF\0").unwrap() + 7;
+        jpeg_bytes[units..units + 5].copy_from_slice(&[0, 0, 2, 0, 1]);
+
+        let square = UhdrConverter::new(&mut &square_bytes[..], 4.0).unwrap();
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        assert_eq!(square.output_extent(), (16, 8));
+        assert_eq!(converter.output_extent(), (16, 16));
+
+        let native = square.compute_linear_pixels(80.0, &ColorGamut::bt2020());
+        let stretched = converter.compute_linear_pixels(80.0, &ColorGamut::bt2020());
+        for y in 0..16 {
+            // The center of each output row in the rows of the primary image.
+            let position = ((y as f32 + 0.5) / 2.0 - 0.5).clamp(0.0, 7.0);
+            let (y0, t) = (position as usize, position.fract());
+            for x in 0..16 {
+                let expected = native.get_at(x, y0) * (1.0 - t) + native.get_at(x, (y0 + 1).min(7)) * t;
+                let actual = stretched.get_at(x, y);
+                for channel in 0..3 {
+                    assert!((actual.rgb()[channel] - expected.rgb()[channel]).abs() < 1e-3, "({}, {}): {:?} != {:?}", x, y, actual, expected);
+                }
+            }
+        }
+
+        let mut tiff_bytes = Vec::new();
+        converter.convert_to_tiff(&mut tiff_bytes, 80.0, TiffTransfer::Linear).unwrap();
+        let tiff = crate::tiff::Tiff::from_reader(&mut std::io::Cursor::new(&tiff_bytes)).unwrap();
+        let extent = [256, 257].map(|tag| match tiff.ifds[0].entry_with_tag(tag).unwrap().field_value {
+            crate::tiff::TiffFieldValue::LONG(ref values) => values[0],
+            crate::tiff::TiffFieldValue::SHORT(ref values) => values[0].into(),
+            _ => panic!(),
+        });
+        assert_eq!(extent, [16, 16]);
+
+        // A crop is in the pixels of the primary image.
+        let cropped = converter.with_crop(CropRect { x: 0, y: 0, width: 4, height: 4 }).unwrap();
+        assert_eq!(cropped.output_extent(), (4, 8));
+    }
+
+    #[cfg(any(feature = "avif", feature = "webp"))]
+    #[test]
+    fn srgb8_pixels_match_primary() {
+        let primary = testutil::TestImage::from_fn(16, 8, |x, y| [(x * 16) as u8, (y * 32) as u8, 200]);
+        let gain_map = testutil::TestImage::from_fn(4, 2, |_, _| [255; 3]);
+        let params = testutil::TestGainMapParams::default();
+
+        let decoded_primary = |converter: &UhdrConverter| -> Vec<[u8; 3]> {
+            (0..8)
+                .flat_map(|y| (0..16).map(move |x| (x, y)))
+                .map(|(x, y)| converter.uhdr_jpeg.fetch_pixel(x, y).map(|value| (value * 255.0).round() as u8))
+                .collect()
+        };
+
+        // Without an ICC profile, the decoded pixels are passed through, unaffected by the gain map and the exposure.
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, params);
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap().with_exposure_ev(2.0);
+        assert_eq!(converter.compute_srgb8_pixels(), decoded_primary(&converter));
+
+        let cropped = converter.clone().with_crop(CropRect { x: 3, y: 2, width: 5, height: 4 }).unwrap();
+        let expected: Vec<[u8; 3]> = (2..6)
+            .flat_map(|y| (3..8).map(move |x| (x, y)))
+            .map(|(x, y)| decoded_primary(&converter)[y * 16 + x])
+            .collect();
+        assert_eq!(cropped.compute_srgb8_pixels(), expected);
+
+        // An sRGB ICC profile round-trips through linear light.
+        let icc_profile = lcms2::Profile::new_srgb().icc().unwrap();
+        let gain_map_bytes = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&params.to_xmp())]);
+        let jpeg_bytes = testutil::build_mpf_jpeg(&primary, &[], Some(&icc_profile), &[gain_map_bytes]);
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        assert!(converter.uhdr_jpeg.icc_color_space().is_some());
+        for (actual, expected) in converter.compute_srgb8_pixels().iter().zip(decoded_primary(&converter)) {
+            for channel in 0..3 {
+                assert!(actual[channel].abs_diff(expected[channel]) <= 1, "{:?} != {:?}", actual, expected);
+            }
+        }
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn sdr_avif_is_8_bit_srgb() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(16, 8, 4, 2);
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+
+        let mut avif_bytes = Vec::new();
+        converter.convert_to_sdr_avif(&mut avif_bytes).unwrap();
+
+        // `pixi`: Version and flags, then the bit depth of each of the 3 channels.
+        let pixi = testutil::find(&avif_bytes, b"pixi").unwrap() + 4;
+        assert_eq!(avif_bytes[pixi + 4..pixi + 8], [3, 8, 8, 8]);
+
+        // `av1C`: The `high_bitdepth` flag is clear.
+        let av1c = testutil::find(&avif_bytes, b"av1C").unwrap() + 4;
+        assert_eq!(avif_bytes[av1c + 2] & 0x40, 0);
+
+        // `colr` is omitted for BT.709 color primaries with sRGB transfer characteristics, the defaults of AVIF.
+        assert!(testutil::find(&avif_bytes, b"colr").is_none());
+    }
+
+    #[cfg(feature = "webp")]
+    #[test]
+    fn webp_decodes_to_primary() {
+        let primary = testutil::TestImage::from_fn(24, 16, |x, _| if x < 12 { [200, 60, 40] } else { [40, 80, 220] });
+        let gain_map = testutil::TestImage::from_fn(6, 4, |_, _| [255, 255, 255]);
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, testutil::TestGainMapParams::default());
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+
+        let mut webp_bytes = Vec::new();
+        converter.convert_to_webp(&mut webp_bytes).unwrap();
+
+        let webp_image = webp::Decoder::new(&webp_bytes).decode().unwrap();
+        assert_eq!((webp_image.width(), webp_image.height()), (24, 16));
+
+        // Lossy, but the flat halves survive closely, unaffected by the gain map.
+        let expected = converter.compute_srgb8_pixels();
+        let channels = if webp_image.is_alpha() { 4 } else { 3 };
+        for (x, y) in [(2, 8), (21, 8)] {
+            let index = (y * 24 + x) * channels;
+            for channel in 0..3 {
+                let actual = webp_image[index + channel] as i32;
+                let expected = expected[y * 24 + x][channel] as i32;
+                assert!((actual - expected).abs() <= 8, "({}, {}) channel {}: {} != {}", x, y, channel, actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn icc_profile_is_reassembled_from_multiple_chunks() {
+        let icc_profile = testutil::large_bt2020_icc_profile();
+        assert!(icc_profile.len() > u16::MAX as usize, "{} bytes", icc_profile.len());
+
+        let primary = testutil::TestImage::from_fn(8, 8, |x, y| [(x * 32) as u8, (y * 32) as u8, 128]);
+        let jpeg_bytes = testutil::encode_jpeg_with_icc_profile(&primary, &[], Some(&icc_profile));
+
+        // Renumbers the chunks from 0 instead of 1, as some encoders do.
+        let mut zero_based_jpeg_bytes = jpeg_bytes.clone();
+        let chunk_offsets: Vec<usize> = segments::header_segments(&jpeg_bytes)
+            .filter(|segment| segment.marker == 0xE2 && segment.payload.starts_with(b"ICC_PROFILE\0"))
+            .map(|segment| segment.payload_offset)
+            .collect();
+        assert_eq!(chunk_offsets.len(), 2);
+        for offset in chunk_offsets {
+            zero_based_jpeg_bytes[offset + 12] -= 1;
+        }
+
+        for jpeg_bytes in [jpeg_bytes, zero_based_jpeg_bytes] {
+            let jpeg = UhdrJpeg::new_from_bytes(&jpeg_bytes).unwrap();
+            let icc_color_space = jpeg.icc_color_space().expect("The ICC profile wasn't reassembled");
+
+            let red = icc_color_space.color_gamut.primaries().red_xy();
+            assert!((red[0] - 0.708).abs() < 1e-3 && (red[1] - 0.292).abs() < 1e-3, "{:?}", red);
+
+            let [r, _, _] = icc_color_space.transfer_characteristics.evaluate(&[0.5; 3]);
+            assert!((r - 0.5f32.powf(2.2)).abs() < 1e-3, "{}", r);
+        }
+    }
+
+    #[test]
+    fn parsed_metadata_and_color_space_are_exposed() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(16, 8, 8, 4);
+        let converter = UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap();
+        let metadata = converter.gain_map_metadata(0).unwrap();
+        assert_eq!(metadata.hdr_capacity_max, testutil::TestGainMapParams::default().hdr_capacity_max);
+        assert!(converter.gain_map_metadata(1).is_none());
+        // Without an ICC profile, sRGB is assumed.
+        assert!(converter.source_icc().is_none());
+        assert_eq!(converter.src_color_gamut().primaries().red_xy(), ColorGamut::srgb().primaries().red_xy());
+
+        let gamma = lcms2::ToneCurve::new(2.2);
+        let primary = testutil::TestImage::from_fn(8, 8, |_, _| [128; 3]);
+        let gain_map = testutil::encode_jpeg(
+            &testutil::TestImage::from_fn(4, 4, |_, _| [64; 3]),
+            &[testutil::xmp_app_segment(&testutil::TestGainMapParams::default().to_xmp())],
+        );
+        let jpeg_bytes = testutil::build_mpf_jpeg(&primary, &[], Some(&testutil::bt2020_icc_profile([&gamma, &gamma, &gamma])), &[gain_map]);
+        let converter = UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap();
+        assert!(converter.source_icc().is_some());
+        let [x, y] = converter.src_color_gamut().primaries().red_xy();
+        assert!((x - 0.708).abs() < 1e-3 && (y - 0.292).abs() < 1e-3, "{:?}", [x, y]);
+    }
+
+    #[test]
+    fn gain_map_preview_has_the_decoded_recovery_values() {
+        let primary = testutil::TestImage::from_fn(16, 8, |_, _| [128; 3]);
+        let gain_map = testutil::TestImage::grayscale_from_fn(8, 4, |x, y| (x * 32 + y * 8) as u8);
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, testutil::TestGainMapParams::default());
+        let converter = UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap();
+
+        let preview = converter.gain_map_preview(0).unwrap();
+        assert_eq!(preview.extent(), (8, 4));
+        for y in 0..4 {
+            for x in 0..8 {
+                let [r, g, b] = preview.get_at(x, y).to_rgb_array();
+                assert!(r == g && g == b, "{:?}", [r, g, b]);
+                // Within the JPEG compression error.
+                assert!((r * 255.0 - (x * 32 + y * 8) as f32).abs() <= 3.0, "({}, {}): {}", x, y, r * 255.0);
+            }
+        }
+
+        assert!(converter.gain_map_preview(1).is_none());
+    }
+
+    #[test]
+    fn uhdr_jpeg_output_preserves_gain_map() {
+        let primary = testutil::TestImage::from_fn(16, 8, |x, y| [(x * 16) as u8, (y * 32) as u8, 128]);
+        let gain_map = testutil::TestImage::from_fn(8, 4, |x, y| [(x * 32) as u8, (y * 64) as u8, 200]);
+        let params = testutil::TestGainMapParams::default();
+
+        // Mis-authored with the gain map first, to be repaired.
+        let reversed_bytes = testutil::build_mpf_jpeg(
+            &gain_map,
+            &[testutil::xmp_app_segment(&params.to_xmp())],
+            None,
+            &[testutil::encode_jpeg(&primary, &[])],
+        );
+        let options = DecodeOptions { primary_index: Some(1), gain_map_index: Some(0), ..Default::default() };
+        let source = UhdrConverter::new_with_options(&mut &reversed_bytes[..], 4.0, &options).unwrap();
+
+        let mut repaired_bytes = Vec::new();
+        source.convert_to_uhdr_jpeg(&mut repaired_bytes).unwrap();
+        let repaired = UhdrConverter::new(&mut &repaired_bytes[..], 4.0).unwrap();
+
+        // The coded image data is copied as is, so the decoded gain map is exactly that of the source.
+        let source_gain_map = &source.gain_maps[0].jpeg;
+        let repaired_gain_map = &repaired.gain_maps[0].jpeg;
+        assert_eq!(repaired_gain_map.extent(), (8, 4));
+        for y in 0..4 {
+            for x in 0..8 {
+                assert_eq!(repaired_gain_map.fetch_pixel(x, y), source_gain_map.fetch_pixel(x, y), "({}, {})", x, y);
+            }
+        }
+        // From the start of scan through the end of image.
+        let scan = |jpeg: &UhdrJpeg| {
+            let bytes = jpeg.coded_bytes();
+            bytes[testutil::find(bytes, &[0xFF, 0xDA]).unwrap()..].to_vec()
+        };
+        assert!(testutil::find(&repaired_bytes, &scan(source_gain_map)).is_some());
+        assert!(testutil::find(&repaired_bytes, &scan(&source.uhdr_jpeg)).is_some());
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        assert!(repaired.compute_linear_pixels(80.0, &dst_color_gamut).pixels() == source.compute_linear_pixels(80.0, &dst_color_gamut).pixels());
+
+        assert!(source.with_crop(CropRect { x: 0, y: 0, width: 4, height: 4 }).unwrap().convert_to_uhdr_jpeg(&mut Vec::new()).is_err());
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn grayscale_primary_converts_like_its_rgb_decode() {
+        let primary = testutil::TestImage::grayscale_from_fn(16, 8, |x, y| (x * 16 + y * 2) as u8);
+        let gain_map = testutil::TestImage::grayscale_from_fn(4, 2, |x, _| (x * 64) as u8);
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, testutil::TestGainMapParams::default());
+
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        let options = DecodeOptions { jpeg: JpegDecodeOptions { force_rgb: true, ..Default::default() }, ..Default::default() };
+        let rgb_converter = UhdrConverter::new_with_options(&mut &jpeg_bytes[..], 4.0, &options).unwrap();
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        assert!(converter.compute_linear_pixels(80.0, &dst_color_gamut).pixels() == rgb_converter.compute_linear_pixels(80.0, &dst_color_gamut).pixels());
+
+        // Encoded as a monochrome AVIF, unlike the RGB decode, and without the gain map's boost being lost.
+        let mut avif_bytes = Vec::new();
+        let (_, error) = converter.convert_to_avif_with_diff_image(&mut avif_bytes, 80.0, &mut std::io::sink()).unwrap();
+        let mut rgb_avif_bytes = Vec::new();
+        rgb_converter.convert_to_avif(&mut rgb_avif_bytes, 80.0).unwrap();
+        let is_monochrome = |avif_bytes: &[u8]| avif_bytes[testutil::find(avif_bytes, b"av1C").unwrap() + 6] & 0x10 != 0;
+        assert!(is_monochrome(&avif_bytes));
+        assert!(!is_monochrome(&rgb_avif_bytes));
+        assert!(avif_bytes.len() < rgb_avif_bytes.len(), "{} >= {}", avif_bytes.len(), rgb_avif_bytes.len());
+        assert!(error.max() < 0.01, "{}", error.max());
+
+        // Color added by the hook is kept.
+        let mut tinted_avif_bytes = Vec::new();
+        converter.convert_to_avif_with_hook(&mut tinted_avif_bytes, 80.0, |pixels: &mut FloatImageContent| {
+            for pixel in pixels.pixels_mut() {
+                *pixel = FloatPixel::new(pixel.r() * 0.5, pixel.g(), pixel.b());
+            }
+        }).unwrap();
+        assert!(!is_monochrome(&tinted_avif_bytes));
+    }
+
+    #[test]
+    fn dark_images_get_a_higher_suggested_white_level_than_bright_ones() {
+        let suggest = |value: u8| {
+            let primary = testutil::TestImage::from_fn(32, 32, |x, _| [value, value, value.saturating_add((x % 2) as u8)]);
+            let gain_map = testutil::TestImage::from_fn(8, 8, |_, _| [128; 3]);
+            let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, testutil::TestGainMapParams::default());
+            UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap().suggest_target_sdr_white_level()
+        };
+        let (bright, mid, dark) = (suggest(220), suggest(128), suggest(40));
+
+        assert_eq!(bright, MIN_AUTO_WHITE_LEVEL);
+        assert_eq!(dark, MAX_AUTO_WHITE_LEVEL);
+        // Within the half-bin error of the histogram, of a sixteenth of a stop.
+        let expected = AUTO_WHITE_MEDIAN_LUMINANCE / crate::transfer::srgb_eotf(128.0 / 255.0);
+        assert!((mid / expected - 1.0).abs() < 0.05, "{} vs {}", mid, expected);
+    }
+
+    #[test]
+    fn low_resolution_gain_map_is_warned_about() {
+        // A gain map of 1/8 the primary image's resolution.
+        let converter = UhdrConverter::new(&mut testutil::gradient_uhdr_jpeg(64, 32, 8, 4).as_slice(), 4.0).unwrap();
+        let info = converter.info();
+        let gain_map_extent = (info.gain_maps[0].width, info.gain_maps[0].height);
+
+        let warning = low_gain_map_resolution_warning(converter.uhdr_jpeg.extent(), gain_map_extent, DEFAULT_MIN_GAIN_MAP_SCALE);
+        assert!(warning.unwrap().contains("0.125 times"));
+        // Configurable.
+        assert!(low_gain_map_resolution_warning((64, 32), gain_map_extent, 0.1).is_none());
+        assert!(low_gain_map_resolution_warning((64, 32), gain_map_extent, 0.0).is_none());
+
+        // A quarter in one dimension is fine, but not an eighth in the other.
+        assert!(low_gain_map_resolution_warning((64, 32), (16, 8), DEFAULT_MIN_GAIN_MAP_SCALE).is_none());
+        assert!(low_gain_map_resolution_warning((64, 32), (16, 4), DEFAULT_MIN_GAIN_MAP_SCALE).is_some());
+    }
+
+    #[test]
+    fn mismatched_gain_map_is_warned_about() {
+        // Half and quarter resolution, and the rounding of odd dimensions.
+        assert!(mismatched_gain_map_warning((4032, 3024), (2016, 1512)).is_none());
+        assert!(mismatched_gain_map_warning((4032, 3024), (1008, 756)).is_none());
+        assert!(mismatched_gain_map_warning((4031, 3023), (1008, 756)).is_none());
+        assert!(mismatched_gain_map_warning((7, 3), (2, 1)).is_none());
+
+        // Rotated, and of another aspect ratio altogether.
+        let warning = mismatched_gain_map_warning((4032, 3024), (756, 1008)).unwrap();
+        assert!(warning.contains("0.188 horizontally but by 0.333 vertically"), "{}", warning);
+        assert!(mismatched_gain_map_warning((64, 32), (16, 16)).is_some());
+    }
+
+    #[test]
+    fn downscaled_gain_map_is_interpolated_smoothly() {
+        // A horizontal ramp in a gain map of half the primary image's resolution.
+        let primary = testutil::TestImage::from_fn(32, 4, |_, _| [128; 3]);
+        let gain_map = testutil::TestImage::from_fn(16, 2, |x, _| [(64 + x * 8) as u8; 3]);
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, Default::default());
+
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+        let linear_pixels = converter.compute_linear_pixels(80.0, &ColorGamut::bt2020());
+        let log_boosts: Vec<f32> = (0..32).map(|x| linear_pixels.get_at(x, 1).g().log2()).collect();
+
+        // Each pixel is boosted a little more than its left neighbor, in steps that change gradually,
+        // rather than in pairs of equal boosts with a jump between them. The edges are clamped to the outermost texels.
+        let steps: Vec<f32> = log_boosts.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        for x in 1..steps.len() - 2 {
+            let ratio = steps[x + 1] / steps[x];
+            assert!(steps[x] > 0.0 && (0.67..1.5).contains(&ratio), "steps at {}: {:?}", x, steps);
+        }
+    }
+
+    #[test]
+    fn ignore_icc_matches_unprofiled_input() {
+        let primary = testutil::TestImage::from_fn(8, 8, |x, y| [(x * 32) as u8, (y * 32) as u8, 128]);
+        let gain_map = testutil::TestImage::from_fn(4, 4, |x, _| [(x * 64) as u8; 3]);
+        let gain_map_bytes = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&testutil::TestGainMapParams::default().to_xmp())]);
+        // Linear tone curves and BT.2020 primaries, far from the 2.2 gamma sRGB assumption.
+        let icc_profile = testutil::bt2020_icc_profile_with_cicp(1);
+
+        let profiled_bytes = testutil::build_mpf_jpeg(&primary, &[], Some(&icc_profile), &[gain_map_bytes.clone()]);
+        let unprofiled_bytes = testutil::build_mpf_jpeg(&primary, &[], None, &[gain_map_bytes]);
+
+        let options = DecodeOptions { ignore_icc: true, ..Default::default() };
+        let profiled = UhdrConverter::new(&mut &profiled_bytes[..], 4.0).unwrap();
+        let ignored = UhdrConverter::new_with_options(&mut &profiled_bytes[..], 4.0, &options).unwrap();
+        let unprofiled = UhdrConverter::new(&mut &unprofiled_bytes[..], 4.0).unwrap();
+
+        assert!(profiled.info().has_icc_profile);
+        assert!(!ignored.info().has_icc_profile);
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        let ignored_pixels = ignored.compute_linear_pixels(80.0, &dst_color_gamut);
+        assert!(ignored_pixels.pixels() == unprofiled.compute_linear_pixels(80.0, &dst_color_gamut).pixels());
+        assert!(ignored_pixels.pixels() != profiled.compute_linear_pixels(80.0, &dst_color_gamut).pixels());
+
+        // Not even parsed, so a corrupt profile doesn't fail decoding.
+        let mut corrupt_profile = icc_profile.clone();
+        corrupt_profile[36..40].copy_from_slice(b"xxxx");
+        let corrupt_bytes = testutil::encode_jpeg_with_icc_profile(&primary, &[], Some(&corrupt_profile));
+        assert!(UhdrJpeg::new_from_bytes(&corrupt_bytes).is_err());
+        assert!(UhdrJpeg::new_from_bytes_ignoring_icc(&corrupt_bytes).is_ok());
+    }
+
+    #[cfg(feature = "half")]
+    #[test]
+    fn half_intermediate_is_close_to_float() {
+        let converter = UhdrConverter::new(&mut testutil::gradient_uhdr_jpeg(32, 24, 8, 6).as_slice(), 4.0).unwrap();
+        let dst_color_gamut = ColorGamut::bt2020();
+
+        let float = converter.compute_linear_pixels(80.0, &dst_color_gamut);
+        let half: HalfImageContent = converter.compute_linear_pixels_as(80.0, &dst_color_gamut);
+
+        for y in 0..24 {
+            for x in 0..32 {
+                let expected = float.get_at(x, y);
+                let actual = half.get_at(x, y);
+                for channel in 0..3 {
+                    // Half of the spacing of `f16` values, relative to the value.
+                    let tolerance = expected[channel].abs() * 2.0f32.powi(-11) + 1e-6;
+                    assert!((actual[channel] - expected[channel]).abs() <= tolerance, "({}, {}): {:?} != {:?}", x, y, actual, expected);
+                }
+            }
+        }
+        assert_eq!(HalfImageContent::from(&float).get_at(5, 7), half.get_at(5, 7));
+
+        assert!(FloatImageContent::from(&half).pixels() == HalfImageContent::from(&float).to_float_image_content().pixels());
+
+        let mut float_tiff = Vec::new();
+        converter.convert_to_tiff(&mut float_tiff, 80.0, TiffTransfer::Linear).unwrap();
+        let mut half_tiff = Vec::new();
+        converter.with_low_memory(true).convert_to_tiff(&mut half_tiff, 80.0, TiffTransfer::Linear).unwrap();
+        assert_eq!(float_tiff.len(), half_tiff.len());
+    }
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    #[test]
+    fn converter_can_be_shared_across_threads() {
+        assert_send::<UhdrConverter>();
+        assert_sync::<UhdrConverter>();
+        assert_send::<UhdrJpeg>();
+        assert_sync::<UhdrJpeg>();
+
+        let curve = lcms2::ToneCurve::new(2.2);
+        let icc_profile = testutil::bt2020_icc_profile([&curve, &curve, &curve]);
+        let primary = testutil::TestImage::from_fn(16, 8, |x, y| [(x * 16) as u8, (y * 32) as u8, 128]);
+        let gain_map = testutil::TestImage::from_fn(8, 4, |x, y| [(x * 32) as u8, (y * 64) as u8, 200]);
+        let gain_map_jpeg = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&testutil::TestGainMapParams::default().to_xmp())]);
+        let jpeg_bytes = testutil::build_mpf_jpeg(&primary, &[], Some(&icc_profile), &[gain_map_jpeg]);
+
+        let converter = UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap();
+        assert!(converter.uhdr_jpeg.icc_color_space().is_some());
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        let expected = converter.compute_linear_pixels(80.0, &dst_color_gamut);
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| converter.compute_linear_pixels(80.0, &dst_color_gamut)))
+                .collect();
+            for handle in handles {
+                let actual = handle.join().unwrap();
+                for y in 0..8 {
+                    for x in 0..16 {
+                        assert_eq!(actual.get_at(x, y), expected.get_at(x, y));
+                    }
+                }
+            }
+        });
+
+        // Moved to another thread.
+        let moved = std::thread::spawn(move || converter.compute_linear_pixels(80.0, &dst_color_gamut)).join().unwrap();
+        assert_eq!(moved.get_at(3, 2), expected.get_at(3, 2));
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn bands_convert_like_the_whole_image() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(30, 21, 8, 4);
+        let crop = CropRect { x: 3, y: 2, width: 25, height: 17 };
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap()
+            .with_crop(crop).unwrap()
+            .with_orientation(Orientation::Rotate90)
+            // Encoded by rav1e directly either way.
+            .with_avif_quantizer(40);
+
+        for (chroma, band_rows) in [(AvifChroma::Chroma444, 1), (AvifChroma::Chroma444, 4), (AvifChroma::Chroma420, 3), (AvifChroma::Chroma444, 1000)] {
+            let options = AvifEncodeOptions { chroma, ..Default::default() };
+            let whole = converter.clone().with_avif_encode_options(options).convert_to_avif_bytes(80.0).unwrap();
+            let banded = converter.clone().with_avif_encode_options(options).with_avif_band_rows(Some(band_rows));
+            assert!(banded.convert_to_avif_bytes(80.0).unwrap() == whole, "{:?} in bands of {}", chroma, band_rows);
+            assert!(matches!(banded.convert_to_avif_with_diff_image(&mut Vec::new(), 80.0, &mut Vec::new()), Err(UhdrError::InvalidOptions(_))));
+        }
+
+        // A grayscale JPEG with a grayscale gain map can't become colored.
+        let primary = testutil::TestImage::grayscale_from_fn(16, 8, |x, y| (x * 16 + y * 2) as u8);
+        let gain_map = testutil::TestImage::grayscale_from_fn(4, 2, |x, _| (x * 64) as u8);
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, testutil::TestGainMapParams::default());
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap().with_avif_quantizer(40);
+        let whole = converter.convert_to_avif_bytes(80.0).unwrap();
+        assert!(converter.with_avif_band_rows(Some(2)).convert_to_avif_bytes(80.0).unwrap() == whole);
+    }
+
+    #[test]
+    fn errors_tell_their_kind() {
+        let primary = testutil::TestImage::from_fn(8, 8, |_, _| [128; 3]);
+        let gain_map = testutil::TestImage::from_fn(4, 4, |_, _| [64; 3]);
+        let new = |jpeg_bytes: &[u8]| UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).err().unwrap();
+
+        assert!(matches!(new(b"not a JPEG"), UhdrError::NotUhdrJpeg(_)));
+        assert!(matches!(new(&testutil::encode_jpeg(&primary, &[])), UhdrError::MissingGainMap(_)));
+        let broken_xmp_bytes = testutil::build_uhdr_jpeg_from_parts(
+            &primary,
+            &[testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment("<x:xmpmeta")])],
+        );
+        assert!(matches!(new(&broken_xmp_bytes), UhdrError::GainMapMetadata(GainMapError::InvalidXml(_))));
+
+        // A profile with a broken signature, which is otherwise complete.
+        let gamma = lcms2::ToneCurve::new(2.2);
+        let mut icc_profile = testutil::bt2020_icc_profile([&gamma, &gamma, &gamma]);
+        icc_profile[36..40].copy_from_slice(b"xxxx");
+        let broken_icc_bytes = testutil::build_mpf_jpeg(
+            &primary,
+            &[],
+            Some(&icc_profile),
+            &[testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&testutil::TestGainMapParams::default().to_xmp())])],
+        );
+        assert!(matches!(new(&broken_icc_bytes), UhdrError::Icc(_)));
+
+        let converter = UhdrConverter::new(&mut testutil::gradient_uhdr_jpeg(16, 8, 8, 4).as_slice(), 4.0).unwrap();
+        let error = converter.with_crop(CropRect { x: 8, y: 0, width: 9, height: 1 }).err().unwrap();
+        assert!(matches!(error, UhdrError::InvalidOptions(_)));
+        // Still usable where a `Box<dyn Error>` is expected.
+        let boxed: Box<dyn std::error::Error> = error.into();
+        assert!(boxed.to_string().starts_with("Crop"), "{}", boxed);
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn lut_is_only_applied_to_bt2020_output() {
+        // Whether the output is converted at once or in bands.
+        let identity = CubeLut::from_cube_str("LUT_3D_SIZE 2\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n").unwrap();
+        let converter = UhdrConverter::new(&mut testutil::gradient_uhdr_jpeg(16, 8, 8, 4).as_slice(), 4.0).unwrap().with_lut(identity);
+        assert!(converter.clone().convert_to_avif_bytes(80.0).is_ok());
+        for band_rows in [None, Some(4)] {
+            let converter = converter.clone().with_avif_color_primaries(AvifColorPrimaries::DisplayP3).with_avif_band_rows(band_rows);
+            assert!(matches!(converter.convert_to_avif_bytes(80.0), Err(UhdrError::InvalidOptions(_))), "{:?}", band_rows);
+        }
+    }
+
+    #[test]
+    fn images_exceeding_max_pixels_are_rejected_before_decoding() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(16, 8, 8, 4);
+        let options = |max_pixels: usize| DecodeOptions { max_pixels: Some(max_pixels), ..Default::default() };
+
+        assert!(UhdrConverter::new_with_options(&mut jpeg_bytes.as_slice(), 4.0, &options(16 * 8)).is_ok());
+        let error = UhdrConverter::new_with_options(&mut jpeg_bytes.as_slice(), 4.0, &options(16 * 8 - 1)).err().unwrap();
+        assert!(error.to_string().contains("Image too large"), "{}", error);
+        // Applies to the gain map as well.
+        assert!(UhdrConverter::new_with_options(&mut jpeg_bytes.as_slice(), 4.0, &options(8 * 4 - 1)).is_err());
+
+        // A tiny file declaring 16000x16000 in its frame header, whose scan data would run out long before that.
+        let mut bomb_bytes = testutil::encode_jpeg(&testutil::TestImage::from_fn(8, 8, |_, _| [0, 0, 0]), &[]);
+        let frame_header_offset = segments::header_segments(&bomb_bytes)
+            .find(|segment| segment.marker == 0xC0)
+            .unwrap()
+            .payload_offset;
+        // Precision, then the height and width.
+        bomb_bytes[frame_header_offset + 1..frame_header_offset + 5].copy_from_slice(&[0x3E, 0x80, 0x3E, 0x80]);
+
+        let error = UhdrConverter::new_with_options(&mut bomb_bytes.as_slice(), 4.0, &options(4096 * 4096)).err().unwrap();
+        assert!(error.to_string().contains("16000x16000 is 256000000 pixels"), "{}", error);
+    }
+
+    #[test]
+    fn luminance_stats_match_linear_pixels() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(16, 8, 8, 4);
+        let converter = UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap();
+
+        let stats = converter.compute_luminance_stats(80.0);
+
+        let linear_pixels = converter.compute_linear_pixels(80.0, &ColorGamut::bt2020());
+        let light_levels: Vec<f32> = linear_pixels.pixels().iter()
+            .map(|pixel| pixel.r().max(pixel.g()).max(pixel.b()))
+            .collect();
+        let max = light_levels.iter().copied().fold(0.0f32, f32::max);
+        let average = light_levels.iter().sum::<f32>() / light_levels.len() as f32;
+
+        assert_eq!(stats.max_cll, max);
+        assert!((stats.max_fall - average).abs() < 1e-3, "{} != {}", stats.max_fall, average);
+        assert!(stats.max_fall < stats.max_cll);
+        assert_eq!(stats.peak_boost, stats.max_cll / 80.0);
+        // The right half is boosted.
+        assert!(stats.peak_boost > 1.0, "{}", stats.peak_boost);
+    }
+
+    #[test]
+    fn info_describes_input() {
+        let jpeg_bytes = testutil::gradient_uhdr_jpeg(16, 8, 4, 2);
+        let converter = UhdrConverter::new(&mut &jpeg_bytes[..], 4.0).unwrap();
+
+        let info = converter.info();
+        assert_eq!((info.width, info.height), (16, 8));
+        assert!(!info.has_icc_profile);
+        assert_eq!(info.gain_maps.len(), 1);
+        assert_eq!((info.gain_maps[0].width, info.gain_maps[0].height), (4, 2));
+        assert_eq!(info.gain_maps[0].metadata.gain_map_max, [2.0; 3]);
+        assert_eq!(info.gain_maps[0].filter, GainMapFilter::Bilinear);
+    }
+
+    #[test]
+    fn area_filter_smooths_oversampled_gain_map() {
+        // A gain map at twice the resolution of the primary image, with full boost on every third texel column.
+        // Point sampling aliases this into strong stripes, while the area average is much flatter.
+        let primary = testutil::TestImage::from_fn(16, 16, |_, _| [128, 128, 128]);
+        let gain_map_value = |x: usize| if x % 3 == 0 { 255 } else { 0 };
+        let gain_map = testutil::TestImage::from_fn(32, 32, |x, _| {
+            let value = gain_map_value(x);
+            [value, value, value]
+        });
+        let jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, testutil::TestGainMapParams::default());
+
+        let converter = UhdrConverter::new(&mut jpeg_bytes.as_slice(), 4.0).unwrap();
+        assert_eq!(converter.gain_maps[0].filter, GainMapFilter::Area);
+
+        // Point sampling, as a gain map of every other texel column of the same resolution as the primary image.
+        // Bilinear sampling of the oversampled gain map is no substitute, since at exactly twice the resolution
+        // it averages the same two texels as the area filter.
+        let point_sampled_gain_map = testutil::TestImage::from_fn(16, 16, |x, _| {
+            let value = gain_map_value(x * 2);
+            [value, value, value]
+        });
+        let point_sampled_jpeg_bytes = testutil::build_uhdr_jpeg(&primary, &point_sampled_gain_map, testutil::TestGainMapParams::default());
+        let point_sampled_converter = UhdrConverter::new(&mut point_sampled_jpeg_bytes.as_slice(), 4.0).unwrap();
+
+        fn variance(content: &FloatImageContent) -> f32 {
+            let values: Vec<f32> = (0..16)
+                .flat_map(|y| (0..16).map(move |x| (x, y)))
+                .map(|(x, y)| content.get_at(x, y).g())
+                .collect();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            values.iter().map(|value| (value - mean).powi(2)).sum::<f32>() / values.len() as f32
         }
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        let area = converter.compute_linear_pixels(80.0, &dst_color_gamut);
+        let point_sampled = point_sampled_converter.compute_linear_pixels(80.0, &dst_color_gamut);
+
+        assert!(variance(&area) * 2.0 < variance(&point_sampled), "area: {}, point sampled: {}", variance(&area), variance(&point_sampled));
     }
 }
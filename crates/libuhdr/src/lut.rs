@@ -0,0 +1,203 @@
+#![cfg(feature = "avif")]
+
+use crate::transfer::{st2084_eotf, st2084_oetf};
+use crate::pixel::{FloatPixel, LinearImageContent};
+
+/// A 3D LUT in the Iridas/Adobe `.cube` format, applied by `UhdrConverter::with_lut`.
+///
+/// The LUT operates on BT.2100 PQ-encoded BT.2020 R'G'B', i.e. an input of `1.0` is 10,000 nits,
+/// which is what HDR grading LUTs for "Rec.2100 ST.2084" expect. Its output is in the same encoding.
+/// It is only applied to output of the BT.2020 primaries, see `UhdrConverter::with_avif_color_primaries`.
+/// `DOMAIN_MIN` and `DOMAIN_MAX` map onto that range, and inputs outside of the domain are clamped to it.
+#[derive(Debug, Clone)]
+pub struct CubeLut {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// `size`^3 entries, with red changing fastest, then green, then blue.
+    table: Vec<[f32; 3]>,
+}
+
+impl CubeLut {
+    /// The largest `LUT_3D_SIZE` the format allows.
+    const MAX_SIZE: usize = 256;
+
+    /// Parses the text of a `.cube` file. Only 3D LUTs are supported.
+    pub fn from_cube_str(text: &str) -> Result<Self, String> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        let parse_float = |value: &str, line_number: usize| value.parse::<f32>()
+            .map_err(|e| format!("Line {}: Invalid value '{}': {}", line_number, value, e));
+        let parse_floats = |values: &[&str], line_number: usize| -> Result<[f32; 3], String> {
+            let &[r, g, b] = values else {
+                return Err(format!("Line {}: Expected 3 values but got {}", line_number, values.len()));
+            };
+            Ok([parse_float(r, line_number)?, parse_float(g, line_number)?, parse_float(b, line_number)?])
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens[0] {
+                "TITLE" => {},
+                "LUT_3D_SIZE" => {
+                    let value = tokens.get(1)
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .filter(|value| (2..=Self::MAX_SIZE).contains(value))
+                        .ok_or_else(|| format!("Line {}: Invalid LUT_3D_SIZE", line_number))?;
+                    size = Some(value);
+                },
+                "LUT_1D_SIZE" => return Err("1D LUTs are not supported".to_string()),
+                "DOMAIN_MIN" => domain_min = parse_floats(&tokens[1..], line_number)?,
+                "DOMAIN_MAX" => domain_max = parse_floats(&tokens[1..], line_number)?,
+                // DaVinci Resolve's form of the domain, the same for all channels.
+                "LUT_3D_INPUT_RANGE" => {
+                    let &[min, max] = &tokens[1..] else {
+                        return Err(format!("Line {}: Expected 2 values but got {}", line_number, tokens.len() - 1));
+                    };
+                    domain_min = [parse_float(min, line_number)?; 3];
+                    domain_max = [parse_float(max, line_number)?; 3];
+                },
+                keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    return Err(format!("Line {}: Unsupported keyword {}", line_number, keyword));
+                },
+                _ => table.push(parse_floats(&tokens, line_number)?),
+            }
+        }
+
+        let size = size.ok_or_else(|| "Missing LUT_3D_SIZE".to_string())?;
+        if table.len() != size * size * size {
+            return Err(format!("Expected {} entries for LUT_3D_SIZE {} but got {}", size * size * size, size, table.len()));
+        }
+        if (0..3).any(|channel| domain_max[channel] <= domain_min[channel]) {
+            return Err(format!("Empty domain from {:?} to {:?}", domain_min, domain_max));
+        }
+
+        Ok(Self { size, domain_min, domain_max, table })
+    }
+
+    /// Looks up `rgb`, in the encoding of the LUT, with trilinear interpolation.
+    pub fn sample(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let max_index = (self.size - 1) as f32;
+        let position: [f32; 3] = std::array::from_fn(|channel| {
+            let normalized = (rgb[channel] - self.domain_min[channel]) / (self.domain_max[channel] - self.domain_min[channel]);
+            // NaN is treated as 0, since it would poison the interpolation.
+            if normalized.is_nan() { 0.0 } else { normalized.clamp(0.0, 1.0) * max_index }
+        });
+        // The lower corner of the cell, which is at most the second to last entry so that the upper corner exists.
+        let base = position.map(|position| (position as usize).min(self.size - 2));
+        let fraction: [f32; 3] = std::array::from_fn(|channel| position[channel] - base[channel] as f32);
+
+        let entry = |r: usize, g: usize, b: usize| self.table[(base[0] + r) + (base[1] + g) * self.size + (base[2] + b) * self.size * self.size];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| -> [f32; 3] { std::array::from_fn(|channel| a[channel] + (b[channel] - a[channel]) * t) };
+
+        let c00 = lerp(entry(0, 0, 0), entry(1, 0, 0), fraction[0]);
+        let c10 = lerp(entry(0, 1, 0), entry(1, 1, 0), fraction[0]);
+        let c01 = lerp(entry(0, 0, 1), entry(1, 0, 1), fraction[0]);
+        let c11 = lerp(entry(0, 1, 1), entry(1, 1, 1), fraction[0]);
+        let c0 = lerp(c00, c10, fraction[1]);
+        let c1 = lerp(c01, c11, fraction[1]);
+        lerp(c0, c1, fraction[2])
+    }
+
+    /// Applies the LUT to linear pixels in nits, usually BT.2020, by PQ-encoding them, looking them up and decoding the result.
+    pub(crate) fn apply<C: LinearImageContent>(&self, content: &mut C, width: usize, height: usize) {
+        for y in 0..height {
+            for x in 0..width {
+                let encoded = content.get_at(x, y).rgb().map(|value| st2084_oetf(value.clamp(0.0, 10000.0) / 10000.0));
+                let [r, g, b] = self.sample(encoded).map(|value| st2084_eotf(value) * 10000.0);
+                content.set_at(x, y, FloatPixel::new(r, g, b));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pixel::FloatImageContent;
+
+    /// A `.cube` of `size` computing `f` of the normalized input.
+    fn cube_text(size: usize, f: impl Fn([f32; 3]) -> [f32; 3]) -> String {
+        let mut text = format!("# Generated\nTITLE \"Test\"\nLUT_3D_SIZE {}\n\n", size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let [r, g, b] = f([r, g, b].map(|value| value as f32 / (size - 1) as f32));
+                    text += &format!("{} {} {}\n", r, g, b);
+                }
+            }
+        }
+        text
+    }
+
+    fn test_content() -> FloatImageContent {
+        let mut content = FloatImageContent::with_extent(4, 2);
+        for (index, nits) in [0.0, 0.5, 10.0, 80.0, 203.0, 1000.0, 4000.0, 10000.0].into_iter().enumerate() {
+            content.set_at(index % 4, index / 4, FloatPixel::new(nits, nits * 0.5, nits * 0.25));
+        }
+        content
+    }
+
+    #[test]
+    fn identity_lut_leaves_pixels_unchanged() {
+        let lut = CubeLut::from_cube_str(&cube_text(17, |rgb| rgb)).unwrap();
+
+        let mut content = test_content();
+        lut.apply(&mut content, 4, 2);
+
+        let expected = test_content();
+        for (actual, expected) in content.pixels().iter().zip(expected.pixels()) {
+            for channel in 0..3 {
+                let (actual, expected) = (actual.rgb()[channel], expected.rgb()[channel]);
+                assert!((actual - expected).abs() <= expected * 1e-3 + 1e-4, "{} != {}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn lut_changes_colors_predictably() {
+        // Swaps red and blue, and halves green, which is linear and hence exact under trilinear interpolation.
+        let lut = CubeLut::from_cube_str(&cube_text(5, |[r, g, b]| [b, g * 0.5, r])).unwrap();
+
+        let sampled = lut.sample([0.8, 0.3, 0.1]);
+        for (actual, expected) in sampled.into_iter().zip([0.1, 0.15, 0.8]) {
+            assert!((actual - expected).abs() < 1e-6, "{:?}", sampled);
+        }
+
+        let mut content = test_content();
+        lut.apply(&mut content, 4, 2);
+        // 1000 nits red and 250 nits blue are swapped.
+        let pixel = content.get_at(1, 1);
+        assert!((pixel.r() - 250.0).abs() < 0.5 && (pixel.b() - 1000.0).abs() < 0.5, "{:?}", pixel);
+
+        // Outside of the domain is clamped.
+        assert_eq!(lut.sample([2.0, -1.0, f32::NAN]), lut.sample([1.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn domain_maps_onto_the_table() {
+        let text = cube_text(2, |rgb| rgb).replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 0.5 0.5 0.5");
+        let lut = CubeLut::from_cube_str(&text).unwrap();
+        assert_eq!(lut.sample([0.25, 0.5, 1.0]), [0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn malformed_cubes_are_errors() {
+        assert!(CubeLut::from_cube_str("").is_err());
+        assert!(CubeLut::from_cube_str("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        // Too few entries.
+        assert!(CubeLut::from_cube_str("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(CubeLut::from_cube_str(&cube_text(2, |rgb| rgb).replace("1 1 1", "1 one 1")).is_err());
+        assert!(CubeLut::from_cube_str("LUT_3D_SIZE 100000\n").is_err());
+    }
+}
@@ -1,5 +1,15 @@
 
-use crate::tiff;
+use crate::segments;
+use crate::tiff::{self, Endianness, TiffFieldValue, TiffIfdEntry, TiffWriter};
+
+/// The tags of the MP Index IFD, and the size of each entry of the MP Entry table.
+const TAG_MP_FORMAT_VERSION: u16 = 0xB000;
+const TAG_NUMBER_OF_IMAGES: u16 = 0xB001;
+const TAG_MP_ENTRY: u16 = 0xB002;
+const MP_ENTRY_SIZE: usize = 16;
+
+/// The only MPF version there is.
+const MP_FORMAT_VERSION: &[u8] = b"0100";
 
 /// Represents the Multi-Page File (MPF) information extracted from TIFF bytes,
 /// which can be contained in a JPEG file.
@@ -18,63 +28,160 @@ pub struct MpfMpEntry {
 }
 
 impl MpfInfo {
+    /// Finds the offset of the MP endian field in `jpeg_bytes`, i.e. the start of the TIFF header in the MPF `APP2` segment.
+    /// The `individual_image_data_offset` of MP entries other than the first one are relative to this offset.
+    pub fn find_mp_endian_offset(jpeg_bytes: &[u8]) -> Option<usize> {
+        const MPF_SIGNATURE: &[u8] = b"MPF\0";
+
+        segments::header_segments(jpeg_bytes)
+            .find(|segment| segment.marker == 0xE2 && segment.payload.starts_with(MPF_SIGNATURE))
+            .map(|segment| segment.payload_offset + MPF_SIGNATURE.len())
+    }
+
+    /// Builds the `APP2` MPF payload, including the `MPF\0` signature, for a primary image followed by secondary images.
+    ///
+    /// - `image_sizes`: Sizes of each image, starting with the primary.
+    /// - `image_offsets`: Offsets of each image relative to the MP endian field, `0` for the primary.
+    ///
+    /// The payload has the same size regardless of the values.
+    pub fn app2_payload(image_sizes: &[u32], image_offsets: &[u32]) -> Vec<u8> {
+        const ENDIANNESS: Endianness = Endianness::BigEndian;
+
+        let mut mp_entries = Vec::new();
+        for (index, (&size, &offset)) in image_sizes.iter().zip(image_offsets).enumerate() {
+            // Baseline MP primary image, representative; otherwise undefined type.
+            let attribute: u32 = if index == 0 { 0x2003_0000 } else { 0x0000_0000 };
+            ENDIANNESS.write_u32(&mut mp_entries, attribute).unwrap();
+            ENDIANNESS.write_u32(&mut mp_entries, size).unwrap();
+            ENDIANNESS.write_u32(&mut mp_entries, offset).unwrap();
+            ENDIANNESS.write_u16(&mut mp_entries, 0).unwrap();
+            ENDIANNESS.write_u16(&mut mp_entries, 0).unwrap();
+        }
+
+        let mut tiff_writer = TiffWriter::new(ENDIANNESS);
+        tiff_writer.append_ifd(&[
+            TiffIfdEntry::new(TAG_MP_FORMAT_VERSION, TiffFieldValue::UNDEFINED(MP_FORMAT_VERSION.to_vec())),
+            TiffIfdEntry::new(TAG_NUMBER_OF_IMAGES, TiffFieldValue::LONG(vec![image_sizes.len() as u32])),
+            TiffIfdEntry::new(TAG_MP_ENTRY, TiffFieldValue::UNDEFINED(mp_entries)),
+        ]).unwrap();
+
+        let mut payload = b"MPF\0".to_vec();
+        payload.extend_from_slice(&tiff_writer.finish());
+        payload
+    }
+
     pub fn mp_entries(&self) -> &[MpfMpEntry] {
         &self.mp_entries
     }
 
+    /// Fails if the MP Index IFD is missing, has an unsupported version, lacks a required tag,
+    /// or has an MP Entry table too short for the number of images.
     pub fn new_from_bytes(mpf_bytes: &[u8]) -> std::io::Result<Self> {
         // https://web.archive.org/web/20160405200235/http://cipa.jp/std/documents/e/DC-007_E.pdf
 
+        let invalid_data = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
         let mpf_tiff = tiff::Tiff::from_reader(&mut std::io::Cursor::new(mpf_bytes))?;
 
-        let mp_index_ifd = mpf_tiff.ifds.first().unwrap();
+        let mp_index_ifd = mpf_tiff.ifds.first()
+            .ok_or_else(|| invalid_data("The MPF block has no MP Index IFD".to_string()))?;
+        let entry_with_tag = |tag: u16, name: &str| {
+            mp_index_ifd.entry_with_tag(tag)
+                .ok_or_else(|| invalid_data(format!("The MP Index IFD has no {} tag", name)))
+        };
+        let undefined_bytes = |tag: u16, name: &str| {
+            entry_with_tag(tag, name)?
+                .field_value_as_undefined()
+                .ok_or_else(|| invalid_data(format!("The {} tag of the MP Index IFD is not of type UNDEFINED", name)))
+        };
 
-        let version_entry = mp_index_ifd.entry_with_tag(0xB000).unwrap();
-        let version_bytes = version_entry.field_value_as_undefined().unwrap();
-        assert_eq!(version_bytes, &[48, 49, 48, 48], "Version bytes must be '0', '1', '0', '0'");
+        let version_bytes = undefined_bytes(TAG_MP_FORMAT_VERSION, "MPFVersion")?;
+        if version_bytes != MP_FORMAT_VERSION {
+            return Err(invalid_data(format!("Unsupported MPF version {:?}, expected \"0100\"", String::from_utf8_lossy(version_bytes))));
+        }
 
         let number_of_images = {
-            let number_of_images_entry = mp_index_ifd.entry_with_tag(0xB001).unwrap();
-            *number_of_images_entry.field_value_as_long().ok_or_else(|| {
-                std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Failed to read number of images",
-                )
-            })?.first().ok_or_else(|| {
-                std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "No value found for number of images",
-                )
-            })?
+            let number_of_images_entry = entry_with_tag(TAG_NUMBER_OF_IMAGES, "NumberOfImages")?;
+            // A BigTIFF-style MPF block may have a `LONG8` count.
+            let number_of_images = number_of_images_entry.field_value_as_long()
+                .map(|values| values.first().copied())
+                .or_else(|| number_of_images_entry.field_value_as_long8().map(|values| values.first().and_then(|&value| u32::try_from(value).ok())))
+                .ok_or_else(|| invalid_data("Failed to read number of images".to_string()))?;
+            number_of_images.ok_or_else(|| invalid_data("No value found for number of images".to_string()))?
         };
 
-        let mut mp_entries: Vec<MpfMpEntry> = Vec::new();
-        {
-            let mp_entry_entry = mp_index_ifd.entry_with_tag(0xB002).unwrap();
-            let mp_entry_bytes = mp_entry_entry.field_value_as_undefined().unwrap();
-            assert!(mp_entry_bytes.len() == 16 * number_of_images as usize);
-
-            for i in 0..number_of_images {
-                let mp_entry_bytes = &mp_entry_bytes[i as usize * 16..(i + 1) as usize * 16];
-                
-                let individual_image_attribute = &mp_entry_bytes[0..4];
-                let individual_image_size = mpf_tiff.header.endianness.read_u32(&mut &mp_entry_bytes[4..8])?;
-                let individual_image_data_offset = mpf_tiff.header.endianness.read_u32(&mut &mp_entry_bytes[8..12])?;
-                let dependent_image_1_entry_number = mpf_tiff.header.endianness.read_u16(&mut &mp_entry_bytes[12..14])?;
-                let dependent_image_2_entry_number = mpf_tiff.header.endianness.read_u16(&mut &mp_entry_bytes[14..16])?;
-
-                mp_entries.push(MpfMpEntry {
-                    individual_image_attribute: individual_image_attribute.try_into().unwrap(),
-                    individual_image_size,
-                    individual_image_data_offset,
-                    dependent_image_1_entry_number,
-                    dependent_image_2_entry_number,
-                });
-            }                
+        let mp_entry_bytes = undefined_bytes(TAG_MP_ENTRY, "MPEntry")?;
+        if mp_entry_bytes.len() / MP_ENTRY_SIZE < number_of_images as usize {
+            return Err(invalid_data(format!(
+                "The MP Entry table is truncated at {} bytes, expected {} for {} images",
+                mp_entry_bytes.len(), MP_ENTRY_SIZE * number_of_images as usize, number_of_images,
+            )));
         }
 
+        let endianness = mpf_tiff.header.endianness;
+        let mp_entries = mp_entry_bytes.chunks_exact(MP_ENTRY_SIZE)
+            .take(number_of_images as usize)
+            .map(|mp_entry_bytes| {
+                Ok(MpfMpEntry {
+                    individual_image_attribute: [mp_entry_bytes[0], mp_entry_bytes[1], mp_entry_bytes[2], mp_entry_bytes[3]],
+                    individual_image_size: endianness.read_u32(&mut &mp_entry_bytes[4..8])?,
+                    individual_image_data_offset: endianness.read_u32(&mut &mp_entry_bytes[8..12])?,
+                    dependent_image_1_entry_number: endianness.read_u16(&mut &mp_entry_bytes[12..14])?,
+                    dependent_image_2_entry_number: endianness.read_u16(&mut &mp_entry_bytes[14..16])?,
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
         Ok(Self {
             mp_entries,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The TIFF block of an MPF `APP2` payload, without the signature.
+    fn mpf_tiff_bytes(entries: &[TiffIfdEntry]) -> Vec<u8> {
+        let mut tiff_writer = TiffWriter::new(Endianness::BigEndian);
+        tiff_writer.append_ifd(entries).unwrap();
+        tiff_writer.finish()
+    }
+
+    #[test]
+    fn app2_payload_round_trips() {
+        let payload = MpfInfo::app2_payload(&[1000, 200], &[0, 960]);
+        let mpf_info = MpfInfo::new_from_bytes(&payload[4..]).unwrap();
+        let entries = mpf_info.mp_entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].individual_image_size, entries[0].individual_image_data_offset), (1000, 0));
+        assert_eq!((entries[1].individual_image_size, entries[1].individual_image_data_offset), (200, 960));
+    }
+
+    #[test]
+    fn malformed_mpf_blocks_are_errors_rather_than_panics() {
+        let payload = MpfInfo::app2_payload(&[1000, 200], &[0, 960]);
+        let tiff_bytes = &payload[4..];
+        for length in 0..tiff_bytes.len() {
+            assert!(MpfInfo::new_from_bytes(&tiff_bytes[..length]).is_err(), "Truncated to {} bytes", length);
+        }
+
+        let version = TiffIfdEntry::new(TAG_MP_FORMAT_VERSION, TiffFieldValue::UNDEFINED(MP_FORMAT_VERSION.to_vec()));
+        let number_of_images = TiffIfdEntry::new(TAG_NUMBER_OF_IMAGES, TiffFieldValue::LONG(vec![3]));
+        // Only 2 of the 3 entries.
+        let mp_entry = TiffIfdEntry::new(TAG_MP_ENTRY, TiffFieldValue::UNDEFINED(vec![0; 2 * MP_ENTRY_SIZE]));
+        let wrong_version = TiffIfdEntry::new(TAG_MP_FORMAT_VERSION, TiffFieldValue::UNDEFINED(b"0200".to_vec()));
+        for (entries, expected) in [
+            (vec![version.clone(), number_of_images.clone(), mp_entry.clone()], "truncated"),
+            (vec![wrong_version, number_of_images.clone(), mp_entry.clone()], "Unsupported MPF version"),
+            (vec![number_of_images.clone(), mp_entry.clone()], "no MPFVersion tag"),
+            (vec![version.clone(), mp_entry], "no NumberOfImages tag"),
+            (vec![version, number_of_images], "no MPEntry tag"),
+        ] {
+            let error = MpfInfo::new_from_bytes(&mpf_tiff_bytes(&entries)).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+            assert!(error.to_string().contains(expected), "{}", error);
+        }
+    }
+}
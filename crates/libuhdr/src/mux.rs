@@ -0,0 +1,294 @@
+//! Muxing an SDR JPEG and a gain map JPEG into an Ultra HDR JPEG.
+//!
+//! See: https://developer.android.com/media/platform/hdr-image-format#encode
+
+use std::io::Write;
+
+use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+use crate::colorspace::{ColorGamut, ColorGamutConverter};
+use crate::gainmap::GainMapMetadata;
+use crate::jpeg::UhdrJpeg;
+use crate::mpf::MpfInfo;
+use crate::pixel::FloatImageContent;
+use crate::segments;
+use crate::uhdr::compute_gain_map;
+
+const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const EXTENDED_XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+const EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+const MPF_SIGNATURE: &[u8] = b"MPF\0";
+
+/// The quality the gain map of `write_uhdr_jpeg_from_hdr` is encoded with. High, since its errors are exponentiated.
+const GAIN_MAP_JPEG_QUALITY: u8 = 95;
+
+/// Writes an Ultra HDR JPEG with `sdr_jpeg_bytes` as the primary image and `gain_map_jpeg_bytes` as the gain map.
+///
+/// Both JPEGs are copied as is, except for their XMP and MPF segments, which are replaced:
+/// The primary image gets the XMP `Container` directory and the MPF segment locating the gain map,
+/// and the gain map gets the `hdrgm` XMP describing `metadata`.
+/// If `metadata` is `None`, it is read from the XMP the gain map JPEG already has.
+pub fn write_uhdr_jpeg<W: Write>(
+    writer: &mut W,
+    sdr_jpeg_bytes: &[u8],
+    gain_map_jpeg_bytes: &[u8],
+    metadata: Option<&GainMapMetadata>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = match metadata {
+        Some(metadata) => *metadata,
+        None => find_xmp(gain_map_jpeg_bytes)
+            .ok_or_else(|| "The gain map JPEG does not contain gain map metadata, and none was specified".to_string())
+            .and_then(|xmp_bytes| GainMapMetadata::new_from_xmp_bytes(xmp_bytes)
+                .map_err(|e| format!("The gain map JPEG does not contain valid gain map metadata: {}", e)))?,
+    };
+
+    let gain_map_jpeg = rewrite_header(gain_map_jpeg_bytes, metadata.to_xmp().as_bytes(), None)
+        .map_err(|e| format!("Invalid gain map JPEG: {}", e))?;
+
+    let primary_xmp = format!(
+        concat!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">"#,
+            r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">"#,
+            r#"<rdf:Description rdf:about="" xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/" xmlns:Container="http://ns.google.com/photos/1.0/container/" xmlns:Item="http://ns.google.com/photos/1.0/container/item/" hdrgm:Version="1.0">"#,
+            "<Container:Directory><rdf:Seq>",
+            r#"<rdf:li rdf:parseType="Resource"><Container:Item Item:Semantic="Primary" Item:Mime="image/jpeg"/></rdf:li>"#,
+            r#"<rdf:li rdf:parseType="Resource"><Container:Item Item:Semantic="GainMap" Item:Mime="image/jpeg" Item:Length="{}"/></rdf:li>"#,
+            "</rdf:Seq></Container:Directory>",
+            "</rdf:Description>",
+            "</rdf:RDF>",
+            "</x:xmpmeta>",
+        ),
+        gain_map_jpeg.len(),
+    );
+
+    // The MPF payload has the same size regardless of the values, so build the primary image once to learn the layout.
+    let build_primary = |image_sizes: &[u32], image_offsets: &[u32]| {
+        rewrite_header(sdr_jpeg_bytes, primary_xmp.as_bytes(), Some(&MpfInfo::app2_payload(image_sizes, image_offsets)))
+            .map_err(|e| format!("Invalid SDR JPEG: {}", e))
+    };
+    let placeholder = build_primary(&[0, 0], &[0, 0])?;
+    let mp_endian_offset = MpfInfo::find_mp_endian_offset(&placeholder)
+        .ok_or_else(|| "Failed to locate the MPF segment just written".to_string())?;
+
+    let primary_size = u32::try_from(placeholder.len()).map_err(|_| "The SDR JPEG is too large for MPF".to_string())?;
+    let gain_map_size = u32::try_from(gain_map_jpeg.len()).map_err(|_| "The gain map JPEG is too large for MPF".to_string())?;
+    let primary_jpeg = build_primary(&[primary_size, gain_map_size], &[0, primary_size - mp_endian_offset as u32])?;
+    debug_assert_eq!(primary_jpeg.len(), placeholder.len());
+
+    writer.write_all(&primary_jpeg)?;
+    writer.write_all(&gain_map_jpeg)?;
+    Ok(())
+}
+
+/// Writes an Ultra HDR JPEG with `sdr_jpeg_bytes` as the primary image and the gain map of `compute_gain_map`
+/// that reconstructs `hdr` from it, i.e. authors one from an SDR and HDR pair, which `write_uhdr_jpeg` then muxes.
+///
+/// `hdr` is linear in `hdr_color_gamut`, where 1 is SDR white, e.g. a Radiance `.hdr` written by `UhdrConverter::convert_to_hdr`,
+/// and is converted to the color gamut of the SDR JPEG first. With `None`, it is taken to be in that of the SDR JPEG already. It must have the extent of the SDR JPEG as stored,
+/// i.e. before its EXIF orientation is applied. The gain map is of the same extent, encoded as a 4:4:4 JPEG,
+/// or a grayscale one if its channels are all the same.
+pub fn write_uhdr_jpeg_from_hdr<W: Write>(
+    writer: &mut W,
+    sdr_jpeg_bytes: &[u8],
+    hdr: &FloatImageContent,
+    hdr_color_gamut: Option<&ColorGamut>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sdr_jpeg = UhdrJpeg::new_from_bytes(sdr_jpeg_bytes).map_err(|e| format!("Invalid SDR JPEG: {}", e))?;
+    let (width, height) = sdr_jpeg.extent();
+    if hdr.extent() != (width, height) {
+        return Err(format!("The SDR JPEG is {}x{}, but the HDR image is {}x{}", width, height, hdr.width(), hdr.height()).into());
+    }
+
+    let mut sdr = FloatImageContent::with_extent(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            sdr.set_at(x, y, sdr_jpeg.fetch_pixel_linear(x, y).into());
+        }
+    }
+    let mut hdr = hdr.clone();
+    if let Some(hdr_color_gamut) = hdr_color_gamut {
+        let to_sdr_color_gamut = ColorGamutConverter::new(hdr_color_gamut, &sdr_jpeg.color_gamut().unwrap_or(ColorGamut::srgb()));
+        for pixel in hdr.pixels_mut() {
+            *pixel = to_sdr_color_gamut.convert(pixel.rgb()).into();
+        }
+    }
+
+    let (gain_map, metadata) = compute_gain_map(&sdr, &hdr);
+    let recovery: Vec<[u8; 3]> = gain_map.pixels().iter()
+        .map(|pixel| pixel.rgb().map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8))
+        .collect();
+
+    let (Ok(jpeg_width), Ok(jpeg_height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(format!("A {}x{} gain map is too large for a JPEG", width, height).into());
+    };
+    let mut gain_map_jpeg_bytes = Vec::new();
+    let mut encoder = Encoder::new(&mut gain_map_jpeg_bytes, GAIN_MAP_JPEG_QUALITY);
+    encoder.set_sampling_factor(SamplingFactor::R_4_4_4);
+    if recovery.iter().all(|&[r, g, b]| r == g && g == b) {
+        let luma: Vec<u8> = recovery.iter().map(|&[r, _, _]| r).collect();
+        encoder.encode(&luma, jpeg_width, jpeg_height, ColorType::Luma)?;
+    } else {
+        encoder.encode(recovery.as_flattened(), jpeg_width, jpeg_height, ColorType::Rgb)?;
+    }
+
+    write_uhdr_jpeg(writer, sdr_jpeg_bytes, &gain_map_jpeg_bytes, Some(&metadata))
+}
+
+/// The XMP packet of `jpeg_bytes`, if any.
+pub(crate) fn find_xmp(jpeg_bytes: &[u8]) -> Option<&[u8]> {
+    segments::header_segments(jpeg_bytes)
+        .filter(|segment| segment.marker == 0xE1)
+        .find_map(|segment| segment.payload.strip_prefix(XMP_SIGNATURE))
+}
+
+/// Copies `jpeg_bytes`, replacing any XMP and MPF segments with `xmp` and `mpf_payload`.
+///
+/// The XMP segment is placed after any JFIF `APP0` and Exif `APP1` segments, which are expected to come first,
+/// and the MPF segment after all `APPn` segments.
+/// Anything after the end of image marker, such as the gain map of an input that already is an Ultra HDR JPEG, is dropped.
+fn rewrite_header(jpeg_bytes: &[u8], xmp: &[u8], mpf_payload: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let segments: Vec<_> = segments::header_segments(jpeg_bytes).collect();
+    let header_end = segments.last()
+        .map(|segment| segment.payload_offset + segment.payload.len())
+        .ok_or_else(|| "Not a JPEG".to_string())?;
+    let image_end = segments::image_length(jpeg_bytes)
+        .ok_or_else(|| "No end of image marker".to_string())?;
+
+    let is_app = |marker: u8| (0xE0..=0xEF).contains(&marker);
+    let is_replaced = |segment: &segments::JpegSegment| {
+        (segment.marker == 0xE1 && (segment.payload.starts_with(XMP_SIGNATURE) || segment.payload.starts_with(EXTENDED_XMP_SIGNATURE)))
+            || (segment.marker == 0xE2 && segment.payload.starts_with(MPF_SIGNATURE))
+    };
+    let is_before_xmp = |segment: &segments::JpegSegment| {
+        segment.marker == 0xE0 || (segment.marker == 0xE1 && segment.payload.starts_with(EXIF_SIGNATURE))
+    };
+
+    let mut xmp_payload = XMP_SIGNATURE.to_vec();
+    xmp_payload.extend_from_slice(xmp);
+
+    let mut bytes = vec![0xFF, 0xD8];
+    let mut pending_xmp = Some(xmp_payload.as_slice());
+    let mut pending_mpf = mpf_payload;
+    for segment in segments.iter().filter(|segment| !is_replaced(segment)) {
+        if !is_before_xmp(segment) && let Some(xmp_payload) = pending_xmp.take() {
+            write_segment(&mut bytes, 0xE1, xmp_payload)?;
+        }
+        if !is_app(segment.marker) && let Some(mpf_payload) = pending_mpf.take() {
+            write_segment(&mut bytes, 0xE2, mpf_payload)?;
+        }
+
+        bytes.extend_from_slice(&jpeg_bytes[segment.payload_offset - 4..segment.payload_offset + segment.payload.len()]);
+    }
+    if pending_xmp.is_some() || pending_mpf.is_some() {
+        return Err("No image data".to_string());
+    }
+
+    bytes.extend_from_slice(&jpeg_bytes[header_end..image_end]);
+    Ok(bytes)
+}
+
+fn write_segment(bytes: &mut Vec<u8>, marker: u8, payload: &[u8]) -> Result<(), String> {
+    let length = u16::try_from(payload.len() + 2)
+        .map_err(|_| format!("Segment of {} bytes doesn't fit in a single marker segment", payload.len()))?;
+    bytes.extend_from_slice(&[0xFF, marker]);
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(payload);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil;
+    use crate::UhdrConverter;
+
+    #[test]
+    fn muxed_jpeg_round_trips() {
+        let primary = testutil::TestImage::from_fn(16, 8, |x, y| [(x * 16) as u8, (y * 32) as u8, 128]);
+        let gain_map = testutil::TestImage::from_fn(8, 4, |x, _| [(x * 32) as u8; 3]);
+        let params = testutil::TestGainMapParams { gain_map_min: -0.5, gamma: 1.5, ..Default::default() };
+
+        let expected_bytes = testutil::build_uhdr_jpeg(&primary, &gain_map, params);
+        let expected = UhdrConverter::new(&mut &expected_bytes[..], 4.0).unwrap();
+        let metadata = expected.info().gain_maps[0].metadata;
+
+        // The SDR JPEG has an ICC profile to preserve, and a stale XMP packet to replace.
+        let icc_profile = lcms2::Profile::new_srgb().icc().unwrap();
+        let sdr_bytes = testutil::encode_jpeg_with_icc_profile(&primary, &[testutil::xmp_app_segment("<stale/>")], Some(&icc_profile));
+
+        let bare_gain_map_bytes = testutil::encode_jpeg(&gain_map, &[]);
+        let gain_map_bytes = testutil::encode_jpeg(&gain_map, &[testutil::xmp_app_segment(&params.to_xmp())]);
+
+        let mut with_metadata = Vec::new();
+        write_uhdr_jpeg(&mut with_metadata, &sdr_bytes, &bare_gain_map_bytes, Some(&metadata)).unwrap();
+        // The metadata the gain map JPEG already has is used if none is specified.
+        let mut with_embedded_metadata = Vec::new();
+        write_uhdr_jpeg(&mut with_embedded_metadata, &sdr_bytes, &gain_map_bytes, None).unwrap();
+        assert!(write_uhdr_jpeg(&mut Vec::new(), &sdr_bytes, &bare_gain_map_bytes, None).is_err());
+        assert!(write_uhdr_jpeg(&mut Vec::new(), b"not a JPEG", &gain_map_bytes, None).is_err());
+
+        let dst_color_gamut = ColorGamut::bt2020();
+        let expected_pixels = expected.compute_linear_pixels(80.0, &dst_color_gamut);
+        for muxed_bytes in [with_metadata, with_embedded_metadata] {
+            let converter = UhdrConverter::new(&mut &muxed_bytes[..], 4.0).unwrap();
+
+            let info = converter.info();
+            assert_eq!((info.width, info.height), (16, 8));
+            assert!(info.has_icc_profile);
+            assert_eq!(info.gain_maps.len(), 1);
+            let muxed_metadata = info.gain_maps[0].metadata;
+            assert_eq!(muxed_metadata.gain_map_min, metadata.gain_map_min);
+            assert_eq!(muxed_metadata.gain_map_max, metadata.gain_map_max);
+            assert_eq!(muxed_metadata.gamma, metadata.gamma);
+            assert_eq!(muxed_metadata.hdr_capacity_max, metadata.hdr_capacity_max);
+
+            // The primary image keeps only the new XMP packet, which announces the gain map.
+            assert!(testutil::find(&muxed_bytes, b"<stale/>").is_none());
+            let primary_xmp = std::str::from_utf8(find_xmp(&muxed_bytes).unwrap()).unwrap();
+            assert!(primary_xmp.contains(r#"Item:Semantic="GainMap""#), "{}", primary_xmp);
+
+            // The sRGB ICC profile only differs from the implicit sRGB of `expected` by rounding.
+            let pixels = converter.compute_linear_pixels(80.0, &dst_color_gamut);
+            for (actual, expected) in pixels.pixels().iter().zip(expected_pixels.pixels()) {
+                for channel in 0..3 {
+                    assert!((actual[channel] - expected[channel]).abs() <= expected[channel].abs() * 0.05 + 0.5, "{:?} != {:?}", actual, expected);
+                }
+            }
+        }
+    }
+    #[test]
+    fn jpeg_authored_from_hdr_reconstructs_it() {
+        let primary = testutil::TestImage::from_fn(16, 8, |x, y| [(x * 16) as u8, (y * 32) as u8, 128]);
+        let sdr_bytes = testutil::encode_jpeg(&primary, &[]);
+        let sdr_jpeg = UhdrJpeg::new_from_bytes(&sdr_bytes).unwrap();
+
+        // A boost of up to 4x towards the right, and of up to 2x more in red towards the bottom, in BT.2020.
+        let to_bt2020 = ColorGamutConverter::new(&ColorGamut::srgb(), &ColorGamut::bt2020());
+        let mut hdr = FloatImageContent::with_extent(16, 8);
+        for y in 0..8 {
+            for x in 0..16 {
+                let [r, g, b] = sdr_jpeg.fetch_pixel_linear(x, y);
+                let boost = 1.0 + x as f32 / 5.0;
+                hdr.set_at(x, y, to_bt2020.convert(&[r * boost * (1.0 + y as f32 / 7.0), g * boost, b * boost]).into());
+            }
+        }
+
+        let mut uhdr_bytes = Vec::new();
+        write_uhdr_jpeg_from_hdr(&mut uhdr_bytes, &sdr_bytes, &hdr, Some(&ColorGamut::bt2020())).unwrap();
+        assert!(write_uhdr_jpeg_from_hdr(&mut Vec::new(), &sdr_bytes, &FloatImageContent::with_extent(8, 8), None).is_err());
+
+        let converter = UhdrConverter::new(&mut &uhdr_bytes[..], 1000.0).unwrap();
+        let metadata = converter.info().gain_maps[0].metadata;
+        // The peak boost of 8x in red, less where the offsets dominate.
+        assert!((2.5..=3.0).contains(&metadata.hdr_capacity_max), "{:?}", metadata);
+
+        // With the headroom of the peak, the HDR rendition is `hdr`, up to the quantization of both JPEGs.
+        let pixels = converter.compute_linear_pixels(1.0, &ColorGamut::bt2020());
+        for (actual, expected) in pixels.pixels().iter().zip(hdr.pixels()) {
+            for (actual_value, expected_value) in actual.rgb().iter().zip(expected.rgb()) {
+                assert!((actual_value - expected_value).abs() <= expected_value.abs() * 0.05 + 0.02, "{:?} != {:?}", actual, expected);
+            }
+        }
+    }
+}
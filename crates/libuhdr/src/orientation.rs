@@ -0,0 +1,124 @@
+use crate::pixel::LinearImageContent;
+
+/// How the stored pixels of an image are to be displayed, i.e. the values of the EXIF `Orientation` tag.
+///
+/// The rotations are clockwise, of the stored image to display it upright.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum Orientation {
+    /// 1: As stored.
+    #[default]
+    Normal,
+    /// 2: Mirrored left to right.
+    MirrorHorizontal,
+    /// 3: Rotated by 180 degrees.
+    Rotate180,
+    /// 4: Mirrored top to bottom.
+    MirrorVertical,
+    /// 5: Mirrored along the top-left to bottom-right diagonal.
+    Transpose,
+    /// 6: Rotated by 90 degrees, as for a phone held upright with the camera sensor in landscape.
+    Rotate90,
+    /// 7: Mirrored along the top-right to bottom-left diagonal.
+    Transverse,
+    /// 8: Rotated by 270 degrees.
+    Rotate270,
+}
+
+impl Orientation {
+    /// `None` for values outside of [1, 8].
+    pub fn from_exif_value(value: u16) -> Option<Self> {
+        match value {
+            1 => Some(Self::Normal),
+            2 => Some(Self::MirrorHorizontal),
+            3 => Some(Self::Rotate180),
+            4 => Some(Self::MirrorVertical),
+            5 => Some(Self::Transpose),
+            6 => Some(Self::Rotate90),
+            7 => Some(Self::Transverse),
+            8 => Some(Self::Rotate270),
+            _ => None,
+        }
+    }
+
+    /// Whether the width and height are swapped for display, i.e. for the transposing orientations 5 to 8.
+    pub fn swaps_dimensions(self) -> bool {
+        matches!(self, Self::Transpose | Self::Rotate90 | Self::Transverse | Self::Rotate270)
+    }
+
+    /// The displayed dimensions of an image stored with `(width, height)`.
+    pub fn apply_to_extent(self, (width, height): (usize, usize)) -> (usize, usize) {
+        if self.swaps_dimensions() { (height, width) } else { (width, height) }
+    }
+
+    /// The coordinates in the stored image of the displayed pixel `(x, y)`, for a stored image of `(width, height)`.
+    pub(crate) fn stored_coordinates(self, (x, y): (usize, usize), (width, height): (usize, usize)) -> (usize, usize) {
+        match self {
+            Self::Normal => (x, y),
+            Self::MirrorHorizontal => (width - 1 - x, y),
+            Self::Rotate180 => (width - 1 - x, height - 1 - y),
+            Self::MirrorVertical => (x, height - 1 - y),
+            Self::Transpose => (y, x),
+            Self::Rotate90 => (y, height - 1 - x),
+            Self::Transverse => (width - 1 - y, height - 1 - x),
+            Self::Rotate270 => (width - 1 - y, x),
+        }
+    }
+
+    /// Rotates and mirrors `content` of the stored `extent` for display.
+    pub(crate) fn apply<C: LinearImageContent>(self, content: C, extent: (usize, usize)) -> C {
+        if self == Self::Normal {
+            return content;
+        }
+
+        let (width, height) = self.apply_to_extent(extent);
+        let mut oriented = C::with_extent(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = self.stored_coordinates((x, y), extent);
+                oriented.set_at(x, y, content.get_at(src_x, src_y));
+            }
+        }
+        oriented
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pixel::{FloatImageContent, FloatPixel};
+
+    #[test]
+    fn all_orientations_are_applied() {
+        // 3x2, each pixel holding its stored index:
+        // 0 1 2
+        // 3 4 5
+        let mut content = FloatImageContent::with_extent(3, 2);
+        for index in 0..6 {
+            content.set_at(index % 3, index / 3, FloatPixel::from([index as f32; 3]));
+        }
+
+        let expected: [(u16, (usize, usize), &[f32]); 8] = [
+            (1, (3, 2), &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]),
+            (2, (3, 2), &[2.0, 1.0, 0.0, 5.0, 4.0, 3.0]),
+            (3, (3, 2), &[5.0, 4.0, 3.0, 2.0, 1.0, 0.0]),
+            (4, (3, 2), &[3.0, 4.0, 5.0, 0.0, 1.0, 2.0]),
+            (5, (2, 3), &[0.0, 3.0, 1.0, 4.0, 2.0, 5.0]),
+            (6, (2, 3), &[3.0, 0.0, 4.0, 1.0, 5.0, 2.0]),
+            (7, (2, 3), &[5.0, 2.0, 4.0, 1.0, 3.0, 0.0]),
+            (8, (2, 3), &[2.0, 5.0, 1.0, 4.0, 0.0, 3.0]),
+        ];
+        for (value, extent, indices) in expected {
+            let orientation = Orientation::from_exif_value(value).unwrap();
+            assert_eq!(orientation.apply_to_extent((3, 2)), extent, "{:?}", orientation);
+
+            let oriented = orientation.apply(content.clone(), (3, 2));
+            let actual: Vec<f32> = oriented.pixels().iter().map(|pixel| pixel.r()).collect();
+            assert_eq!(actual, indices, "{:?}", orientation);
+        }
+
+        assert_eq!(Orientation::from_exif_value(0), None);
+        assert_eq!(Orientation::from_exif_value(9), None);
+    }
+}
@@ -2,103 +2,2025 @@
 
 use std::io::Write;
 
+use log::info;
 use ravif::*;
 use rav1e::color::ColorPrimaries as Rav1eColorPrimaries;
+use rav1e::color::MatrixCoefficients as Rav1eMatrixCoefficients;
 use rav1e::color::TransferCharacteristics as Rav1eTransferCharacteristics;
 use rav1e::color::PixelRange;
 
-use crate::pixel::FloatImageContent;
+use crate::colorspace::{Cicp, ColorGamut};
+use crate::pixel::LinearImageContent;
+use crate::transfer::{hlg_inverse_ootf, hlg_inverse_oetf, hlg_oetf, st2084_eotf, st2084_oetf, st2084_oetf_in_place};
 
-pub fn write_hdr10_linear_pixels_to_avif<W: Write>(
+pub fn write_hdr10_linear_pixels_to_avif<W: Write, C: LinearImageContent>(
     writer: &mut W,
     width: usize,
     height: usize,
-    content: &FloatImageContent,
+    content: &C,
 ) -> std::io::Result<()> {
+    write_hdr10_linear_pixels_to_avif_with_options(writer, width, height, content, &Hdr10AvifOptions::default())
+}
+
+/// Optional parameters of `write_hdr10_linear_pixels_to_avif_with_options`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hdr10AvifOptions<'a> {
+    /// Encodes with this AV1 base quantizer, see `write_hdr10_ycbcr_pixels_to_avif_with_quantizer`,
+    /// instead of the quality of `encode`.
+    pub quantizer: Option<u8>,
+    /// The quality, speed and range of the encoder.
+    pub encode: AvifEncodeOptions,
+    /// A TIFF block to add as an `Exif` item.
+    pub exif: Option<&'a [u8]>,
+    /// How the quantization to code values rounds.
+    pub rounding: Rounding,
+    /// The bit depth of the samples.
+    pub bit_depth: AvifBitDepth,
+    /// The peak luminance in nits the content was mastered for. Luminance is clamped to it instead of the 10,000 nits of PQ,
+    /// and it is tagged as the peak of the default `MasteringDisplay` in an `mdcv` box unless `mastering_display` is specified.
+    /// For HLG, it is only clamped to, and at most to `HLG_NOMINAL_PEAK_LUMINANCE` anyway.
+    pub peak_luminance: Option<f32>,
+    /// The content light levels to tag PQ output with, e.g. the values of the mastering, instead of those measured
+    /// from the clamped content. Ignored for HLG.
+    pub light_levels: Option<Hdr10LightLevels>,
+    /// The mastering display to tag PQ output with in an `mdcv` box. Ignored for HLG.
+    pub mastering_display: Option<MasteringDisplay>,
+    /// The transfer characteristics the samples are encoded with.
+    pub transfer: AvifTransfer,
+    /// The primaries of the color gamut the linear pixels are in, which are signalled along with their matrix.
+    pub primaries: AvifColorPrimaries,
+    /// Whether the samples are Y'C'bC'r with the matrix of `primaries`, or ICtCp.
+    pub color_space: AvifColorSpace,
+}
+
+/// The quality, speed, range and chroma subsampling HDR AVIF is encoded with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AvifEncodeOptions {
+    /// `ravif`'s quality in [0, 100], where 100 is a quantizer of 0. Below 85, it maps roughly linearly onto the quantizer,
+    /// e.g. quality 80 is quantizer 121.
+    pub quality: f32,
+    /// rav1e's speed preset in [0, 10], where 0 is the slowest and compresses best.
+    pub speed: u8,
+    /// The range of the Y'CbCr code values.
+    pub range: AvifRange,
+    /// The resolution of C'b and C'r. 4:4:4 by default, which is what `ravif` always encoded before the option existed.
+    pub chroma: AvifChroma,
+}
+
+impl Default for AvifEncodeOptions {
+    fn default() -> Self {
+        Self { quality: 100.0, speed: 4, range: AvifRange::Full, chroma: AvifChroma::Chroma444 }
+    }
+}
+
+/// The chroma subsampling of HDR AVIF output.
+///
+/// C'b and C'r are derived from PQ- or HLG-encoded values, so at a saturated HDR edge they change abruptly.
+/// Subsampling them to 4:2:0 smears them across the edge onto the luma of the other side, causing visible fringing,
+/// in exchange for half as many samples to encode and a smaller file at the same quantizer, by how much depending on
+/// the chroma detail of the content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AvifChroma {
+    /// C'b and C'r at full resolution, in AV1's High profile for 10 bits.
+    #[default]
+    Chroma444,
+    /// C'b and C'r at half the width and height, averaged over blocks of 2x2 pixels, in AV1's Main profile for 10 bits,
+    /// which every AV1 decoder supports. Always encoded by rav1e directly, as `ravif` only encodes 4:4:4.
+    Chroma420,
+    /// Only Y', as a monochrome AV1 picture in the Main profile, for neutral content whose C'b and C'r are all centered anyway.
+    /// Any color is dropped. `UhdrConverter` switches to it by itself for grayscale JPEGs that convert to neutral pixels.
+    Monochrome,
+}
+
+impl AvifChroma {
+    fn rav1e(self) -> rav1e::color::ChromaSampling {
+        match self {
+            Self::Chroma444 => rav1e::color::ChromaSampling::Cs444,
+            Self::Chroma420 => rav1e::color::ChromaSampling::Cs420,
+            Self::Monochrome => rav1e::color::ChromaSampling::Cs400,
+        }
+    }
+
+    /// The base-2 logarithm of the number of pixels per C'b and C'r sample, both horizontally and vertically.
+    /// Monochrome has no C'b and C'r samples, but is signalled as subsampled in both directions, per the AV1 specification.
+    fn shift(self) -> usize {
+        match self {
+            Self::Chroma444 => 0,
+            Self::Chroma420 | Self::Monochrome => 1,
+        }
+    }
+
+    /// The number of planes of the AV1 picture.
+    fn plane_count(self) -> usize {
+        match self {
+            Self::Chroma444 | Self::Chroma420 => 3,
+            Self::Monochrome => 1,
+        }
+    }
+}
+
+/// The range of the Y'CbCr code values of HDR AVIF output, per Rec. ITU-R BT.2100-3, Table 9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AvifRange {
+    /// All code values, e.g. [0, 1023] for 10 bits.
+    #[default]
+    Full,
+    /// The "Narrow" range of video, e.g. Y' in [64, 940] and C'b, C'r in [64, 960] for 10 bits,
+    /// which some players and TVs expect.
+    Limited,
+}
+
+impl AvifRange {
+    fn rav1e(self) -> PixelRange {
+        match self {
+            Self::Full => PixelRange::Full,
+            Self::Limited => PixelRange::Limited,
+        }
+    }
+
+    /// The scale of Y' and of C'b, C'r and the offset of Y' in code values of `bit_depth` bits.
+    /// The offset of C'b and C'r is `2^(n - 1)` either way.
+    fn scales_and_offset(self, bit_depth: u8) -> (f32, f32, f32) {
+        match self {
+            Self::Full => {
+                let max = ((1u32 << bit_depth) - 1) as f32;
+                (max, max, 0.0)
+            },
+            Self::Limited => {
+                let step = (1u32 << (bit_depth - 8)) as f32;
+                (219.0 * step, 224.0 * step, 16.0 * step)
+            },
+        }
+    }
+}
+
+impl AvifEncodeOptions {
+    pub const MAX_QUALITY: f32 = 100.0;
+    pub const MAX_SPEED: u8 = 10;
+
+    /// Fails unless `quality` is in [0, `MAX_QUALITY`] and `speed` in [0, `MAX_SPEED`].
+    pub fn validate(&self) -> std::io::Result<()> {
+        if !(0.0..=Self::MAX_QUALITY).contains(&self.quality) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The AVIF quality {} is not in [0, {}]", self.quality, Self::MAX_QUALITY),
+            ));
+        }
+        if self.speed > Self::MAX_SPEED {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The AVIF speed {} is not in [0, {}]", self.speed, Self::MAX_SPEED),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The AV1 base quantizer `ravif` maps `quality` to, so that rav1e driven directly encodes at the same quantizer.
+    ///
+    /// This is a copy of `quality_to_quantizer` in `ravif`'s `av1encoder.rs`, which is private, and which `ravif` offers
+    /// no way around, hence the exact version of `ravif` in `Cargo.toml`. Check it against this when updating `ravif`.
+    fn quantizer(&self) -> u8 {
+        let quality = self.quality / 100.0;
+        let x = if quality >= 0.85 {
+            (1.0 - quality) * 3.0
+        } else if quality > 0.25 {
+            1.0 - 0.125 - quality * 0.5
+        } else {
+            1.0 - quality
+        };
+        (x * 255.0).round() as u8
+    }
+
+    /// `ravif` rejects a quality below 1 and a speed of 0.
+    fn is_supported_by_ravif(&self) -> bool {
+        self.quality >= 1.0 && self.speed >= 1
+    }
+}
+
+/// The parameters of `encode_ycbcr_pixels_to_av1`, and the color description `mux_hdr_avif` signals.
+struct Av1EncodeParams {
+    quantizer: u8,
+    speed: u8,
+    bit_depth: u8,
+    transfer: AvifTransfer,
+    range: AvifRange,
+    chroma: AvifChroma,
+    primaries: AvifColorPrimaries,
+    color_space: AvifColorSpace,
+}
+
+impl Av1EncodeParams {
+    /// The color primaries and matrix coefficients of `primaries`, or those of ICtCp for `AvifColorSpace::Ictcp`.
+    fn rav1e_color_primaries(&self) -> (Rav1eColorPrimaries, Rav1eMatrixCoefficients) {
+        let (color_primaries, matrix_coefficients) = self.primaries.rav1e();
+        match self.color_space {
+            AvifColorSpace::Ycbcr => (color_primaries, matrix_coefficients),
+            AvifColorSpace::Ictcp => (color_primaries, Rav1eMatrixCoefficients::ICtCp),
+        }
+    }
+}
+
+/// The transfer characteristics of HDR AVIF output. The primaries and the matrix are those of `AvifColorPrimaries` either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AvifTransfer {
+    /// BT.2100 PQ, i.e. HDR10, which encodes absolute luminance up to 10,000 nits.
+    #[default]
+    Pq,
+    /// BT.2100 HLG, i.e. ARIB STD-B67, which encodes luminance relative to the display's peak, and which TVs and phones
+    /// that don't support PQ often do. Luminance is encoded for the reference display of `HLG_NOMINAL_PEAK_LUMINANCE`,
+    /// with `HLG_REFERENCE_WHITE_LUMINANCE` at a 75% signal, and clipped above the nominal peak.
+    Hlg,
+}
+
+/// The nominal peak luminance in nits of the HLG reference display, for which the system gamma is `1.2`.
+pub const HLG_NOMINAL_PEAK_LUMINANCE: f32 = crate::colorspace::SourceTransfer::HLG_NOMINAL_PEAK_LUMINANCE;
+
+/// The luminance in nits of HLG reference white, which SDR white is mapped to.
+pub const HLG_REFERENCE_WHITE_LUMINANCE: f32 = crate::colorspace::SourceTransfer::HLG_REFERENCE_WHITE_LUMINANCE;
+
+impl AvifTransfer {
+    fn rav1e(self) -> Rav1eTransferCharacteristics {
+        match self {
+            Self::Pq => Rav1eTransferCharacteristics::SMPTE2084,
+            Self::Hlg => Rav1eTransferCharacteristics::HLG,
+        }
+    }
+
+    fn avif_serialize(self) -> avif_serialize::constants::TransferCharacteristics {
+        match self {
+            Self::Pq => avif_serialize::constants::TransferCharacteristics::Smpte2084,
+            Self::Hlg => avif_serialize::constants::TransferCharacteristics::Hlg,
+        }
+    }
+}
+
+/// The color primaries of HDR AVIF output, i.e. the color gamut its pixels are converted to, each signalled with the Y'C'bC'r
+/// matrix conventionally paired with it: BT.2020 non-constant luminance for BT.2020, as in HDR10, and BT.709 otherwise.
+///
+/// Primaries without an H.273 code point, `Custom`, are signalled as unspecified in the `nclx` `colr` box
+/// and by an ICC profile in a second, `prof` `colr` box.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AvifColorPrimaries {
+    /// BT.2020, which holds the colors of any source.
+    #[default]
+    Bt2020,
+    /// Display P3, for P3 displays, which avoids expanding P3 content to BT.2020 only for the display to map it back.
+    /// Colors outside of P3 are clipped.
+    DisplayP3,
+    /// DCI-P3 with the DCI white point, as in digital cinema. Colors outside of it are clipped.
+    DciP3,
+    /// BT.709, i.e. the primaries of sRGB. Colors outside of it are clipped.
+    Bt709,
+    /// The chromaticities of the red, green and blue primaries and of the white point, as x and y, e.g. of Adobe RGB.
+    /// Colors outside of them are clipped. Readers that ignore the ICC profile take the pixels as BT.709.
+    Custom([[f64; 2]; 4]),
+}
+
+impl AvifColorPrimaries {
+    /// The primaries of `color_gamut` if it has an H.273 code point among these, see `ColorGamut::cicp_color_primaries`,
+    /// and `Custom` otherwise, e.g. for Adobe RGB.
+    pub fn from_color_gamut(color_gamut: &ColorGamut) -> Self {
+        match color_gamut.cicp_color_primaries() {
+            Some(Cicp::COLOR_PRIMARIES_BT2020) => Self::Bt2020,
+            Some(Cicp::COLOR_PRIMARIES_SMPTE432) => Self::DisplayP3,
+            Some(Cicp::COLOR_PRIMARIES_SMPTE431) => Self::DciP3,
+            Some(Cicp::COLOR_PRIMARIES_BT709) => Self::Bt709,
+            _ => {
+                let primaries = color_gamut.primaries();
+                Self::Custom([primaries.red_xy(), primaries.green_xy(), primaries.blue_xy(), color_gamut.white_point_xy()])
+            }
+        }
+    }
+
+    /// The color gamut of these primaries, `None` for `Custom` primaries that span no gamut.
+    fn try_color_gamut(self) -> Option<ColorGamut> {
+        match self {
+            Self::Bt2020 => Some(ColorGamut::bt2020()),
+            Self::DisplayP3 => Some(ColorGamut::display_p3()),
+            Self::DciP3 => Some(ColorGamut::dci_p3()),
+            Self::Bt709 => Some(ColorGamut::srgb()),
+            Self::Custom([red, green, blue, white_point]) => ColorGamut::from_xy([red, green, blue], white_point),
+        }
+    }
+
+    /// # Panics
+    /// For `Custom` primaries that span no gamut, which `Hdr10AvifOptions::validate` rejects.
+    pub fn color_gamut(self) -> ColorGamut {
+        self.try_color_gamut().expect("Custom primaries must span a gamut")
+    }
+
+    /// `K_R` and `K_B` of the Y'C'bC'r matrix, per Rec. ITU-R BT.2100 and BT.709 respectively.
+    fn luma_coefficients(self) -> (f32, f32) {
+        match self {
+            Self::Bt2020 => (0.2627, 0.0593),
+            Self::DisplayP3 | Self::DciP3 | Self::Bt709 | Self::Custom(_) => (0.2126, 0.0722),
+        }
+    }
+
+    /// The luma of non-linear R'G'B'.
+    fn luma(self, [r, g, b]: [f32; 3]) -> f32 {
+        let (kr, kb) = self.luma_coefficients();
+        kr * r + (1.0 - kr - kb) * g + kb * b
+    }
+
+    fn rav1e(self) -> (Rav1eColorPrimaries, Rav1eMatrixCoefficients) {
+        match self {
+            Self::Bt2020 => (Rav1eColorPrimaries::BT2020, Rav1eMatrixCoefficients::BT2020NCL),
+            Self::DisplayP3 => (Rav1eColorPrimaries::SMPTE432, Rav1eMatrixCoefficients::BT709),
+            Self::DciP3 => (Rav1eColorPrimaries::SMPTE431, Rav1eMatrixCoefficients::BT709),
+            Self::Bt709 => (Rav1eColorPrimaries::BT709, Rav1eMatrixCoefficients::BT709),
+            Self::Custom(_) => (Rav1eColorPrimaries::Unspecified, Rav1eMatrixCoefficients::BT709),
+        }
+    }
+
+    fn avif_serialize(self) -> (avif_serialize::constants::ColorPrimaries, avif_serialize::constants::MatrixCoefficients) {
+        use avif_serialize::constants::{ColorPrimaries, MatrixCoefficients};
+        match self {
+            Self::Bt2020 => (ColorPrimaries::Bt2020, MatrixCoefficients::Bt2020Ncl),
+            Self::DisplayP3 => (ColorPrimaries::DisplayP3, MatrixCoefficients::Bt709),
+            Self::DciP3 => (ColorPrimaries::DciP3, MatrixCoefficients::Bt709),
+            Self::Bt709 => (ColorPrimaries::Bt709, MatrixCoefficients::Bt709),
+            Self::Custom(_) => (ColorPrimaries::Unspecified, MatrixCoefficients::Bt709),
+        }
+    }
+}
+
+/// How the pixels of HDR AVIF output are represented as an intensity and two color differences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AvifColorSpace {
+    /// Y'C'bC'r, derived from the R'G'B' values with the matrix of `AvifColorPrimaries`, as in HDR10.
+    #[default]
+    Ycbcr,
+    /// BT.2100 ICtCp, signalled with the matrix coefficients 14, see `linear_bt2020_to_ictcp`. Its color differences
+    /// are less correlated with the intensity than those of Y'C'bC'r, so saturated colors quantize with less error,
+    /// see `ictcp_round_trip_is_closer_than_ycbcr`, but fewer decoders support it. Only for PQ and the BT.2020 primaries,
+    /// and always encoded by rav1e directly, since `ravif` can't signal it.
+    Ictcp,
+}
+
+/// The bit depth of HDR AVIF output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AvifBitDepth {
+    /// HDR10 proper, which every HDR-capable decoder supports.
+    #[default]
+    Ten,
+    /// Avoids banding in smooth gradients of bright content, at the cost of AV1's Professional profile,
+    /// which fewer decoders support, and a slower encode.
+    Twelve,
+    /// 12 bits if the content is bright and has smooth gradients, where 10-bit PQ steps may be visible as banding,
+    /// otherwise 10 bits. See `select_bit_depth`.
+    Auto,
+}
+
+/// The largest width and height of an AV1 frame, whose `frame_width_minus_1` and `frame_height_minus_1` have 16 bits.
+pub const AV1_MAX_DIMENSION: usize = 1 << 16;
+
+/// The largest width and height of an AV1 frame that rav1e encodes, i.e. of a single-image AVIF,
+/// which is one less than `AV1_MAX_DIMENSION`, as rav1e's configuration limits them to `u16::MAX`.
+pub const RAV1E_MAX_DIMENSION: usize = AV1_MAX_DIMENSION - 1;
+
+/// What AVIF output does if it is wider or taller than `RAV1E_MAX_DIMENSION`, e.g. for a huge panorama.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum AvifOversize {
+    /// Fails with an error before anything is computed.
+    #[default]
+    Fail,
+    /// Downscales the output to fit with a warning, preserving the aspect ratio. See `fit_av1_dimensions`.
+    Downscale,
+}
+
+/// Fails with `InvalidInput` if an AVIF of `width` by `height` pixels can't be encoded, rather than with whatever error
+/// the encoder would report.
+pub fn check_av1_dimensions(width: usize, height: usize) -> std::io::Result<()> {
+    if width > RAV1E_MAX_DIMENSION || height > RAV1E_MAX_DIMENSION {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{}x{} exceeds the maximum AV1 frame dimensions of {}x{} that rav1e encodes", width, height, RAV1E_MAX_DIMENSION, RAV1E_MAX_DIMENSION),
+        ));
+    }
+    Ok(())
+}
+
+/// The largest dimensions with the aspect ratio of `width` by `height` that fit into `RAV1E_MAX_DIMENSION`,
+/// or the dimensions themselves if they already do.
+pub fn fit_av1_dimensions(width: usize, height: usize) -> (usize, usize) {
+    let longer = width.max(height);
+    if longer <= RAV1E_MAX_DIMENSION {
+        return (width, height);
+    }
+    let scale = |length: usize| ((length as f64 * RAV1E_MAX_DIMENSION as f64 / longer as f64).round() as usize).clamp(1, RAV1E_MAX_DIMENSION);
+    (scale(width), scale(height))
+}
+
+/// The content light levels of HDR10 static metadata, as a `clli` box of an AVIF, in nits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Hdr10LightLevels {
+    /// Maximum Content Light Level, of the brightest pixel.
+    pub max_cll: f32,
+    /// Maximum Frame-Average Light Level.
+    pub max_fall: f32,
+}
+
+/// The mastering display color volume of HDR10 static metadata, as an `mdcv` box of an AVIF per SMPTE ST 2086.
+///
+/// Defaults to a display with the BT.2020 primaries, a D65 white point and a luminance range of 0.005 to 1000 nits.
+#[derive(Debug, Clone, Copy)]
+pub struct MasteringDisplay {
+    /// The primaries and the white point of the display.
+    pub color_gamut: ColorGamut,
+    /// The minimum luminance of the display in nits.
+    pub min_luminance: f32,
+    /// The peak luminance of the display in nits.
+    pub max_luminance: f32,
+}
+
+impl Default for MasteringDisplay {
+    fn default() -> Self {
+        Self { color_gamut: ColorGamut::bt2020(), min_luminance: 0.005, max_luminance: 1000.0 }
+    }
+}
+
+/// The fields of an `mdcv` box as `avif_serialize` takes them, per SMPTE ST 2086:
+/// Chromaticities in units of 0.00002 and luminance in units of 0.0001 nits.
+#[derive(Debug, PartialEq, Eq)]
+struct St2086 {
+    /// In the order green, blue, red.
+    primaries: [(u16, u16); 3],
+    white_point: (u16, u16),
+    max_luminance: u32,
+    min_luminance: u32,
+}
+
+impl MasteringDisplay {
+    fn to_st2086(self) -> St2086 {
+        let chromaticity = |[x, y]: [f64; 2]| {
+            let units = |value: f64| (value * 50000.0).round().clamp(0.0, 50000.0) as u16;
+            (units(x), units(y))
+        };
+        let luminance = |nits: f32| (nits as f64 * 10000.0).round().clamp(0.0, u32::MAX as f64) as u32;
+
+        let primaries = self.color_gamut.primaries();
+        St2086 {
+            primaries: [primaries.green_xy(), primaries.blue_xy(), primaries.red_xy()].map(chromaticity),
+            white_point: chromaticity(self.color_gamut.white_point_xy()),
+            max_luminance: luminance(self.max_luminance),
+            min_luminance: luminance(self.min_luminance),
+        }
+    }
+}
+
+/// The metadata `mux_hdr_avif` adds to HDR AVIF output besides its color description.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AvifMetadata<'a> {
+    /// A TIFF block to add as an `Exif` item.
+    pub exif: Option<&'a [u8]>,
+    /// The content light levels to add as a `clli` box.
+    pub light_levels: Option<Hdr10LightLevels>,
+    /// The mastering display to add as an `mdcv` box.
+    pub mastering_display: Option<MasteringDisplay>,
+}
+
+/// The per-pixel error of an HDR AVIF encode, between the input and the decoded output.
+#[derive(Debug, Clone)]
+pub struct AvifEncodingError {
+    pub width: usize,
+    pub height: usize,
+    /// The absolute differences of the PQ- or HLG-encoded R'G'B' values in [0, 1], row by row.
+    /// Both are roughly perceptually uniform, so the same difference is about as visible at any luminance.
+    pub pixels: Vec<[f32; 3]>,
+    /// The transfer the differences are of.
+    pub transfer: AvifTransfer,
+    /// The bit depth the AVIF was encoded with, whose largest code value a difference of 1.0 corresponds to.
+    pub bit_depth: u8,
+}
+
+impl AvifEncodingError {
+    /// - `source_pixels`: The encoded R'G'B' values of the input, before quantization.
+    /// - `decoded_pixels`: The Y'CbCr or ICtCp code values that the AV1 data decodes to, of the bit depth, range and matrix
+    ///   of `params`.
+    fn new(width: usize, height: usize, source_pixels: &[[f32; 3]], decoded_pixels: &[[u16; 3]], params: &Av1EncodeParams) -> Self {
+        let (luma_scale, chroma_scale, luma_offset) = params.range.scales_and_offset(params.bit_depth);
+        let center = (1u32 << (params.bit_depth - 1)) as f32;
+        let (kr, kb) = params.primaries.luma_coefficients();
+        let pixels = source_pixels.iter()
+            .zip(decoded_pixels)
+            .map(|(source, &[y, cb, cr])| {
+                // The inverse of the derivation in `write_hdr10_linear_pixels_to_avif_with_options` and of `quantize`.
+                let y = (y as f32 - luma_offset) / luma_scale;
+                let cb = (cb as f32 - center) / chroma_scale;
+                let cr = (cr as f32 - center) / chroma_scale;
+                let decoded = match params.color_space {
+                    AvifColorSpace::Ycbcr => {
+                        let r = y + 2.0 * (1.0 - kr) * cr;
+                        let b = y + 2.0 * (1.0 - kb) * cb;
+                        let g = (y - kr * r - kb * b) / (1.0 - kr - kb);
+                        [r, g, b]
+                    },
+                    AvifColorSpace::Ictcp => ictcp_to_pq_bt2020([y, cb, cr]),
+                };
+                let decoded = decoded.map(|value| value.clamp(0.0, 1.0));
+                std::array::from_fn(|channel| (decoded[channel] - source[channel]).abs())
+            })
+            .collect();
+        Self { width, height, pixels, transfer: params.transfer, bit_depth: params.bit_depth }
+    }
+
+    /// The largest difference of any channel of any pixel.
+    pub fn max(&self) -> f32 {
+        self.pixels.iter().flatten().fold(0.0, |max, &value| max.max(value))
+    }
+
+    /// The mean difference over all channels of all pixels.
+    pub fn mean(&self) -> f32 {
+        let sum: f64 = self.pixels.iter().flatten().map(|&value| value as f64).sum();
+        (sum / (self.pixels.len() * 3).max(1) as f64) as f32
+    }
+}
+
+/// Same as `write_hdr10_linear_pixels_to_avif`, but with the parameters of `options`.
+pub fn write_hdr10_linear_pixels_to_avif_with_options<W: Write, C: LinearImageContent>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    content: &C,
+    options: &Hdr10AvifOptions,
+) -> std::io::Result<()> {
+    encode_hdr_avif(writer, width, height, content, options, false)?;
+    Ok(())
+}
+
+/// Same as `write_hdr10_linear_pixels_to_avif_with_options`, but also returns the error of the encode, e.g. for QA.
+///
+/// The decoded output is the reconstruction of the encoder, which is exactly what a conforming AV1 decoder outputs.
+/// It is only available from rav1e, so the AVIF is always encoded by rav1e directly, with the quantizer `ravif` maps
+/// the quality of `options.encode` to if `options.quantizer` is unspecified, as for 12 bits.
+pub fn write_hdr10_linear_pixels_to_avif_with_error<W: Write, C: LinearImageContent>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    content: &C,
+    options: &Hdr10AvifOptions,
+) -> std::io::Result<AvifEncodingError> {
+    encode_hdr_avif(writer, width, height, content, options, true)
+        .map(|error| error.expect("The error is measured if requested"))
+}
+
+/// Writes an HDR AVIF, returning its error if `measure_error`.
+fn encode_hdr_avif<W: Write, C: LinearImageContent>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    content: &C,
+    options: &Hdr10AvifOptions,
+    measure_error: bool,
+) -> std::io::Result<Option<AvifEncodingError>> {
+    check_av1_dimensions(width, height)?;
+    options.validate()?;
+
+    let bit_depth = match options.bit_depth {
+        AvifBitDepth::Ten => 10,
+        AvifBitDepth::Twelve => 12,
+        AvifBitDepth::Auto => select_bit_depth(width, height, content, options.clamped_peak_luminance(), options.primaries),
+    };
+
+    let mut converter = YcbcrConverter::new(width, options, bit_depth);
     let mut ycbcr_pixels: Vec<[u16; 3]> = Vec::with_capacity(width * height);
+    let mut source_pixels: Vec<[f32; 3]> = if measure_error { Vec::with_capacity(width * height) } else { Vec::new() };
+    converter.convert_rows(content, 0..height, 0, &mut ycbcr_pixels, measure_error.then_some(&mut source_pixels));
+    let metadata = converter.metadata(width * height);
+
+    let params = options.av1_encode_params(bit_depth);
+    // `ravif` can only encode 10-bit 4:4:4 Y'C'bC'r with primaries that have a code point, and only some qualities and speeds.
+    // Otherwise, rav1e is driven directly, with the quantizer `ravif` would map the quality to.
+    if bit_depth == 12
+        || matches!(params.primaries, AvifColorPrimaries::Custom(_))
+        || params.chroma != AvifChroma::Chroma444
+        || params.color_space != AvifColorSpace::Ycbcr
+        || measure_error
+        || options.quantizer.is_some()
+        || !options.encode.is_supported_by_ravif()
+    {
+        let mut decoded_pixels = Vec::new();
+        let reconstruction = measure_error.then_some(&mut decoded_pixels);
+        let av1_data = encode_ycbcr_pixels_to_av1(width, height, &ycbcr_pixels, &params, reconstruction)?;
+        writer.write_all(&mux_hdr_avif(&av1_data, width, height, &params, &metadata))?;
+        return Ok(measure_error.then(|| AvifEncodingError::new(width, height, &source_pixels, &decoded_pixels, &params)));
+    }
+
+    let av1_data = encode_ycbcr_pixels_with_ravif(width, height, &ycbcr_pixels, &options.encode, &params)?;
+    writer.write_all(&mux_hdr_avif(&av1_data, width, height, &params, &metadata))?;
+    Ok(None)
+}
+
+/// Encodes an HDR AVIF like `write_hdr10_linear_pixels_to_avif_with_options`, but from linear pixels supplied
+/// in bands of rows, top to bottom, which are converted to Y'CbCr and written into the AV1 frame right away,
+/// so that neither the linear pixels nor the code values of the whole image are ever in memory, for huge images.
+///
+/// What is of the whole image is the AV1 frame, at 2 bytes per sample, i.e. 6 bytes per pixel for 4:4:4,
+/// 3 for 4:2:0 and 2 for monochrome, and rav1e's working set while `finish` encodes it, which bands don't reduce.
+/// The AVIF is always encoded by rav1e directly, as for 12 bits, and `AvifBitDepth::Auto` is 10 bits,
+/// since picking it would take every pixel up front.
+pub struct Hdr10AvifBandEncoder<'a> {
+    width: usize,
+    height: usize,
+    converter: YcbcrConverter<'a>,
+    params: Av1EncodeParams,
+    context: rav1e::Context<u16>,
+    frame: rav1e::Frame<u16>,
+    /// The code values of the current band, reused to not allocate for every band.
+    ycbcr_pixels: Vec<[u16; 3]>,
+    /// The number of rows written so far.
+    next_row: usize,
+}
+
+impl<'a> Hdr10AvifBandEncoder<'a> {
+    pub fn new(width: usize, height: usize, options: &Hdr10AvifOptions<'a>) -> std::io::Result<Self> {
+        options.validate()?;
+
+        let bit_depth = match options.bit_depth {
+            AvifBitDepth::Ten | AvifBitDepth::Auto => 10,
+            AvifBitDepth::Twelve => 12,
+        };
+        let params = options.av1_encode_params(bit_depth);
+        let context = new_av1_context(width, height, &params)?;
+        let frame = context.new_frame();
+        Ok(Self {
+            width,
+            height,
+            converter: YcbcrConverter::new(width, options, bit_depth),
+            params,
+            context,
+            frame,
+            ycbcr_pixels: Vec::new(),
+            next_row: 0,
+        })
+    }
+
+    /// Converts the first `rows` rows of `band`, which must be `width` pixels wide, as the next rows of the image.
+    ///
+    /// For 4:2:0, every band but the last must have an even number of rows, so that no block of 2x2 pixels straddles two bands.
+    pub fn write_band<C: LinearImageContent>(&mut self, band: &C, rows: usize) -> std::io::Result<()> {
+        let invalid_input = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+        if self.next_row + rows > self.height {
+            return Err(invalid_input(format!("{} more rows than the height of {}", self.next_row + rows - self.height, self.height)));
+        }
+        if self.params.chroma == AvifChroma::Chroma420 && !self.next_row.is_multiple_of(2) {
+            return Err(invalid_input("For 4:2:0, only the last band can have an odd number of rows".to_string()));
+        }
+
+        self.ycbcr_pixels.clear();
+        self.converter.convert_rows(band, 0..rows, self.next_row * self.width, &mut self.ycbcr_pixels, None);
+        write_ycbcr_rows_to_frame(&mut self.frame, &self.ycbcr_pixels, self.width, self.next_row, self.params.chroma);
+        self.next_row += rows;
+        Ok(())
+    }
+
+    /// Encodes the frame once all rows are written, and writes the AVIF to `writer`.
+    pub fn finish<W: Write>(self, writer: &mut W) -> std::io::Result<()> {
+        if self.next_row != self.height {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Only {} of {} rows were written", self.next_row, self.height),
+            ));
+        }
+        // The bands are no longer needed during the encode.
+        drop(self.ycbcr_pixels);
+
+        let (width, height, params) = (self.width, self.height, &self.params);
+        let metadata = self.converter.metadata(width * height);
+        let av1_data = encode_av1_frame(self.context, self.frame, width, height, params, None)?;
+        writer.write_all(&mux_hdr_avif(&av1_data, width, height, params, &metadata))
+    }
+}
+
+impl Hdr10AvifOptions<'_> {
+    /// Fails if the options of `encode` are invalid, see `AvifEncodeOptions::validate`, if `Custom` primaries span no gamut,
+    /// or if ICtCp is requested for HLG or primaries other than BT.2020.
+    pub fn validate(&self) -> std::io::Result<()> {
+        self.encode.validate()?;
+        if self.primaries.try_color_gamut().is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("The primaries {:?} span no color gamut", self.primaries),
+            ));
+        }
+        if self.color_space == AvifColorSpace::Ictcp && (self.transfer != AvifTransfer::Pq || self.primaries != AvifColorPrimaries::Bt2020) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "ICtCp is only supported with PQ and the BT.2020 primaries",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The luminance in nits that the transfer characteristics encode as 1: 10,000 for PQ and `HLG_NOMINAL_PEAK_LUMINANCE` for HLG.
+    fn max_luminance(&self) -> f32 {
+        match self.transfer {
+            AvifTransfer::Pq => 10000.0,
+            AvifTransfer::Hlg => HLG_NOMINAL_PEAK_LUMINANCE,
+        }
+    }
+
+    /// The luminance in nits that the linear pixels are clamped to: `peak_luminance`, at most `max_luminance`.
+    fn clamped_peak_luminance(&self) -> f32 {
+        let max_luminance = self.max_luminance();
+        self.peak_luminance.unwrap_or(max_luminance).clamp(0.0, max_luminance)
+    }
+
+    /// The parameters to encode the code values of `bit_depth` bits with, by rav1e directly at `quantizer`
+    /// or else at the quantizer `ravif` maps the quality to.
+    fn av1_encode_params(&self, bit_depth: u8) -> Av1EncodeParams {
+        Av1EncodeParams {
+            quantizer: self.quantizer.unwrap_or_else(|| self.encode.quantizer()),
+            speed: self.encode.speed,
+            bit_depth,
+            transfer: self.transfer,
+            range: self.encode.range,
+            chroma: self.encode.chroma,
+            primaries: self.primaries,
+            color_space: self.color_space,
+        }
+    }
+}
+
+/// Converts rows of linear pixels to Y'CbCr code values, measuring the content light levels along the way.
+struct YcbcrConverter<'a> {
+    width: usize,
+    options: Hdr10AvifOptions<'a>,
+    bit_depth: u8,
+    /// The R'G'B' values of a row, reused to not allocate for every row.
+    row_values: Vec<f32>,
+    max_cll: f32,
+    light_level_sum: f64,
+}
+
+impl<'a> YcbcrConverter<'a> {
+    fn new(width: usize, options: &Hdr10AvifOptions<'a>, bit_depth: u8) -> Self {
+        Self { width, options: *options, bit_depth, row_values: Vec::with_capacity(width * 3), max_cll: 0.0, light_level_sum: 0.0 }
+    }
+
+    /// Appends the code values of the pixels of the rows `rows` of `content` to `ycbcr_pixels`,
+    /// and their encoded R'G'B' values, before quantization, to `source_pixels` if specified.
+    ///
+    /// - `first_pixel_index`: The index in the whole image of the first pixel of the rows, for `Rounding`.
+    fn convert_rows<C: LinearImageContent>(
+        &mut self,
+        content: &C,
+        rows: std::ops::Range<usize>,
+        first_pixel_index: usize,
+        ycbcr_pixels: &mut Vec<[u16; 3]>,
+        mut source_pixels: Option<&mut Vec<[f32; 3]>>,
+    ) {
+        let options = &self.options;
+        let max_luminance = options.max_luminance();
+        let peak_luminance = options.clamped_peak_luminance();
+        let (kr, kb) = options.primaries.luma_coefficients();
+
+        for y in rows.clone() {
+            self.row_values.clear();
+            for x in 0..self.width {
+                let pixel = content.get_at(x, y);
+
+                // Clamp the values to the range [0, peak_luminance], at most [0, 10000] for HDR10 PQ
+                // and [0, 1000] for HLG, and normalize to [0, 1] for the OETF.
+                let rgb = pixel.to_serialized_rgb().map(|value| value.clamp(0.0, peak_luminance));
+                // CTA-861.3: The light level of a pixel is the maximum of its components.
+                let light_level = rgb.iter().fold(0.0f32, |max, &value| max.max(value));
+                self.max_cll = self.max_cll.max(light_level);
+                self.light_level_sum += light_level as f64;
+
+                let rgb = rgb.map(|value| value / max_luminance);
+                match options.color_space {
+                    AvifColorSpace::Ycbcr => self.row_values.extend(rgb),
+                    // ICtCp applies PQ to LMS instead of R'G'B'.
+                    AvifColorSpace::Ictcp => self.row_values.extend(linear_bt2020_to_lms(rgb)),
+                }
+            }
+
+            match options.transfer {
+                // The OETF is applied to a whole row at once, so that it can be vectorized.
+                AvifTransfer::Pq => st2084_oetf_in_place(&mut self.row_values),
+                AvifTransfer::Hlg => for rgb in self.row_values.as_chunks_mut::<3>().0 {
+                    *rgb = hlg_inverse_ootf(*rgb).map(hlg_oetf);
+                },
+            }
+
+            let row_pixel_index = first_pixel_index + (y - rows.start) * self.width;
+            for (x, &values) in self.row_values.as_chunks::<3>().0.iter().enumerate() {
+                let [y, cb, cr] = match options.color_space {
+                    AvifColorSpace::Ycbcr => {
+                        let [r, g, b] = values;
+                        if let Some(source_pixels) = source_pixels.as_deref_mut() {
+                            source_pixels.push([r, g, b]);
+                        }
+
+                        // Rec. ITU-R BT.2100-3,
+                        // "Non-Constant Luminance Y'C'bC'r signal format", Derivation of Y', Derivation of colour difference signals,
+                        // with the coefficients of BT.709 for primaries other than BT.2020.
+                        let y = options.primaries.luma([r, g, b]);
+                        [y, (b - y) / (2.0 * (1.0 - kb)), (r - y) / (2.0 * (1.0 - kr))]
+                    },
+                    AvifColorSpace::Ictcp => {
+                        let ictcp = pq_lms_to_ictcp(values);
+                        if let Some(source_pixels) = source_pixels.as_deref_mut() {
+                            source_pixels.push(ictcp_to_pq_bt2020(ictcp));
+                        }
+                        ictcp
+                    },
+                };
+
+                // ICtCp is quantized like Y'C'bC'r, per Rec. ITU-R BT.2100-3, Table 9.
+                ycbcr_pixels.push(quantize([y, cb, cr], self.bit_depth, options.encode.range, options.rounding, row_pixel_index + x));
+            }
+        }
+    }
+
+    /// The metadata of the output once all `pixel_count` pixels of the image are converted: The `Exif` item, and for PQ,
+    /// the HDR10 static metadata, i.e. the measured light levels unless `options.light_levels` is specified, and the mastering display.
+    fn metadata(&self, pixel_count: usize) -> AvifMetadata<'a> {
+        let options = &self.options;
+        let is_pq = options.transfer == AvifTransfer::Pq;
+        let light_levels = is_pq.then(|| options.light_levels.unwrap_or(Hdr10LightLevels {
+            max_cll: self.max_cll,
+            max_fall: (self.light_level_sum / pixel_count.max(1) as f64) as f32,
+        }));
+        let mastering_display = options.mastering_display
+            .or_else(|| options.peak_luminance.map(|_| MasteringDisplay { max_luminance: options.clamped_peak_luminance(), ..Default::default() }))
+            .filter(|_| is_pq);
+        AvifMetadata { exif: options.exif, light_levels, mastering_display }
+    }
+}
+
+/// The luminance in nits above which `AvifBitDepth::Auto` considers content bright enough for 10-bit PQ steps to be visible
+/// in smooth gradients. Below it, i.e. in the lower half of the PQ range, 10-bit steps are below the threshold of visibility.
+const AUTO_TWELVE_BIT_MIN_PEAK: f32 = 1000.0;
+
+/// The share of single 10-bit code value steps among all steps between neighboring pixels above which
+/// `AvifBitDepth::Auto` considers the content to have smooth gradients, i.e. ones that 10 bits resolve only barely.
+/// Texture and noise instead step by several code values.
+const AUTO_TWELVE_BIT_MIN_SMOOTHNESS: f32 = 0.5;
+
+/// Picks the bit depth for `AvifBitDepth::Auto` from the luminance range and gradient smoothness of `content`,
+/// after clamping to `peak_luminance`: 12 if its brightest pixel exceeds `AUTO_TWELVE_BIT_MIN_PEAK` nits and it is smooth
+/// by `AUTO_TWELVE_BIT_MIN_SMOOTHNESS`, otherwise 10.
+///
+/// The smoothness is measured on the 10-bit PQ luma of horizontally and vertically neighboring pixels.
+fn select_bit_depth<C: LinearImageContent>(width: usize, height: usize, content: &C, peak_luminance: f32, primaries: AvifColorPrimaries) -> u8 {
+    let mut max_light_level = 0.0f32;
+    let (mut steps, mut single_steps) = (0usize, 0usize);
+    let mut previous_row: Vec<i32> = Vec::with_capacity(width);
+    let mut row: Vec<i32> = Vec::with_capacity(width);
+    let mut row_values: Vec<f32> = Vec::with_capacity(width * 3);
     for y in 0..height {
+        row_values.clear();
         for x in 0..width {
-            let pixel = content.get_at(x, y);
+            let rgb = content.get_at(x, y).to_serialized_rgb().map(|value| value.clamp(0.0, peak_luminance));
+            max_light_level = rgb.iter().fold(max_light_level, |max, &value| max.max(value));
+            row_values.extend(rgb.map(|value| value / 10000.0));
+        }
+        st2084_oetf_in_place(&mut row_values);
 
-            let [r, g, b] = pixel.rgb();
+        row.clear();
+        row.extend(row_values.as_chunks::<3>().0.iter().map(|&rgb| (primaries.luma(rgb) * 1023.0).round() as i32));
 
-            // Clamp the values to the range [0, 10000] for HDR10 PQ.
-            let r = r.clamp(0.0, 10000.0);
-            let g = g.clamp(0.0, 10000.0);
-            let b = b.clamp(0.0, 10000.0);
+        let left_neighbors = row.windows(2).map(|pair| (pair[0], pair[1]));
+        let top_neighbors = previous_row.iter().copied().zip(row.iter().copied());
+        for (a, b) in left_neighbors.chain(top_neighbors) {
+            match a.abs_diff(b) {
+                0 => {},
+                1 => {
+                    steps += 1;
+                    single_steps += 1;
+                },
+                _ => steps += 1,
+            }
+        }
+        std::mem::swap(&mut previous_row, &mut row);
+    }
 
-            // Normalize to [0, 1] for the HDR10 PQ OETF.
-            let r = st2084_oetf(r / 10000.0);
-            let g = st2084_oetf(g / 10000.0);
-            let b = st2084_oetf(b / 10000.0);
+    let smoothness = if steps == 0 { 0.0 } else { single_steps as f32 / steps as f32 };
+    let bit_depth = if max_light_level > AUTO_TWELVE_BIT_MIN_PEAK && smoothness > AUTO_TWELVE_BIT_MIN_SMOOTHNESS { 12 } else { 10 };
+    info!(
+        "Selected a bit depth of {} for a peak of {} nits, with {:.0}% of luma steps being single 10-bit code values",
+        bit_depth, max_light_level, smoothness * 100.0,
+    );
+    bit_depth
+}
 
-            // Rec. ITU-R BT.2100-3,
-            // "Non-Constant Luminance Y'C'bC'r signal format", Derivation of Y', Derivation of colour difference signals
-            let y = 0.2627 * r + 0.6780 * g + 0.0593 * b;
-            let cb = (b - y) / 1.8814 + 0.5;
-            let cr = (r - y) / 1.4746 + 0.5;
+/// Quantizes Y' in [0, 1] and C'b, C'r in [-0.5, 0.5] to code values of `bit_depth` bits in `range`.
+///
+/// Rec. ITU-R BT.2100-3, Table 9, "Full" range: `D'Y = Round((2^n - 1) × E'Y)` and `D'C = Round((2^n - 1) × E'C + 2^(n - 1))`.
+/// For 10 bits, that is `1023 × Y'` and `1023 × C' + 512`, so neutral chroma is exactly 512, and for 12 bits, 2048.
+/// Offsetting the chroma by `0.5` before scaling by 1023 instead would center it at 511.5,
+/// which only rounds to 512 if `C'` is exactly 0, and to 511 for the tiniest negative rounding error of a neutral pixel.
+/// The extremes of `C'` round to 1024 and 1 for 10 bits, the former of which is clamped to 1023 like in the table.
+///
+/// The "Narrow" range is `D'Y = Round((219 × E'Y + 16) × 2^(n - 8))` and `D'C = Round((224 × E'C + 128) × 2^(n - 8))`,
+/// i.e. Y' in [64, 940] and C' in [64, 960] for 10 bits, also 512 for neutral chroma.
+///
+/// `Round` is per the table. Other `rounding` modes round the scaled values differently, the pixel being the `pixel_index`-th of the image.
+fn quantize([y, cb, cr]: [f32; 3], bit_depth: u8, range: AvifRange, rounding: Rounding, pixel_index: usize) -> [u16; 3] {
+    let max = ((1u32 << bit_depth) - 1) as f32;
+    let center = (1u32 << (bit_depth - 1)) as f32;
+    let (luma_scale, chroma_scale, luma_offset) = range.scales_and_offset(bit_depth);
+    let quantize = |value: f32, channel: usize| rounding.round(value, pixel_index * 3 + channel).clamp(0.0, max) as u16;
+    [
+        quantize(y * luma_scale + luma_offset, 0),
+        quantize(cb * chroma_scale + center, 1),
+        quantize(cr * chroma_scale + center, 2),
+    ]
+}
 
-            ycbcr_pixels.push([
-                (y * 1023.0).round() as u16,
-                (cb * 1023.0).round() as u16,
-                (cr * 1023.0).round() as u16,
-            ]);
+/// How scaled values are rounded to integer code values when quantizing HDR10 output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum Rounding {
+    /// To the nearest code value, with halfway cases away from zero, i.e. `f32::round`.
+    #[default]
+    Round,
+    /// Down to the code value below, i.e. `f32::floor`, as encoders that truncate do.
+    Floor,
+    /// Up or down at random, rounding up with a probability equal to the fractional part, so that the rounding error
+    /// averages out to zero over an area. Breaks up banding in smooth gradients like dithering does, but without a pattern.
+    ///
+    /// The random values only depend on `seed` and the position of the sample, so the output is reproducible for a given seed.
+    Stochastic { seed: u64 },
+}
+
+impl Rounding {
+    /// Rounds `value`, the `sample_index`-th sample of the image, to an integer.
+    pub fn round(self, value: f32, sample_index: usize) -> f32 {
+        match self {
+            Self::Round => value.round(),
+            Self::Floor => value.floor(),
+            // In `f64`, so that adding a value just below 1 never rounds up to the next integer.
+            Self::Stochastic { seed } => (value as f64 + uniform_random(seed, sample_index)).floor() as f32,
         }
     }
+}
 
-    write_hdr10_ycbcr_pixels_to_avif(writer, width, height, &ycbcr_pixels)
+/// The `index`-th value in [0, 1) of a SplitMix64 generator seeded with `seed`.
+/// Its state advances by a constant, so any value can be computed directly, regardless of the order samples are processed in.
+fn uniform_random(seed: u64, index: usize) -> f64 {
+    let mut z = seed.wrapping_add((index as u64).wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    // The top 53 bits, which `f64` represents exactly.
+    (z >> 11) as f64 / (1u64 << 53) as f64
 }
 
 /// - `pixels`: A slice of HDR10 pixels, each represented as an array of 3 `u16`` values (Y', Cb, Cr).
 ///   The values MUST be in the range [0, 1023].
+///
+/// Fails with the error of the encoder if it rejects the input, e.g. for too few `pixels` or unsupported dimensions.
+///
+/// The chroma is encoded at full resolution, i.e. 4:4:4. This matters more than for SDR:
+/// Cb and Cr are derived from PQ-encoded values, so at a saturated HDR edge they change abruptly,
+/// and subsampling them to 4:2:0 would smear them across the edge onto the luma of the other side, causing visible fringing.
 pub fn write_hdr10_ycbcr_pixels_to_avif<W: Write>(
     writer: &mut W,
     width: usize,
     height: usize,
     ycbcr_pixels: &[[u16; 3]],
 ) -> std::io::Result<()> {
-    const TRANSFER_CHARACTERISTICS: Rav1eTransferCharacteristics = Rav1eTransferCharacteristics::SMPTE2084;
-    const COLOR_PRIMARIES: Rav1eColorPrimaries = Rav1eColorPrimaries::BT2020;
-    const MATRIX_COEFFICIENTS: MatrixCoefficients = MatrixCoefficients::BT2020NCL;
+    let encode = AvifEncodeOptions::default();
+    let params = Hdr10AvifOptions { encode, ..Default::default() }.av1_encode_params(10);
+    let av1_data = encode_ycbcr_pixels_with_ravif(width, height, ycbcr_pixels, &encode, &params)?;
+    writer.write_all(&mux_hdr_avif(&av1_data, width, height, &params, &AvifMetadata::default()))
+}
+
+/// Encodes 10-bit 4:4:4 YCbCr pixels of the transfer, range and primaries of `params` as an AV1 still picture with `ravif`
+/// at the quality and speed of `encode`, which must be supported by `ravif`.
+///
+/// Only the AV1 data of the AVIF `ravif` writes is kept, to be muxed by `mux_hdr_avif`: `ravif` can add neither an `Exif` item
+/// nor `clli` and `mdcv` boxes, and its `colr` box has the sRGB code points whatever the AV1 sequence header signals.
+fn encode_ycbcr_pixels_with_ravif(
+    width: usize,
+    height: usize,
+    ycbcr_pixels: &[[u16; 3]],
+    encode: &AvifEncodeOptions,
+    params: &Av1EncodeParams,
+) -> std::io::Result<Vec<u8>> {
+    let (color_primaries, matrix_coefficients) = params.primaries.rav1e();
+
+    check_av1_dimensions(width, height)?;
 
     let res = Encoder::new()
-        .with_quality(100.0)
-        .with_speed(4)
+        .with_quality(encode.quality)
+        .with_speed(encode.speed)
         .encode_raw_plane_10_with_params(
             width, height,
             ycbcr_pixels.iter().cloned(),
             None::<[_; 0]>,
-            PixelRange::Full,
-            TRANSFER_CHARACTERISTICS,
-            COLOR_PRIMARIES,
-            MATRIX_COEFFICIENTS
+            params.range.rav1e(),
+            params.transfer.rav1e(),
+            color_primaries,
+            matrix_coefficients,
         )
-        .unwrap()
-        ;
+        .map_err(std::io::Error::other)?;
+
+    mdat_payload(&res.avif_file)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| std::io::Error::other("No `mdat` box in the AVIF written by ravif"))
+}
+
+/// Same as `write_hdr10_ycbcr_pixels_to_avif`, but drives rav1e directly with `quantizer` as the AV1 base quantizer,
+/// in [0, 255] where lower is higher quality, instead of going through `ravif`'s 0–100 quality.
+///
+/// `ravif` maps its quality to a quantizer too, with a quality of 100 being a quantizer of 0, but tweaks the rav1e speed settings
+/// depending on it. Here, rav1e's own speed preset 4 is used regardless of `quantizer`.
+///
+/// Adds the `Exif` item, `clli` box and `mdcv` box of `metadata` if specified.
+pub fn write_hdr10_ycbcr_pixels_to_avif_with_quantizer<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    ycbcr_pixels: &[[u16; 3]],
+    quantizer: u8,
+    metadata: &AvifMetadata,
+) -> std::io::Result<()> {
+    let params = Av1EncodeParams {
+        quantizer,
+        speed: 4,
+        bit_depth: 10,
+        transfer: AvifTransfer::Pq,
+        range: AvifRange::Full,
+        chroma: AvifChroma::Chroma444,
+        primaries: AvifColorPrimaries::Bt2020,
+        color_space: AvifColorSpace::Ycbcr,
+    };
+    let av1_data = encode_ycbcr_pixels_to_av1(width, height, ycbcr_pixels, &params, None)?;
+    writer.write_all(&mux_hdr_avif(&av1_data, width, height, &params, metadata))?;
+    Ok(())
+}
+
+/// Encodes YCbCr pixels as an AV1 still picture with rav1e, with the bit depth, transfer characteristics, range,
+/// chroma subsampling, primaries, AV1 base quantizer and speed preset of `params`. `ycbcr_pixels` are at full resolution,
+/// and C'b and C'r are subsampled by `subsample_chroma` if `params.chroma` is 4:2:0, or left out if it is monochrome.
+///
+/// - `reconstruction`: Receives the reconstructed pixels if specified, i.e. what the AV1 data decodes to,
+///   with subsampled C'b and C'r upsampled to full resolution by repeating each sample over its block.
+fn encode_ycbcr_pixels_to_av1(
+    width: usize,
+    height: usize,
+    ycbcr_pixels: &[[u16; 3]],
+    params: &Av1EncodeParams,
+    reconstruction: Option<&mut Vec<[u16; 3]>>,
+) -> std::io::Result<Vec<u8>> {
+    if ycbcr_pixels.len() < width * height {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Fewer pixels than width * height"));
+    }
+
+    let context = new_av1_context(width, height, params)?;
+    let mut frame = context.new_frame();
+    write_ycbcr_rows_to_frame(&mut frame, &ycbcr_pixels[..width * height], width, 0, params.chroma);
+    encode_av1_frame(context, frame, width, height, params, reconstruction)
+}
+
+/// A rav1e encoder of a still picture of `width` by `height` pixels with `params`.
+fn new_av1_context(width: usize, height: usize, params: &Av1EncodeParams) -> std::io::Result<rav1e::Context<u16>> {
+    use rav1e::color::ColorDescription;
+    use rav1e::config::SpeedSettings;
+    use rav1e::{Config, EncoderConfig};
+
+    check_av1_dimensions(width, height)?;
+
+    let (color_primaries, matrix_coefficients) = params.rav1e_color_primaries();
+    let config = Config::new().with_encoder_config(EncoderConfig {
+        width,
+        height,
+        bit_depth: params.bit_depth.into(),
+        chroma_sampling: params.chroma.rav1e(),
+        pixel_range: params.range.rav1e(),
+        color_description: Some(ColorDescription {
+            transfer_characteristics: params.transfer.rav1e(),
+            color_primaries,
+            matrix_coefficients,
+        }),
+        still_picture: true,
+        quantizer: params.quantizer.into(),
+        min_quantizer: params.quantizer,
+        speed_settings: SpeedSettings::from_preset(params.speed),
+        ..Default::default()
+    });
+    config.new_context().map_err(std::io::Error::other)
+}
+
+/// Writes the rows of `ycbcr_pixels`, which are `width` wide, into `frame` from the row `first_row` on,
+/// subsampling C'b and C'r by `subsample_chroma` if `chroma` is 4:2:0, and leaving them out if it is monochrome.
+///
+/// For 4:2:0, `first_row` must be even, so that each block of 2x2 pixels is within one call.
+fn write_ycbcr_rows_to_frame(frame: &mut rav1e::Frame<u16>, ycbcr_pixels: &[[u16; 3]], width: usize, first_row: usize, chroma: AvifChroma) {
+    let height = ycbcr_pixels.len() / width;
+    for (plane_index, plane) in frame.planes.iter_mut().enumerate().take(chroma.plane_count()) {
+        let shift = if plane_index == 0 { 0 } else { chroma.shift() };
+        debug_assert_eq!(first_row % (1 << shift), 0);
+        let (plane_width, plane_height) = (width.div_ceil(1 << shift), height.div_ceil(1 << shift));
+        for (y, row) in plane.rows_iter_mut().skip(first_row >> shift).take(plane_height).enumerate() {
+            for (x, sample) in row[..plane_width].iter_mut().enumerate() {
+                *sample = if shift == 0 {
+                    ycbcr_pixels[y * width + x][plane_index]
+                } else {
+                    subsample_chroma(ycbcr_pixels, width, height, plane_index, (x, y), shift)
+                };
+            }
+        }
+    }
+}
+
+/// Encodes `frame`, the only frame of `context`, and returns the AV1 data. See `encode_ycbcr_pixels_to_av1` about `reconstruction`.
+fn encode_av1_frame(
+    mut context: rav1e::Context<u16>,
+    frame: rav1e::Frame<u16>,
+    width: usize,
+    height: usize,
+    params: &Av1EncodeParams,
+    mut reconstruction: Option<&mut Vec<[u16; 3]>>,
+) -> std::io::Result<Vec<u8>> {
+    use rav1e::EncoderStatus;
+
+    context.send_frame(frame).map_err(std::io::Error::other)?;
+    context.flush();
+
+    let mut av1_data = Vec::new();
+    let mut reconstructed = false;
+    loop {
+        match context.receive_packet() {
+            Ok(mut packet) => {
+                av1_data.append(&mut packet.data);
+                // The reconstruction is after the loop filters, which is what a decoder outputs.
+                if let Some(pixels) = reconstruction.as_deref_mut() && let Some(frame) = packet.rec {
+                    // Monochrome pictures decode to centered C'b and C'r.
+                    let center = 1 << (params.bit_depth - 1);
+                    pixels.clear();
+                    pixels.resize(width * height, [0, center, center]);
+                    for (plane_index, plane) in frame.planes.iter().enumerate().take(params.chroma.plane_count()) {
+                        let shift = if plane_index == 0 { 0 } else { params.chroma.shift() };
+                        for (y, row) in plane.rows_iter().take(height.div_ceil(1 << shift)).enumerate() {
+                            for full_y in (y << shift)..((y + 1) << shift).min(height) {
+                                for x in 0..width {
+                                    pixels[full_y * width + x][plane_index] = row[x >> shift];
+                                }
+                            }
+                        }
+                    }
+                    reconstructed = true;
+                }
+            },
+            Err(EncoderStatus::Encoded) => continue,
+            Err(EncoderStatus::LimitReached) => break,
+            Err(e) => return Err(std::io::Error::other(e)),
+        }
+    }
+    if reconstruction.is_some() && !reconstructed {
+        return Err(std::io::Error::other("rav1e output no reconstruction"));
+    }
+    Ok(av1_data)
+}
+
+/// The mean of the `plane_index`-th samples of `ycbcr_pixels` over the block of `1 << shift` by `1 << shift` pixels of
+/// the subsampled sample at `(x, y)`, rounded to the nearest code value. The blocks at the right and bottom edges of an image
+/// with odd dimensions are partial.
+fn subsample_chroma(ycbcr_pixels: &[[u16; 3]], width: usize, height: usize, plane_index: usize, (x, y): (usize, usize), shift: usize) -> u16 {
+    let (mut sum, mut count) = (0u32, 0u32);
+    for full_y in (y << shift)..((y + 1) << shift).min(height) {
+        for full_x in (x << shift)..((x + 1) << shift).min(width) {
+            sum += ycbcr_pixels[full_y * width + full_x][plane_index] as u32;
+            count += 1;
+        }
+    }
+    ((sum + count / 2) / count) as u16
+}
+
+/// Muxes the AV1 data of a still picture encoded with `params` into an AVIF that signals its bit depth, transfer, range,
+/// chroma subsampling and primaries, with the `Exif` item, `clli` box and `mdcv` box of `metadata` if specified.
+fn mux_hdr_avif(av1_data: &[u8], width: usize, height: usize, params: &Av1EncodeParams, metadata: &AvifMetadata) -> Vec<u8> {
+    let &Av1EncodeParams { bit_depth, transfer, range, chroma, primaries, color_space, .. } = params;
+    let (color_primaries, matrix_coefficients) = primaries.avif_serialize();
+    let mut aviffy = avif_serialize::Aviffy::new();
+    let subsampled = chroma != AvifChroma::Chroma444;
+    aviffy
+        .set_chroma_subsampling((subsampled, subsampled))
+        .set_monochrome(chroma == AvifChroma::Monochrome)
+        // The Main profile, i.e. 0, which rav1e encodes 10-bit 4:2:0 and monochrome in, rather than the High profile of 4:4:4.
+        // `avif_serialize` raises it to the Professional profile for 12 bits.
+        .set_seq_profile(if subsampled { 0 } else { 1 })
+        .set_matrix_coefficients(matrix_coefficients)
+        .set_transfer_characteristics(transfer.avif_serialize())
+        .set_color_primaries(color_primaries)
+        .set_full_color_range(range == AvifRange::Full);
+    if let Some(exif) = metadata.exif {
+        aviffy.set_exif(exif.to_vec());
+    }
+    if let Some(light_levels) = metadata.light_levels {
+        let nits = |value: f32| value.round().clamp(0.0, u16::MAX as f32) as u16;
+        aviffy.set_content_light_level(nits(light_levels.max_cll), nits(light_levels.max_fall));
+    }
+    if let Some(mastering_display) = metadata.mastering_display {
+        let st2086 = mastering_display.to_st2086();
+        aviffy.set_mastering_display(st2086.primaries, st2086.white_point, st2086.max_luminance, st2086.min_luminance);
+    }
+    let mut avif_file = aviffy.to_vec(av1_data, None, width as u32, height as u32, bit_depth);
+    if color_space == AvifColorSpace::Ictcp {
+        // `avif_serialize` has no constant for ICtCp, so the BT.2020 matrix it signals is replaced.
+        set_nclx_matrix_coefficients(&mut avif_file, MATRIX_COEFFICIENTS_ICTCP).expect("The `colr` box is written for any primaries");
+    }
+    if let AvifColorPrimaries::Custom(_) = primaries {
+        let mut colr = Vec::new();
+        let icc_profile = custom_primaries_icc_profile(&primaries.color_gamut(), transfer);
+        colr.extend((16 + icc_profile.len() as u32).to_be_bytes());
+        colr.extend(b"colrprof");
+        colr.extend(icc_profile);
+        // The color item is the first one `avif_serialize` writes.
+        avif_file = crate::isobmff::add_item_property(&avif_file, 1, &colr).expect("`avif_serialize` writes a `meta` box with properties");
+    }
+    avif_file
+}
+
+/// The number of entries of the tone curves of `custom_primaries_icc_profile`.
+const ICC_TONE_CURVE_ENTRIES: usize = 4096;
+
+/// An ICC profile of `color_gamut` whose tone curves are the EOTF of PQ, normalized to 10,000 nits, or the inverse OETF
+/// of HLG, i.e. scene light, since the luminance-dependent OOTF of HLG can't be a tone curve per channel.
+fn custom_primaries_icc_profile(color_gamut: &ColorGamut, transfer: AvifTransfer) -> Vec<u8> {
+    use lcms2::{CIExyY, CIExyYTRIPLE, Profile, ToneCurve};
+
+    let xy = |[x, y]: [f64; 2]| CIExyY { x, y, Y: 1.0 };
+    let primaries = color_gamut.primaries();
+    let primaries = CIExyYTRIPLE {
+        Red: xy(primaries.red_xy()),
+        Green: xy(primaries.green_xy()),
+        Blue: xy(primaries.blue_xy()),
+    };
+    let table: Vec<u16> = (0..ICC_TONE_CURVE_ENTRIES)
+        .map(|index| {
+            let value = index as f32 / (ICC_TONE_CURVE_ENTRIES - 1) as f32;
+            let linear = match transfer {
+                AvifTransfer::Pq => st2084_eotf(value),
+                AvifTransfer::Hlg => hlg_inverse_oetf(value),
+            };
+            (linear.clamp(0.0, 1.0) * 65535.0).round() as u16
+        })
+        .collect();
+    let curve = ToneCurve::new_tabulated(&table);
+    Profile::new_rgb(&xy(color_gamut.white_point_xy()), &primaries, &[&curve, &curve, &curve])
+        .and_then(|profile| profile.icc())
+        .expect("The primaries span a gamut")
+}
+
+/// The H.273 matrix coefficients of BT.2100 ICtCp.
+const MATRIX_COEFFICIENTS_ICTCP: u16 = 14;
+
+/// Overwrites the matrix coefficients of the `nclx` `colr` box of an AVIF as written by `avif_serialize`,
+/// whose `meta` box precedes the `mdat` box, so that the first match can't be in the AV1 data.
+/// `None` if there is no such box.
+fn set_nclx_matrix_coefficients(avif_file: &mut [u8], matrix_coefficients: u16) -> Option<()> {
+    // The colour type is followed by the colour primaries, the transfer characteristics and the matrix coefficients.
+    let colour_type = avif_file.windows(8).position(|window| window == b"colrnclx")? + 8;
+    avif_file.get_mut(colour_type + 4..colour_type + 6)?.copy_from_slice(&matrix_coefficients.to_be_bytes());
+    Some(())
+}
+
+/// The payload of the top-level `mdat` box of an AVIF, which is exactly the AV1 data of the color item
+/// for an AVIF without alpha as written by `avif_serialize`.
+fn mdat_payload(avif_file: &[u8]) -> Option<&[u8]> {
+    let mut position = 0;
+    while let Some(header) = avif_file.get(position..position + 8) {
+        let size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let (payload_offset, size) = match size {
+            // The box extends to the end of the file.
+            0 => (position + 8, avif_file.len() - position),
+            1 => (position + 16, usize::try_from(u64::from_be_bytes(avif_file.get(position + 8..position + 16)?.try_into().unwrap())).ok()?),
+            _ => (position + 8, size),
+        };
+        let end = position.checked_add(size).filter(|&end| end >= payload_offset)?;
+        if &header[4..8] == b"mdat" {
+            return avif_file.get(payload_offset..end);
+        }
+        position = end;
+    }
+    None
+}
+
+/// - `pixels`: A slice of sRGB pixels, i.e. BT.709 primaries with the sRGB transfer characteristics, each represented as an array of 3 `u8` values (R, G, B).
+///
+/// The AVIF is encoded with a bit depth of 8, and signals BT.709 color primaries, sRGB transfer characteristics and the BT.601 matrix.
+pub fn write_srgb8_pixels_to_avif<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[[u8; 3]],
+) -> std::io::Result<()> {
+    check_av1_dimensions(width, height)?;
+
+    let pixels: Vec<RGB8> = pixels.iter().map(|&[r, g, b]| RGB8::new(r, g, b)).collect();
+
+    let res = Encoder::new()
+        .with_quality(100.0)
+        .with_speed(4)
+        .with_bit_depth(BitDepth::Eight)
+        .encode_rgb(Img::new(pixels.as_slice(), width, height))
+        .map_err(std::io::Error::other)?;
 
     writer.write_all(&res.avif_file)?;
     Ok(())
 }
 
-/// SMPTE ST.2084 PQ (Perceptual Quantizer) EOTF^-1:
-/// PQ is actually defined by the EOTF. This is its inverse, divided by 10,000.
-/// 
-/// Also in [_Rec. ITU-R BT.2100-3_](https://www.itu.int/rec/R-REC-BT.2100-3-202502-I/en).
+/// Converts normalized linear BT.2020 RGB, as for `st2084_oetf`, to BT.2100 ICtCp with PQ,
+/// where I is in [0, 1], and Ct and Cp are in [-0.5, 0.5].
 ///
-/// - `color`: Normalized color [0, 1] to map non-linearly to [0, 1].
-fn st2084_oetf(color: f32) -> f32
-{
-    const M1: f32 = 2610.0 / 16384.0;
-    const M2: f32 = 2523.0 / 4096.0 * 128.0;
-    const C1: f32 = 3424.0 / 4096.0;
-    const C2: f32 = 2413.0 / 4096.0 * 32.0;
-    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+/// ICtCp decorrelates intensity from chroma better than Y'C'bC'r for saturated HDR colors, so quantizing it is perceptually
+/// more uniform; see `ictcp_round_trip_is_closer_than_ycbcr`. HDR AVIF output is encoded with it for `AvifColorSpace::Ictcp`.
+pub fn linear_bt2020_to_ictcp(rgb: [f32; 3]) -> [f32; 3] {
+    pq_lms_to_ictcp(linear_bt2020_to_lms(rgb).map(st2084_oetf))
+}
+
+/// The LMS of Rec. ITU-R BT.2100-3, "Derivation of ICtCp", with the cross-talk matrix in units of 1/4096.
+fn linear_bt2020_to_lms([r, g, b]: [f32; 3]) -> [f32; 3] {
+    [
+        (1688.0 * r + 2146.0 * g + 262.0 * b) / 4096.0,
+        (683.0 * r + 2951.0 * g + 462.0 * b) / 4096.0,
+        (99.0 * r + 309.0 * g + 3688.0 * b) / 4096.0,
+    ]
+}
+
+/// ICtCp from the PQ-encoded L'M'S' of `linear_bt2020_to_lms`.
+fn pq_lms_to_ictcp([l, m, s]: [f32; 3]) -> [f32; 3] {
+    [
+        0.5 * l + 0.5 * m,
+        (6610.0 * l - 13613.0 * m + 7003.0 * s) / 4096.0,
+        (17933.0 * l - 17390.0 * m - 543.0 * s) / 4096.0,
+    ]
+}
+
+/// The PQ-encoded BT.2020 R'G'B' of ICtCp, to compare it with the R'G'B' of Y'C'bC'r output.
+fn ictcp_to_pq_bt2020(ictcp: [f32; 3]) -> [f32; 3] {
+    ictcp_to_linear_bt2020(ictcp).map(|value| st2084_oetf(value.clamp(0.0, 1.0)))
+}
+
+/// The inverse of `linear_bt2020_to_ictcp`.
+pub fn ictcp_to_linear_bt2020(ictcp: [f32; 3]) -> [f32; 3] {
+    let [i, ct, cp] = ictcp;
+    let l = i + 0.008609037 * ct + 0.111029625 * cp;
+    let m = i - 0.008609037 * ct - 0.111029625 * cp;
+    let s = i + 0.56003134 * ct - 0.32062717 * cp;
+
+    let [l, m, s] = [l, m, s].map(st2084_eotf);
+
+    [
+        3.4366067 * l - 2.506452 * m + 0.06984542 * s,
+        -0.79132956 * l + 1.9836005 * m - 0.19227089 * s,
+        -0.0259499 * l - 0.098913715 * m + 1.1248636 * s,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let cp = f32::powf(color.abs(), M1);
-    let numerator = C1 + C2 * cp;
-    let denominator = 1.0 + C3 * cp;
+    use crate::pixel::{FloatImageContent, FloatPixel};
 
-    let color = f32::powf(numerator / denominator, M2);
+    #[test]
+    fn hlg_encoding_round_trips_through_the_hlg_decoding() {
+        use crate::colorspace::SourceTransfer;
+
+        for nits in [[0.5, 1.0, 2.0], [10.0, 50.0, 5.0], [HLG_REFERENCE_WHITE_LUMINANCE; 3], [700.0, 300.0, 500.0], [1000.0; 3]] {
+            let signal = hlg_inverse_ootf(nits.map(|value| value / HLG_NOMINAL_PEAK_LUMINANCE)).map(hlg_oetf);
+            let decoded = SourceTransfer::Hlg.to_linear(signal, None).map(|value| value * HLG_REFERENCE_WHITE_LUMINANCE);
+            for (decoded, nits) in decoded.iter().zip(nits) {
+                assert!((decoded - nits).abs() < nits * 1e-3, "{:?} decoded as {:?}", nits, decoded);
+            }
+        }
+
+        // BT.2408 reference white is at a 75% signal.
+        let signal = hlg_inverse_ootf([HLG_REFERENCE_WHITE_LUMINANCE / HLG_NOMINAL_PEAK_LUMINANCE; 3]).map(hlg_oetf);
+        assert!((signal[0] - 0.75).abs() < 0.005, "{:?}", signal);
+        assert_eq!(hlg_inverse_ootf([0.0; 3]).map(hlg_oetf), [0.0; 3]);
+    }
+
+    #[test]
+    fn avif_is_tagged_with_its_transfer() {
+        let content = FloatImageContent::with_extent(8, 8);
+        // Through `ravif` and through rav1e directly.
+        for quantizer in [None, Some(100)] {
+            for (transfer, transfer_characteristics) in [(AvifTransfer::Pq, 16), (AvifTransfer::Hlg, 18)] {
+                let mut avif_bytes = Vec::new();
+                let options = Hdr10AvifOptions { quantizer, transfer, peak_luminance: Some(4000.0), ..Default::default() };
+                write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, 8, 8, &content, &options).unwrap();
+
+                let colr = crate::testutil::find(&avif_bytes, b"colrnclx").unwrap() + 8;
+                assert_eq!(avif_bytes[colr..colr + 6], [0, 9, 0, transfer_characteristics, 0, 9]);
+                // HDR10 static metadata is for PQ only.
+                assert_eq!(crate::testutil::find(&avif_bytes, b"clli").is_some(), transfer == AvifTransfer::Pq);
+                assert_eq!(crate::testutil::find(&avif_bytes, b"mdcv").is_some(), transfer == AvifTransfer::Pq);
+            }
+        }
+    }
+
+    #[test]
+    fn avif_is_tagged_with_its_primaries_and_their_matrix() {
+        let mut content = FloatImageContent::with_extent(8, 8);
+        for (index, pixel) in content.pixels_mut().iter_mut().enumerate() {
+            *pixel = FloatPixel::new(400.0, 100.0 + index as f32 * 10.0, 20.0);
+        }
+
+        for (primaries, color_primaries, matrix_coefficients) in [
+            (AvifColorPrimaries::Bt2020, 9, 9),
+            (AvifColorPrimaries::DisplayP3, 12, 1),
+            (AvifColorPrimaries::DciP3, 11, 1),
+            (AvifColorPrimaries::Bt709, 1, 1),
+        ] {
+            assert_eq!(AvifColorPrimaries::from_color_gamut(&primaries.color_gamut()), primaries);
+
+            // Through `ravif` and through rav1e directly.
+            for quantizer in [None, Some(0)] {
+                let mut avif_bytes = Vec::new();
+                let options = Hdr10AvifOptions { quantizer, primaries, ..Default::default() };
+                write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, 8, 8, &content, &options).unwrap();
+
+                let colr = crate::testutil::find(&avif_bytes, b"colrnclx").unwrap() + 8;
+                assert_eq!(avif_bytes[colr..colr + 6], [0, color_primaries, 0, 16, 0, matrix_coefficients], "{:?}", primaries);
+            }
+
+            // The decoded pixels are inverted with the same matrix, so a near-lossless encode decodes back closely.
+            let options = Hdr10AvifOptions { quantizer: Some(0), primaries, ..Default::default() };
+            let error = write_hdr10_linear_pixels_to_avif_with_error(&mut Vec::new(), 8, 8, &content, &options).unwrap();
+            assert!(error.max() < 5.0 / 1023.0, "{:?}: {}", primaries, error.max());
+        }
+
+    }
+
+    #[test]
+    fn avif_with_custom_primaries_is_tagged_with_an_icc_profile() {
+        let mut content = FloatImageContent::with_extent(8, 8);
+        for (index, pixel) in content.pixels_mut().iter_mut().enumerate() {
+            *pixel = FloatPixel::new(400.0, 100.0 + index as f32 * 10.0, 20.0);
+        }
+        let primaries = AvifColorPrimaries::from_color_gamut(&ColorGamut::adobe_rgb());
+        assert!(matches!(primaries, AvifColorPrimaries::Custom(_)), "{:?}", primaries);
+
+        for transfer in [AvifTransfer::Pq, AvifTransfer::Hlg] {
+            let mut avif_bytes = Vec::new();
+            let options = Hdr10AvifOptions { transfer, primaries, ..Default::default() };
+            write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, 8, 8, &content, &options).unwrap();
+
+            // Unspecified primaries with the BT.709 matrix.
+            let colr = crate::testutil::find(&avif_bytes, b"colrnclx").unwrap() + 8;
+            assert_eq!(avif_bytes[colr..colr + 6], [0, 2, 0, transfer.avif_serialize() as u8, 0, 1]);
+            let prof = crate::testutil::find(&avif_bytes, b"colrprof").unwrap();
+            let size = u32::from_be_bytes(avif_bytes[prof - 4..prof].try_into().unwrap()) as usize;
+            let color_gamut = ColorGamut::from_icc_profile_bytes(&avif_bytes[prof + 8..prof - 4 + size]).unwrap();
+            for (actual, expected) in [
+                (color_gamut.primaries().red_xy(), ColorGamut::adobe_rgb().primaries().red_xy()),
+                (color_gamut.primaries().green_xy(), ColorGamut::adobe_rgb().primaries().green_xy()),
+                (color_gamut.white_point_xy(), ColorGamut::adobe_rgb().white_point_xy()),
+            ] {
+                assert!((actual[0] - expected[0]).abs() < 1e-3 && (actual[1] - expected[1]).abs() < 1e-3, "{:?} vs {:?}", actual, expected);
+            }
+            assert!(mdat_payload(&avif_bytes).is_some_and(|av1_data| !av1_data.is_empty()));
+        }
+
+        let options = Hdr10AvifOptions { quantizer: Some(0), primaries, ..Default::default() };
+        let error = write_hdr10_linear_pixels_to_avif_with_error(&mut Vec::new(), 8, 8, &content, &options).unwrap();
+        assert!(error.max() < 5.0 / 1023.0, "{}", error.max());
+
+        let collinear = AvifColorPrimaries::Custom([[0.2, 0.2], [0.3, 0.3], [0.4, 0.4], [0.3127, 0.329]]);
+        let options = Hdr10AvifOptions { primaries: collinear, ..Default::default() };
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn ictcp_avif_is_tagged_with_matrix_coefficients_14() {
+        let mut content = FloatImageContent::with_extent(8, 8);
+        for (index, pixel) in content.pixels_mut().iter_mut().enumerate() {
+            *pixel = FloatPixel::new(400.0, 100.0 + index as f32 * 10.0, 20.0);
+        }
 
-    return color;
+        // With the quantizer of the quality, since `ravif` can't encode ICtCp, and with a given one.
+        for quantizer in [None, Some(0)] {
+            let mut avif_bytes = Vec::new();
+            let options = Hdr10AvifOptions { quantizer, color_space: AvifColorSpace::Ictcp, ..Default::default() };
+            write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, 8, 8, &content, &options).unwrap();
+
+            let colr = crate::testutil::find(&avif_bytes, b"colrnclx").unwrap() + 8;
+            assert_eq!(avif_bytes[colr..colr + 6], [0, 9, 0, 16, 0, 14]);
+        }
+
+        // The decoded ICtCp is compared as R'G'B', so a near-lossless encode decodes back closely.
+        let options = Hdr10AvifOptions { quantizer: Some(0), color_space: AvifColorSpace::Ictcp, ..Default::default() };
+        let error = write_hdr10_linear_pixels_to_avif_with_error(&mut Vec::new(), 8, 8, &content, &options).unwrap();
+        assert!(error.max() < 5.0 / 1023.0, "{}", error.max());
+
+        for options in [
+            Hdr10AvifOptions { transfer: AvifTransfer::Hlg, color_space: AvifColorSpace::Ictcp, ..Default::default() },
+            Hdr10AvifOptions { primaries: AvifColorPrimaries::DisplayP3, color_space: AvifColorSpace::Ictcp, ..Default::default() },
+        ] {
+            let error = write_hdr10_linear_pixels_to_avif_with_options(&mut Vec::new(), 8, 8, &content, &options).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn content_light_levels_are_measured_unless_overridden() {
+        fn clli(avif_bytes: &[u8]) -> [u16; 2] {
+            let clli = crate::testutil::find(avif_bytes, b"clli").unwrap() + 4;
+            [0, 2].map(|offset| u16::from_be_bytes([avif_bytes[clli + offset], avif_bytes[clli + offset + 1]]))
+        }
+
+        // Half of the pixels at 1000 nits in one channel, the other half black.
+        let mut content = FloatImageContent::with_extent(8, 8);
+        for y in 0..8 {
+            for x in 0..4 {
+                content.set_at(x, y, [100.0, 1000.0, 200.0].into());
+            }
+        }
+
+        let mut avif_bytes = Vec::new();
+        write_hdr10_linear_pixels_to_avif(&mut avif_bytes, 8, 8, &content).unwrap();
+        assert_eq!(clli(&avif_bytes), [1000, 500]);
+        // Without a known mastering peak, there is no `mdcv`.
+        assert!(crate::testutil::find(&avif_bytes, b"mdcv").is_none());
+
+        let light_levels = Hdr10LightLevels { max_cll: 4000.0, max_fall: 400.0 };
+        let options = Hdr10AvifOptions { light_levels: Some(light_levels), ..Default::default() };
+        let mut avif_bytes = Vec::new();
+        write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, 8, 8, &content, &options).unwrap();
+        assert_eq!(clli(&avif_bytes), [4000, 400]);
+    }
+
+    #[test]
+    fn mastering_display_is_written_as_st2086() {
+        // The BT.2020 primaries and D65 in units of 0.00002, in the order green, blue, red.
+        assert_eq!(MasteringDisplay::default().to_st2086(), St2086 {
+            primaries: [(8500, 39850), (6550, 2300), (35400, 14600)],
+            white_point: (15635, 16450),
+            max_luminance: 10_000_000,
+            min_luminance: 50,
+        });
+
+        let mastering_display = MasteringDisplay { color_gamut: ColorGamut::display_p3(), min_luminance: 0.0001, max_luminance: 4000.0 };
+        let options = Hdr10AvifOptions { mastering_display: Some(mastering_display), ..Default::default() };
+        let content = FloatImageContent::with_extent(8, 8);
+        let mut avif_bytes = Vec::new();
+        write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, 8, 8, &content, &options).unwrap();
+
+        let mdcv = crate::testutil::find(&avif_bytes, b"mdcv").unwrap() + 4;
+        let mut expected = Vec::new();
+        for (x, y) in [(13250u16, 34500u16), (7500, 3000), (34000, 16000), (15635, 16450)] {
+            expected.extend(x.to_be_bytes());
+            expected.extend(y.to_be_bytes());
+        }
+        expected.extend(40_000_000u32.to_be_bytes());
+        expected.extend(1u32.to_be_bytes());
+        assert_eq!(avif_bytes[mdcv..mdcv + 24], expected);
+
+        // HDR10 static metadata is for PQ only.
+        let options = Hdr10AvifOptions { transfer: AvifTransfer::Hlg, ..options };
+        let mut avif_bytes = Vec::new();
+        write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, 8, 8, &content, &options).unwrap();
+        assert!(crate::testutil::find(&avif_bytes, b"mdcv").is_none());
+    }
+
+    #[test]
+    fn neutral_pixels_have_centered_chroma() {
+        // Sweeping the whole range, some of which leave `b - y` a tiny bit negative in `f32`.
+        for step in 0..=1000 {
+            let value = step as f32 / 1000.0;
+            let y = 0.2627 * value + 0.6780 * value + 0.0593 * value;
+            let [_, cb, cr] = quantize([y, (value - y) / 1.8814, (value - y) / 1.4746], 10, AvifRange::Full, Rounding::Round, 0);
+            assert_eq!((cb, cr), (512, 512), "{}", value);
+        }
+
+        assert_eq!(quantize([1.0, 0.5, -0.5], 10, AvifRange::Full, Rounding::Round, 0), [1023, 1023, 1]);
+        assert_eq!(quantize([1.0, 0.5, -0.5], 12, AvifRange::Full, Rounding::Round, 0), [4095, 4095, 1]);
+        assert_eq!(quantize([0.0, 0.0, 0.0], 10, AvifRange::Full, Rounding::Round, 0), [0, 512, 512]);
+        assert_eq!(quantize([0.0, 0.0, 0.0], 12, AvifRange::Full, Rounding::Round, 0), [0, 2048, 2048]);
+    }
+
+    #[test]
+    fn limited_range_code_values_are_within_the_narrow_range() {
+        for (bit_depth, [y_min, y_max], [c_min, c_max]) in [(10, [64, 940], [64, 960]), (12, [256, 3760], [256, 3840])] {
+            let center = 1 << (bit_depth - 1);
+            assert_eq!(quantize([0.0, 0.0, 0.0], bit_depth, AvifRange::Limited, Rounding::Round, 0), [y_min, center, center]);
+            assert_eq!(quantize([1.0, 0.5, -0.5], bit_depth, AvifRange::Limited, Rounding::Round, 0), [y_max, c_max, c_min]);
+
+            // The corners of the R'G'B' cube reach the extremes of Y', C'b and C'r.
+            for corner in 0..8 {
+                let [r, g, b] = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1].map(|bit| bit as f32);
+                let y = 0.2627 * r + 0.6780 * g + 0.0593 * b;
+                let [y, cb, cr] = quantize([y, (b - y) / 1.8814, (r - y) / 1.4746], bit_depth, AvifRange::Limited, Rounding::Round, 0);
+                assert!((y_min..=y_max).contains(&y), "{} bits, corner {}: Y' {}", bit_depth, corner, y);
+                assert!((c_min..=c_max).contains(&cb), "{} bits, corner {}: C'b {}", bit_depth, corner, cb);
+                assert!((c_min..=c_max).contains(&cr), "{} bits, corner {}: C'r {}", bit_depth, corner, cr);
+            }
+        }
+    }
+
+    #[test]
+    fn limited_range_avif_is_signalled_and_decodes_back() {
+        let mut content = FloatImageContent::with_extent(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                content.set_at(x, y, [x as f32 * 60.0, y as f32 * 60.0, 100.0].into());
+            }
+        }
+
+        // Through `ravif` and through rav1e directly.
+        for quantizer in [None, Some(0)] {
+            for range in [AvifRange::Full, AvifRange::Limited] {
+                let options = Hdr10AvifOptions { quantizer, encode: AvifEncodeOptions { range, ..Default::default() }, ..Default::default() };
+                let mut avif_bytes = Vec::new();
+                write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, 16, 16, &content, &options).unwrap();
+
+                // `full_range_flag` is the top bit after the code points.
+                let colr = crate::testutil::find(&avif_bytes, b"colrnclx").unwrap() + 8;
+                assert_eq!(avif_bytes[colr + 6] >> 7 == 1, range == AvifRange::Full, "{:?}", range);
+            }
+        }
+
+        // The error is measured with the inverse of the limited-range quantization.
+        let options = Hdr10AvifOptions { quantizer: Some(0), encode: AvifEncodeOptions { range: AvifRange::Limited, ..Default::default() }, ..Default::default() };
+        let error = write_hdr10_linear_pixels_to_avif_with_error(&mut Vec::new(), 16, 16, &content, &options).unwrap();
+        assert!(error.max() < 0.01, "{}", error.max());
+    }
+
+    #[test]
+    fn rounding_modes_stay_within_one_code_value() {
+        let ycbcr: Vec<[f32; 3]> = (0..=1000)
+            .map(|step| step as f32 / 1000.0)
+            .map(|value| [value, value - 0.5, 0.5 - value * 0.7])
+            .collect();
+        let quantize = |rounding: Rounding| -> Vec<[u16; 3]> {
+            ycbcr.iter().enumerate().map(|(index, &pixel)| quantize(pixel, 10, AvifRange::Full, rounding, index)).collect()
+        };
+
+        let round = quantize(Rounding::Round);
+        for rounding in [Rounding::Floor, Rounding::Stochastic { seed: 1 }] {
+            for (a, b) in round.iter().flatten().zip(quantize(rounding).iter().flatten()) {
+                assert!(a.abs_diff(*b) <= 1, "{:?}: {} vs {}", rounding, a, b);
+            }
+        }
+        assert_ne!(quantize(Rounding::Floor), round);
+
+        // Reproducible for a seed, and different for another.
+        let stochastic = quantize(Rounding::Stochastic { seed: 1 });
+        assert_eq!(quantize(Rounding::Stochastic { seed: 1 }), stochastic);
+        assert_ne!(quantize(Rounding::Stochastic { seed: 2 }), stochastic);
+
+        // Rounds up as often as the fractional part, e.g. a quarter of the time for .25.
+        let rounded_up = (0..10000)
+            .filter(|&index| Rounding::Stochastic { seed: 3 }.round(100.25, index) == 101.0)
+            .count();
+        assert!((2300..2700).contains(&rounded_up), "{}", rounded_up);
+    }
+
+    #[test]
+    fn chroma_is_not_subsampled() {
+        const WIDTH: usize = 16;
+        const HEIGHT: usize = 8;
+
+        // A saturated edge between bright red and blue, where 4:2:0 would fringe.
+        let mut content = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let pixel = if x < WIDTH / 2 { [1000.0, 0.0, 0.0] } else { [0.0, 0.0, 1000.0] };
+                content.set_at(x, y, pixel.into());
+            }
+        }
+
+        let mut avif_bytes = Vec::new();
+        write_hdr10_linear_pixels_to_avif(&mut avif_bytes, WIDTH, HEIGHT, &content).unwrap();
+
+        // `av1C`: The High profile, which 10-bit 4:4:4 requires, and clear `chroma_subsampling_x` and `chroma_subsampling_y` flags.
+        let av1c = crate::testutil::find(&avif_bytes, b"av1C").unwrap() + 4;
+        assert_eq!(avif_bytes[av1c + 1] >> 5, 1);
+        assert_eq!(avif_bytes[av1c + 2] & 0x0C, 0);
+    }
+
+    #[test]
+    fn chroma_420_is_subsampled_smaller_and_fringes_at_edges() {
+        // Saturated detail with an edge between bright red and blue in the middle of a 2x2 block, at odd dimensions.
+        const WIDTH: usize = 33;
+        const HEIGHT: usize = 17;
+        let mut content = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let pixel = if x < 7 {
+                    [1000.0, 0.0, 0.0]
+                } else if x == 7 {
+                    [0.0, 0.0, 1000.0]
+                } else {
+                    let hash = (x * 7919 + y * 104729) % 251;
+                    [hash as f32 * 4.0, (250 - hash) as f32 * 4.0, (hash * 3 % 251) as f32 * 4.0]
+                };
+                content.set_at(x, y, pixel.into());
+            }
+        }
+
+        let encode = |chroma: AvifChroma| {
+            let options = Hdr10AvifOptions { quantizer: Some(0), encode: AvifEncodeOptions { chroma, ..Default::default() }, ..Default::default() };
+            let mut avif_bytes = Vec::new();
+            let error = write_hdr10_linear_pixels_to_avif_with_error(&mut avif_bytes, WIDTH, HEIGHT, &content, &options).unwrap();
+            (avif_bytes, error)
+        };
+        let (full, full_error) = encode(AvifChroma::Chroma444);
+        let (subsampled, subsampled_error) = encode(AvifChroma::Chroma420);
+        assert!(subsampled.len() < full.len(), "{} >= {}", subsampled.len(), full.len());
+
+        // `av1C`: The Main profile and set `chroma_subsampling_x` and `chroma_subsampling_y` flags.
+        let av1c = crate::testutil::find(&subsampled, b"av1C").unwrap() + 4;
+        assert_eq!(subsampled[av1c + 1] >> 5, 0);
+        assert_eq!(subsampled[av1c + 2] & 0x0C, 0x0C);
+
+        // Red bleeds into the blue pixel at the edge.
+        assert!(full_error.pixels[7][0] < 0.01, "{:?}", full_error.pixels[7]);
+        assert!(subsampled_error.pixels[7][0] > 0.1, "{:?}", subsampled_error.pixels[7]);
+    }
+
+    #[test]
+    fn monochrome_has_only_luma_and_is_smaller() {
+        const WIDTH: usize = 33;
+        const HEIGHT: usize = 17;
+        let mut content = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let value = ((x * 7919 + y * 104729) % 251) as f32 * 4.0;
+                content.set_at(x, y, [value; 3].into());
+            }
+        }
+
+        let encode = |chroma: AvifChroma| {
+            let options = Hdr10AvifOptions { quantizer: Some(0), encode: AvifEncodeOptions { chroma, ..Default::default() }, ..Default::default() };
+            let mut avif_bytes = Vec::new();
+            let error = write_hdr10_linear_pixels_to_avif_with_error(&mut avif_bytes, WIDTH, HEIGHT, &content, &options).unwrap();
+            (avif_bytes, error)
+        };
+        let (full, _) = encode(AvifChroma::Chroma444);
+        let (monochrome, monochrome_error) = encode(AvifChroma::Monochrome);
+        assert!(monochrome.len() < full.len(), "{} >= {}", monochrome.len(), full.len());
+
+        // `av1C`: The Main profile and set `mono_chrome`, `chroma_subsampling_x` and `chroma_subsampling_y` flags.
+        let av1c = crate::testutil::find(&monochrome, b"av1C").unwrap() + 4;
+        assert_eq!(monochrome[av1c + 1] >> 5, 0);
+        assert_eq!(monochrome[av1c + 2] & 0x1C, 0x1C);
+
+        // Neutral content decodes from Y' alone, with centered C'b and C'r.
+        assert!(monochrome_error.max() < 2.0 / 1023.0, "{}", monochrome_error.max());
+    }
+
+    #[test]
+    fn bands_encode_like_the_whole_image() {
+        // Odd dimensions, so that the last band and the last 2x2 blocks are partial.
+        const WIDTH: usize = 21;
+        const HEIGHT: usize = 11;
+        let mut content = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let hash = (x * 7919 + y * 104729) % 251;
+                content.set_at(x, y, [hash as f32 * 4.0, (250 - hash) as f32, (x * 40) as f32].into());
+            }
+        }
+
+        let encode_in_bands = |options: &Hdr10AvifOptions, band_rows: usize| {
+            let mut encoder = Hdr10AvifBandEncoder::new(WIDTH, HEIGHT, options)?;
+            let mut band = FloatImageContent::with_extent(WIDTH, band_rows);
+            for first_row in (0..HEIGHT).step_by(band_rows) {
+                let rows = band_rows.min(HEIGHT - first_row);
+                for y in 0..rows {
+                    for x in 0..WIDTH {
+                        band.set_at(x, y, content.get_at(x, first_row + y));
+                    }
+                }
+                encoder.write_band(&band, rows)?;
+            }
+            let mut avif_bytes = Vec::new();
+            encoder.finish(&mut avif_bytes).map(|()| avif_bytes)
+        };
+
+        for chroma in [AvifChroma::Chroma444, AvifChroma::Chroma420] {
+            let options = Hdr10AvifOptions {
+                quantizer: Some(40),
+                encode: AvifEncodeOptions { chroma, ..Default::default() },
+                rounding: Rounding::Stochastic { seed: 7 },
+                ..Default::default()
+            };
+            let mut expected = Vec::new();
+            write_hdr10_linear_pixels_to_avif_with_options(&mut expected, WIDTH, HEIGHT, &content, &options).unwrap();
+            for band_rows in [2, 4, HEIGHT] {
+                assert!(encode_in_bands(&options, band_rows).unwrap() == expected, "{:?} in bands of {}", chroma, band_rows);
+            }
+        }
+
+        let options = Hdr10AvifOptions { encode: AvifEncodeOptions { chroma: AvifChroma::Chroma420, ..Default::default() }, ..Default::default() };
+        assert!(encode_in_bands(&options, 3).unwrap_err().to_string().contains("odd number of rows"));
+        let mut encoder = Hdr10AvifBandEncoder::new(WIDTH, HEIGHT, &Hdr10AvifOptions::default()).unwrap();
+        assert!(encoder.write_band(&content, HEIGHT + 1).is_err());
+        encoder.write_band(&content, 1).unwrap();
+        assert!(encoder.finish(&mut Vec::new()).unwrap_err().to_string().contains("Only 1 of 11 rows"));
+    }
+
+    #[test]
+    fn subsampled_chroma_is_the_rounded_block_mean() {
+        // 3x3, i.e. a full, two partial and a single-pixel block.
+        let pixels: Vec<[u16; 3]> = [1, 2, 9, 4, 6, 5, 7, 8, 3].iter().map(|&value| [0, value, 100 + value]).collect();
+        assert_eq!(subsample_chroma(&pixels, 3, 3, 1, (0, 0), 1), 3);
+        assert_eq!(subsample_chroma(&pixels, 3, 3, 2, (0, 0), 1), 103);
+        assert_eq!(subsample_chroma(&pixels, 3, 3, 1, (1, 0), 1), 7);
+        assert_eq!(subsample_chroma(&pixels, 3, 3, 1, (0, 1), 1), 8);
+        assert_eq!(subsample_chroma(&pixels, 3, 3, 1, (1, 1), 1), 3);
+    }
+
+    #[test]
+    fn auto_bit_depth_selects_12_bit_for_smooth_wide_range_gradients() {
+        const WIDTH: usize = 512;
+        const HEIGHT: usize = 4;
+
+        // Exponential ramps from `min` to `max` nits, smooth enough to step by at most one 10-bit code value.
+        let ramp = |min: f32, max: f32| {
+            let mut content = FloatImageContent::with_extent(WIDTH, HEIGHT);
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let value = min * (max / min).powf(x as f32 / (WIDTH - 1) as f32);
+                    content.set_at(x, y, [value; 3].into());
+                }
+            }
+            content
+        };
+        let wide_range = ramp(100.0, 4000.0);
+        let low_range = ramp(0.1, 200.0);
+
+        assert_eq!(select_bit_depth(WIDTH, HEIGHT, &wide_range, 10000.0, AvifColorPrimaries::Bt2020), 12);
+        assert_eq!(select_bit_depth(WIDTH, HEIGHT, &low_range, 10000.0, AvifColorPrimaries::Bt2020), 10);
+        // Clamped to a low peak, the wide range is no longer bright.
+        assert_eq!(select_bit_depth(WIDTH, HEIGHT, &wide_range, 800.0, AvifColorPrimaries::Bt2020), 10);
+
+        // Bright noise isn't smooth.
+        let mut noise = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                noise.set_at(x, y, [(uniform_random(0, y * WIDTH + x) * 4000.0) as f32; 3].into());
+            }
+        }
+        assert_eq!(select_bit_depth(WIDTH, HEIGHT, &noise, 10000.0, AvifColorPrimaries::Bt2020), 10);
+
+        // `av1C`: The Professional profile, which 12-bit requires, and the `high_bitdepth` and `twelve_bit` flags.
+        let options = Hdr10AvifOptions { bit_depth: AvifBitDepth::Auto, ..Default::default() };
+        let mut avif_bytes = Vec::new();
+        write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, WIDTH, HEIGHT, &wide_range, &options).unwrap();
+        let av1c = crate::testutil::find(&avif_bytes, b"av1C").unwrap() + 4;
+        assert_eq!(avif_bytes[av1c + 1] >> 5, 2);
+        assert_eq!(avif_bytes[av1c + 2] & 0x60, 0x60);
+
+        // Explicitly requested 12 bits are signalled the same way, and as 12 bits per channel in `pixi`,
+        // both through `ravif` and through rav1e directly.
+        for quantizer in [None, Some(100)] {
+            let options = Hdr10AvifOptions { bit_depth: AvifBitDepth::Twelve, quantizer, ..Default::default() };
+            let mut avif_bytes = Vec::new();
+            write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, WIDTH, HEIGHT, &low_range, &options).unwrap();
+            let av1c = crate::testutil::find(&avif_bytes, b"av1C").unwrap() + 4;
+            assert_eq!(avif_bytes[av1c + 1] >> 5, 2, "{:?}", quantizer);
+            assert_eq!(avif_bytes[av1c + 2] & 0x60, 0x60, "{:?}", quantizer);
+            let pixi = crate::testutil::find(&avif_bytes, b"pixi").unwrap() + 4;
+            assert_eq!(avif_bytes[pixi + 4..pixi + 8], [3, 12, 12, 12], "{:?}", quantizer);
+        }
+    }
+
+    #[test]
+    fn invalid_input_is_an_error_rather_than_a_panic() {
+        let ycbcr_pixels = vec![[512u16; 3]; 4 * 4];
+
+        let mut avif_bytes = Vec::new();
+        assert!(write_hdr10_ycbcr_pixels_to_avif(&mut avif_bytes, 8, 8, &ycbcr_pixels).is_err());
+        assert!(write_hdr10_ycbcr_pixels_to_avif(&mut avif_bytes, 0, 0, &ycbcr_pixels).is_err());
+        assert!(write_hdr10_ycbcr_pixels_to_avif_with_quantizer(&mut avif_bytes, 8, 8, &ycbcr_pixels, 100, &AvifMetadata::default()).is_err());
+        assert!(write_hdr10_ycbcr_pixels_to_avif_with_quantizer(&mut avif_bytes, 0, 0, &ycbcr_pixels, 100, &AvifMetadata::default()).is_err());
+        assert!(avif_bytes.is_empty());
+
+        write_hdr10_ycbcr_pixels_to_avif(&mut avif_bytes, 4, 4, &ycbcr_pixels).unwrap();
+    }
+
+    #[test]
+    fn encode_options_are_validated_and_applied() {
+        const WIDTH: usize = 32;
+        const HEIGHT: usize = 32;
+
+        let mut content = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let value = ((x * 7 + y * 13) % 17) as f32 * 50.0;
+                content.set_at(x, y, [value, 1000.0 - value, value * 0.5].into());
+            }
+        }
+        let encode = |quality: f32, speed: u8| {
+            let mut avif_bytes = Vec::new();
+            let options = Hdr10AvifOptions { encode: AvifEncodeOptions { quality, speed, ..Default::default() }, ..Default::default() };
+            write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, WIDTH, HEIGHT, &content, &options).map(|()| avif_bytes)
+        };
+
+        for (quality, speed) in [(100.5, 4), (-1.0, 4), (f32::NAN, 4), (100.0, 11)] {
+            let error = encode(quality, speed).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput, "{}", error);
+        }
+
+        let default = encode(100.0, 4).unwrap();
+        let fast = encode(50.0, 10).unwrap();
+        assert!(fast.len() * 2 < default.len(), "{} bytes at quality 50 vs {} bytes at 100", fast.len(), default.len());
+        // Beyond what `ravif` supports, through rav1e directly.
+        assert!(encode(0.0, 0).unwrap().len() < fast.len());
+
+        // As `ravif` maps them.
+        let quantizer = |quality: f32| AvifEncodeOptions { quality, ..Default::default() }.quantizer();
+        assert_eq!((quantizer(100.0), quantizer(80.0), quantizer(0.0)), (0, 121, 255));
+    }
+
+    #[test]
+    fn higher_quantizer_gives_smaller_file() {
+        const WIDTH: usize = 64;
+        const HEIGHT: usize = 64;
+
+        // Detailed enough that the quantizer matters.
+        let mut content = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let value = ((x * 7 + y * 13) % 17) as f32 * 50.0 + (x ^ y) as f32;
+                content.set_at(x, y, [value, value * 0.5, 1000.0 - value].into());
+            }
+        }
+
+        let encode = |quantizer: u8| {
+            let mut avif_bytes = Vec::new();
+            let options = Hdr10AvifOptions { quantizer: Some(quantizer), ..Default::default() };
+            write_hdr10_linear_pixels_to_avif_with_options(&mut avif_bytes, WIDTH, HEIGHT, &content, &options).unwrap();
+            avif_bytes
+        };
+        let (fine, coarse) = (encode(20), encode(200));
+
+        assert!(coarse.len() * 2 < fine.len(), "{} bytes at 200 vs {} bytes at 20", coarse.len(), fine.len());
+        // Still 4:4:4 in the High profile, with HDR10 `colr`.
+        let av1c = crate::testutil::find(&coarse, b"av1C").unwrap() + 4;
+        assert_eq!(coarse[av1c + 1] >> 5, 1);
+        assert_eq!(coarse[av1c + 2] & 0x0C, 0);
+        let colr = crate::testutil::find(&coarse, b"colrnclx").unwrap() + 8;
+        assert_eq!(coarse[colr..colr + 6], [0, 9, 0, 16, 0, 9]);
+    }
+
+    #[test]
+    fn ictcp_round_trip_is_closer_than_ycbcr() {
+        /// Quantizes to 10 bits, with chroma offset by half the range as in `write_hdr10_linear_pixels_to_avif`.
+        fn quantize([a, b, c]: [f32; 3]) -> [f32; 3] {
+            let quantize = |value: f32| (value * 1023.0).round() / 1023.0;
+            [quantize(a), quantize(b + 0.5) - 0.5, quantize(c + 0.5) - 0.5]
+        }
+
+        fn ycbcr_round_trip(rgb: [f32; 3]) -> [f32; 3] {
+            let [r, g, b] = rgb.map(st2084_oetf);
+            let y = 0.2627 * r + 0.6780 * g + 0.0593 * b;
+            let [y, cb, cr] = quantize([y, (b - y) / 1.8814, (r - y) / 1.4746]);
+
+            let r = y + 1.4746 * cr;
+            let b = y + 1.8814 * cb;
+            let g = (y - 0.2627 * r - 0.0593 * b) / 0.6780;
+            [r, g, b].map(st2084_eotf)
+        }
+
+        fn ictcp_round_trip(rgb: [f32; 3]) -> [f32; 3] {
+            ictcp_to_linear_bt2020(quantize(linear_bt2020_to_ictcp(rgb)))
+        }
+
+        /// ΔE ITP of Rec. ITU-R BT.2124, where 1 is about a just-noticeable difference.
+        fn delta_e_itp(a: [f32; 3], b: [f32; 3]) -> f32 {
+            let [i_a, ct_a, cp_a] = linear_bt2020_to_ictcp(a);
+            let [i_b, ct_b, cp_b] = linear_bt2020_to_ictcp(b.map(|value| value.max(0.0)));
+            720.0 * ((i_a - i_b).powi(2) + (0.5 * (ct_a - ct_b)).powi(2) + (cp_a - cp_b).powi(2)).sqrt()
+        }
+
+        let rgb = [0.25, 0.5, 0.75];
+        let round_tripped = ictcp_to_linear_bt2020(linear_bt2020_to_ictcp(rgb));
+        for channel in 0..3 {
+            assert!((round_tripped[channel] - rgb[channel]).abs() < 1e-4, "{:?} != {:?}", round_tripped, rgb);
+        }
+
+        // Saturated colors from 1 to 1000 nits.
+        for nits in [1.0, 10.0, 100.0, 1000.0] {
+            let worst = |round_trip: fn([f32; 3]) -> [f32; 3]| {
+                [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 0.0], [0.0, 1.0, 1.0], [1.0, 0.2, 0.0]]
+                    .map(|color: [f32; 3]| color.map(|value| value * nits / 10000.0))
+                    .map(|rgb| delta_e_itp(rgb, round_trip(rgb)))
+                    .into_iter()
+                    .fold(0.0f32, f32::max)
+            };
+            let (ictcp, ycbcr) = (worst(ictcp_round_trip), worst(ycbcr_round_trip));
+            assert!(ictcp < ycbcr, "{} nits: ICtCp {} >= Y'CbCr {}", nits, ictcp, ycbcr);
+        }
+    }
 }
@@ -1,12 +1,27 @@
 #![cfg(feature = "exr")]
 
+use std::io::Write;
+
 use exr::prelude::*;
 use exr::meta::attribute::Chromaticities;
 
 use crate::colorspace::ColorGamut;
+use crate::pixel::FloatImageContent;
+
+/// Writes `content` as an RGB EXR with 3 `f32` channels, leaving out the padding element of the pixels.
+pub fn write_linear_pixels_to_exr<W: Write>(writer: &mut W, content: &FloatImageContent, color_gamut: &ColorGamut) -> std::io::Result<()> {
+    let (width, height) = content.extent();
+    write_rgb_image_to_exr(writer, width, height, color_gamut, |x, y| {
+        let [r, g, b] = content.get_at(x, y).to_serialized_rgb();
+        (r, g, b)
+    })
+}
 
-pub fn write_rgb_image_to_exr<F: Fn(usize, usize) -> (f32, f32, f32) + Sync>(
-    filename: &str,
+/// Writes a PIZ-compressed RGB EXR with 3 `f32` channels, tagged with the chromaticities of `color_gamut`.
+///
+/// The EXR is assembled in memory, since its offset tables need a seekable writer.
+pub fn write_rgb_image_to_exr<W: Write, F: Fn(usize, usize) -> (f32, f32, f32) + Sync>(
+    writer: &mut W,
     width: usize,
     height: usize,
     color_gamut: &ColorGamut,
@@ -25,7 +40,7 @@ pub fn write_rgb_image_to_exr<F: Fn(usize, usize) -> (f32, f32, f32) + Sync>(
     image_attributes.chromaticities = Some(chromaticities);
 
     let channels = SpecificChannels::rgb(|Vec2(x, y)| {
-        f(x as usize, y as usize)
+        f(x, y)
     });
 
     let mut image = Image::from_channels((width, height), channels);
@@ -33,7 +48,37 @@ pub fn write_rgb_image_to_exr<F: Fn(usize, usize) -> (f32, f32, f32) + Sync>(
 
     image.layer_data.encoding.compression = Compression::PIZ;
 
-    image.write().to_file(filename).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    
-    Ok(())
+    let mut exr_bytes = std::io::Cursor::new(Vec::new());
+    image.write().to_buffered(&mut exr_bytes).map_err(std::io::Error::other)?;
+
+    writer.write_all(exr_bytes.get_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pixel::FloatPixel;
+
+    #[test]
+    fn exr_has_exactly_3_samples_per_pixel() {
+        const WIDTH: usize = 5;
+        const HEIGHT: usize = 3;
+
+        let mut content = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for (index, pixel) in content.pixels_mut().iter_mut().enumerate() {
+            *pixel = FloatPixel::new(index as f32, 0.5, 2.0);
+        }
+
+        let mut exr_bytes = Vec::new();
+        write_linear_pixels_to_exr(&mut exr_bytes, &content, &ColorGamut::bt2020()).unwrap();
+        let image = read().no_deep_data().largest_resolution_level().all_channels().all_layers().all_attributes()
+            .from_buffered(std::io::Cursor::new(exr_bytes))
+            .unwrap();
+
+        let channels = &image.layer_data[0].channel_data.list;
+        let names: Vec<String> = channels.iter().map(|channel| channel.name.to_string()).collect();
+        assert_eq!(names, ["B", "G", "R"]);
+        assert_eq!(channels.iter().map(|channel| channel.sample_data.len()).sum::<usize>(), WIDTH * HEIGHT * 3);
+    }
 }
@@ -1,54 +1,59 @@
 #![cfg(feature = "heif")]
 
+use std::io::Write;
+
 use libheif_rs::{
     Channel, RgbChroma, ColorSpace, CompressionFormat,
-    EncoderQuality, HeifContext, Image, Result, LibHeif
+    EncoderQuality, HeifContext, Image, LibHeif,
+    ColorPrimaries, ColorProfileNCLX, MatrixCoefficients, TransferCharacteristics,
 };
 
-
-use crate::colorspace::ColorGamut;
-
-pub fn write_rgb_image_to_heif<F: Fn(usize, usize) -> (f32, f32, f32) + Sync>(
-    filename: &str,
+/// Writes a 10-bit HEVC HEIF tagged as BT.2020 PQ, i.e. HDR10, like the AVIF of `outavif`.
+///
+/// - `f`: The PQ-encoded BT.2020 R'G'B' of a pixel, in [0, 1]. Values outside of it are clamped.
+pub fn write_hdr10_rgb_image_to_heif<W: Write, F: Fn(usize, usize) -> (f32, f32, f32) + Sync>(
+    writer: &mut W,
     width: usize,
     height: usize,
-    color_gamut: &ColorGamut,
     f: F,
 ) -> std::io::Result<()> {
     let width = width as u32;
     let height = height as u32;
 
-    let mut image = Image::new(width, height, ColorSpace::Rgb(RgbChroma::HdrRgbLe)).unwrap();
+    let mut image = Image::new(width, height, ColorSpace::Rgb(RgbChroma::HdrRgbLe)).map_err(std::io::Error::other)?;
 
-    image.create_plane(Channel::Interleaved, width, height, 10);
+    image.create_plane(Channel::Interleaved, width, height, 10).map_err(std::io::Error::other)?;
 
     let planes = image.planes_mut();
-    let plane = planes.interleaved.unwrap();
+    let plane = planes.interleaved.ok_or_else(|| std::io::Error::other("Failed to create the interleaved RGB plane"))?;
     let stride = plane.stride;
     let data = plane.data;
-    
+
     for y in 0..height {
-        let mut row_start = stride * y as usize;
+        let row_start = stride * y as usize;
         for x in 0..width {
             let (r, g, b) = f(x as usize, y as usize);
-            
-            let r = (r * 1023.0).round() as u16;
-            let g = (g * 1023.0).round() as u16;
-            let b = (b * 1023.0).round() as u16;
-
-            data[row_start + 0 .. row_start + 2].copy_from_slice(&r.to_le_bytes());
-            data[row_start + 2 .. row_start + 4].copy_from_slice(&g.to_le_bytes());
-            data[row_start + 4 .. row_start + 6].copy_from_slice(&b.to_le_bytes());
+
+            let pixel_start = row_start + x as usize * 6;
+            for (index, value) in [r, g, b].into_iter().enumerate() {
+                let value = (value.clamp(0.0, 1.0) * 1023.0).round() as u16;
+                data[pixel_start + index * 2..pixel_start + index * 2 + 2].copy_from_slice(&value.to_le_bytes());
+            }
         }
     }
 
-    // Encode image and save it into file.
+    let mut nclx = ColorProfileNCLX::new().ok_or_else(|| std::io::Error::other("Failed to allocate the nclx color profile"))?;
+    nclx.set_color_primaries(ColorPrimaries::ITU_R_BT_2020_2_and_2100_0);
+    nclx.set_transfer_characteristics(TransferCharacteristics::ITU_R_BT_2100_0_PQ);
+    nclx.set_matrix_coefficients(MatrixCoefficients::ITU_R_BT_2020_2_NonConstantLuminance);
+    nclx.set_full_range_flag(1);
+    image.set_color_profile_nclx(&nclx).map_err(std::io::Error::other)?;
+
     let lib_heif = LibHeif::new();
-    let mut context = HeifContext::new().unwrap();
-    let mut encoder = lib_heif.encoder_for_format(CompressionFormat::Hevc).unwrap();
-    encoder.set_quality(EncoderQuality::Lossy(100)).unwrap();
-    context.encode_image(&image, &mut encoder, None).unwrap();
-    context.write_to_file(filename).unwrap();
+    let mut context = HeifContext::new().map_err(std::io::Error::other)?;
+    let mut encoder = lib_heif.encoder_for_format(CompressionFormat::Hevc).map_err(std::io::Error::other)?;
+    encoder.set_quality(EncoderQuality::Lossy(100)).map_err(std::io::Error::other)?;
+    context.encode_image(&image, &mut encoder, None).map_err(std::io::Error::other)?;
 
-    Ok(())
+    writer.write_all(&context.write_to_bytes().map_err(std::io::Error::other)?)
 }
@@ -0,0 +1,125 @@
+#![cfg(feature = "png")]
+
+use std::io::Write;
+
+use crate::colorspace::{Cicp, ColorGamut};
+use crate::encoder::PngTransfer;
+
+impl PngTransfer {
+    /// The `cICP` transfer characteristics, per ITU-T H.273.
+    fn transfer_characteristics(self) -> u8 {
+        match self {
+            Self::Pq => Cicp::TRANSFER_CHARACTERISTICS_PQ,
+            Self::Linear => 8,
+        }
+    }
+
+    fn encode(self, value: f32) -> f32 {
+        match self {
+            Self::Pq => crate::transfer::st2084_oetf(value),
+            Self::Linear => value,
+        }
+    }
+}
+
+/// Writes a 16-bit BT.2020 RGB PNG.
+///
+/// - `f`: Returns the linear BT.2020 RGB value at the given pixel, normalized to [0, 1]: For `PngTransfer::Pq`, 1 is 10,000 nits.
+///   Values outside the range are clamped.
+///
+/// The BT.2020 primaries and `transfer` are recorded in a `cICP` chunk, as HDR-capable browsers read it,
+/// and the primaries also in a `cHRM` chunk for older readers.
+pub fn write_bt2020_rgb_image_to_png<W: Write, F: Fn(usize, usize) -> (f32, f32, f32)>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    transfer: PngTransfer,
+    f: F,
+) -> std::io::Result<()> {
+    let invalid_input = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, message.to_string());
+    let png_width: u32 = width.try_into().map_err(|_| invalid_input("Image width too large for PNG"))?;
+    let png_height: u32 = height.try_into().map_err(|_| invalid_input("Image height too large for PNG"))?;
+
+    // Big-endian, as PNG samples are.
+    let mut samples: Vec<u8> = Vec::with_capacity(width * height * 3 * 2);
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = f(x, y);
+            for value in [r, g, b] {
+                let value = transfer.encode(value.clamp(0.0, 1.0));
+                samples.extend(((value * 65535.0).round() as u16).to_be_bytes());
+            }
+        }
+    }
+
+    let mut png_bytes = Vec::new();
+    {
+        let color_gamut = ColorGamut::bt2020();
+        let primaries = color_gamut.primaries();
+        let xy = |[x, y]: [f64; 2]| (x as f32, y as f32);
+
+        let mut encoder = png::Encoder::new(&mut png_bytes, png_width, png_height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        encoder.set_source_chromaticities(png::SourceChromaticities::new(
+            xy(color_gamut.white_point_xy()),
+            xy(primaries.red_xy()),
+            xy(primaries.green_xy()),
+            xy(primaries.blue_xy()),
+        ));
+
+        let mut png_writer = encoder.write_header().map_err(std::io::Error::other)?;
+        // BT.2020 primaries, `transfer`, RGB and full range.
+        let cicp = [Cicp::COLOR_PRIMARIES_BT2020, transfer.transfer_characteristics(), 0, 1];
+        png_writer.write_chunk(png::chunk::ChunkType(*b"cICP"), &cicp).map_err(std::io::Error::other)?;
+        png_writer.write_image_data(&samples).map_err(std::io::Error::other)?;
+        png_writer.finish().map_err(std::io::Error::other)?;
+    }
+
+    writer.write_all(&png_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_16_bit_png_with_its_transfer_tagged() {
+        const WIDTH: usize = 5;
+        const HEIGHT: usize = 3;
+
+        let pixel = |x: usize, y: usize| {
+            (x as f32 / (WIDTH - 1) as f32, y as f32 / (HEIGHT - 1) as f32, 0.01)
+        };
+
+        for (transfer, transfer_characteristics) in [(PngTransfer::Pq, 16), (PngTransfer::Linear, 8)] {
+            let mut png_bytes = Vec::new();
+            write_bt2020_rgb_image_to_png(&mut png_bytes, WIDTH, HEIGHT, transfer, pixel).unwrap();
+
+            let cicp = crate::testutil::find(&png_bytes, b"cICP").unwrap() + 4;
+            assert_eq!(png_bytes[cicp..cicp + 4], [9, transfer_characteristics, 0, 1]);
+            assert!(crate::testutil::find(&png_bytes, b"cHRM").is_some());
+
+            let mut reader = png::Decoder::new(std::io::Cursor::new(&png_bytes)).read_info().unwrap();
+            let mut samples = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut samples).unwrap();
+            assert_eq!((info.width, info.height), (WIDTH as u32, HEIGHT as u32));
+            assert_eq!((info.color_type, info.bit_depth), (png::ColorType::Rgb, png::BitDepth::Sixteen));
+
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let (r, g, b) = pixel(x, y);
+                    for (channel, expected) in [r, g, b].into_iter().enumerate() {
+                        let index = ((y * WIDTH + x) * 3 + channel) * 2;
+                        let sample = u16::from_be_bytes([samples[index], samples[index + 1]]);
+                        let decoded = match transfer {
+                            PngTransfer::Pq => crate::transfer::st2084_eotf(sample as f32 / 65535.0),
+                            PngTransfer::Linear => sample as f32 / 65535.0,
+                        };
+                        assert!((decoded - expected).abs() <= 1e-4 + expected * 1e-3, "{:?} ({}, {}) channel {}: {} != {}", transfer, x, y, channel, decoded, expected);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+#![cfg(feature = "hdr")]
+
+use std::io::Write;
+
+use crate::colorspace::ColorGamut;
+use crate::pixel::FloatImageContent;
+
+/// The narrowest and widest scanlines that can be run-length encoded. Others are written flat.
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7FFF;
+
+/// The longest run and the longest literal of the run-length encoding of a scanline component.
+const MAX_RUN: usize = 127;
+const MAX_LITERAL: usize = 128;
+
+/// A run shorter than this is written as part of a literal instead, where it costs no more.
+const MIN_RUN: usize = 4;
+
+/// Writes `content` as a Radiance RGBE `.hdr`, leaving out the padding element of the pixels.
+pub fn write_linear_pixels_to_hdr<W: Write>(writer: &mut W, content: &FloatImageContent, color_gamut: &ColorGamut) -> std::io::Result<()> {
+    let (width, height) = content.extent();
+    write_rgb_image_to_hdr(writer, width, height, color_gamut, |x, y| {
+        let [r, g, b] = content.get_at(x, y).to_serialized_rgb();
+        (r, g, b)
+    })
+}
+
+/// Writes a run-length encoded Radiance RGBE `.hdr`, tagged with the chromaticities of `color_gamut` in a `PRIMARIES` line.
+///
+/// RGBE shares an 8-bit exponent between 8-bit mantissas, so each channel is within about 1% of the brightest one of its pixel.
+/// Negative values and NaN are written as 0, and values too small for the exponent as black.
+pub fn write_rgb_image_to_hdr<W: Write, F: Fn(usize, usize) -> (f32, f32, f32)>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    color_gamut: &ColorGamut,
+    f: F,
+) -> std::io::Result<()> {
+    let primaries = color_gamut.primaries();
+    let [rx, ry] = primaries.red_xy();
+    let [gx, gy] = primaries.green_xy();
+    let [bx, by] = primaries.blue_xy();
+    let [wx, wy] = color_gamut.white_point_xy();
+
+    let mut hdr_bytes = Vec::new();
+    writeln!(hdr_bytes, "#?RADIANCE")?;
+    writeln!(hdr_bytes, "FORMAT=32-bit_rle_rgbe")?;
+    writeln!(hdr_bytes, "PRIMARIES={:.4} {:.4} {:.4} {:.4} {:.4} {:.4} {:.4} {:.4}", rx, ry, gx, gy, bx, by, wx, wy)?;
+    writeln!(hdr_bytes)?;
+    // Top to bottom, left to right.
+    writeln!(hdr_bytes, "-Y {} +X {}", height, width)?;
+
+    let mut scanline: Vec<[u8; 4]> = Vec::with_capacity(width);
+    for y in 0..height {
+        scanline.clear();
+        scanline.extend((0..width).map(|x| {
+            let (r, g, b) = f(x, y);
+            to_rgbe([r, g, b])
+        }));
+        write_scanline(&mut hdr_bytes, &scanline);
+    }
+
+    writer.write_all(&hdr_bytes)
+}
+
+/// Encodes a linear pixel as RGBE: The mantissas of the channels in units of `2^(e - 136)`, where `e` is the exponent
+/// that puts the brightest channel in [128, 256).
+fn to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let rgb = rgb.map(|value| if value > 0.0 { value } else { 0.0 });
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    // Below `2^-128`, the biased exponent would be 0, which stands for black.
+    if (max as f64) < 2.0f64.powi(-128) {
+        return [0; 4];
+    }
+
+    // `max` is in [2^(exponent - 1), 2^exponent). Clamped before adding 1, since infinity saturates to `i32::MAX`.
+    let exponent = ((max as f64).log2().floor() as i32).min(126) + 1;
+    let scale = 256.0 / 2.0f64.powi(exponent);
+    let [r, g, b] = rgb.map(|value| (value as f64 * scale).min(255.0) as u8);
+    [r, g, b, (exponent + 128) as u8]
+}
+
+/// Writes a scanline with the adaptive run-length encoding of Radiance: The R, G, B and E components separately,
+/// each as runs and literals, after a marker that a flat scanline can't start with. Scanlines of widths that can't be encoded
+/// are written flat.
+fn write_scanline(hdr_bytes: &mut Vec<u8>, scanline: &[[u8; 4]]) {
+    let width = scanline.len();
+    if !(MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) {
+        hdr_bytes.extend(scanline.iter().flatten());
+        return;
+    }
+
+    hdr_bytes.extend([2, 2, (width >> 8) as u8, width as u8]);
+    let mut component_bytes = Vec::with_capacity(width);
+    for component in 0..4 {
+        component_bytes.clear();
+        component_bytes.extend(scanline.iter().map(|rgbe| rgbe[component]));
+        write_rle_component(hdr_bytes, &component_bytes);
+    }
+}
+
+/// Writes runs of at least `MIN_RUN` equal bytes as a count above 128 followed by the byte,
+/// and everything in between as literals of a count of at most 128 followed by the bytes.
+fn write_rle_component(hdr_bytes: &mut Vec<u8>, bytes: &[u8]) {
+    let mut position = 0;
+    while position < bytes.len() {
+        // Find the next run long enough to be worth it.
+        let mut run_start = position;
+        let mut run_length = 0;
+        while run_start < bytes.len() {
+            run_length = bytes[run_start..].iter().take(MAX_RUN).take_while(|&&byte| byte == bytes[run_start]).count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+            run_length = 0;
+        }
+
+        for literal in bytes[position..run_start].chunks(MAX_LITERAL) {
+            hdr_bytes.push(literal.len() as u8);
+            hdr_bytes.extend(literal);
+        }
+        if run_length > 0 {
+            hdr_bytes.extend([128 + run_length as u8, bytes[run_start]]);
+        }
+        position = run_start + run_length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pixel::FloatPixel;
+
+    /// Decodes an `.hdr` as written by `write_rgb_image_to_hdr`, returning its header lines, dimensions and pixels.
+    fn read_hdr(hdr_bytes: &[u8]) -> (Vec<String>, (usize, usize), Vec<[f32; 3]>) {
+        let header_end = hdr_bytes.windows(2).position(|window| window == b"\n\n").unwrap();
+        let header: Vec<String> = std::str::from_utf8(&hdr_bytes[..header_end]).unwrap().lines().map(str::to_string).collect();
+        let resolution_end = header_end + 2 + hdr_bytes[header_end + 2..].iter().position(|&byte| byte == b'\n').unwrap();
+        let resolution = std::str::from_utf8(&hdr_bytes[header_end + 2..resolution_end]).unwrap();
+        let [_, height, _, width] = resolution.split(' ').collect::<Vec<_>>()[..] else { panic!("{}", resolution) };
+        let (width, height): (usize, usize) = (width.parse().unwrap(), height.parse().unwrap());
+
+        let mut data = &hdr_bytes[resolution_end + 1..];
+        let mut pixels = Vec::with_capacity(width * height);
+        for _ in 0..height {
+            let mut scanline = vec![[0u8; 4]; width];
+            if data.starts_with(&[2, 2]) && (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) {
+                assert_eq!(usize::from(data[2]) << 8 | usize::from(data[3]), width);
+                data = &data[4..];
+                for component in 0..4 {
+                    let mut x = 0;
+                    while x < width {
+                        let count = data[0] as usize;
+                        if count > 128 {
+                            for rgbe in &mut scanline[x..x + count - 128] {
+                                rgbe[component] = data[1];
+                            }
+                            x += count - 128;
+                            data = &data[2..];
+                        } else {
+                            assert!(count > 0);
+                            for (rgbe, &byte) in scanline[x..x + count].iter_mut().zip(&data[1..=count]) {
+                                rgbe[component] = byte;
+                            }
+                            x += count;
+                            data = &data[1 + count..];
+                        }
+                    }
+                    assert_eq!(x, width);
+                }
+            } else {
+                for rgbe in &mut scanline {
+                    *rgbe = data[..4].try_into().unwrap();
+                    data = &data[4..];
+                }
+            }
+            pixels.extend(scanline.iter().map(|&[r, g, b, e]| {
+                if e == 0 {
+                    [0.0; 3]
+                } else {
+                    let unit = 2.0f32.powi(e as i32 - 136);
+                    [r, g, b].map(|mantissa| (mantissa as f32 + 0.5) * unit)
+                }
+            }));
+        }
+        assert!(data.is_empty());
+        (header, (width, height), pixels)
+    }
+
+    #[test]
+    fn hdr_round_trips_within_the_rgbe_precision() {
+        // Flat, because too narrow, and run-length encoded, with runs, literals and literals longer than 128.
+        for (width, height) in [(5, 3), (300, 4)] {
+            let mut content = FloatImageContent::with_extent(width, height);
+            for (index, pixel) in content.pixels_mut().iter_mut().enumerate() {
+                let (x, y) = (index % width, index / width);
+                *pixel = if x < width / 2 {
+                    FloatPixel::new(y as f32 * 4.0, 0.25, 1.0)
+                } else {
+                    FloatPixel::new(x as f32 * 0.37, 2.0 + (index % 7) as f32, 1000.0 / (1 + x) as f32)
+                };
+            }
+
+            let mut hdr_bytes = Vec::new();
+            write_linear_pixels_to_hdr(&mut hdr_bytes, &content, &ColorGamut::bt2020()).unwrap();
+            let (header, extent, pixels) = read_hdr(&hdr_bytes);
+
+            assert_eq!(header[0], "#?RADIANCE");
+            assert!(header.contains(&"FORMAT=32-bit_rle_rgbe".to_string()));
+            assert!(header.contains(&"PRIMARIES=0.7080 0.2920 0.1700 0.7970 0.1310 0.0460 0.3127 0.3290".to_string()), "{:?}", header);
+            assert_eq!(extent, (width, height));
+
+            for (expected, actual) in content.pixels().iter().zip(&pixels) {
+                let expected = expected.to_serialized_rgb();
+                let max = expected.iter().fold(0.0f32, |max, &value| max.max(value));
+                for channel in 0..3 {
+                    assert!((expected[channel] - actual[channel]).abs() <= max / 128.0, "{:?} != {:?}", expected, actual);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_encoded_as_black_or_clamped() {
+        assert_eq!(to_rgbe([0.0; 3]), [0; 4]);
+        assert_eq!(to_rgbe([-1.0, f32::NAN, 1e-40]), [0; 4]);
+        assert_eq!(to_rgbe([1.0, 0.5, -1.0]), [128, 64, 0, 129]);
+        assert_eq!(to_rgbe([f32::MAX, 0.0, 0.0])[3], 255);
+        assert_eq!(to_rgbe([f32::INFINITY, 1.0, 0.0]), [255, 0, 0, 255]);
+    }
+}
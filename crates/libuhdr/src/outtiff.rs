@@ -0,0 +1,183 @@
+use std::io::Write;
+
+use crate::colorspace::ColorGamut;
+use crate::tiff::{Endianness, TiffFieldValue, TiffIfdEntry, TiffWriter};
+
+// Baseline and extension TIFF tags used for writing RGB images.
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const TAG_IMAGE_DESCRIPTION: u16 = 270;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_PLANAR_CONFIGURATION: u16 = 284;
+const TAG_TRANSFER_FUNCTION: u16 = 301;
+const TAG_WHITE_POINT: u16 = 318;
+const TAG_PRIMARY_CHROMATICITIES: u16 = 319;
+const TAG_SAMPLE_FORMAT: u16 = 339;
+
+/// How the normalized linear values are encoded into the 16-bit samples of a TIFF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TiffTransfer {
+    /// The samples are linear in light.
+    #[default]
+    Linear,
+    /// The samples are encoded with a pure power function of `1 / 2.2`.
+    Gamma22,
+}
+
+impl TiffTransfer {
+    fn encode(self, value: f32) -> f32 {
+        match self {
+            TiffTransfer::Linear => value,
+            TiffTransfer::Gamma22 => value.powf(1.0 / 2.2),
+        }
+    }
+
+    fn decode(self, value: f32) -> f32 {
+        match self {
+            TiffTransfer::Linear => value,
+            TiffTransfer::Gamma22 => value.powf(2.2),
+        }
+    }
+}
+
+/// Writes an uncompressed 16-bit RGB TIFF.
+///
+/// - `f`: Returns the linear RGB value at the given pixel, normalized to [0, 1]. Values outside the range are clamped.
+///
+/// The primaries and white point of `color_gamut` are recorded in the `PrimaryChromaticities` and `WhitePoint` tags,
+/// and `transfer` is recorded as the `TransferFunction` tag.
+pub fn write_rgb_image_to_tiff<W: Write, F: Fn(usize, usize) -> (f32, f32, f32)>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    color_gamut: &ColorGamut,
+    transfer: TiffTransfer,
+    f: F,
+) -> std::io::Result<()> {
+    const ENDIANNESS: Endianness = Endianness::LittleEndian;
+
+    let image_width: u32 = width.try_into()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Image width too large for TIFF"))?;
+    let image_length: u32 = height.try_into()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Image height too large for TIFF"))?;
+
+    let strip_byte_count: u32 = (width * height * 3 * 2).try_into()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Image too large for TIFF"))?;
+
+    let mut strip: Vec<u8> = Vec::with_capacity(strip_byte_count as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = f(x, y);
+            for value in [r, g, b] {
+                let value = transfer.encode(value.clamp(0.0, 1.0));
+                ENDIANNESS.write_u16(&mut strip, (value * 65535.0).round() as u16)?;
+            }
+        }
+    }
+
+    let mut tiff_writer = TiffWriter::new(ENDIANNESS);
+    let strip_offset = tiff_writer.append_data(&strip);
+
+    let primaries = color_gamut.primaries();
+    let to_rational = |value: f64| ((value * 1_000_000.0).round() as u32, 1_000_000u32);
+
+    // One table shared by all channels, mapping each sample value to linear intensity.
+    let transfer_function: Vec<u16> = (0..=u16::MAX)
+        .map(|value| (transfer.decode(value as f32 / 65535.0) * 65535.0).round() as u16)
+        .collect();
+
+    let description = match transfer {
+        TiffTransfer::Linear => "Linear RGB",
+        TiffTransfer::Gamma22 => "Gamma 2.2 RGB",
+    };
+
+    let entries = [
+        TiffIfdEntry::new(TAG_IMAGE_WIDTH, TiffFieldValue::LONG(vec![image_width])),
+        TiffIfdEntry::new(TAG_IMAGE_LENGTH, TiffFieldValue::LONG(vec![image_length])),
+        TiffIfdEntry::new(TAG_BITS_PER_SAMPLE, TiffFieldValue::SHORT(vec![16, 16, 16])),
+        // No compression.
+        TiffIfdEntry::new(TAG_COMPRESSION, TiffFieldValue::SHORT(vec![1])),
+        // RGB.
+        TiffIfdEntry::new(TAG_PHOTOMETRIC_INTERPRETATION, TiffFieldValue::SHORT(vec![2])),
+        TiffIfdEntry::new(TAG_IMAGE_DESCRIPTION, TiffFieldValue::ASCII(description.to_string())),
+        TiffIfdEntry::new(TAG_STRIP_OFFSETS, TiffFieldValue::LONG(vec![strip_offset])),
+        TiffIfdEntry::new(TAG_SAMPLES_PER_PIXEL, TiffFieldValue::SHORT(vec![3])),
+        TiffIfdEntry::new(TAG_ROWS_PER_STRIP, TiffFieldValue::LONG(vec![image_length])),
+        TiffIfdEntry::new(TAG_STRIP_BYTE_COUNTS, TiffFieldValue::LONG(vec![strip_byte_count])),
+        // Chunky, i.e. interleaved.
+        TiffIfdEntry::new(TAG_PLANAR_CONFIGURATION, TiffFieldValue::SHORT(vec![1])),
+        TiffIfdEntry::new(TAG_TRANSFER_FUNCTION, TiffFieldValue::SHORT(transfer_function)),
+        TiffIfdEntry::new(TAG_WHITE_POINT, TiffFieldValue::RATIONAL(
+            color_gamut.white_point_xy().map(to_rational).to_vec(),
+        )),
+        TiffIfdEntry::new(TAG_PRIMARY_CHROMATICITIES, TiffFieldValue::RATIONAL(
+            [primaries.red_xy(), primaries.green_xy(), primaries.blue_xy()]
+                .iter()
+                .flatten()
+                .map(|&value| to_rational(value))
+                .collect(),
+        )),
+        // Unsigned integer data.
+        TiffIfdEntry::new(TAG_SAMPLE_FORMAT, TiffFieldValue::SHORT(vec![1, 1, 1])),
+    ];
+    tiff_writer.append_ifd(&entries)?;
+
+    writer.write_all(&tiff_writer.finish())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tiff::Tiff;
+
+    #[test]
+    fn writes_16_bit_tiff_readable_by_parser() {
+        const WIDTH: usize = 5;
+        const HEIGHT: usize = 3;
+
+        let pixel = |x: usize, y: usize| {
+            (x as f32 / (WIDTH - 1) as f32, y as f32 / (HEIGHT - 1) as f32, 0.25)
+        };
+
+        for transfer in [TiffTransfer::Linear, TiffTransfer::Gamma22] {
+            let mut bytes = Vec::new();
+            write_rgb_image_to_tiff(&mut bytes, WIDTH, HEIGHT, &ColorGamut::bt2020(), transfer, pixel).unwrap();
+
+            let tiff = Tiff::from_reader(&mut std::io::Cursor::new(&bytes)).unwrap();
+            let ifd = &tiff.ifds[0];
+
+            let long = |tag: u16| ifd.entry_with_tag(tag).unwrap().field_value_as_long().unwrap()[0];
+            let short = |tag: u16| match &ifd.entry_with_tag(tag).unwrap().field_value {
+                TiffFieldValue::SHORT(values) => values.clone(),
+                other => panic!("Expected SHORT values for tag {}, got {:?}", tag, other.field_type()),
+            };
+            assert_eq!(long(TAG_IMAGE_WIDTH), WIDTH as u32);
+            assert_eq!(long(TAG_IMAGE_LENGTH), HEIGHT as u32);
+            assert_eq!(short(TAG_BITS_PER_SAMPLE), [16, 16, 16]);
+            assert_eq!(short(TAG_TRANSFER_FUNCTION).len(), 65536);
+
+            let strip_offset = long(TAG_STRIP_OFFSETS) as usize;
+            assert_eq!(long(TAG_STRIP_BYTE_COUNTS) as usize, WIDTH * HEIGHT * 3 * 2);
+
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let (r, g, b) = pixel(x, y);
+                    for (channel, expected) in [r, g, b].into_iter().enumerate() {
+                        let index = strip_offset + ((y * WIDTH + x) * 3 + channel) * 2;
+                        let sample = u16::from_le_bytes([bytes[index], bytes[index + 1]]);
+                        let decoded = transfer.decode(sample as f32 / 65535.0);
+                        assert!((decoded - expected).abs() < 1e-3, "{:?} ({}, {}) channel {}: {} != {}", transfer, x, y, channel, decoded, expected);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+#![cfg(feature = "webp")]
+
+use std::io::Write;
+
+/// Lossy quality in [0, 100], as for `cwebp -q`.
+/// WebP output is meant for compatibility rather than fidelity, so this is lower than the lossless-like AVIF quality.
+const QUALITY: f32 = 90.0;
+
+/// - `pixels`: Row-major 8-bit sRGB pixels, which WebP assumes in the absence of an ICC profile.
+pub fn write_srgb8_pixels_to_webp<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[[u8; 3]],
+) -> std::io::Result<()> {
+    let encoder = webp::Encoder::from_rgb(pixels.as_flattened(), width as u32, height as u32);
+
+    // Fails e.g. for dimensions beyond the 16383-pixel limit of WebP.
+    let webp = encoder.encode_simple(false, QUALITY)
+        .map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+
+    writer.write_all(&webp)?;
+    Ok(())
+}
@@ -16,10 +16,118 @@ impl FloatImageContent {
         Self { width, height, pixels }
     }
 
+    pub fn extent(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Row-major pixels.
+    pub fn pixels(&self) -> &[FloatPixel] {
+        &self.pixels
+    }
+
+    /// Row-major pixels.
+    pub fn pixels_mut(&mut self) -> &mut [FloatPixel] {
+        &mut self.pixels
+    }
+
+    /// Row-major, interleaved RGB samples, exactly 3 per pixel, e.g. for raw float output.
+    pub fn to_interleaved_rgb(&self) -> Vec<f32> {
+        self.pixels.iter().flat_map(|pixel| pixel.to_serialized_rgb()).collect()
+    }
+
+    /// Panics if `(x, y)` is out of bounds. See `try_get_at`.
+    pub fn get_at(&self, x: usize, y: usize) -> FloatPixel {
+        match self.try_get_at(x, y) {
+            Some(pixel) => pixel,
+            None => panic!("Attempted to get pixel at ({}, {}) out of bounds for image of size {}x{}", x, y, self.width, self.height),
+        }
+    }
+
+    /// Panics if `(x, y)` is out of bounds. See `try_set_at`.
+    pub fn set_at(&mut self, x: usize, y: usize, pixel: FloatPixel) {
+        if let Err(message) = self.try_set_at(x, y, pixel) {
+            panic!("{}", message);
+        }
+    }
+
+    /// The pixel at `(x, y)`, or `None` if `x` or `y` is out of bounds.
+    pub fn try_get_at(&self, x: usize, y: usize) -> Option<FloatPixel> {
+        self.index_of(x, y).map(|index| self.pixels[index])
+    }
+
+    /// Sets the pixel at `(x, y)`, or returns why it couldn't if `x` or `y` is out of bounds.
+    pub fn try_set_at(&mut self, x: usize, y: usize, pixel: FloatPixel) -> Result<(), String> {
+        match self.index_of(x, y) {
+            Some(index) => {
+                self.pixels[index] = pixel;
+                Ok(())
+            }
+            None => Err(format!("Attempted to set pixel at ({}, {}) out of bounds for image of size {}x{}", x, y, self.width, self.height)),
+        }
+    }
+
+    /// The index of `(x, y)` in `pixels`. Each coordinate is checked on its own, so that `x` past the end of a row
+    /// doesn't wrap around to the next one.
+    fn index_of(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| y * self.width + x)
+    }
+}
+
+/// Storage for the linear pixels computed by a conversion.
+pub trait LinearImageContent {
+    fn with_extent(width: usize, height: usize) -> Self;
+    fn get_at(&self, x: usize, y: usize) -> FloatPixel;
+    fn set_at(&mut self, x: usize, y: usize, pixel: FloatPixel);
+}
+
+impl LinearImageContent for FloatImageContent {
+    fn with_extent(width: usize, height: usize) -> Self {
+        FloatImageContent::with_extent(width, height)
+    }
+
+    fn get_at(&self, x: usize, y: usize) -> FloatPixel {
+        FloatImageContent::get_at(self, x, y)
+    }
+
+    fn set_at(&mut self, x: usize, y: usize, pixel: FloatPixel) {
+        FloatImageContent::set_at(self, x, y, pixel)
+    }
+}
+
+/// Same as `FloatImageContent`, but stores the RGB elements as `f16`, in 6 bytes per pixel instead of 16.
+///
+/// Pixels are still passed around as `FloatPixel`, so all math is done in `f32` and only the stored results are rounded.
+/// `f16` has 11 significant bits and a maximum of 65504, so the relative error is at most 2^-11 for the range of HDR10
+/// output in nits.
+#[cfg(feature = "half")]
+#[derive(Clone)]
+pub struct HalfImageContent {
+    width: usize,
+    height: usize,
+    /// Row-major pixel data in linear RGB format.
+    pixels: Vec<[half::f16; 3]>,
+}
+
+#[cfg(feature = "half")]
+impl HalfImageContent {
+    pub fn with_extent(width: usize, height: usize) -> Self {
+        let pixel_count = width * height;
+        let pixels = vec![[half::f16::ZERO; 3]; pixel_count];
+        Self { width, height, pixels }
+    }
+
     pub fn get_at(&self, x: usize, y: usize) -> FloatPixel {
         let index = y * self.width + x;
         if index < self.pixels.len() {
-            self.pixels[index]
+            self.pixels[index].map(half::f16::to_f32).into()
         } else {
             panic!("Attempted to get pixel at ({}, {}) out of bounds for image of size {}x{}", x, y, self.width, self.height);
         }
@@ -28,11 +136,50 @@ impl FloatImageContent {
     pub fn set_at(&mut self, x: usize, y: usize, pixel: FloatPixel) {
         let index = y * self.width + x;
         if index < self.pixels.len() {
-            self.pixels[index] = pixel;
+            self.pixels[index] = pixel.to_rgb_array().map(half::f16::from_f32);
         } else {
             panic!("Attempted to set pixel at ({}, {}) out of bounds for image of size {}x{}", x, y, self.width, self.height);
         }
     }
+
+    pub fn to_float_image_content(&self) -> FloatImageContent {
+        let pixels = self.pixels.iter()
+            .map(|pixel| pixel.map(half::f16::to_f32).into())
+            .collect();
+        FloatImageContent { width: self.width, height: self.height, pixels }
+    }
+}
+
+#[cfg(feature = "half")]
+impl From<&FloatImageContent> for HalfImageContent {
+    fn from(content: &FloatImageContent) -> Self {
+        let pixels = content.pixels.iter()
+            .map(|pixel| pixel.to_rgb_array().map(half::f16::from_f32))
+            .collect();
+        Self { width: content.width, height: content.height, pixels }
+    }
+}
+
+#[cfg(feature = "half")]
+impl From<&HalfImageContent> for FloatImageContent {
+    fn from(content: &HalfImageContent) -> Self {
+        content.to_float_image_content()
+    }
+}
+
+#[cfg(feature = "half")]
+impl LinearImageContent for HalfImageContent {
+    fn with_extent(width: usize, height: usize) -> Self {
+        HalfImageContent::with_extent(width, height)
+    }
+
+    fn get_at(&self, x: usize, y: usize) -> FloatPixel {
+        HalfImageContent::get_at(self, x, y)
+    }
+
+    fn set_at(&mut self, x: usize, y: usize, pixel: FloatPixel) {
+        HalfImageContent::set_at(self, x, y, pixel)
+    }
 }
 
 /// A pixel with 4 elements, where the last element is padding for 4-element, 16-byte alignment.
@@ -68,6 +215,11 @@ impl ops::Add for FloatPixel {
     type Output = Self;
 
     fn add(self, other: Self) -> Self::Output {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(self.to_f32x4() + other.to_f32x4())
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 self.inner[0] + other.inner[0],
@@ -83,6 +235,11 @@ impl ops::Sub for FloatPixel {
     type Output = Self;
 
     fn sub(self, other: Self) -> Self::Output {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(self.to_f32x4() - other.to_f32x4())
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 self.inner[0] - other.inner[0],
@@ -98,6 +255,11 @@ impl ops::Mul<f32> for FloatPixel {
     type Output = Self;
 
     fn mul(self, scalar: f32) -> Self::Output {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(self.to_f32x4() * wide::f32x4::splat(scalar))
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 self.inner[0] * scalar,
@@ -113,6 +275,11 @@ impl ops::Mul for FloatPixel {
     type Output = Self;
 
     fn mul(self, other: Self) -> Self::Output {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(self.to_f32x4() * other.to_f32x4())
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 self.inner[0] * other.inner[0],
@@ -128,6 +295,11 @@ impl ops::Div<f32> for FloatPixel {
     type Output = Self;
 
     fn div(self, scalar: f32) -> Self::Output {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(self.to_f32x4() / wide::f32x4::splat(scalar))
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 self.inner[0] / scalar,
@@ -143,6 +315,11 @@ impl ops::Div for FloatPixel {
     type Output = Self;
 
     fn div(self, other: Self) -> Self::Output {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(self.to_f32x4() / other.to_f32x4())
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 self.inner[0] / other.inner[0],
@@ -167,9 +344,26 @@ impl FloatPixel {
         Self { inner: [r, g, b, 0.0] }
     }
 
+    /// Borrows the RGB elements, excluding the padding element.
     #[inline]
     pub fn rgb(&self) -> &[f32; 3] {
-        unsafe { &*(self.inner.as_ptr() as *const [f32; 3]) }
+        // Always `Some` since `inner` has 4 elements; the bounds check is optimized away.
+        self.inner.first_chunk::<3>().unwrap()
+    }
+
+    /// Copies out the RGB elements, excluding the padding element.
+    #[inline]
+    pub fn to_rgb_array(self) -> [f32; 3] {
+        *self.rgb()
+    }
+
+    /// Same as `to_rgb_array`, for writing the pixel to an output. Every output path goes through this,
+    /// so that the padding element, which is always 0, never leaks into one, and is checked in debug builds
+    /// should computing it, e.g. with SIMD, ever change that.
+    #[inline]
+    pub(crate) fn to_serialized_rgb(self) -> [f32; 3] {
+        debug_assert_eq!(self.inner[3], 0.0, "The padding element of {:?} is not 0", self);
+        self.to_rgb_array()
     }
 
     #[inline]
@@ -187,8 +381,34 @@ impl FloatPixel {
         self.inner[2]
     }
 
+    /// The elements as the lanes of an `f32x4`, padding included.
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    fn to_f32x4(self) -> wide::f32x4 {
+        wide::f32x4::from(self.inner)
+    }
+
+    /// The lanes of `lanes` as a pixel, with the padding element reset to 0,
+    /// since operating on it as well can make it anything, e.g. NaN for `0 / 0`.
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    fn from_f32x4(lanes: wide::f32x4) -> Self {
+        let rgb_mask: wide::f32x4 = bytemuck::cast([u32::MAX, u32::MAX, u32::MAX, 0]);
+        Self { inner: (lanes & rgb_mask).to_array() }
+    }
+
+    /// `lhs` raised to the power of `rhs`, element-wise.
+    ///
+    /// With the `simd` feature, it is computed as `exp2(log2(lhs) * rhs)` with the polynomial approximations of
+    /// `st2084_oetf_in_place`, to a relative error on the order of `1e-6`, for non-negative `lhs` only,
+    /// where 0 is treated as `2^-127`.
     #[inline]
     pub fn powf(lhs: &Self, rhs: &Self) -> Self {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(crate::transfer::simd::exp2(crate::transfer::simd::log2(lhs.to_f32x4()) * rhs.to_f32x4()))
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 f32::powf(lhs.inner[0], rhs.inner[0]),
@@ -199,8 +419,30 @@ impl FloatPixel {
         }
     }
 
+    #[inline]
+    pub fn clamp(&self, min: f32, max: f32) -> Self {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(self.to_f32x4().max(wide::f32x4::splat(min)).min(wide::f32x4::splat(max)))
+        }
+        #[cfg(not(feature = "simd"))]
+        Self {
+            inner: [
+                self.inner[0].clamp(min, max),
+                self.inner[1].clamp(min, max),
+                self.inner[2].clamp(min, max),
+                0.0,
+            ],
+        }
+    }
+
     #[inline]
     pub fn rcp(&self) -> Self {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(wide::f32x4::ONE / self.to_f32x4())
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 1.0 / self.inner[0],
@@ -211,8 +453,15 @@ impl FloatPixel {
         }
     }
 
+    /// 2 raised to the power of each element. With the `simd` feature, it is approximated like `powf`,
+    /// and the exponents are clamped to [-126, 127].
     #[inline]
     pub fn exp2(&self) -> Self {
+        #[cfg(feature = "simd")]
+        {
+            Self::from_f32x4(crate::transfer::simd::exp2(self.to_f32x4()))
+        }
+        #[cfg(not(feature = "simd"))]
         Self {
             inner: [
                 f32::exp2(self.inner[0]),
@@ -224,3 +473,96 @@ impl FloatPixel {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaved_rgb_has_exactly_3_samples_per_pixel() {
+        let mut content = FloatImageContent::with_extent(3, 2);
+        content.set_at(2, 1, FloatPixel::new(1.0, 2.0, 3.0));
+        content.set_at(0, 0, FloatPixel::new(-1.0, 0.5, 0.25) * 2.0 + FloatPixel::one());
+
+        let samples = content.to_interleaved_rgb();
+        assert_eq!(samples.len(), 3 * 2 * 3);
+        assert_eq!(samples[..3], [-1.0, 2.0, 1.5]);
+        assert_eq!(samples[15..], [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "padding element")]
+    fn nonzero_padding_is_caught_before_serialization() {
+        let pixel = FloatPixel { inner: [1.0, 2.0, 3.0, 4.0] };
+        pixel.to_serialized_rgb();
+    }
+
+    #[test]
+    fn ops_match_scalar_math_and_keep_the_padding_zero() {
+        let values = [0.0, 1e-4, 0.25, 0.5, 1.0, 2.2, 13.0];
+        let close = |actual: f32, expected: f32| (actual - expected).abs() <= 1e-5 * expected.abs().max(1.0);
+        for &a in &values {
+            for &b in &values {
+                let (lhs, rhs) = (FloatPixel::new(a, b, a + b), FloatPixel::new(b, a, 1.0));
+                let results = [
+                    (lhs + rhs, [a + b, b + a, a + b + 1.0]),
+                    (lhs - rhs, [a - b, b - a, a + b - 1.0]),
+                    (lhs * rhs, [a * b, b * a, a + b]),
+                    (lhs * 3.0, [a * 3.0, b * 3.0, (a + b) * 3.0]),
+                    (lhs.clamp(0.1, 1.0), [a.clamp(0.1, 1.0), b.clamp(0.1, 1.0), (a + b).clamp(0.1, 1.0)]),
+                    (rhs.exp2(), [b.exp2(), a.exp2(), 2.0]),
+                ];
+                for (actual, expected) in results {
+                    assert!((0..3).all(|channel| close(actual[channel], expected[channel])), "{:?} != {:?}", actual, expected);
+                    assert_eq!(actual.inner[3], 0.0);
+                }
+                // Away from 0, which the `simd` feature approximates as `2^-127`.
+                if a > 0.0 && b > 0.0 {
+                    let actual = FloatPixel::powf(&lhs, &rhs);
+                    let expected = [a.powf(b), b.powf(a), a + b];
+                    assert!((0..3).all(|channel| close(actual[channel], expected[channel])), "{:?} != {:?}", actual, expected);
+                    let quotient = lhs / rhs;
+                    assert!(close(quotient.r(), a / b) && close(quotient.g(), b / a), "{:?}", quotient);
+                }
+            }
+        }
+
+        // Operations on the padding would give NaN or infinity.
+        let zero = FloatPixel::zero();
+        for pixel in [zero / zero, zero / 0.0, zero.rcp(), zero * f32::INFINITY, FloatPixel::powf(&zero, &zero), zero.exp2()] {
+            assert_eq!(pixel.inner[3], 0.0, "{:?}", pixel);
+        }
+    }
+
+    #[test]
+    fn try_accessors_reject_out_of_bounds_coordinates() {
+        let mut content = FloatImageContent::with_extent(3, 2);
+        assert_eq!((content.width(), content.height()), content.extent());
+
+        let pixel = FloatPixel::new(1.0, 2.0, 3.0);
+        assert!(content.try_set_at(2, 1, pixel).is_ok());
+        assert_eq!(content.try_get_at(2, 1).map(|pixel| pixel.to_rgb_array()), Some([1.0, 2.0, 3.0]));
+
+        // `(3, 0)` would be the index of `(0, 1)` if it weren't checked per coordinate.
+        for (x, y) in [(3, 0), (0, 2), (usize::MAX, usize::MAX)] {
+            assert!(content.try_get_at(x, y).is_none(), "({}, {})", x, y);
+            assert!(content.try_set_at(x, y, pixel).unwrap_err().contains("out of bounds"), "({}, {})", x, y);
+        }
+        assert_eq!(content.get_at(0, 1).to_rgb_array(), [0.0; 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds for image of size 3x2")]
+    fn get_at_panics_out_of_bounds() {
+        FloatImageContent::with_extent(3, 2).get_at(3, 0);
+    }
+
+    #[test]
+    fn to_rgb_array_matches_accessors() {
+        let pixel = FloatPixel::new(0.25, -1.5, 1000.0);
+
+        assert_eq!(pixel.to_rgb_array(), [pixel.r(), pixel.g(), pixel.b()]);
+        assert_eq!(pixel.rgb(), &pixel.to_rgb_array());
+    }
+}
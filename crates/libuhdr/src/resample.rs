@@ -0,0 +1,88 @@
+use crate::pixel::{FloatPixel, LinearImageContent};
+
+/// Bilinearly resamples `src` of `src_extent` to `dst_extent`, aligning the pixel centers of both and clamping at the edges.
+///
+/// Meant for upsampling: Downsampling by more than 2x would skip source pixels and alias.
+pub(crate) fn resample_bilinear<C: LinearImageContent>(src: &C, src_extent: (usize, usize), dst_extent: (usize, usize)) -> C {
+    let (src_width, src_height) = src_extent;
+    let (dst_width, dst_height) = dst_extent;
+
+    let columns: Vec<_> = (0..dst_width).map(|x| taps(x, src_width, dst_width)).collect();
+
+    let mut dst = C::with_extent(dst_width, dst_height);
+    for y in 0..dst_height {
+        let (y0, y1, t) = taps(y, src_height, dst_height);
+        for (x, &(x0, x1, s)) in columns.iter().enumerate() {
+            let top = src.get_at(x0, y0) * (1.0 - s) + src.get_at(x1, y0) * s;
+            let bottom = src.get_at(x0, y1) * (1.0 - s) + src.get_at(x1, y1) * s;
+            let pixel: FloatPixel = top * (1.0 - t) + bottom * t;
+            dst.set_at(x, y, pixel);
+        }
+    }
+    dst
+}
+
+/// Downsamples `src` of `src_extent` to `dst_extent` with `resample_bilinear`, in steps of at most 2x,
+/// at which each destination pixel averages the source pixels it covers, so that no source pixels are skipped.
+pub(crate) fn downsample_bilinear<C: LinearImageContent>(src: &C, src_extent: (usize, usize), dst_extent: (usize, usize)) -> C {
+    let step = |src_len: usize, dst_len: usize| dst_len.max(src_len.div_ceil(2));
+    let mut extent = (step(src_extent.0, dst_extent.0), step(src_extent.1, dst_extent.1));
+    let mut content = resample_bilinear(src, src_extent, extent);
+    while extent != dst_extent {
+        let next_extent = (step(extent.0, dst_extent.0), step(extent.1, dst_extent.1));
+        content = resample_bilinear(&content, extent, next_extent);
+        extent = next_extent;
+    }
+    content
+}
+
+/// The 2 source pixels straddling the center of destination pixel `dst`, and the weight of the second.
+fn taps(dst: usize, src_len: usize, dst_len: usize) -> (usize, usize, f32) {
+    let position = ((dst as f32 + 0.5) * src_len as f32 / dst_len as f32 - 0.5).clamp(0.0, (src_len - 1) as f32);
+    let first = position as usize;
+    (first, (first + 1).min(src_len - 1), position - first as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pixel::FloatImageContent;
+
+    #[test]
+    fn upsampling_interpolates_between_pixel_centers() {
+        let mut src = FloatImageContent::with_extent(2, 1);
+        src.set_at(0, 0, FloatPixel::new(0.0, 0.0, 0.0));
+        src.set_at(1, 0, FloatPixel::new(4.0, 8.0, 12.0));
+
+        let dst = resample_bilinear(&src, (2, 1), (4, 2));
+
+        // Centers at 0.25 and 0.75 of the source pixels, clamped outside of the outermost centers.
+        for y in 0..2 {
+            let row: Vec<f32> = (0..4).map(|x| dst.get_at(x, y).r()).collect();
+            assert_eq!(row, [0.0, 1.0, 3.0, 4.0]);
+        }
+        assert_eq!(dst.get_at(2, 1).b(), 9.0);
+
+        // The same extent is a copy.
+        assert_eq!(resample_bilinear(&src, (2, 1), (2, 1)).pixels(), src.pixels());
+    }
+
+    #[test]
+    fn downsampling_averages_every_source_pixel() {
+        // Alternating columns, every 4th of which a single step of 8x would sample.
+        let mut src = FloatImageContent::with_extent(16, 2);
+        for y in 0..2 {
+            for x in 0..16 {
+                let value = if x % 2 == 0 { 0.0 } else { 1.0 };
+                src.set_at(x, y, FloatPixel::new(value, value, value));
+            }
+        }
+
+        let dst = downsample_bilinear(&src, (16, 2), (2, 1));
+        for x in 0..2 {
+            assert_eq!(dst.get_at(x, 0).g(), 0.5);
+        }
+        assert_eq!(downsample_bilinear(&src, (16, 2), (16, 2)).pixels(), src.pixels());
+    }
+}
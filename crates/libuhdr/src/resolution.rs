@@ -0,0 +1,215 @@
+use log::warn;
+
+use crate::segments;
+use crate::tiff::{Endianness, Tiff, TiffFieldValue, TiffIfdEntry, TiffWriter};
+
+/// TIFF tags of the resolution, in IFD0 of an EXIF block.
+pub(crate) const TAG_X_RESOLUTION: u16 = 282;
+pub(crate) const TAG_Y_RESOLUTION: u16 = 283;
+pub(crate) const TAG_RESOLUTION_UNIT: u16 = 296;
+
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const JFIF_PREFIX: &[u8] = b"JFIF\0";
+
+/// Pixel aspect ratios beyond this factor are taken to be bogus metadata rather than anamorphic pixels,
+/// which are at most twice as wide as tall in practice.
+const MAX_PIXEL_ASPECT_RATIO: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum ResolutionUnit {
+    Inch,
+    Centimeter,
+}
+
+/// The physical resolution of an image for print, e.g. 300 pixels per inch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Resolution {
+    /// Pixels per `unit` horizontally.
+    pub x: f32,
+    /// Pixels per `unit` vertically.
+    pub y: f32,
+    pub unit: ResolutionUnit,
+}
+
+impl Resolution {
+    /// Reads the resolution from the JFIF `APP0` segment of a JPEG, or from its EXIF `APP1` segment if the former
+    /// is missing or only specifies an aspect ratio, like most readers do.
+    /// `None` if neither has a physical resolution.
+    pub fn from_jpeg_bytes(jpeg_bytes: &[u8]) -> Option<Self> {
+        jfif_payload(jpeg_bytes).and_then(Self::from_jfif_payload)
+            .or_else(|| exif_tiff(jpeg_bytes).and_then(Self::from_exif_tiff))
+    }
+
+    /// - `payload`: The JFIF `APP0` payload after the identifier.
+    fn from_jfif_payload(payload: &[u8]) -> Option<Self> {
+        let (units, x, y) = jfif_densities(payload)?;
+        let unit = match units {
+            1 => ResolutionUnit::Inch,
+            2 => ResolutionUnit::Centimeter,
+            // 0 is an aspect ratio only.
+            _ => return None,
+        };
+        Self::new(x, y, unit)
+    }
+
+    /// - `tiff_bytes`: The TIFF block of an EXIF `APP1` segment, i.e. after the identifier.
+    pub(crate) fn from_exif_tiff(tiff_bytes: &[u8]) -> Option<Self> {
+        let tiff = Tiff::from_reader(&mut std::io::Cursor::new(tiff_bytes)).ok()?;
+        let ifd0 = tiff.ifds.first()?;
+
+        let rational = |tag: u16| match ifd0.entry_with_tag(tag)?.field_value {
+            TiffFieldValue::RATIONAL(ref values) => values.first()
+                .filter(|(_, denominator)| *denominator != 0)
+                .map(|&(numerator, denominator)| numerator as f32 / denominator as f32),
+            _ => None,
+        };
+        let unit = match ifd0.entry_with_tag(TAG_RESOLUTION_UNIT).map(|entry| &entry.field_value) {
+            // Inches if unspecified.
+            None => ResolutionUnit::Inch,
+            Some(TiffFieldValue::SHORT(values)) => match values.first() {
+                Some(2) => ResolutionUnit::Inch,
+                Some(3) => ResolutionUnit::Centimeter,
+                // 1 means no absolute unit.
+                _ => return None,
+            },
+            Some(_) => return None,
+        };
+        Self::new(rational(TAG_X_RESOLUTION)?, rational(TAG_Y_RESOLUTION)?, unit)
+    }
+
+    /// `None` unless both densities are positive and finite.
+    fn new(x: f32, y: f32, unit: ResolutionUnit) -> Option<Self> {
+        let valid = |value: f32| value.is_finite() && value > 0.0;
+        (valid(x) && valid(y)).then_some(Self { x, y, unit })
+    }
+
+    /// The width of a pixel relative to its height.
+    pub fn pixel_aspect_ratio(&self) -> f32 {
+        self.y / self.x
+    }
+
+    /// A minimal EXIF TIFF block whose IFD0 only has the resolution, e.g. for an AVIF `Exif` item.
+    pub fn to_exif_tiff(&self) -> Vec<u8> {
+        let mut writer = TiffWriter::new(Endianness::BigEndian);
+        writer.append_ifd(&[
+            TiffIfdEntry::new(TAG_X_RESOLUTION, TiffFieldValue::RATIONAL(vec![exif_rational(self.x)])),
+            TiffIfdEntry::new(TAG_Y_RESOLUTION, TiffFieldValue::RATIONAL(vec![exif_rational(self.y)])),
+            TiffIfdEntry::new(TAG_RESOLUTION_UNIT, TiffFieldValue::SHORT(vec![self.unit.exif_value()])),
+        ]).expect("3 entries always fit into an IFD");
+        writer.finish()
+    }
+}
+
+impl ResolutionUnit {
+    /// The value of the EXIF `ResolutionUnit` tag.
+    pub(crate) fn exif_value(self) -> u16 {
+        match self {
+            ResolutionUnit::Inch => 2,
+            ResolutionUnit::Centimeter => 3,
+        }
+    }
+}
+
+/// A density as an EXIF `RATIONAL`: Whole densities, which they almost always are, exactly; others to a thousandth.
+pub(crate) fn exif_rational(value: f32) -> (u32, u32) {
+    if value.fract() == 0.0 && value <= u32::MAX as f32 {
+        (value as u32, 1)
+    } else {
+        ((value * 1000.0).round() as u32, 1000)
+    }
+}
+
+/// The width of a pixel relative to its height, e.g. `0.5` for pixels half as wide as tall, as in video-derived JPEGs:
+/// The ratio of the vertical to the horizontal density of the JFIF segment, which specifies it even without a unit,
+/// or else of the EXIF resolution.
+///
+/// `1.0` if neither specifies it, or if it is beyond `MAX_PIXEL_ASPECT_RATIO`.
+pub fn pixel_aspect_ratio_from_jpeg_bytes(jpeg_bytes: &[u8]) -> f32 {
+    let pixel_aspect_ratio = jfif_payload(jpeg_bytes)
+        .and_then(jfif_densities)
+        .filter(|&(_, x, y)| x > 0.0 && y > 0.0)
+        .map(|(_, x, y)| y / x)
+        .or_else(|| Resolution::from_jpeg_bytes(jpeg_bytes).map(|resolution| resolution.pixel_aspect_ratio()))
+        .unwrap_or(1.0);
+
+    if !(1.0 / MAX_PIXEL_ASPECT_RATIO..=MAX_PIXEL_ASPECT_RATIO).contains(&pixel_aspect_ratio) {
+        warn!("Ignoring the implausible pixel aspect ratio of {}", pixel_aspect_ratio);
+        return 1.0;
+    }
+    pixel_aspect_ratio
+}
+
+fn app_payload<'a>(jpeg_bytes: &'a [u8], marker: u8, prefix: &[u8]) -> Option<&'a [u8]> {
+    segments::header_segments(jpeg_bytes)
+        .find(|segment| segment.marker == marker && segment.payload.starts_with(prefix))
+        .map(|segment| &segment.payload[prefix.len()..])
+}
+
+/// The JFIF `APP0` payload after the identifier.
+fn jfif_payload(jpeg_bytes: &[u8]) -> Option<&[u8]> {
+    app_payload(jpeg_bytes, 0xE0, JFIF_PREFIX)
+}
+
+/// The TIFF block of the EXIF `APP1` segment.
+pub(crate) fn exif_tiff(jpeg_bytes: &[u8]) -> Option<&[u8]> {
+    app_payload(jpeg_bytes, 0xE1, EXIF_PREFIX)
+}
+
+/// The units, X density and Y density of a JFIF `APP0` payload.
+fn jfif_densities(payload: &[u8]) -> Option<(u8, f32, f32)> {
+    // After the version.
+    let &[_, _, units, x_high, x_low, y_high, y_low, ..] = payload else {
+        return None;
+    };
+    Some((units, u16::from_be_bytes([x_high, x_low]).into(), u16::from_be_bytes([y_high, y_low]).into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::{self, TestImage};
+
+    fn exif_app_segment(resolution: &Resolution) -> (u8, Vec<u8>) {
+        (1, [EXIF_PREFIX, &resolution.to_exif_tiff()].concat())
+    }
+
+    #[test]
+    fn resolution_is_read_from_exif_unless_jfif_has_it() {
+        let image = TestImage::from_fn(8, 8, |_, _| [128; 3]);
+        let resolution = Resolution { x: 300.0, y: 150.5, unit: ResolutionUnit::Centimeter };
+
+        // The JFIF segment `jpeg-encoder` writes only has an aspect ratio.
+        let mut jpeg_bytes = testutil::encode_jpeg(&image, &[exif_app_segment(&resolution)]);
+        assert_eq!(Resolution::from_jpeg_bytes(&jpeg_bytes), Some(resolution));
+
+        let units = testutil::find(&jpeg_bytes, JFIF_PREFIX).unwrap() + JFIF_PREFIX.len() + 2;
+        jpeg_bytes[units..units + 5].copy_from_slice(&[1, 0, 72, 0, 96]);
+        assert_eq!(Resolution::from_jpeg_bytes(&jpeg_bytes), Some(Resolution { x: 72.0, y: 96.0, unit: ResolutionUnit::Inch }));
+
+        assert_eq!(Resolution::from_jpeg_bytes(&testutil::encode_jpeg(&image, &[])), None);
+    }
+
+    #[test]
+    fn pixel_aspect_ratio_is_read_from_jfif_even_without_units() {
+        let image = TestImage::from_fn(8, 8, |_, _| [128; 3]);
+        let resolution = Resolution { x: 300.0, y: 150.0, unit: ResolutionUnit::Inch };
+        let mut jpeg_bytes = testutil::encode_jpeg(&image, &[exif_app_segment(&resolution)]);
+        let units = testutil::find(&jpeg_bytes, JFIF_PREFIX).unwrap() + JFIF_PREFIX.len() + 2;
+
+        // The 1:1 aspect ratio `jpeg-encoder` writes takes precedence.
+        assert_eq!(pixel_aspect_ratio_from_jpeg_bytes(&jpeg_bytes), 1.0);
+
+        jpeg_bytes[units..units + 5].copy_from_slice(&[0, 0, 8, 0, 9]);
+        assert_eq!(pixel_aspect_ratio_from_jpeg_bytes(&jpeg_bytes), 9.0 / 8.0);
+
+        // Without a JFIF aspect ratio, the EXIF resolution.
+        jpeg_bytes[units..units + 5].copy_from_slice(&[0, 0, 0, 0, 0]);
+        assert_eq!(pixel_aspect_ratio_from_jpeg_bytes(&jpeg_bytes), 0.5);
+
+        jpeg_bytes[units..units + 5].copy_from_slice(&[0, 0, 100, 0, 1]);
+        assert_eq!(pixel_aspect_ratio_from_jpeg_bytes(&jpeg_bytes), 1.0);
+    }
+}
@@ -0,0 +1,82 @@
+/// A marker segment in the header of a JPEG, i.e. before the first start of scan.
+#[derive(Debug, Clone, Copy)]
+pub struct JpegSegment<'a> {
+    /// The marker code, e.g. `0xE2` for `APP2`.
+    pub marker: u8,
+    /// The offset of `payload` in the JPEG.
+    pub payload_offset: usize,
+    /// The segment data, excluding the length field.
+    pub payload: &'a [u8],
+}
+
+/// Iterates over the marker segments in the header of `jpeg_bytes`.
+/// Ends at the first start of scan, or at the first malformed or truncated segment.
+pub fn header_segments(jpeg_bytes: &[u8]) -> HeaderSegments<'_> {
+    // Not a JPEG if it doesn't start with the start of image marker.
+    let position = if jpeg_bytes.get(0..2) == Some(&[0xFF, 0xD8]) { 2 } else { jpeg_bytes.len() };
+    HeaderSegments { jpeg_bytes, position }
+}
+
+/// The length of the JPEG at the start of `jpeg_bytes`, up to and including its end of image marker,
+/// excluding anything appended to it such as further MPF images.
+/// `None` if the end of image marker is missing.
+pub fn image_length(jpeg_bytes: &[u8]) -> Option<usize> {
+    let mut segments = header_segments(jpeg_bytes);
+    let header_end = segments.by_ref()
+        .last()
+        .map(|segment| segment.payload_offset + segment.payload.len())?;
+
+    // In entropy-coded data, `0xFF` is always followed by a stuffed `0x00` or a restart marker,
+    // so the first end of image marker after the header ends the image.
+    jpeg_bytes[header_end..].windows(2)
+        .position(|window| window == [0xFF, 0xD9])
+        .map(|position| header_end + position + 2)
+}
+
+pub struct HeaderSegments<'a> {
+    jpeg_bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Iterator for HeaderSegments<'a> {
+    type Item = JpegSegment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let jpeg_bytes = self.jpeg_bytes;
+        loop {
+            if *jpeg_bytes.get(self.position)? != 0xFF {
+                self.position = jpeg_bytes.len();
+                return None;
+            }
+            let marker = *jpeg_bytes.get(self.position + 1)?;
+            match marker {
+                // Fill bytes.
+                0xFF => {
+                    self.position += 1;
+                    continue;
+                },
+                // Start of scan or end of image: no more header segments.
+                0xDA | 0xD9 => {
+                    self.position = jpeg_bytes.len();
+                    return None;
+                },
+                // Standalone markers without a length.
+                0x01 | 0xD0..=0xD7 => {
+                    self.position += 2;
+                    continue;
+                },
+                _ => {},
+            }
+
+            let length = u16::from_be_bytes([*jpeg_bytes.get(self.position + 2)?, *jpeg_bytes.get(self.position + 3)?]) as usize;
+            let payload_offset = self.position + 4;
+            let Some(payload) = length.checked_sub(2).and_then(|payload_length| jpeg_bytes.get(payload_offset..payload_offset + payload_length)) else {
+                self.position = jpeg_bytes.len();
+                return None;
+            };
+
+            self.position += 2 + length;
+            return Some(JpegSegment { marker, payload_offset, payload });
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::pixel::FloatImageContent;
+
+/// Luminance statistics of the _HDR rendition_, from `UhdrConverter::compute_luminance_stats`.
+///
+/// The light levels are in nits and are based on `max(R, G, B)` of each pixel in BT.2020, as defined by CTA-861.3,
+/// clamped to the [0, 10000] range of PQ like HDR10 output is.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LuminanceStats {
+    /// Maximum Content Light Level: The brightest pixel.
+    pub max_cll: f32,
+    /// Maximum Frame-Average Light Level: The average over all pixels.
+    pub max_fall: f32,
+    /// `max_cll` relative to SDR white, i.e. `target_sdr_white_level`.
+    pub peak_boost: f32,
+}
+
+impl LuminanceStats {
+    pub(crate) fn from_linear_pixels(linear_pixels: &FloatImageContent, target_sdr_white_level: f32) -> Self {
+        let pixels = linear_pixels.pixels();
+        if pixels.is_empty() {
+            return Self::default();
+        }
+
+        let (max_cll, sum) = pixels.iter()
+            .map(|pixel| pixel.rgb().iter().fold(0.0f32, |max, &value| max.max(value)).min(10000.0))
+            .fold((0.0f32, 0.0f64), |(max, sum), light_level| (max.max(light_level), sum + light_level as f64));
+
+        Self {
+            max_cll,
+            max_fall: (sum / pixels.len() as f64) as f32,
+            peak_boost: max_cll / target_sdr_white_level,
+        }
+    }
+}
+
+/// The luminance in nits `suggest_target_sdr_white_level` places the median luminance of the _SDR rendition_ at:
+/// That of 18% grey per _Rec. ITU-R BT.2408_, for which HDR reference white is 203 nits.
+pub const AUTO_WHITE_MEDIAN_LUMINANCE: f32 = 26.0;
+/// The lowest white level `suggest_target_sdr_white_level` suggests, that of sRGB by Windows convention.
+pub const MIN_AUTO_WHITE_LEVEL: f32 = 80.0;
+/// The highest white level `suggest_target_sdr_white_level` suggests, that of HDR reference white per BT.2408.
+pub const MAX_AUTO_WHITE_LEVEL: f32 = 203.0;
+
+/// A histogram of the relative luminance of the _SDR rendition_, where `1.0` is SDR white, in bins of an eighth of a stop.
+pub(crate) struct LuminanceHistogram {
+    bins: Vec<u64>,
+    count: u64,
+}
+
+impl LuminanceHistogram {
+    const BINS_PER_STOP: f32 = 8.0;
+    /// Darker luminance counts toward the first bin. 16 stops is below what 8 bits of sRGB can resolve.
+    const MIN_LOG2: f32 = -16.0;
+    /// Brighter luminance, e.g. of an HLG-encoded primary image, counts toward the last bin.
+    const MAX_LOG2: f32 = 4.0;
+
+    pub(crate) fn new() -> Self {
+        let bin_count = ((Self::MAX_LOG2 - Self::MIN_LOG2) * Self::BINS_PER_STOP) as usize;
+        Self { bins: vec![0; bin_count], count: 0 }
+    }
+
+    pub(crate) fn add(&mut self, luminance: f32) {
+        let position = (luminance.max(f32::MIN_POSITIVE).log2() - Self::MIN_LOG2) * Self::BINS_PER_STOP;
+        let index = (position.max(0.0) as usize).min(self.bins.len() - 1);
+        self.bins[index] += 1;
+        self.count += 1;
+    }
+
+    /// The center of the bin of the median, `None` if empty.
+    pub(crate) fn median(&self) -> Option<f32> {
+        let half = self.count.div_ceil(2);
+        let mut cumulative = 0;
+        let index = self.bins.iter().position(|&count| {
+            cumulative += count;
+            cumulative >= half && count > 0
+        })?;
+        Some(((index as f32 + 0.5) / Self::BINS_PER_STOP + Self::MIN_LOG2).exp2())
+    }
+
+    /// A `target_sdr_white_level` that places the median at `AUTO_WHITE_MEDIAN_LUMINANCE`, within
+    /// [`MIN_AUTO_WHITE_LEVEL`, `MAX_AUTO_WHITE_LEVEL`]: A dark image is brightened, and a bright one isn't made glaring.
+    pub(crate) fn suggest_target_sdr_white_level(&self) -> f32 {
+        self.median()
+            .map_or(MAX_AUTO_WHITE_LEVEL, |median| AUTO_WHITE_MEDIAN_LUMINANCE / median)
+            .clamp(MIN_AUTO_WHITE_LEVEL, MAX_AUTO_WHITE_LEVEL)
+    }
+}
@@ -0,0 +1,245 @@
+//! Synthetic Ultra HDR JPEGs for tests, since the `test` directory isn't populated in every checkout.
+
+use jpeg_encoder::{ColorType, Encoder};
+
+use crate::mpf::MpfInfo;
+
+const XMP_NAMESPACE_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Gain map metadata written as `hdrgm` XMP attributes.
+#[derive(Debug, Clone, Copy)]
+pub struct TestGainMapParams {
+    pub gain_map_min: f32,
+    pub gain_map_max: f32,
+    pub gamma: f32,
+    pub offset_sdr: f32,
+    pub offset_hdr: f32,
+    pub hdr_capacity_min: f32,
+    pub hdr_capacity_max: f32,
+    pub base_rendition_is_hdr: bool,
+}
+
+impl Default for TestGainMapParams {
+    fn default() -> Self {
+        Self {
+            gain_map_min: 0.0,
+            gain_map_max: 2.0,
+            gamma: 1.0,
+            offset_sdr: 0.015625,
+            offset_hdr: 0.015625,
+            hdr_capacity_min: 0.0,
+            hdr_capacity_max: 2.0,
+            base_rendition_is_hdr: false,
+        }
+    }
+}
+
+impl TestGainMapParams {
+    pub fn to_xmp(self) -> String {
+        format!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/" hdrgm:Version="1.0" hdrgm:GainMapMin="{}" hdrgm:GainMapMax="{}" hdrgm:Gamma="{}" hdrgm:OffsetSDR="{}" hdrgm:OffsetHDR="{}" hdrgm:HDRCapacityMin="{}" hdrgm:HDRCapacityMax="{}" hdrgm:BaseRenditionIsHDR="{}"/></rdf:RDF></x:xmpmeta>"#,
+            self.gain_map_min, self.gain_map_max, self.gamma, self.offset_sdr, self.offset_hdr, self.hdr_capacity_min, self.hdr_capacity_max,
+            self.base_rendition_is_hdr,
+        )
+    }
+}
+
+/// An 8-bit RGB image.
+#[derive(Debug, Clone)]
+pub struct TestImage {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+    /// Whether to encode the red channel as a single-component grayscale JPEG.
+    pub grayscale: bool,
+}
+
+impl TestImage {
+    pub fn from_fn<F: Fn(usize, usize) -> [u8; 3]>(width: usize, height: usize, f: F) -> Self {
+        let mut rgb = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                rgb.extend_from_slice(&f(x, y));
+            }
+        }
+        Self { width, height, rgb, grayscale: false }
+    }
+
+    /// An image with `R = G = B`, encoded as a single-component grayscale JPEG.
+    pub fn grayscale_from_fn<F: Fn(usize, usize) -> u8>(width: usize, height: usize, f: F) -> Self {
+        Self { grayscale: true, ..Self::from_fn(width, height, |x, y| [f(x, y); 3]) }
+    }
+}
+
+/// Encodes `image` as a JPEG at maximum quality with the given `(APPn number, payload)` segments.
+pub fn encode_jpeg(image: &TestImage, app_segments: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    encode_jpeg_with_icc_profile(image, app_segments, None)
+}
+
+pub fn encode_jpeg_with_icc_profile(image: &TestImage, app_segments: &[(u8, Vec<u8>)], icc_profile: Option<&[u8]>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut encoder = Encoder::new(&mut bytes, 100);
+    if let Some(icc_profile) = icc_profile {
+        encoder.add_icc_profile(icc_profile).unwrap();
+    }
+    for (number, payload) in app_segments {
+        encoder.add_app_segment(*number, payload.clone()).unwrap();
+    }
+    if image.grayscale {
+        let luma: Vec<u8> = image.rgb.iter().step_by(3).copied().collect();
+        encoder.encode(&luma, image.width as u16, image.height as u16, ColorType::Luma).unwrap();
+    } else {
+        encoder.encode(&image.rgb, image.width as u16, image.height as u16, ColorType::Rgb).unwrap();
+    }
+    bytes
+}
+
+pub fn xmp_app_segment(xmp: &str) -> (u8, Vec<u8>) {
+    let mut payload = XMP_NAMESPACE_PREFIX.to_vec();
+    payload.extend_from_slice(xmp.as_bytes());
+    (1, payload)
+}
+
+/// Builds the `APP2` MPF segment for a primary image followed by secondary images, see `MpfInfo::app2_payload`.
+pub fn mpf_app_segment(image_sizes: &[u32], image_offsets: &[u32]) -> (u8, Vec<u8>) {
+    (2, MpfInfo::app2_payload(image_sizes, image_offsets))
+}
+
+/// Builds an Ultra HDR JPEG from a primary image and a gain map image described by `params`.
+pub fn build_uhdr_jpeg(primary: &TestImage, gain_map: &TestImage, params: TestGainMapParams) -> Vec<u8> {
+    let gain_map_bytes = encode_jpeg(gain_map, &[xmp_app_segment(&params.to_xmp())]);
+    build_uhdr_jpeg_from_parts(primary, &[gain_map_bytes])
+}
+
+/// Builds a JPEG with an MPF block referencing the already-encoded `secondary_images`, which are appended after the primary.
+pub fn build_uhdr_jpeg_from_parts(primary: &TestImage, secondary_images: &[Vec<u8>]) -> Vec<u8> {
+    build_mpf_jpeg(primary, &[], None, secondary_images)
+}
+
+/// Builds a JPEG with an MPF block referencing the already-encoded `secondary_images`,
+/// where the first image `first` also has the given `app_segments` and ICC profile.
+pub fn build_mpf_jpeg(first: &TestImage, app_segments: &[(u8, Vec<u8>)], icc_profile: Option<&[u8]>, secondary_images: &[Vec<u8>]) -> Vec<u8> {
+    let image_count = 1 + secondary_images.len();
+    let encode_primary = |image_sizes: &[u32], image_offsets: &[u32]| {
+        let mut app_segments = app_segments.to_vec();
+        app_segments.push(mpf_app_segment(image_sizes, image_offsets));
+        encode_jpeg_with_icc_profile(first, &app_segments, icc_profile)
+    };
+
+    // The MPF payload has the same size regardless of the values, so encode once to learn the layout.
+    let placeholder = encode_primary(&vec![0; image_count], &vec![0; image_count]);
+    let primary_size = placeholder.len() as u32;
+    let mp_endian_offset = find(&placeholder, b"MPF\0").unwrap() as u32 + 4;
+
+    let mut image_sizes = vec![primary_size];
+    let mut image_offsets = vec![0];
+    let mut position = primary_size;
+    for secondary_image in secondary_images {
+        image_sizes.push(secondary_image.len() as u32);
+        image_offsets.push(position - mp_endian_offset);
+        position += secondary_image.len() as u32;
+    }
+
+    let mut bytes = encode_primary(&image_sizes, &image_offsets);
+    assert_eq!(bytes.len() as u32, primary_size);
+    for secondary_image in secondary_images {
+        bytes.extend_from_slice(secondary_image);
+    }
+    bytes
+}
+
+/// An ICC profile with BT.2020 primaries and the given tone curves.
+pub fn bt2020_icc_profile(curves: [&lcms2::ToneCurve; 3]) -> Vec<u8> {
+    use lcms2::{CIExyY, CIExyYTRIPLE, Profile};
+
+    let white_point = CIExyY { x: 0.3127, y: 0.3290, Y: 1.0 };
+    let primaries = CIExyYTRIPLE {
+        Red: CIExyY { x: 0.708, y: 0.292, Y: 1.0 },
+        Green: CIExyY { x: 0.170, y: 0.797, Y: 1.0 },
+        Blue: CIExyY { x: 0.131, y: 0.046, Y: 1.0 },
+    };
+    Profile::new_rgb(&white_point, &primaries, &curves)
+        .unwrap()
+        .icc()
+        .unwrap()
+}
+
+/// An ICC profile with BT.2020 primaries and 2.2 gamma tone curves, tabulated so finely that the profile exceeds the
+/// 65,519 bytes that fit into a single `APP2` segment.
+pub fn large_bt2020_icc_profile() -> Vec<u8> {
+    use lcms2::ToneCurve;
+
+    const ENTRIES: usize = 16384;
+
+    // Distinct curves, so that they aren't shared by the tags.
+    let curves: Vec<ToneCurve> = (0..3)
+        .map(|channel| {
+            let table: Vec<u16> = (0..ENTRIES)
+                .map(|index| {
+                    let value = (index as f32 / (ENTRIES - 1) as f32).powf(2.2);
+                    // Perturb the least significant bit.
+                    ((value * 65535.0).round() as u16).saturating_sub(channel)
+                })
+                .collect();
+            ToneCurve::new_tabulated(&table)
+        })
+        .collect();
+
+    bt2020_icc_profile([&curves[0], &curves[1], &curves[2]])
+}
+
+/// An ICC profile with BT.2020 primaries, linear tone curves, and a `cicp` tag with the given transfer characteristics.
+pub fn bt2020_icc_profile_with_cicp(transfer_characteristics: u8) -> Vec<u8> {
+    let curve = lcms2::ToneCurve::new(1.0);
+    let mut icc_profile = bt2020_icc_profile([&curve, &curve, &curve]);
+
+    // `lcms2` can't write a `cicp` tag, so append one: The tag table grows by an entry, shifting all tag data by 12 bytes.
+    const TAG_TABLE_OFFSET: usize = 128;
+    let read_u32 = |bytes: &[u8], offset: usize| u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+    let tag_count = read_u32(&icc_profile, TAG_TABLE_OFFSET) as usize;
+    let tag_table_end = TAG_TABLE_OFFSET + 4 + tag_count * 12;
+    for index in 0..tag_count {
+        let offset_position = TAG_TABLE_OFFSET + 4 + index * 12 + 4;
+        let offset = read_u32(&icc_profile, offset_position) + 12;
+        icc_profile[offset_position..offset_position + 4].copy_from_slice(&offset.to_be_bytes());
+    }
+    icc_profile[TAG_TABLE_OFFSET..TAG_TABLE_OFFSET + 4].copy_from_slice(&(tag_count as u32 + 1).to_be_bytes());
+
+    while icc_profile.len() % 4 != 0 {
+        icc_profile.push(0);
+    }
+    let cicp_offset = icc_profile.len() as u32 + 12;
+
+    let mut entry = b"cicp".to_vec();
+    entry.extend_from_slice(&cicp_offset.to_be_bytes());
+    entry.extend_from_slice(&12u32.to_be_bytes());
+    icc_profile.splice(tag_table_end..tag_table_end, entry);
+
+    // BT.2020 primaries and non-constant luminance matrix, full range.
+    icc_profile.extend_from_slice(b"cicp\0\0\0\0");
+    icc_profile.extend_from_slice(&[9, transfer_characteristics, 9, 1]);
+
+    let size = icc_profile.len() as u32;
+    icc_profile[0..4].copy_from_slice(&size.to_be_bytes());
+    // Clear the profile ID, which is an MD5 of the profile.
+    icc_profile[84..100].fill(0);
+
+    icc_profile
+}
+
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// A gradient primary with a gain map boosting the right half more than the left half.
+pub fn gradient_uhdr_jpeg(width: usize, height: usize, gain_map_width: usize, gain_map_height: usize) -> Vec<u8> {
+    let primary = TestImage::from_fn(width, height, |x, y| {
+        [(x * 255 / width) as u8, (y * 255 / height) as u8, 128]
+    });
+    let gain_map = TestImage::from_fn(gain_map_width, gain_map_height, |x, _| {
+        let value = if x * 2 < gain_map_width { 64 } else { 192 };
+        [value, value, value]
+    });
+    build_uhdr_jpeg(&primary, &gain_map, TestGainMapParams::default())
+}
@@ -1,7 +1,7 @@
 
 // https://www.itu.int/itudoc/itu-t/com16/tiff-fx/docs/tiff6.pdf
 
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, Write};
 
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
@@ -24,8 +24,9 @@ pub struct Tiff {
 #[derive(Debug, Clone)]
 pub struct TiffHeader {
     pub endianness: Endianness,
+    /// 42 for a classic TIFF, 43 for a BigTIFF, which has 64-bit offsets and counts.
     pub version: u16,
-    pub first_ifd_offset: u32,
+    pub first_ifd_offset: u64,
 }
 
 /// Image File Directory (IFD) structure
@@ -33,7 +34,7 @@ pub struct TiffHeader {
 pub struct TiffIfd {
     pub entries: Vec<TiffIfdEntry>,
 
-    next_ifd_offset: Option<u32>,
+    next_ifd_offset: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -99,8 +100,32 @@ impl Endianness {
             Endianness::BigEndian => Ok(u32::from_be_bytes(buffer)),
         }
     }
+
+    pub fn write_u16<W: Write>(self, writer: &mut W, value: u16) -> std::io::Result<()> {
+        match self {
+            Endianness::LittleEndian => writer.write_all(&value.to_le_bytes()),
+            Endianness::BigEndian => writer.write_all(&value.to_be_bytes()),
+        }
+    }
+
+    pub fn write_u32<W: Write>(self, writer: &mut W, value: u32) -> std::io::Result<()> {
+        match self {
+            Endianness::LittleEndian => writer.write_all(&value.to_le_bytes()),
+            Endianness::BigEndian => writer.write_all(&value.to_be_bytes()),
+        }
+    }
+
+    pub fn write_u64<W: Write>(self, writer: &mut W, value: u64) -> std::io::Result<()> {
+        match self {
+            Endianness::LittleEndian => writer.write_all(&value.to_le_bytes()),
+            Endianness::BigEndian => writer.write_all(&value.to_be_bytes()),
+        }
+    }
 }
 
+/// More than any TIFF this crate reads, i.e. MPF blocks, has in practice.
+const MAX_IFD_COUNT: usize = 64;
+
 impl Tiff {
     pub fn from_reader<R: Read + Seek>(reader: &mut R) -> std::io::Result<Self> {
         let header = TiffHeader::new(reader)?;
@@ -109,7 +134,11 @@ impl Tiff {
 
         let mut ifd_offset = Some(header.first_ifd_offset);
         while let Some(offset) = ifd_offset {
-            reader.seek(std::io::SeekFrom::Start(offset as u64))?;
+            // A malformed chain could otherwise loop forever.
+            if ifds.len() >= MAX_IFD_COUNT {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Too many IFDs"));
+            }
+            reader.seek(std::io::SeekFrom::Start(offset))?;
             let ifd = TiffIfd::new(reader, header.endianness, header.version)?;
 
             ifd_offset = ifd.next_ifd_offset;
@@ -118,6 +147,13 @@ impl Tiff {
 
         Ok(Tiff { header, ifds })
     }
+
+    /// Reads the IFD at `offset` that isn't part of the chain, e.g. one referenced by a `SubIFDs` entry.
+    #[cfg(feature = "dng")]
+    pub fn read_ifd_at<R: Read + Seek>(&self, reader: &mut R, offset: u64) -> std::io::Result<TiffIfd> {
+        reader.seek(std::io::SeekFrom::Start(offset))?;
+        TiffIfd::new(reader, self.header.endianness, self.header.version)
+    }
 }
 
 impl TiffIfd {
@@ -127,6 +163,16 @@ impl TiffIfd {
 }
 
 impl TiffIfdEntry {
+    /// Creates an entry whose field type and count are derived from `field_value`.
+    pub fn new(tag: u16, field_value: TiffFieldValue) -> Self {
+        Self {
+            tag,
+            field_type: field_value.field_type(),
+            count: field_value.count(),
+            field_value,
+        }
+    }
+
     pub fn field_value_size(&self) -> usize {
         self.field_value.size()
     }
@@ -146,6 +192,135 @@ impl TiffIfdEntry {
             None
         }
     }
+
+    pub fn field_value_as_long8(&self) -> Option<&[u64]> {
+        if let TiffFieldValue::LONG8(ref data) = self.field_value {
+            Some(data)
+        } else {
+            None
+        }
+    }
+}
+
+/// Serializes a classic (version 42) TIFF into memory.
+///
+/// Raw data blocks (e.g. image strips) and IFDs can be appended in any order;
+/// the offsets they are written at are returned so that they can be referenced from IFD entries.
+/// IFDs are chained in the order they are appended.
+pub struct TiffWriter {
+    endianness: Endianness,
+    bytes: Vec<u8>,
+    /// Position of the "next IFD offset" field to patch when another IFD is appended.
+    /// Initially the header's first IFD offset field.
+    next_ifd_offset_position: usize,
+}
+
+impl TiffWriter {
+    pub fn new(endianness: Endianness) -> Self {
+        let mut bytes = Vec::new();
+        let byte_order: u16 = match endianness {
+            Endianness::LittleEndian => 0x4949,
+            Endianness::BigEndian => 0x4D4D,
+        };
+        // The byte order mark reads the same in either endianness.
+        bytes.extend_from_slice(&byte_order.to_le_bytes());
+        endianness.write_u16(&mut bytes, 42).unwrap();
+        endianness.write_u32(&mut bytes, 0).unwrap();
+
+        Self {
+            endianness,
+            bytes,
+            next_ifd_offset_position: 4,
+        }
+    }
+
+    /// Appends raw bytes at the next word boundary and returns their offset from the start of the TIFF.
+    pub fn append_data(&mut self, data: &[u8]) -> u32 {
+        self.align_to_word();
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(data);
+        offset
+    }
+
+    /// Appends an IFD with its entries sorted by tag, as required by the specification,
+    /// followed by any field values that don't fit into their entries.
+    /// Returns the offset of the IFD from the start of the TIFF.
+    pub fn append_ifd(&mut self, entries: &[TiffIfdEntry]) -> std::io::Result<u32> {
+        self.append_ifd_impl(entries, true)
+    }
+
+    /// Same as `append_ifd`, but leaves the IFD out of the chain, for IFDs referenced by offset from an entry of another,
+    /// e.g. `SubIFDs`. Only needed to build DNGs for tests so far.
+    #[cfg(all(test, feature = "dng"))]
+    pub fn append_unchained_ifd(&mut self, entries: &[TiffIfdEntry]) -> std::io::Result<u32> {
+        self.append_ifd_impl(entries, false)
+    }
+
+    fn append_ifd_impl(&mut self, entries: &[TiffIfdEntry], chained: bool) -> std::io::Result<u32> {
+        let mut entries: Vec<&TiffIfdEntry> = entries.iter().collect();
+        entries.sort_by_key(|entry| entry.tag);
+
+        let entry_count: u16 = entries.len().try_into()
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Too many IFD entries"))?;
+
+        self.align_to_word();
+        let ifd_offset = self.bytes.len() as u32;
+        if chained {
+            self.patch_u32(self.next_ifd_offset_position, ifd_offset);
+        }
+
+        // Out-of-line values are placed right after the IFD: 2 bytes of count, 12 bytes per entry and 4 bytes of next IFD offset.
+        let values_offset = ifd_offset as usize + 2 + entries.len() * 12 + 4;
+        let mut out_of_line_values: Vec<u8> = Vec::new();
+
+        let endianness = self.endianness;
+        endianness.write_u16(&mut self.bytes, entry_count)?;
+        for entry in &entries {
+            endianness.write_u16(&mut self.bytes, entry.tag)?;
+            endianness.write_u16(&mut self.bytes, entry.field_type as u16)?;
+            endianness.write_u32(&mut self.bytes, entry.count)?;
+
+            let mut value_bytes = Vec::new();
+            entry.field_value.write_to(&mut value_bytes, endianness)?;
+
+            if value_bytes.len() <= 4 {
+                value_bytes.resize(4, 0);
+                self.bytes.extend_from_slice(&value_bytes);
+            } else {
+                endianness.write_u32(&mut self.bytes, (values_offset + out_of_line_values.len()) as u32)?;
+                out_of_line_values.extend_from_slice(&value_bytes);
+                // Keep each value word-aligned.
+                if !out_of_line_values.len().is_multiple_of(2) {
+                    out_of_line_values.push(0);
+                }
+            }
+        }
+
+        if chained {
+            self.next_ifd_offset_position = self.bytes.len();
+        }
+        endianness.write_u32(&mut self.bytes, 0)?;
+
+        self.bytes.extend_from_slice(&out_of_line_values);
+
+        Ok(ifd_offset)
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn align_to_word(&mut self) {
+        if !self.bytes.len().is_multiple_of(2) {
+            self.bytes.push(0);
+        }
+    }
+
+    fn patch_u32(&mut self, position: usize, value: u32) {
+        let mut buffer = Vec::with_capacity(4);
+        self.endianness.write_u32(&mut buffer, value).unwrap();
+        self.bytes[position..position + 4].copy_from_slice(&buffer);
+    }
 }
 
 impl TiffHeader {
@@ -173,7 +348,17 @@ impl TiffHeader {
             ));
         }
 
-        let first_ifd_offset = read_u32(reader, endianness)?;
+        let first_ifd_offset = if version == 43 {
+            // The size of offsets, which is always 8, and a reserved 0.
+            let offset_size = read_u16(reader, endianness)?;
+            let reserved = read_u16(reader, endianness)?;
+            if offset_size != 8 || reserved != 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid BigTIFF header"));
+            }
+            read_u64(reader, endianness)?
+        } else {
+            read_u32(reader, endianness)?.into()
+        };
 
         Ok(TiffHeader {
             endianness,
@@ -185,15 +370,34 @@ impl TiffHeader {
 
 impl TiffIfd {
     /// * `reader` - The `Read` from which to read the IFD. Must be positioned at the start of the IFD.
+    ///
+    /// A BigTIFF IFD has 64-bit entry counts, value counts and offsets where a classic one has 16-bit entry counts,
+    /// and 32-bit value counts and offsets.
     fn new<R: Read + Seek>(reader: &mut R, endianness: Endianness, version: u16) -> std::io::Result<Self> {
         let value_offset_size = match version {
             42 => 4usize, // 32-bit offset
             43 => 8usize, // 64-bit offset
             _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Unsupported TIFF version")),
         };
+        let read_offset = |reader: &mut R| match value_offset_size {
+            4 => read_u32(reader, endianness).map(u64::from),
+            _ => read_u64(reader, endianness),
+        };
 
-        let entry_count = read_u16(reader, endianness)?;
-        if entry_count < 1 {
+        let stream_length = {
+            let position = reader.stream_position()?;
+            let length = reader.seek(std::io::SeekFrom::End(0))?;
+            reader.seek(std::io::SeekFrom::Start(position))?;
+            length
+        };
+
+        let entry_count = match value_offset_size {
+            4 => read_u16(reader, endianness)?.into(),
+            _ => read_u64(reader, endianness)?,
+        };
+        // Each entry has a tag, a field type, a count and a value or offset.
+        let entry_size = 2 + 2 + value_offset_size as u64 * 2;
+        if entry_count < 1 || entry_count.saturating_mul(entry_size) > stream_length {
             return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid IFD entry count"));
         }
 
@@ -202,25 +406,29 @@ impl TiffIfd {
         for _ in 0..entry_count {
             let tag = read_u16(reader, endianness)?;
             let field_type = read_u16(reader, endianness)?;
-            let count = read_u32(reader, endianness)?;
+            let count: u32 = read_offset(reader)?.try_into()
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Field value count out of range"))?;
 
             let field_type = TiffFieldType::from_u16(field_type)
                 .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid field type"))?;
 
-            let size = field_type.size() * count as usize;
+            let size = field_type.size().saturating_mul(count as usize);
 
             let field_value = if size <= value_offset_size {
-                // The field value is stored directly in the IFD entry
-                TiffFieldValue::from_reader(reader, endianness, field_type, count)?
+                // The field value is stored directly in the IFD entry, left-justified and padded to the offset size.
+                let field_value = TiffFieldValue::from_reader(reader, endianness, field_type, count)?;
+                reader.seek(std::io::SeekFrom::Current((value_offset_size - size) as i64))?;
+                field_value
             } else {
                 // The field value is stored in a separate location.
                 // We need to seek to that location and read the value from there.
 
-                let value_offset: u64 = match value_offset_size {
-                    4 => read_u32(reader, endianness)? as u64,
-                    8 => read_u64(reader, endianness)?,
-                    _ => unreachable!(),
-                };
+                let value_offset = read_offset(reader)?;
+
+                // Checked before reading, since the count is used to allocate the field value.
+                if value_offset.checked_add(size as u64).is_none_or(|end| end > stream_length) {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Field value out of bounds"));
+                }
 
                 let old_position = reader.stream_position()?;
                 reader.seek(std::io::SeekFrom::Start(value_offset))?;
@@ -238,7 +446,7 @@ impl TiffIfd {
             });
         }
 
-        let next_ifd_offset = read_u32(reader, endianness)?;
+        let next_ifd_offset = read_offset(reader)?;
         let next_ifd_offset = if next_ifd_offset == 0 {
             None
         } else {
@@ -268,7 +476,8 @@ impl TiffFieldType {
             TiffFieldType::SRATIONAL => 8,
             TiffFieldType::FLOAT => 4,
             TiffFieldType::DOUBLE => 8,
-            _ => unimplemented!(),
+            TiffFieldType::LONG8 => 8,
+            TiffFieldType::SLONG8 => 8,
         }
     }
 }
@@ -360,8 +569,87 @@ impl TiffFieldValue {
                 }
                 Ok(TiffFieldValue::DOUBLE(values))
             },
-            // Handle other field types similarly
-            _ => unimplemented!(),
+            TiffFieldType::LONG8 => {
+                let mut values = vec![0; count as usize];
+                for i in 0..count {
+                    values[i as usize] = read_u64(reader, endianness)?;
+                }
+                Ok(TiffFieldValue::LONG8(values))
+            },
+            TiffFieldType::SLONG8 => {
+                let mut values = vec![0; count as usize];
+                for i in 0..count {
+                    values[i as usize] = read_u64(reader, endianness)? as i64;
+                }
+                Ok(TiffFieldValue::SLONG8(values))
+            },
+        }
+    }
+
+    pub fn field_type(&self) -> TiffFieldType {
+        match self {
+            TiffFieldValue::BYTE(_) => TiffFieldType::BYTE,
+            TiffFieldValue::ASCII(_) => TiffFieldType::ASCII,
+            TiffFieldValue::SHORT(_) => TiffFieldType::SHORT,
+            TiffFieldValue::LONG(_) => TiffFieldType::LONG,
+            TiffFieldValue::RATIONAL(_) => TiffFieldType::RATIONAL,
+            TiffFieldValue::SBYTE(_) => TiffFieldType::SBYTE,
+            TiffFieldValue::UNDEFINED(_) => TiffFieldType::UNDEFINED,
+            TiffFieldValue::SSHORT(_) => TiffFieldType::SSHORT,
+            TiffFieldValue::SLONG(_) => TiffFieldType::SLONG,
+            TiffFieldValue::SRATIONAL(_) => TiffFieldType::SRATIONAL,
+            TiffFieldValue::FLOAT(_) => TiffFieldType::FLOAT,
+            TiffFieldValue::DOUBLE(_) => TiffFieldType::DOUBLE,
+            TiffFieldValue::LONG8(_) => TiffFieldType::LONG8,
+            TiffFieldValue::SLONG8(_) => TiffFieldType::SLONG8,
+        }
+    }
+
+    /// The number of values, which for `ASCII` includes the terminating NUL.
+    pub fn count(&self) -> u32 {
+        let count = match self {
+            TiffFieldValue::BYTE(values) => values.len(),
+            TiffFieldValue::ASCII(string) => string.len() + 1,
+            TiffFieldValue::SHORT(values) => values.len(),
+            TiffFieldValue::LONG(values) => values.len(),
+            TiffFieldValue::RATIONAL(values) => values.len(),
+            TiffFieldValue::SBYTE(values) => values.len(),
+            TiffFieldValue::UNDEFINED(values) => values.len(),
+            TiffFieldValue::SSHORT(values) => values.len(),
+            TiffFieldValue::SLONG(values) => values.len(),
+            TiffFieldValue::SRATIONAL(values) => values.len(),
+            TiffFieldValue::FLOAT(values) => values.len(),
+            TiffFieldValue::DOUBLE(values) => values.len(),
+            TiffFieldValue::LONG8(values) => values.len(),
+            TiffFieldValue::SLONG8(values) => values.len(),
+        };
+        count as u32
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W, endianness: Endianness) -> std::io::Result<()> {
+        match self {
+            TiffFieldValue::BYTE(values) | TiffFieldValue::UNDEFINED(values) => writer.write_all(values),
+            TiffFieldValue::ASCII(string) => {
+                writer.write_all(string.as_bytes())?;
+                writer.write_all(&[0])
+            },
+            TiffFieldValue::SHORT(values) => values.iter().try_for_each(|&value| endianness.write_u16(writer, value)),
+            TiffFieldValue::LONG(values) => values.iter().try_for_each(|&value| endianness.write_u32(writer, value)),
+            TiffFieldValue::RATIONAL(values) => values.iter().try_for_each(|&(numerator, denominator)| {
+                endianness.write_u32(writer, numerator)?;
+                endianness.write_u32(writer, denominator)
+            }),
+            TiffFieldValue::SBYTE(values) => values.iter().try_for_each(|&value| writer.write_all(&[value as u8])),
+            TiffFieldValue::SSHORT(values) => values.iter().try_for_each(|&value| endianness.write_u16(writer, value as u16)),
+            TiffFieldValue::SLONG(values) => values.iter().try_for_each(|&value| endianness.write_u32(writer, value as u32)),
+            TiffFieldValue::SRATIONAL(values) => values.iter().try_for_each(|&(numerator, denominator)| {
+                endianness.write_u32(writer, numerator as u32)?;
+                endianness.write_u32(writer, denominator as u32)
+            }),
+            TiffFieldValue::FLOAT(values) => values.iter().try_for_each(|&value| endianness.write_u32(writer, value.to_bits())),
+            TiffFieldValue::DOUBLE(values) => values.iter().try_for_each(|&value| endianness.write_u64(writer, value.to_bits())),
+            TiffFieldValue::LONG8(values) => values.iter().try_for_each(|&value| endianness.write_u64(writer, value)),
+            TiffFieldValue::SLONG8(values) => values.iter().try_for_each(|&value| endianness.write_u64(writer, value as u64)),
         }
     }
 
@@ -379,7 +667,8 @@ impl TiffFieldValue {
             TiffFieldValue::SRATIONAL(values) => values.len() * (std::mem::size_of::<i32>() * 2),
             TiffFieldValue::FLOAT(values) => values.len() * std::mem::size_of::<f32>(),
             TiffFieldValue::DOUBLE(values) => values.len() * std::mem::size_of::<f64>(),
-            _ => unimplemented!(),
+            TiffFieldValue::LONG8(values) => values.len() * std::mem::size_of::<u64>(),
+            TiffFieldValue::SLONG8(values) => values.len() * std::mem::size_of::<i64>(),
         }
     }
 }
@@ -428,3 +717,98 @@ fn read_f64<R: Read>(reader: &mut R, endianness: Endianness) -> std::io::Result<
         Endianness::BigEndian => Ok(f64::from_be_bytes(buffer)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_entry_tiff() -> Vec<u8> {
+        let mut writer = TiffWriter::new(Endianness::LittleEndian);
+        writer.append_ifd(&[TiffIfdEntry::new(256, TiffFieldValue::LONG(vec![1, 2, 3]))]).unwrap();
+        writer.finish()
+    }
+
+    #[test]
+    fn field_value_beyond_the_end_is_an_error() {
+        let mut bytes = single_entry_tiff();
+        assert!(Tiff::from_reader(&mut std::io::Cursor::new(&bytes)).is_ok());
+
+        // The count of the entry, which would otherwise be allocated before reading fails.
+        let count_offset = 8 + 2 + 4;
+        bytes[count_offset..count_offset + 4].copy_from_slice(&0x4000_0000u32.to_le_bytes());
+
+        let error = Tiff::from_reader(&mut std::io::Cursor::new(&bytes)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn cyclic_ifd_chain_is_an_error() {
+        let mut bytes = single_entry_tiff();
+
+        // Points the next IFD offset of the only IFD back at itself.
+        let next_ifd_offset = 8 + 2 + 12;
+        bytes[next_ifd_offset..next_ifd_offset + 4].copy_from_slice(&8u32.to_le_bytes());
+
+        assert!(Tiff::from_reader(&mut std::io::Cursor::new(&bytes)).is_err());
+    }
+
+    #[test]
+    fn long8_fields_in_a_classic_tiff_are_read() {
+        // As DNGs may have, e.g. for `SubIFDs`.
+        let mut writer = TiffWriter::new(Endianness::LittleEndian);
+        writer.append_ifd(&[
+            TiffIfdEntry::new(330, TiffFieldValue::LONG8(vec![1 << 40, 8])),
+            TiffIfdEntry::new(1000, TiffFieldValue::SLONG8(vec![-5])),
+        ]).unwrap();
+        let bytes = writer.finish();
+
+        let tiff = Tiff::from_reader(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert!(matches!(&tiff.ifds[0].entry_with_tag(330).unwrap().field_value, TiffFieldValue::LONG8(values) if values == &[1 << 40, 8]));
+        assert!(matches!(&tiff.ifds[0].entry_with_tag(1000).unwrap().field_value, TiffFieldValue::SLONG8(values) if values == &[-5]));
+    }
+
+    #[test]
+    fn big_tiff_long8_and_slong8_values_round_trip() {
+        let long8 = TiffFieldValue::LONG8(vec![1 << 40, u64::MAX]);
+        let slong8 = TiffFieldValue::SLONG8(vec![-5]);
+        let value_bytes = |value: &TiffFieldValue| {
+            let mut bytes = Vec::new();
+            value.write_to(&mut bytes, Endianness::BigEndian).unwrap();
+            bytes
+        };
+
+        let mut bytes = Vec::new();
+        let endianness = Endianness::BigEndian;
+        // Header: Byte order, version, offset size, reserved and the first IFD offset.
+        bytes.extend_from_slice(b"MM");
+        endianness.write_u16(&mut bytes, 43).unwrap();
+        endianness.write_u16(&mut bytes, 8).unwrap();
+        endianness.write_u16(&mut bytes, 0).unwrap();
+        endianness.write_u64(&mut bytes, 16).unwrap();
+        // IFD: The entry count, 2 entries of 20 bytes and the next IFD offset, followed by the out-of-line value.
+        let out_of_line_offset = 16 + 8 + 2 * 20 + 8;
+        endianness.write_u64(&mut bytes, 2).unwrap();
+        for (tag, value, inline) in [(1000, &long8, false), (1001, &slong8, true)] {
+            endianness.write_u16(&mut bytes, tag).unwrap();
+            endianness.write_u16(&mut bytes, value.field_type() as u16).unwrap();
+            endianness.write_u64(&mut bytes, value.count().into()).unwrap();
+            if inline {
+                bytes.extend_from_slice(&value_bytes(value));
+            } else {
+                endianness.write_u64(&mut bytes, out_of_line_offset).unwrap();
+            }
+        }
+        endianness.write_u64(&mut bytes, 0).unwrap();
+        assert_eq!(bytes.len() as u64, out_of_line_offset);
+        bytes.extend_from_slice(&value_bytes(&long8));
+
+        let tiff = Tiff::from_reader(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(tiff.header.version, 43);
+        let [ifd] = tiff.ifds.as_slice() else {
+            panic!("Expected 1 IFD, got {}", tiff.ifds.len());
+        };
+        assert_eq!(ifd.entry_with_tag(1000).unwrap().field_value_as_long8(), Some([1 << 40, u64::MAX].as_slice()));
+        assert!(matches!(&ifd.entry_with_tag(1001).unwrap().field_value, TiffFieldValue::SLONG8(values) if values == &[-5]));
+        assert_eq!(ifd.entry_with_tag(1001).unwrap().field_value_as_long8(), None);
+    }
+}
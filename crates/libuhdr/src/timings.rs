@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use log::debug;
+
+/// Wall-clock time spent in each phase of a conversion, from `UhdrConverter::convert_to_avif_timed`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ConversionTimings {
+    /// Decoding the primary image and gain map JPEGs, and parsing their metadata except for ICC profiles.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_duration_as_secs"))]
+    pub jpeg_decode: Duration,
+    /// Parsing the ICC profiles of the primary image and gain maps.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_duration_as_secs"))]
+    pub icc_parse: Duration,
+    /// Computing the linear pixels of the _HDR rendition_, including any hook.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_duration_as_secs"))]
+    pub pixel_pass: Duration,
+    /// Encoding and writing the output.
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_duration_as_secs"))]
+    pub encode: Duration,
+}
+
+impl ConversionTimings {
+    /// The sum of all phases.
+    pub fn total(&self) -> Duration {
+        self.jpeg_decode + self.icc_parse + self.pixel_pass + self.encode
+    }
+
+    /// Logs the breakdown at debug level.
+    pub fn log(&self) {
+        let total = self.total();
+        let percentage = |duration: Duration| {
+            if total.is_zero() { 0.0 } else { duration.as_secs_f64() / total.as_secs_f64() * 100.0 }
+        };
+
+        debug!("Conversion took {:?}:", total);
+        for (name, duration) in [
+            ("JPEG decode", self.jpeg_decode),
+            ("ICC parse", self.icc_parse),
+            ("Pixel pass", self.pixel_pass),
+            ("Encode", self.encode),
+        ] {
+            debug!("  {}: {:?} ({:.1}%)", name, duration, percentage(duration));
+        }
+    }
+}
+
+/// Durations are serialized as fractional seconds, which are easier to consume than `serde`'s `{ secs, nanos }`.
+#[cfg(feature = "serde")]
+fn serialize_duration_as_secs<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
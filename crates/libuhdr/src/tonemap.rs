@@ -0,0 +1,75 @@
+/// A global tone-mapping operator, compressing the _HDR rendition_ into the SDR range for `compute_tone_mapped_srgb8_pixels`.
+///
+/// Operators apply to each of R, G and B in linear light relative to SDR white, mapping the peak to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum ToneMapOperator {
+    /// Extended Reinhard with the peak as the white point. An identity when there is no headroom.
+    Reinhard,
+    /// John Hable's filmic curve from _Uncharted 2_, with the peak as the linear white point.
+    Hable,
+    /// Krzysztof Narkowicz's fit of the _ACES_ reference rendering transform, normalized so the peak maps to 1.
+    Aces,
+}
+
+impl ToneMapOperator {
+    /// Maps `value`, relative to SDR white, to [0, 1]. `peak` is the brightest value to preserve and is at least 1.
+    pub fn apply(self, value: f32, peak: f32) -> f32 {
+        let value = value.max(0.0);
+        let peak = peak.max(1.0);
+
+        let mapped = match self {
+            Self::Reinhard => value * (1.0 + value / (peak * peak)) / (1.0 + value),
+            Self::Hable => hable(value) / hable(peak),
+            Self::Aces => aces(value) / aces(peak),
+        };
+        mapped.clamp(0.0, 1.0)
+    }
+}
+
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15; // Shoulder strength
+    const B: f32 = 0.50; // Linear strength
+    const C: f32 = 0.10; // Linear angle
+    const D: f32 = 0.20; // Toe strength
+    const E: f32 = 0.02; // Toe numerator
+    const F: f32 = 0.30; // Toe denominator
+
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn aces(x: f32) -> f32 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapOperator; 3] = [ToneMapOperator::Reinhard, ToneMapOperator::Hable, ToneMapOperator::Aces];
+
+    #[test]
+    fn operators_map_black_to_0_and_the_peak_to_1_monotonically() {
+        for operator in OPERATORS {
+            for peak in [1.0, 4.0, 16.0] {
+                assert!(operator.apply(0.0, peak) < 1e-6, "{:?}", operator);
+                assert!((operator.apply(peak, peak) - 1.0).abs() < 1e-5, "{:?} at {}", operator, peak);
+                assert_eq!(operator.apply(peak * 2.0, peak), 1.0, "{:?}", operator);
+
+                let mut previous = 0.0;
+                for step in 1..=100 {
+                    let mapped = operator.apply(peak * step as f32 / 100.0, peak);
+                    assert!(mapped >= previous, "{:?} is not monotonic at {}", operator, peak);
+                    previous = mapped;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reinhard_is_an_identity_without_headroom() {
+        for value in [0.0, 0.18, 0.5, 1.0] {
+            assert!((ToneMapOperator::Reinhard.apply(value, 1.0) - value).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Transfer functions, i.e. the non-linear encodings of light of the color spaces the crate reads and writes:
+//! SMPTE ST 2084 PQ and HLG of BT.2100 for HDR, and sRGB and BT.709 for SDR.
+//!
+//! Linear values are normalized to [0, 1]: For PQ, 1 is 10,000 nits, and for HLG, the nominal peak of the reference display.
+
+/// Applies `st2084_oetf` to each of `values`.
+///
+/// With the `simd` feature, 4 values are processed at once as an `f32x4`,
+/// using polynomial approximations of `log2` and `exp2` in place of `powf`.
+/// The result stays well within 1 10-bit code value of `st2084_oetf`.
+pub fn st2084_oetf_in_place(values: &mut [f32]) {
+    #[cfg(feature = "simd")]
+    let values = {
+        let mut chunks = values.chunks_exact_mut(4);
+        for chunk in &mut chunks {
+            let color = wide::f32x4::from(*chunk.first_chunk::<4>().unwrap());
+            chunk.copy_from_slice(&simd::st2084_oetf_f32x4(color).to_array());
+        }
+        chunks.into_remainder()
+    };
+
+    for value in values {
+        *value = st2084_oetf(*value);
+    }
+}
+
+#[cfg(feature = "simd")]
+pub(crate) mod simd {
+    use bytemuck::cast;
+    use wide::{f32x4, i32x4, CmpGt};
+
+    /// Vectorized `st2084_oetf`.
+    #[inline(always)]
+    pub fn st2084_oetf_f32x4(color: f32x4) -> f32x4 {
+        const M1: f32 = 2610.0 / 16384.0;
+        const M2: f32 = 2523.0 / 4096.0 * 128.0;
+        const C1: f32 = 3424.0 / 4096.0;
+        const C2: f32 = 2413.0 / 4096.0 * 32.0;
+        const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+        let cp = pow(color.abs(), M1);
+        let numerator = f32x4::splat(C1) + f32x4::splat(C2) * cp;
+        let denominator = f32x4::splat(1.0) + f32x4::splat(C3) * cp;
+
+        pow(numerator / denominator, M2)
+    }
+
+    /// `base^exponent` for non-negative `base`.
+    /// Zero and subnormal `base` are treated as `2^-127`, which is close enough to zero for the PQ curve.
+    #[inline(always)]
+    fn pow(base: f32x4, exponent: f32) -> f32x4 {
+        exp2(log2(base) * f32x4::splat(exponent))
+    }
+
+    /// `log2(x)` for non-negative `x`, with an absolute error on the order of `1e-7`.
+    #[inline(always)]
+    pub(crate) fn log2(x: f32x4) -> f32x4 {
+        let bits: i32x4 = cast(x);
+
+        // Split into `mantissa * 2^exponent` with `mantissa` in [1, 2).
+        let exponent: i32x4 = (bits >> 23) - i32x4::splat(127);
+        let mantissa: f32x4 = cast((bits & i32x4::splat(0x007F_FFFF)) | i32x4::splat(0x3F80_0000));
+
+        // Move `mantissa` into [sqrt(1/2), sqrt(2)) so that the series below converges quickly.
+        let above = mantissa.cmp_gt(f32x4::SQRT_2);
+        let mantissa = above.blend(mantissa * f32x4::HALF, mantissa);
+        let exponent = exponent.round_float() + above.blend(f32x4::ONE, f32x4::ZERO);
+
+        // `ln(m) = 2 * atanh(t)` where `t = (m - 1) / (m + 1)`, with `|t| < 0.172`.
+        let t = (mantissa - f32x4::ONE) / (mantissa + f32x4::ONE);
+        let t2 = t * t;
+        let series = t2.mul_add(
+            t2.mul_add(t2.mul_add(f32x4::splat(1.0 / 7.0), f32x4::splat(1.0 / 5.0)), f32x4::splat(1.0 / 3.0)),
+            f32x4::ONE,
+        );
+
+        exponent + t * series * f32x4::splat(2.0 * std::f32::consts::LOG2_E)
+    }
+
+    /// `2^y`, with a relative error on the order of `1e-7`. `y` is clamped to the normal range of `f32`.
+    #[inline(always)]
+    pub(crate) fn exp2(y: f32x4) -> f32x4 {
+        let y = y.max(f32x4::splat(-126.0)).min(f32x4::splat(127.0));
+
+        // Split into `2^n * 2^f` with `f` in [-0.5, 0.5].
+        let n: i32x4 = y.fast_round_int();
+        let x = (y - n.round_float()) * f32x4::LN_2;
+
+        // Taylor series of `e^x`.
+        let mut p = f32x4::splat(1.0 / 720.0);
+        for coefficient in [1.0 / 120.0, 1.0 / 24.0, 1.0 / 6.0, 1.0 / 2.0, 1.0, 1.0] {
+            p = p.mul_add(x, f32x4::splat(coefficient));
+        }
+
+        // Add `n` to the exponent bits.
+        cast(cast::<f32x4, i32x4>(p) + (n << 23))
+    }
+}
+
+/// SMPTE ST.2084 PQ (Perceptual Quantizer) EOTF^-1:
+/// PQ is actually defined by the EOTF. This is its inverse, divided by 10,000.
+///
+/// Also in [_Rec. ITU-R BT.2100-3_](https://www.itu.int/rec/R-REC-BT.2100-3-202502-I/en).
+///
+/// - `color`: Normalized color [0, 1] to map non-linearly to [0, 1].
+pub fn st2084_oetf(color: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let cp = f32::powf(color.abs(), M1);
+    let numerator = C1 + C2 * cp;
+    let denominator = 1.0 + C3 * cp;
+
+    f32::powf(numerator / denominator, M2)
+}
+
+/// SMPTE ST.2084 PQ EOTF, divided by 10,000, i.e. the inverse of `st2084_oetf`.
+///
+/// - `value`: Non-linear value [0, 1] to map to normalized color [0, 1].
+pub fn st2084_eotf(value: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let ep = f32::powf(value.max(0.0), 1.0 / M2);
+    let numerator = (ep - C1).max(0.0);
+    let denominator = C2 - C3 * ep;
+
+    f32::powf(numerator / denominator, 1.0 / M1)
+}
+
+/// Rec. ITU-R BT.2100-3, HLG OETF: Maps normalized scene light in [0, 1] to the non-linear signal in [0, 1].
+pub fn hlg_oetf(value: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 1.0 - 4.0 * A;
+    const C: f32 = 0.559_910_7;
+
+    let value = value.clamp(0.0, 1.0);
+    if value <= 1.0 / 12.0 {
+        (3.0 * value).sqrt()
+    } else {
+        A * (12.0 * value - B).ln() + C
+    }
+}
+
+/// Rec. ITU-R BT.2100-3, HLG OETF^-1: Maps the non-linear signal in [0, 1] to normalized scene light in [0, 1].
+pub fn hlg_inverse_oetf(value: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 1.0 - 4.0 * A;
+    const C: f32 = 0.559_910_7;
+
+    let value = value.max(0.0);
+    if value <= 0.5 {
+        value * value / 3.0
+    } else {
+        (((value - C) / A).exp() + B) / 12.0
+    }
+}
+
+/// Rec. ITU-R BT.2100-3, the HLG reference OOTF with the system gamma of `1.2` of the nominal peak:
+/// Maps normalized scene light to display light normalized to `HLG_NOMINAL_PEAK_LUMINANCE`, both in [0, 1].
+pub fn hlg_ootf(rgb: [f32; 3]) -> [f32; 3] {
+    const SYSTEM_GAMMA: f32 = 1.2;
+
+    // With BT.2020 luminance coefficients.
+    let scene_luminance = 0.2627 * rgb[0] + 0.6780 * rgb[1] + 0.0593 * rgb[2];
+    let scale = scene_luminance.powf(SYSTEM_GAMMA - 1.0);
+    rgb.map(|value| value * scale)
+}
+
+/// Rec. ITU-R BT.2100-3, the inverse of the HLG reference OOTF with the system gamma of `1.2` of the nominal peak:
+/// Maps display light normalized to `HLG_NOMINAL_PEAK_LUMINANCE` to normalized scene light, both in [0, 1].
+pub fn hlg_inverse_ootf(rgb: [f32; 3]) -> [f32; 3] {
+    const SYSTEM_GAMMA: f32 = 1.2;
+
+    // With BT.2020 luminance coefficients.
+    let display_luminance = 0.2627 * rgb[0] + 0.6780 * rgb[1] + 0.0593 * rgb[2];
+    if display_luminance <= 0.0 {
+        return [0.0; 3];
+    }
+    let scale = display_luminance.powf((1.0 - SYSTEM_GAMMA) / SYSTEM_GAMMA);
+    rgb.map(|value| value * scale)
+}
+
+/// sRGB EOTF per _IEC 61966-2-1_: Maps the non-linear signal in [0, 1] to linear light in [0, 1].
+pub fn srgb_eotf(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// sRGB OETF per _IEC 61966-2-1_: Maps linear light in [0, 1] to the non-linear signal in [0, 1].
+pub fn srgb_oetf(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Rec. ITU-R BT.709-6 OETF: Maps linear scene light in [0, 1] to the non-linear signal in [0, 1].
+pub fn bt709_oetf(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value < 0.018 {
+        4.5 * value
+    } else {
+        1.099 * value.powf(0.45) - 0.099
+    }
+}
+
+/// The inverse of `bt709_oetf`: Maps the non-linear signal in [0, 1] to linear scene light in [0, 1].
+///
+/// This is not the EOTF of BT.709 displays, which is the pure 2.4 power of _Rec. ITU-R BT.1886_.
+pub fn bt709_inverse_oetf(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value < 4.5 * 0.018 {
+        value / 4.5
+    } else {
+        ((value + 0.099) / 1.099).powf(1.0 / 0.45)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn st2084_oetf_in_place_within_one_code_value() {
+        const STEPS: usize = 100_000;
+
+        // Denser near black, where PQ is steepest.
+        // The odd count also exercises the remainder not processed as a whole `f32x4`.
+        let colors: Vec<f32> = (0..=STEPS).map(|i| (i as f32 / STEPS as f32).powi(4)).collect();
+        let mut values = colors.clone();
+        st2084_oetf_in_place(&mut values);
+
+        let max_error = values.iter()
+            .zip(&colors)
+            .map(|(&approximate, &color)| (approximate - st2084_oetf(color)).abs())
+            .fold(0.0f32, f32::max);
+
+        assert!(max_error * 1023.0 < 1.0, "Max error of {} code values", max_error * 1023.0);
+    }
+
+    #[test]
+    fn inverses_round_trip() {
+        let pairs: [(&str, fn(f32) -> f32, fn(f32) -> f32); 4] = [
+            ("PQ", st2084_oetf, st2084_eotf),
+            ("HLG", hlg_oetf, hlg_inverse_oetf),
+            ("sRGB", srgb_oetf, srgb_eotf),
+            ("BT.709", bt709_oetf, bt709_inverse_oetf),
+        ];
+        for (name, encode, decode) in pairs {
+            for step in 0..=1000 {
+                let value = step as f32 / 1000.0;
+                let round_tripped = decode(encode(value));
+                assert!((round_tripped - value).abs() < 1e-4, "{}: {} round-tripped to {}", name, value, round_tripped);
+            }
+            assert!(encode(1.0) > 0.999 && encode(1.0) <= 1.0, "{}: {}", name, encode(1.0));
+        }
+
+        for rgb in [[0.0; 3], [0.01, 0.5, 0.2], [1.0; 3]] {
+            let round_tripped = hlg_inverse_ootf(hlg_ootf(rgb));
+            for (round_tripped, value) in round_tripped.iter().zip(rgb) {
+                assert!((round_tripped - value).abs() < 1e-5, "HLG OOTF: {:?} round-tripped to {:?}", rgb, round_tripped);
+            }
+        }
+    }
+
+    #[test]
+    fn sdr_curves_have_their_linear_segments() {
+        // Both are linear near black, unlike a pure power function.
+        assert!((srgb_eotf(0.04) - 0.04 / 12.92).abs() < 1e-7);
+        assert!((bt709_oetf(0.01) - 0.045).abs() < 1e-7);
+        // And continuous where the segments meet, up to the rounding of the constants of BT.709.
+        assert!((srgb_oetf(0.003_130_8) - 1.055 * 0.003_130_8f32.powf(1.0 / 2.4) + 0.055).abs() < 1e-5);
+        assert!((bt709_oetf(0.018) - 4.5 * 0.018).abs() < 1e-3);
+    }
+}
@@ -1,14 +1,16 @@
 
-use crate::gainmap::GainMapMetadata;
-use crate::pixel::FloatPixel;
+use crate::gainmap::{GainMapFormulation, GainMapMetadata};
+use crate::pixel::{FloatImageContent, FloatPixel};
 
 #[derive(Debug, Clone, Copy)]
 pub struct UhdrBoostComputer {
     inv_gamma: FloatPixel,
     gain_map_min: FloatPixel,
     gain_map_max: FloatPixel,
-    offset_sdr: FloatPixel,
-    offset_hdr: FloatPixel,
+    /// The offsets of the base and the alternate rendition, and the signed weight factor of the gains,
+    /// as given by the `GainMapFormulation` of the metadata.
+    offset_base: FloatPixel,
+    offset_alternate: FloatPixel,
     weight_factor: f32,
 }
 
@@ -22,27 +24,262 @@ impl UhdrBoostComputer {
 
         let weight_factor = gain_map_metadata.compute_weight_factor(log2_max_display_boost);
 
+        let offset_sdr: FloatPixel = gain_map_metadata.offset_sdr.into();
+        let offset_hdr: FloatPixel = gain_map_metadata.offset_hdr.into();
+        let (offset_base, offset_alternate, weight_factor) = match gain_map_metadata.formulation {
+            // The gains are always from SDR to HDR, so they are divided out of an HDR base.
+            GainMapFormulation::Adobe if gain_map_metadata.base_rendition_is_hdr => (offset_hdr, offset_sdr, -weight_factor),
+            GainMapFormulation::Adobe => (offset_sdr, offset_hdr, weight_factor),
+            // The gains are from the base to the alternate rendition, which the offsets are of.
+            GainMapFormulation::Iso21496 => (offset_sdr, offset_hdr, weight_factor),
+        };
+
         Self {
             inv_gamma,
             gain_map_min: gain_map_metadata.gain_map_min.into(),
             gain_map_max: gain_map_metadata.gain_map_max.into(),
-            offset_sdr: gain_map_metadata.offset_sdr.into(),
-            offset_hdr: gain_map_metadata.offset_hdr.into(),
+            offset_base,
+            offset_alternate,
             weight_factor,
         }
     }
 
+    /// Maps a pixel of the base rendition to the rendition for the display: Up towards the HDR rendition for an SDR base,
+    /// and down towards the SDR rendition for an HDR base, i.e. with `base_rendition_is_hdr`.
+    ///
+    /// - `base`: The linear pixel of the base rendition, i.e. the primary image.
+    /// - `recovery`: The gain map value, nominally in [0, 1].
+    ///   Values outside of it, e.g. from filtering or lossy compression, are clamped, like the 8-bit gain map texels of the reference.
+    ///   Without clamping, `powf` would give NaN for a negative value.
+    ///   Extended ranges, including boosts below `1.0`, are instead expressed by `gain_map_min` and `gain_map_max`.
     pub fn compute_boosted(
         &self,
-        sdr: FloatPixel,
+        base: FloatPixel,
         recovery: FloatPixel,
     ) -> FloatPixel {
-        let log_recovery = FloatPixel::powf(&recovery, &self.inv_gamma);
+        let log_recovery = FloatPixel::powf(&recovery.clamp(0.0, 1.0), &self.inv_gamma);
 
         let log_boost = self.gain_map_min * (FloatPixel::one() - log_recovery) + self.gain_map_max * log_recovery;
         let boost = (log_boost * self.weight_factor).exp2();
 
-        let boosted = (sdr + self.offset_sdr) * boost - self.offset_hdr;
+        let boosted = (base + self.offset_base) * boost - self.offset_alternate;
         boosted
     }
 }
+
+/// The offsets added to both renditions before taking their ratio, which keep the ratio finite for black pixels.
+/// This is the value the Ultra HDR specification suggests.
+const GAIN_MAP_OFFSET: f32 = 1.0 / 64.0;
+
+/// Computes the gain map that reconstructs `hdr` from `sdr` with `UhdrBoostComputer`, and its metadata, i.e. the inverse of
+/// applying a gain map. Both are linear RGB in the same color gamut, where 1 is SDR white.
+///
+/// Following the specification, the gain of each pixel is `log2((hdr + offset_hdr) / (sdr + offset_sdr))`, per channel.
+/// `gain_map_min` and `gain_map_max` are the extremes of the gains, so that the recovery values of the returned gain map,
+/// which are the gains normalized to [0, 1] between them, span the whole range. The gamma is 1.
+/// `hdr_capacity_max` is the largest gain, so that a display with that much headroom reconstructs `hdr` exactly,
+/// and the map is applied partially below it down to a `hdr_capacity_min` of 0.
+///
+/// Negative values, i.e. out-of-gamut colors, are clamped to 0.
+///
+/// Panics if `sdr` and `hdr` differ in extent.
+pub fn compute_gain_map(sdr: &FloatImageContent, hdr: &FloatImageContent) -> (FloatImageContent, GainMapMetadata) {
+    assert_eq!(sdr.extent(), hdr.extent(), "The SDR and HDR images must have the same extent");
+
+    let offset = FloatPixel::new(GAIN_MAP_OFFSET, GAIN_MAP_OFFSET, GAIN_MAP_OFFSET);
+    let log_gains: Vec<[f32; 3]> = sdr.pixels().iter()
+        .zip(hdr.pixels())
+        .map(|(sdr, hdr)| {
+            let ratio = (hdr.clamp(0.0, f32::MAX) + offset) * (sdr.clamp(0.0, f32::MAX) + offset).rcp();
+            ratio.rgb().map(f32::log2)
+        })
+        .collect();
+
+    let mut gain_map_min = [f32::INFINITY; 3];
+    let mut gain_map_max = [f32::NEG_INFINITY; 3];
+    for log_gain in &log_gains {
+        for channel in 0..3 {
+            gain_map_min[channel] = gain_map_min[channel].min(log_gain[channel]);
+            gain_map_max[channel] = gain_map_max[channel].max(log_gain[channel]);
+        }
+    }
+    // Only for empty images.
+    if log_gains.is_empty() {
+        (gain_map_min, gain_map_max) = ([0.0; 3], [0.0; 3]);
+    }
+
+    let (width, height) = sdr.extent();
+    let mut gain_map = FloatImageContent::with_extent(width, height);
+    for (recovery, log_gain) in gain_map.pixels_mut().iter_mut().zip(&log_gains) {
+        *recovery = std::array::from_fn::<f32, 3, _>(|channel| {
+            let range = gain_map_max[channel] - gain_map_min[channel];
+            // A uniform gain is entirely expressed by `gain_map_min`.
+            if range > 0.0 { (log_gain[channel] - gain_map_min[channel]) / range } else { 0.0 }
+        }).into();
+    }
+
+    let metadata = GainMapMetadata {
+        base_rendition_is_hdr: false,
+        gain_map_min,
+        gain_map_max,
+        gamma: [1.0; 3],
+        offset_sdr: [GAIN_MAP_OFFSET; 3],
+        offset_hdr: [GAIN_MAP_OFFSET; 3],
+        hdr_capacity_min: 0.0,
+        hdr_capacity_max: gain_map_max.into_iter().fold(0.0, f32::max),
+        formulation: GainMapFormulation::Adobe,
+    };
+
+    (gain_map, metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_values_at_and_beyond_range_give_finite_boosts() {
+        let metadata = GainMapMetadata {
+            base_rendition_is_hdr: false,
+            // Extended range, attenuating at a recovery of 0.
+            gain_map_min: [-1.0; 3],
+            gain_map_max: [3.0; 3],
+            gamma: [2.0; 3],
+            offset_sdr: [0.0; 3],
+            offset_hdr: [0.0; 3],
+            hdr_capacity_min: 0.0,
+            hdr_capacity_max: 3.0,
+            formulation: GainMapFormulation::Adobe,
+        };
+        let boost_computer = UhdrBoostComputer::new(&metadata, 3.0);
+        let sdr = FloatPixel::new(0.5, 0.5, 0.5);
+
+        let boost = |recovery: f32| boost_computer.compute_boosted(sdr, FloatPixel::new(recovery, recovery, recovery)).g() / 0.5;
+
+        assert_eq!(boost(0.0), 0.5);
+        assert_eq!(boost(1.0), 8.0);
+        // `0.25^(1/2)` is halfway in the log2 domain.
+        assert_eq!(boost(0.25), 2.0);
+
+        for recovery in [-f32::EPSILON, -1e-30, -0.01] {
+            assert_eq!(boost(recovery), boost(0.0), "{}", recovery);
+        }
+        assert_eq!(boost(1.0 + f32::EPSILON * 4.0), boost(1.0));
+    }
+
+    #[test]
+    fn per_channel_offsets_are_applied_per_channel() {
+        // Pretty-printed, as some authoring tools do.
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+            <rdf:Description xmlns:hdrgm="http://ns.adobe.com/hdr-gain-map/1.0/" hdrgm:HDRCapacityMax="1" hdrgm:GainMapMax="1">
+                <hdrgm:OffsetSDR>
+                    <rdf:Seq>
+                        <rdf:li> 0.25 </rdf:li>
+                        <rdf:li>0.5</rdf:li>
+                        <rdf:li>0</rdf:li>
+                    </rdf:Seq>
+                </hdrgm:OffsetSDR>
+                <hdrgm:OffsetHDR><rdf:Seq><rdf:li>0</rdf:li><rdf:li>0.125</rdf:li><rdf:li>1</rdf:li></rdf:Seq></hdrgm:OffsetHDR>
+                <hdrgm:Gamma>2</hdrgm:Gamma>
+            </rdf:Description>
+        </rdf:RDF></x:xmpmeta>"#;
+        let metadata = GainMapMetadata::new_from_xmp_bytes(xmp.as_bytes()).unwrap();
+        assert_eq!(metadata.offset_sdr, [0.25, 0.5, 0.0]);
+        assert_eq!(metadata.offset_hdr, [0.0, 0.125, 1.0]);
+        assert_eq!(metadata.gamma, [2.0; 3]);
+
+        // A boost of 2.
+        let boost_computer = UhdrBoostComputer::new(&metadata, 1.0);
+        let boosted = boost_computer.compute_boosted(FloatPixel::new(0.5, 0.5, 0.5), FloatPixel::one());
+        assert_eq!([boosted.r(), boosted.g(), boosted.b()], [1.5, 1.875, 0.0]);
+    }
+
+    #[test]
+    fn applying_computed_gain_map_reconstructs_hdr() {
+        const WIDTH: usize = 16;
+        const HEIGHT: usize = 8;
+
+        let mut sdr = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        let mut hdr = FloatImageContent::with_extent(WIDTH, HEIGHT);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let value = (x + y * WIDTH) as f32 / (WIDTH * HEIGHT) as f32;
+                let sdr_pixel = FloatPixel::new(value, value * value, 1.0 - value);
+                sdr.set_at(x, y, sdr_pixel);
+                // Highlights boosted up to 8x, shadows slightly darkened, and a black SDR pixel that is lit in HDR.
+                let boost = (value * 3.0).exp2() * if y == 0 { 0.75 } else { 1.0 };
+                hdr.set_at(x, y, sdr_pixel * boost + FloatPixel::new(0.0, if x == 0 && y == 0 { 0.5 } else { 0.0 }, 0.0));
+            }
+        }
+
+        let (gain_map, metadata) = compute_gain_map(&sdr, &hdr);
+        assert!(metadata.hdr_capacity_max > 2.9, "{:?}", metadata);
+        assert!(gain_map.pixels().iter().all(|recovery| recovery.rgb().iter().all(|value| (0.0..=1.0).contains(value))));
+
+        let boost_computer = UhdrBoostComputer::new(&metadata, metadata.hdr_capacity_max);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let reconstructed = boost_computer.compute_boosted(sdr.get_at(x, y), gain_map.get_at(x, y));
+                let expected = hdr.get_at(x, y);
+                for channel in 0..3 {
+                    let (actual, expected) = (reconstructed.rgb()[channel], expected.rgb()[channel]);
+                    assert!((actual - expected).abs() <= expected * 1e-4 + 1e-5, "({}, {}): {:?} != {:?}", x, y, reconstructed, hdr.get_at(x, y));
+                }
+            }
+        }
+
+        // Without any headroom, the SDR rendition is left as is.
+        let boost_computer = UhdrBoostComputer::new(&metadata, 0.0);
+        assert_eq!(boost_computer.compute_boosted(sdr.get_at(5, 5), gain_map.get_at(5, 5)).g(), sdr.get_at(5, 5).g());
+    }
+
+    #[test]
+    fn hdr_base_is_mapped_to_sdr_by_each_formulation() {
+        // The same HDR base pixel mapped to SDR by a quarter, with distinct offsets.
+        let adobe = GainMapMetadata {
+            base_rendition_is_hdr: true,
+            gain_map_min: [0.0; 3],
+            gain_map_max: [2.0; 3],
+            gamma: [1.0; 3],
+            offset_sdr: [0.125; 3],
+            offset_hdr: [0.25; 3],
+            hdr_capacity_min: 0.0,
+            hdr_capacity_max: 2.0,
+            formulation: GainMapFormulation::Adobe,
+        };
+        // The gains, offsets and headrooms are relative to the base rendition.
+        let iso = GainMapMetadata {
+            base_rendition_is_hdr: false,
+            gain_map_min: [-2.0; 3],
+            gain_map_max: [0.0; 3],
+            offset_sdr: [0.125; 3],
+            offset_hdr: [0.25; 3],
+            hdr_capacity_min: 2.0,
+            hdr_capacity_max: 0.0,
+            formulation: GainMapFormulation::Iso21496,
+            ..adobe
+        };
+
+        let base = FloatPixel::one();
+        let boosted = |metadata: &GainMapMetadata, recovery: f32, log2_max_display_boost: f32| {
+            UhdrBoostComputer::new(metadata, log2_max_display_boost)
+                .compute_boosted(base, FloatPixel::new(recovery, recovery, recovery))
+                .g()
+        };
+
+        // Reference vectors for full SDR, halfway and full HDR.
+        for (log2_max_display_boost, adobe_expected, iso_expected) in [(0.0, 0.1875, 0.03125), (1.0, 0.5, 0.3125), (2.0, 1.125, 0.875)] {
+            assert_eq!(boosted(&adobe, 1.0, log2_max_display_boost), adobe_expected, "{}", log2_max_display_boost);
+            assert_eq!(boosted(&iso, 0.0, log2_max_display_boost), iso_expected, "{}", log2_max_display_boost);
+        }
+
+        // Both agree for an SDR base rendition.
+        let sdr_base = GainMapMetadata { base_rendition_is_hdr: false, ..adobe };
+        let iso_sdr_base = GainMapMetadata { formulation: GainMapFormulation::Iso21496, ..sdr_base };
+        for log2_max_display_boost in [-1.0, 0.0, 0.5, 1.5, 2.0, 3.0] {
+            for recovery in [0.0, 0.5, 1.0] {
+                assert_eq!(boosted(&sdr_base, recovery, log2_max_display_boost), boosted(&iso_sdr_base, recovery, log2_max_display_boost));
+            }
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{error, info};
+
+use libuhdr::EncoderRegistry;
+
+use crate::{Args, convert, new_report, resolve_encoder};
+use crate::report::ConversionReport;
+
+/// The extensions of the files `--input-dir` converts, compared ignoring case.
+const INPUT_EXTENSIONS: &[&str] = &["jpg", "jpeg"];
+
+/// The characters that make `--input` a pattern for `--output-dir` rather than a single file.
+const GLOB_CHARACTERS: &[char] = &['*', '?', '['];
+
+/// Converts every JPEG in `--input-dir`, and in its subdirectories with `--recursive`, to `--output-dir`
+/// with the other options of `args`, on `--jobs` threads.
+///
+/// Each failure is logged as it happens and summarized at the end, without aborting the other files.
+/// With `--json`, the reports of all files are printed as an array.
+pub fn convert_dir(args: &Args) -> Result<(), String> {
+    let (Some(input_dir_path), Some(output_dir_path)) = (&args.input_dir_path, &args.output_dir_path) else {
+        return Err("Both `--input-dir` and `--output-dir` must be specified".to_string());
+    };
+
+    let input_dir_path = Path::new(input_dir_path);
+    let input_paths = list_input_files(input_dir_path, args.recursive)
+        .map_err(|e| format!("Failed to read input directory: {}", e))?;
+    convert_files(args, &input_paths, input_dir_path, Path::new(output_dir_path))
+}
+
+/// Converts every file matching the pattern of `--input`, e.g. `photos/*.jpg`, to `--output-dir`,
+/// as `convert_dir` does for the files of a directory. See `glob_input_files` for the patterns supported.
+pub fn convert_glob(args: &Args) -> Result<(), String> {
+    let (Some(pattern), Some(output_dir_path)) = (&args.input_file_path, &args.output_dir_path) else {
+        return Err("Both `--input` and `--output-dir` must be specified".to_string());
+    };
+
+    let input_paths = glob_input_files(pattern)?;
+    let input_dir_path = input_paths[0].parent().unwrap_or(Path::new(""));
+    convert_files(args, &input_paths, input_dir_path, Path::new(output_dir_path))
+}
+
+/// Whether `--input` is a pattern, which is then an error without `--output-dir`, unless a file has that very name.
+pub fn is_glob(input: &str) -> bool {
+    input.contains(GLOB_CHARACTERS) && !Path::new(input).exists()
+}
+
+/// Converts `input_paths`, found in `input_dir_path`, to `output_dir_path` with the other options of `args`.
+fn convert_files(args: &Args, input_paths: &[PathBuf], input_dir_path: &Path, output_dir_path: &Path) -> Result<(), String> {
+    let registry = EncoderRegistry::default();
+    let encoder = resolve_encoder(args, &registry)?;
+    let (format, output_extension) = (encoder.name(), encoder.extensions().first().copied().unwrap_or(encoder.name()));
+
+    let output_paths = output_paths(input_paths, input_dir_path, output_dir_path, &args.output_template, output_extension)?;
+    std::fs::create_dir_all(output_dir_path).map_err(|e| format!("Failed to create output directory: {}", e))?;
+
+    let jobs = args.jobs
+        .map(|jobs| jobs as usize)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |parallelism| parallelism.get()))
+        .min(input_paths.len())
+        .max(1);
+    info!("Converting {} files with {} jobs", input_paths.len(), jobs);
+
+    let next_index = AtomicUsize::new(0);
+    let reports: Vec<Mutex<Option<ConversionReport>>> = input_paths.iter().map(|_| Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                loop {
+                    let index = next_index.fetch_add(1, Ordering::Relaxed);
+                    let (Some(input_path), Some(output_path)) = (input_paths.get(index), output_paths.get(index)) else {
+                        break;
+                    };
+                    let report = convert_file(args, format, input_path, output_path);
+                    *reports[index].lock().unwrap() = Some(report);
+                }
+            });
+        }
+    });
+    let reports: Vec<ConversionReport> = reports.into_iter()
+        .map(|report| report.into_inner().unwrap().expect("Every file is converted by some job"))
+        .collect();
+
+    #[cfg(feature = "json")]
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&reports).expect("Failed to serialize the conversion reports"));
+    }
+
+    let failures: Vec<&ConversionReport> = reports.iter().filter(|report| report.error.is_some()).collect();
+    let skipped = reports.iter().filter(|report| report.skipped).count();
+    info!("Converted {} of {} files, skipped {}", reports.len() - failures.len() - skipped, reports.len(), skipped);
+    for failure in &failures {
+        error!("{}: {}", failure.input.as_deref().unwrap_or_default(), failure.error.as_deref().unwrap_or_default());
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} of {} files failed to convert", failures.len(), reports.len()))
+    }
+}
+
+/// The files with one of the `INPUT_EXTENSIONS` directly in `dir_path`, or in any of its subdirectories if `recursive`,
+/// sorted by path. Symbolic links to directories aren't followed, so that a link to an ancestor can't loop.
+fn list_input_files(dir_path: &Path, recursive: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if recursive && entry.file_type()?.is_dir() {
+            paths.extend(list_input_files(&path, recursive)?);
+            continue;
+        }
+        let is_input = path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| INPUT_EXTENSIONS.iter().any(|input_extension| extension.eq_ignore_ascii_case(input_extension)));
+        if is_input && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// The files matching `pattern`, sorted by path, failing if there are none.
+///
+/// Only the file name may contain wildcards: `*` for any characters, `?` for any one character,
+/// and `[…]` for one of the characters listed, with ranges like `a-z`, or any other if it starts with `!`.
+/// As in shells, wildcards don't match a leading `.` of hidden files.
+fn glob_input_files(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let pattern_path = Path::new(pattern);
+    let (Some(dir_path), Some(file_pattern)) = (pattern_path.parent(), pattern_path.file_name().and_then(|name| name.to_str())) else {
+        return Err(format!("The input pattern {:?} doesn't end in a file name", pattern));
+    };
+    if dir_path.to_string_lossy().contains(GLOB_CHARACTERS) {
+        return Err(format!("The input pattern {:?} has wildcards in a directory, which is only supported in the file name", pattern));
+    }
+
+    // `*.jpg` has an empty parent.
+    let read_dir_path = if dir_path.as_os_str().is_empty() { Path::new(".") } else { dir_path };
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(read_dir_path).map_err(|e| format!("Failed to read the directory of {:?}: {}", pattern, e))? {
+        let entry = entry.map_err(|e| format!("Failed to read the directory of {:?}: {}", pattern, e))?;
+        let is_match = entry.file_name().to_str().is_some_and(|name| glob_matches(file_pattern, name));
+        if is_match && entry.path().is_file() {
+            paths.push(dir_path.join(entry.file_name()));
+        }
+    }
+    if paths.is_empty() {
+        return Err(format!("No files match {:?}", pattern));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Whether the file name `name` matches `pattern`; see `glob_input_files`.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+
+    // Backtracks to the last `*`, making it match one more character, whenever the rest doesn't match.
+    let (mut pattern_index, mut name_index) = (0, 0);
+    let mut last_star = None;
+    while name_index < name.len() {
+        if pattern.get(pattern_index) == Some(&'*') {
+            last_star = Some((pattern_index + 1, name_index));
+            pattern_index += 1;
+        } else if let Some(length) = match_one(&pattern[pattern_index..], name[name_index]) {
+            pattern_index += length;
+            name_index += 1;
+        } else if let Some((star_pattern_index, star_name_index)) = last_star {
+            last_star = Some((star_pattern_index, star_name_index + 1));
+            (pattern_index, name_index) = (star_pattern_index, star_name_index + 1);
+        } else {
+            return false;
+        }
+    }
+    pattern[pattern_index..].iter().all(|&c| c == '*')
+}
+
+/// The length of the start of `pattern` if it matches `c`, other than `*`.
+/// A `[` without a closing `]` is literal, and a `]` right after the `[` or `[!` is one of the characters listed.
+fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    match pattern.first()? {
+        '?' => Some(1),
+        '[' => {
+            let negated = pattern.get(1) == Some(&'!');
+            let start = 1 + negated as usize;
+            let Some(end) = pattern.iter().skip(start + 1).position(|&c| c == ']').map(|offset| start + 1 + offset) else {
+                return (c == '[').then_some(1);
+            };
+            let class = &pattern[start..end];
+            let mut matched = false;
+            let mut index = 0;
+            while index < class.len() {
+                if class.get(index + 1) == Some(&'-') && let Some(&last) = class.get(index + 2) {
+                    matched |= (class[index]..=last).contains(&c);
+                    index += 3;
+                } else {
+                    matched |= class[index] == c;
+                    index += 1;
+                }
+            }
+            (matched != negated).then_some(end + 1)
+        },
+        &literal => (literal == c).then_some(1),
+    }
+}
+
+/// The output path of each of `input_paths` in `output_dir_path`, in the same subdirectory as the input in `input_dir_path`,
+/// with the file name of `template`, e.g. `photo.v2.avif` for `photo.v2.jpg` and `{stem}.{ext}`; see `render_template`.
+///
+/// Fails if two inputs would be converted to the same output, e.g. `a.jpg` and `a.jpeg`, rather than have one overwrite
+/// the other.
+fn output_paths(
+    input_paths: &[PathBuf],
+    input_dir_path: &Path,
+    output_dir_path: &Path,
+    template: &str,
+    output_extension: &str,
+) -> Result<Vec<PathBuf>, String> {
+    let mut inputs_by_output: HashMap<PathBuf, &Path> = HashMap::new();
+    let mut output_paths = Vec::with_capacity(input_paths.len());
+    for input_path in input_paths {
+        let parent_path = input_path.parent().unwrap_or(input_dir_path);
+        let subdir_path = parent_path.strip_prefix(input_dir_path).unwrap_or(Path::new(""));
+        // E.g. `.` has no name of its own.
+        let parent = parent_path.file_name()
+            .map(PathBuf::from)
+            .or_else(|| parent_path.canonicalize().ok().and_then(|path| path.file_name().map(PathBuf::from)))
+            .unwrap_or_default();
+        let stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = render_template(template, &stem, output_extension, &parent.to_string_lossy())?;
+        let output_path = output_dir_path.join(subdir_path).join(file_name);
+        if let Some(other_input_path) = inputs_by_output.insert(output_path.clone(), input_path) {
+            return Err(format!(
+                "Both {} and {} would be converted to {}",
+                other_input_path.display(), input_path.display(), output_path.display(),
+            ));
+        }
+        output_paths.push(output_path);
+    }
+    Ok(output_paths)
+}
+
+/// Replaces `{stem}`, `{ext}` and `{parent}` in `template` with the respective values.
+///
+/// Fails for any other `{…}`, an unclosed `{`, and for names that are empty, absolute, or contain `..`,
+/// so that every output stays in `--output-dir`.
+fn render_template(template: &str, stem: &str, extension: &str, parent: &str) -> Result<PathBuf, String> {
+    let mut file_name = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        file_name.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            return Err(format!("Unclosed `{{` in the output template {:?}", template));
+        };
+        file_name.push_str(match &rest[start + 1..start + length] {
+            "stem" => stem,
+            "ext" => extension,
+            "parent" => parent,
+            token => return Err(format!("Unknown token `{{{}}}` in the output template {:?}", token, template)),
+        });
+        rest = &rest[start + length + 1..];
+    }
+    file_name.push_str(rest);
+
+    let file_name = PathBuf::from(file_name);
+    if file_name.as_os_str().is_empty() || !file_name.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(format!("The output template {:?} gives {:?}, which isn't a relative path within the output directory", template, file_name));
+    }
+    Ok(file_name)
+}
+
+/// Converts `input_path` to `output_path` in the format `format` with the other options of `args`,
+/// returning the report, whose `error` is set if it failed.
+fn convert_file(args: &Args, format: &str, input_path: &Path, output_path: &Path) -> ConversionReport {
+    let args = Args {
+        input_file_path: Some(input_path.to_string_lossy().into_owned()),
+        output_file_path: Some(output_path.to_string_lossy().into_owned()),
+        format: Some(format.to_string()),
+        input_dir_path: None,
+        output_dir_path: None,
+        ..args.clone()
+    };
+    let mut report = new_report(&args);
+
+    if args.no_clobber && output_path.exists() {
+        info!("Skipping {}, {} already exists", input_path.display(), output_path.display());
+        report.skipped = true;
+        return report;
+    }
+
+    let result = if same_file(input_path, output_path) {
+        Err("The output would overwrite the input".to_string())
+    } else if let Some(parent) = output_path.parent() && let Err(e) = std::fs::create_dir_all(parent) {
+        Err(format!("Failed to create output directory: {}", e))
+    } else {
+        convert(&args, &mut report)
+    };
+    match result {
+        Ok(()) => info!("Converted {} to {}", input_path.display(), output_path.display()),
+        Err(e) => {
+            error!("Failed to convert {}: {}", input_path.display(), e);
+            report.error = Some(e);
+        },
+    }
+    report
+}
+
+/// Whether both paths exist and are the same file, e.g. for `uhdr-jpeg` output to the input directory.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::Parser;
+
+    #[test]
+    fn directory_is_converted_without_aborting_on_failures() {
+        let directory = std::env::temp_dir().join(format!("uhdr2avif-batch-{}", std::process::id()));
+        let (input_dir, output_dir) = (directory.join("in"), directory.join("out"));
+        std::fs::create_dir_all(&input_dir).unwrap();
+        std::fs::write(input_dir.join("a.jpg"), crate::tests::uhdr_jpeg_bytes()).unwrap();
+        std::fs::write(input_dir.join("b.JPEG"), crate::tests::uhdr_jpeg_bytes()).unwrap();
+        std::fs::write(input_dir.join("broken.jpg"), b"not a JPEG").unwrap();
+        std::fs::write(input_dir.join("notes.txt"), b"ignored").unwrap();
+
+        let args = Args::parse_from([
+            "uhdr2avif",
+            "--input-dir", input_dir.to_str().unwrap(),
+            "--output-dir", output_dir.to_str().unwrap(),
+            "--jobs", "2",
+            "--speed", "10",
+        ]);
+        let result = convert_dir(&args);
+
+        let mut outputs: Vec<_> = std::fs::read_dir(&output_dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        outputs.sort();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(result, Err("1 of 3 files failed to convert".to_string()));
+        assert_eq!(outputs, ["a.avif", "b.avif"]);
+
+        assert!(Args::try_parse_from(["uhdr2avif", "--input-dir", "in"]).is_err());
+        assert!(Args::try_parse_from(["uhdr2avif", "--input-dir", "in", "--output-dir", "out", "-i", "input.jpg"]).is_err());
+    }
+
+    #[test]
+    fn outputs_keep_dots_in_the_stem_and_must_not_collide() {
+        let output_dir = Path::new("out");
+        let input_paths = [PathBuf::from("in/photo.v1.jpg"), PathBuf::from("in/photo.v2.jpg")];
+        assert_eq!(
+            output_paths(&input_paths, Path::new("in"), output_dir, "{stem}.{ext}", "avif"),
+            Ok(vec![output_dir.join("photo.v1.avif"), output_dir.join("photo.v2.avif")]),
+        );
+
+        let input_paths = [PathBuf::from("in/a.jpeg"), PathBuf::from("in/a.jpg")];
+        let error = output_paths(&input_paths, Path::new("in"), output_dir, "{stem}.{ext}", "avif").unwrap_err();
+        assert!(error.contains("a.jpeg") && error.contains("a.jpg"), "{}", error);
+    }
+
+    #[test]
+    fn output_template_names_the_outputs() {
+        let input_paths = [PathBuf::from("in/a.jpg"), PathBuf::from("in/2024/b.jpg")];
+        let output_paths = |template| output_paths(&input_paths, Path::new("in"), Path::new("out"), template, "avif");
+        let expected = |paths: [&str; 2]| Ok(paths.map(PathBuf::from).to_vec());
+
+        assert_eq!(output_paths("{stem}.{ext}"), expected(["out/a.avif", "out/2024/b.avif"]));
+        assert_eq!(output_paths("hdr_{stem}.{ext}"), expected(["out/hdr_a.avif", "out/2024/hdr_b.avif"]));
+        assert_eq!(output_paths("{parent}-{stem}.{ext}"), expected(["out/in-a.avif", "out/2024/2024-b.avif"]));
+        assert_eq!(output_paths("{ext}/{stem}.{ext}"), expected(["out/avif/a.avif", "out/2024/avif/b.avif"]));
+
+        for template in ["{stem}.{format}", "{stem.{ext}", "../{stem}.{ext}", "/tmp/{stem}", ""] {
+            assert!(output_paths(template).is_err(), "{}", template);
+        }
+        // Only the names differ by input, so outputs without `{stem}` collide.
+        assert!(output_paths("{parent}.{ext}").is_ok());
+        assert!(output_paths("out.{ext}").is_ok());
+        assert!(super::output_paths(&[PathBuf::from("in/a.jpg"), PathBuf::from("in/b.jpg")], Path::new("in"), Path::new("out"), "out.{ext}", "avif").is_err());
+    }
+
+    #[test]
+    fn recursive_conversion_mirrors_the_input_tree_without_clobbering() {
+        let directory = std::env::temp_dir().join(format!("uhdr2avif-recursive-{}", std::process::id()));
+        let (input_dir, output_dir) = (directory.join("in"), directory.join("out"));
+        std::fs::create_dir_all(input_dir.join("2024/june")).unwrap();
+        std::fs::write(input_dir.join("a.jpg"), crate::tests::uhdr_jpeg_bytes()).unwrap();
+        std::fs::write(input_dir.join("2024/b.jpg"), crate::tests::uhdr_jpeg_bytes()).unwrap();
+        std::fs::write(input_dir.join("2024/june/c.jpg"), crate::tests::uhdr_jpeg_bytes()).unwrap();
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("a.avif"), b"kept").unwrap();
+
+        let args = Args::parse_from([
+            "uhdr2avif",
+            "--input-dir", input_dir.to_str().unwrap(),
+            "--output-dir", output_dir.to_str().unwrap(),
+            "--recursive",
+            "--no-clobber",
+            "--speed", "10",
+        ]);
+        let result = convert_dir(&args);
+
+        let kept = std::fs::read(output_dir.join("a.avif")).unwrap();
+        let converted = ["2024/b.avif", "2024/june/c.avif"].map(|path| output_dir.join(path).is_file());
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(kept, b"kept");
+        assert_eq!(converted, [true, true]);
+
+        assert!(Args::try_parse_from(["uhdr2avif", "-i", "input.jpg", "-o", "output.avif", "--recursive"]).is_err());
+    }
+
+    #[test]
+    fn glob_input_converts_every_match() {
+        let directory = std::env::temp_dir().join(format!("uhdr2avif-glob-{}", std::process::id()));
+        let (input_dir, output_dir) = (directory.join("in"), directory.join("out"));
+        std::fs::create_dir_all(&input_dir).unwrap();
+        for name in ["a.jpg", "b.jpg", "c1.jpg", "c.jpeg", ".hidden.jpg"] {
+            std::fs::write(input_dir.join(name), crate::tests::uhdr_jpeg_bytes()).unwrap();
+        }
+
+        let convert_pattern = |pattern: &str| {
+            let args = Args::parse_from([
+                "uhdr2avif",
+                "-i", input_dir.join(pattern).to_str().unwrap(),
+                "--output-dir", output_dir.to_str().unwrap(),
+                "--speed", "10",
+            ]);
+            convert_glob(&args)
+        };
+        let result = convert_pattern("[!c]*.jpg");
+        let no_match_result = convert_pattern("*.png");
+
+        let mut outputs: Vec<_> = std::fs::read_dir(&output_dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        outputs.sort();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(outputs, ["a.avif", "b.avif"]);
+        assert!(no_match_result.unwrap_err().starts_with("No files match"));
+
+        assert!(Args::try_parse_from(["uhdr2avif", "-i", "*.jpg", "--output-dir", "out", "-o", "output.avif"]).is_err());
+    }
+
+    #[test]
+    fn glob_patterns_match_like_shells() {
+        assert!(glob_matches("*.jpg", "photo.jpg"));
+        assert!(!glob_matches("*.jpg", ".jpg.jpg"));
+        assert!(glob_matches(".*", ".hidden"));
+        assert!(glob_matches("IMG_????.jpg", "IMG_0042.jpg"));
+        assert!(!glob_matches("IMG_????.jpg", "IMG_042.jpg"));
+        assert!(glob_matches("*a*b*", "xxaxxbxx"));
+        assert!(!glob_matches("*a*b", "xxaxxbxx"));
+        assert!(glob_matches("[a-c]?.jpg", "b1.jpg"));
+        assert!(!glob_matches("[!a-c]?.jpg", "b1.jpg"));
+        assert!(glob_matches("[]x].jpg", "].jpg"));
+        assert!(glob_matches("[.jpg", "[.jpg"));
+    }
+}
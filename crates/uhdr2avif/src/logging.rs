@@ -2,9 +2,16 @@
 #[derive(Default)]
 pub struct LoggingConfig {
     output_to_file: bool,
+    quiet: bool,
 }
 
 impl LoggingConfig {
+    /// Only log warnings and errors.
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
     pub fn output_to_file(mut self) -> Self {
         self.output_to_file = true;
         self
@@ -15,7 +22,8 @@ impl LoggingConfig {
 
         use fern::colors::{Color, ColoredLevelConfig};
 
-        let base_config = fern::Dispatch::new();
+        let base_config = fern::Dispatch::new()
+          .level(if self.quiet { log::LevelFilter::Warn } else { log::LevelFilter::Trace });
 
         let colors_line = ColoredLevelConfig::new()
           .error(Color::Red)
@@ -1,13 +1,21 @@
 
+mod batch;
 mod logging;
+mod report;
 
 use std::fs::File;
 use std::io::{Read, Write};
 
-use log::trace;
-use clap::Parser;
+use log::{info, trace};
+use clap::{Parser, ValueEnum};
+use clap::builder::{PossibleValue, PossibleValuesParser};
 
-use libuhdr::UhdrConverter;
+use libuhdr::{
+    AvifBitDepth, AvifChroma, AvifColorPrimaries, AvifColorSpace, AvifEncodeOptions, AvifOversize, AvifRange, AvifTransfer, CropRect, CubeLut, DecodeOptions, EncodeOptions, EncoderRegistry, GainMapFilter, GainMapMetadata, HdrEncoder,
+    JpegDecodeOptions, LuminanceStats, Rounding, ToneMapOperator, UhdrConverter, UhdrInfo, PngTransfer, TiffTransfer,
+};
+
+use report::{ConversionReport, ConversionSettings};
 
 /// Luminance level in nits for sRGB (1, 1, 1) by Windows convention.
 const WINDOWS_SDR_WHITE_LEVEL: f32 = 80.0f32;
@@ -17,40 +25,655 @@ const DEFAULT_MAX_DISPLAY_BOOST: f32 = ASSUMED_DISPLAY_MAX_BRIGHTNESS / WINDOWS_
 
 const DEFAULT_TARGET_SDR_WHITE_LEVEL: f32 = WINDOWS_SDR_WHITE_LEVEL;
 
-#[derive(Parser, Debug)]
+/// The seed of `--rounding stochastic` with `--deterministic`.
+const DETERMINISTIC_SEED: u64 = 0;
+
+/// The format used if neither `--format` is specified nor the output file extension is that of a registered format.
+const DEFAULT_FORMAT: &str = "avif";
+
+/// The formats that are only available when building with the feature of the same name.
+const OPTIONAL_FORMATS: &[&str] = &["exr", "hdr", "heif", "png", "webp"];
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum TiffTransferArg {
+    /// Linear samples.
+    Linear,
+    /// Samples encoded with a pure 2.2 gamma.
+    Gamma22,
+}
+
+impl From<TiffTransferArg> for TiffTransfer {
+    fn from(value: TiffTransferArg) -> Self {
+        match value {
+            TiffTransferArg::Linear => TiffTransfer::Linear,
+            TiffTransferArg::Gamma22 => TiffTransfer::Gamma22,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum PngTransferArg {
+    /// PQ-encoded absolute luminance, as in HDR10.
+    Pq,
+    /// Linear samples.
+    Linear,
+}
+
+impl From<PngTransferArg> for PngTransfer {
+    fn from(value: PngTransferArg) -> Self {
+        match value {
+            PngTransferArg::Pq => PngTransfer::Pq,
+            PngTransferArg::Linear => PngTransfer::Linear,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum GainMapFilterArg {
+    /// Bilinear filtering.
+    Bilinear,
+    /// Area averaging, avoiding aliasing when the gain map is larger than the primary image.
+    Area,
+}
+
+impl From<GainMapFilterArg> for GainMapFilter {
+    fn from(value: GainMapFilterArg) -> Self {
+        match value {
+            GainMapFilterArg::Bilinear => GainMapFilter::Bilinear,
+            GainMapFilterArg::Area => GainMapFilter::Area,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum RoundingArg {
+    /// To the nearest code value.
+    Round,
+    /// Down to the code value below.
+    Floor,
+    /// Up or down at random in proportion to the fractional part, reducing banding without a dither pattern.
+    Stochastic,
+}
+
+impl RoundingArg {
+    /// - `deterministic`: Whether to seed stochastic rounding with `DETERMINISTIC_SEED` instead of the current time.
+    fn to_rounding(self, deterministic: bool) -> Rounding {
+        match self {
+            RoundingArg::Round => Rounding::Round,
+            RoundingArg::Floor => Rounding::Floor,
+            RoundingArg::Stochastic => Rounding::Stochastic {
+                seed: if deterministic {
+                    DETERMINISTIC_SEED
+                } else {
+                    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |duration| duration.as_nanos() as u64)
+                },
+            },
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ToneMapArg {
+    /// Extended Reinhard, which leaves images without headroom as is.
+    Reinhard,
+    /// John Hable's filmic curve.
+    Hable,
+    /// A fit of the ACES filmic curve.
+    Aces,
+}
+
+impl From<ToneMapArg> for ToneMapOperator {
+    fn from(value: ToneMapArg) -> Self {
+        match value {
+            ToneMapArg::Reinhard => ToneMapOperator::Reinhard,
+            ToneMapArg::Hable => ToneMapOperator::Hable,
+            ToneMapArg::Aces => ToneMapOperator::Aces,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum BitDepthArg {
+    /// HDR10 proper.
+    #[value(name = "10")]
+    Ten,
+    /// Less banding in smooth gradients, but fewer decoders support it.
+    #[value(name = "12")]
+    Twelve,
+    /// 12 bits for bright content with smooth gradients, otherwise 10 bits.
+    Auto,
+}
+
+impl From<BitDepthArg> for AvifBitDepth {
+    fn from(value: BitDepthArg) -> Self {
+        match value {
+            BitDepthArg::Ten => AvifBitDepth::Ten,
+            BitDepthArg::Twelve => AvifBitDepth::Twelve,
+            BitDepthArg::Auto => AvifBitDepth::Auto,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum TransferArg {
+    /// BT.2100 PQ, i.e. HDR10.
+    Pq,
+    /// BT.2100 HLG.
+    Hlg,
+}
+
+impl From<TransferArg> for AvifTransfer {
+    fn from(value: TransferArg) -> Self {
+        match value {
+            TransferArg::Pq => AvifTransfer::Pq,
+            TransferArg::Hlg => AvifTransfer::Hlg,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum PrimariesArg {
+    /// BT.2020, i.e. HDR10.
+    Bt2020,
+    /// Display P3.
+    #[value(name = "display-p3")]
+    DisplayP3,
+    /// DCI-P3 with the DCI white point.
+    #[value(name = "dci-p3")]
+    DciP3,
+    /// BT.709, i.e. the sRGB primaries.
+    Bt709,
+    /// Adobe RGB (1998), which has no H.273 code point, so it is signalled by an ICC profile.
+    #[value(name = "adobe-rgb")]
+    AdobeRgb,
+    /// ProPhoto RGB, which has no H.273 code point, so it is signalled by an ICC profile.
+    #[value(name = "prophoto-rgb")]
+    ProphotoRgb,
+}
+
+impl From<PrimariesArg> for AvifColorPrimaries {
+    fn from(value: PrimariesArg) -> Self {
+        match value {
+            PrimariesArg::Bt2020 => AvifColorPrimaries::Bt2020,
+            PrimariesArg::DisplayP3 => AvifColorPrimaries::DisplayP3,
+            PrimariesArg::DciP3 => AvifColorPrimaries::DciP3,
+            PrimariesArg::Bt709 => AvifColorPrimaries::Bt709,
+            PrimariesArg::AdobeRgb => AvifColorPrimaries::from_color_gamut(&libuhdr::ColorGamut::adobe_rgb()),
+            PrimariesArg::ProphotoRgb => AvifColorPrimaries::from_color_gamut(&libuhdr::ColorGamut::prophoto_rgb()),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorSpaceArg {
+    /// Y'C'bC'r, i.e. HDR10.
+    Ycbcr,
+    /// BT.2100 ICtCp.
+    Ictcp,
+}
+
+impl From<ColorSpaceArg> for AvifColorSpace {
+    fn from(value: ColorSpaceArg) -> Self {
+        match value {
+            ColorSpaceArg::Ycbcr => AvifColorSpace::Ycbcr,
+            ColorSpaceArg::Ictcp => AvifColorSpace::Ictcp,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeArg {
+    /// All code values.
+    Full,
+    /// The limited range of video, e.g. 64 to 940 for 10-bit luma.
+    Limited,
+}
+
+impl From<RangeArg> for AvifRange {
+    fn from(value: RangeArg) -> Self {
+        match value {
+            RangeArg::Full => AvifRange::Full,
+            RangeArg::Limited => AvifRange::Limited,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ChromaArg {
+    /// Chroma at full resolution.
+    #[value(name = "444")]
+    Chroma444,
+    /// Chroma at half the width and height.
+    #[value(name = "420")]
+    Chroma420,
+}
+
+impl From<ChromaArg> for AvifChroma {
+    fn from(value: ChromaArg) -> Self {
+        match value {
+            ChromaArg::Chroma444 => AvifChroma::Chroma444,
+            ChromaArg::Chroma420 => AvifChroma::Chroma420,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OversizeArg {
+    /// Fail with an error.
+    Fail,
+    /// Downscale to fit with a warning, preserving the aspect ratio.
+    Downscale,
+}
+
+impl From<OversizeArg> for AvifOversize {
+    fn from(value: OversizeArg) -> Self {
+        match value {
+            OversizeArg::Fail => AvifOversize::Fail,
+            OversizeArg::Downscale => AvifOversize::Downscale,
+        }
+    }
+}
+
+/// `--max-display-boost`: A boost, or the one the gain map was authored for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MaxDisplayBoostArg {
+    Value(f32),
+    /// `auto`: `UhdrConverter::authored_max_display_boost`, falling back to `DEFAULT_MAX_DISPLAY_BOOST` without a gain map.
+    Auto,
+}
+
+impl MaxDisplayBoostArg {
+    /// The boost to decode with, before the gain map is known.
+    fn initial_value(self) -> f32 {
+        match self {
+            Self::Value(value) => value,
+            Self::Auto => DEFAULT_MAX_DISPLAY_BOOST,
+        }
+    }
+}
+
+impl std::fmt::Display for MaxDisplayBoostArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Value(value) => write!(f, "{}", value),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 struct Args {
     /// The input file to process.
     /// If not specified, the program will read from stdin if `--stdin` is enabled.
+    /// With `--output-dir`, a pattern like `"photos/*.jpg"` instead, quoted for the shell not to expand it,
+    /// to convert every file it matches; wildcards are supported in the file name only.
     #[arg(short='i', long="input")]
     input_file_path: Option<String>,
     /// Read input from stdin if true.
     #[arg(long="stdin", default_value_t = false)]
     stdin: bool,
     /// The output file to write to.
-    #[arg(short='o', long="output")]
+    #[arg(short='o', long="output", alias="out")]
     output_file_path: Option<String>,
     /// Write output to stdout if true.
     /// If not specified, the program will write to stdout if `--stdout` is provided.
     #[arg(long="stdout", default_value_t = false)]
     stdout: bool,
+    /// Convert every `.jpg` and `.jpeg` file in this directory, or in its subdirectories too with `--recursive`,
+    /// to a file named by `--output-template` in `--output-dir`, instead of a single input.
+    /// Failures are reported at the end without aborting the other files.
+    #[arg(
+        long="input-dir",
+        requires = "output_dir_path",
+        conflicts_with_all = ["input_file_path", "stdin", "output_file_path", "stdout", "emit_sdr_also_file_path", "diff_image_file_path", "summary_only", "mux"],
+    )]
+    input_dir_path: Option<String>,
+    /// The directory to write the outputs of `--input-dir`, or of the pattern of `--input`, to, which is created if missing.
+    /// Nothing is converted if two inputs would have the same output, e.g. `a.jpg` and `a.jpeg`.
+    #[arg(
+        long="output-dir",
+        conflicts_with_all = ["stdin", "output_file_path", "stdout", "emit_sdr_also_file_path", "diff_image_file_path", "summary_only", "mux"],
+    )]
+    output_dir_path: Option<String>,
+    /// The file name of each output in `--output-dir`, where `{stem}` is the file name of the input without its extension,
+    /// `{ext}` the extension of the output format, and `{parent}` the name of the directory of the input.
+    /// May contain `/` to write into subdirectories of `--output-dir`, but not `..`.
+    #[arg(long="output-template", default_value = "{stem}.{ext}", requires = "output_dir_path", conflicts_with_all = ["output_file_path", "stdout"])]
+    output_template: String,
+    /// Also convert the JPEGs in the subdirectories of `--input-dir`, into the same subdirectories of `--output-dir`.
+    #[arg(long="recursive", default_value_t = false, requires = "input_dir_path", conflicts_with = "input_file_path")]
+    recursive: bool,
+    /// Skip the inputs of `--input-dir` or of the pattern of `--input` whose output already exists instead of overwriting it.
+    #[arg(long="no-clobber", default_value_t = false, requires = "output_dir_path", conflicts_with_all = ["output_file_path", "stdout"])]
+    no_clobber: bool,
+    /// How many files of `--input-dir` or of the pattern of `--input` to convert concurrently. Defaults to the available parallelism.
+    #[arg(long="jobs", requires = "output_dir_path", conflicts_with_all = ["output_file_path", "stdout"], value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
     /// The maximum available boost supported by a display, at a given point in time.
     /// This is a constant value that should be set based on the display's capabilities.
     /// This value is used to compute the boosted Ultra HDR "HDR rendition" value.
-    #[arg(long="max-display-boost", default_value_t = DEFAULT_MAX_DISPLAY_BOOST)]
-    max_display_boost: f32,
+    /// `auto` uses `2^HDRCapacityMax` of the gain map instead, at which it applies fully, for the whole HDR rendition
+    /// the author intended. The chosen boost is logged.
+    #[arg(long="max-display-boost", default_value_t = MaxDisplayBoostArg::Value(DEFAULT_MAX_DISPLAY_BOOST), value_parser = parse_max_display_boost)]
+    max_display_boost: MaxDisplayBoostArg,
     /// The target SDR white level in nits to scale (1, 1, 1) to.
     /// The boosted Ultra HDR "HDR rendition" value is scaled by this value.
     #[arg(long="target-sdr-white-level", default_value_t = DEFAULT_TARGET_SDR_WHITE_LEVEL)]
     target_sdr_white_level: f32,
+    /// Suggest the target SDR white level from the luminance histogram of the SDR rendition and use it,
+    /// instead of `--target-sdr-white-level`: Dark images get a higher one than bright ones. The chosen level is logged.
+    #[arg(long="auto-white", default_value_t = false, conflicts_with_all = ["target_sdr_white_level", "sdr_passthrough"])]
+    auto_white: bool,
+    /// Exposure compensation in stops, multiplying the linear "HDR rendition" value by `2^ev` uniformly,
+    /// unlike `--max-display-boost` which only affects how much of the gain map is applied.
+    #[arg(long="ev", default_value_t = 0.0, allow_negative_numbers = true)]
+    ev: f32,
+    /// The output format.
+    /// If not specified, it is inferred from the output file extension, defaulting to `avif`.
+    #[arg(long="format", value_parser = format_parser())]
+    format: Option<String>,
+    /// How the 16-bit samples of a TIFF output are encoded.
+    /// The nominal peak of the HDR rendition, `--target-sdr-white-level` times `--max-display-boost`, maps to the maximum sample value.
+    #[arg(long="tiff-transfer", value_enum, default_value_t = TiffTransferArg::Linear)]
+    tiff_transfer: TiffTransferArg,
+    /// How the 16-bit samples of a PNG output are encoded. With `linear`, the nominal peak of the HDR rendition
+    /// maps to the maximum sample value as for `--tiff-transfer`.
+    #[arg(long="png-transfer", value_enum, default_value_t = PngTransferArg::Pq)]
+    png_transfer: PngTransferArg,
+    /// Converts only the region `x,y,w,h` in pixels of the primary image, which also becomes the output dimensions.
+    #[arg(long="crop", value_parser = parse_crop)]
+    crop: Option<CropRect>,
+    /// How the gain map is resampled onto the primary image.
+    /// If not specified, `area` is used if the gain map is larger than the primary image, and `bilinear` otherwise.
+    #[arg(long="gain-map-filter", value_enum)]
+    gain_map_filter: Option<GainMapFilterArg>,
+    /// The MPF image to use as the primary image, for files with incorrect MPF information.
+    /// If only `--gainmap-index` is specified, this defaults to the first image other than the gain map.
+    #[arg(long="primary-index")]
+    primary_index: Option<usize>,
+    /// The MPF image to use as the gain map, for files with incorrect MPF information.
+    /// If only `--primary-index` is specified, this defaults to the first image other than the primary image.
+    #[arg(long="gainmap-index")]
+    gain_map_index: Option<usize>,
+    /// Re-encode the primary image, i.e. the authored SDR rendition, as an 8-bit sRGB AVIF, without applying the gain map.
+    /// Options that only affect the HDR rendition, such as `--max-display-boost` and `--ev`, are ignored.
+    #[arg(long="sdr-passthrough", default_value_t = false)]
+    sdr_passthrough: bool,
+    /// Also write the primary image, i.e. the authored SDR rendition, as an 8-bit sRGB AVIF to this file alongside the HDR AVIF,
+    /// reusing the decoded input, as `--sdr-passthrough` would.
+    #[arg(long="emit-sdr-also", conflicts_with_all = ["sdr_passthrough", "summary_only"])]
+    emit_sdr_also_file_path: Option<String>,
+    /// Tone map the HDR rendition with this operator into an 8-bit sRGB AVIF instead of writing an HDR AVIF,
+    /// mapping the brightest pixel of the HDR rendition, as per `--max-display-boost` and `--ev`, to white.
+    /// The options of the HDR AVIF's encoding, such as `--quality`, `--transfer` and `--chroma`, don't apply:
+    /// The SDR AVIF is always encoded like that of `--sdr-passthrough`.
+    #[arg(
+        long="tone-map",
+        value_enum,
+        conflicts_with_all = [
+            "sdr_passthrough", "emit_sdr_also_file_path", "summary_only", "diff_image_file_path", "auto_white", "qp", "quality",
+            "speed", "lut_file_path", "rounding", "transfer", "bit_depth", "derive_peak", "strip_metadata", "range", "chroma",
+            "primaries", "colorspace", "band_rows",
+        ],
+    )]
+    tone_map: Option<ToneMapArg>,
+    /// Encode the HDR AVIF with this AV1 base quantizer, from 0 to 255 where lower is higher quality,
+    /// instead of `--quality`, whose default of 100 corresponds to a quantizer of 0. Drives rav1e directly.
+    #[arg(long="qp", conflicts_with = "sdr_passthrough")]
+    qp: Option<u8>,
+    /// The quality of the HDR AVIF, from 0 to 100, as `ravif` maps it to an AV1 base quantizer.
+    #[arg(long="quality", default_value_t = 100.0, conflicts_with_all = ["sdr_passthrough", "qp"])]
+    quality: f32,
+    /// The rav1e speed preset of the HDR AVIF encode, from 0, the slowest, which compresses best, to 10, the fastest.
+    #[arg(long="speed", default_value_t = 4, conflicts_with = "sdr_passthrough")]
+    speed: u8,
+    /// The range of the Y'CbCr code values of the HDR AVIF, for players and TVs that expect the limited range of video.
+    #[arg(long="range", value_enum, default_value_t = RangeArg::Full, conflicts_with = "sdr_passthrough")]
+    range: RangeArg,
+    /// The chroma subsampling of the HDR AVIF. `420` makes the file smaller, but fringes saturated HDR edges.
+    #[arg(long="chroma", value_enum, default_value_t = ChromaArg::Chroma444, conflicts_with = "sdr_passthrough")]
+    chroma: ChromaArg,
+    /// Apply a 3D LUT from an Iridas `.cube` file to the HDR AVIF, after the gamut conversion to BT.2020 and before the PQ encoding.
+    /// The LUT operates on PQ-encoded BT.2020 R'G'B', where 1.0 is 10,000 nits, so it is only applied with `--primaries bt2020`.
+    #[arg(long="lut", conflicts_with = "sdr_passthrough")]
+    lut_file_path: Option<String>,
+    /// How the HDR AVIF is rounded to code values.
+    #[arg(long="rounding", value_enum, default_value_t = RoundingArg::Round, conflicts_with = "sdr_passthrough")]
+    rounding: RoundingArg,
+    /// The bit depth of the HDR AVIF. `auto` analyzes the luminance range and gradient smoothness of the image
+    /// and logs its decision.
+    #[arg(long="bit-depth", value_enum, default_value_t = BitDepthArg::Ten, conflicts_with = "sdr_passthrough")]
+    bit_depth: BitDepthArg,
+    /// Clamp the HDR AVIF to the peak luminance it was authored for, `2^HDRCapacityMax` times `--target-sdr-white-level`,
+    /// instead of the 10,000 nits of PQ, and tag it with that peak and its content light levels as `mdcv` and `clli` boxes.
+    #[arg(long="derive-peak", default_value_t = false, conflicts_with = "sdr_passthrough")]
+    derive_peak: bool,
+    /// Don't carry the EXIF metadata of the input, e.g. the capture date, camera model and GPS position, into the HDR AVIF.
+    #[arg(long="strip-metadata", default_value_t = false, conflicts_with = "sdr_passthrough")]
+    strip_metadata: bool,
+    /// The transfer characteristics of the HDR AVIF. HLG is relative to the display's peak,
+    /// so `--target-sdr-white-level` is ignored for it: SDR white is always at the HLG reference white of 203 nits.
+    #[arg(long="transfer", value_enum, default_value_t = TransferArg::Pq, conflicts_with = "sdr_passthrough")]
+    transfer: TransferArg,
+    /// The color primaries of the HDR AVIF, with the BT.2020 matrix for BT.2020 and the BT.709 matrix otherwise.
+    /// Primaries without an H.273 code point are signalled as unspecified, along with an ICC profile of them.
+    /// Colors outside of a gamut narrower than BT.2020 are clipped, e.g. for P3 displays that would map them back anyway.
+    #[arg(long="primaries", value_enum, default_value_t = PrimariesArg::Bt2020, conflicts_with = "sdr_passthrough")]
+    primaries: PrimariesArg,
+    /// Whether the HDR AVIF is Y'C'bC'r or ICtCp, tagged with the matrix coefficients 14. ICtCp quantizes saturated
+    /// colors with less error, but fewer decoders support it. Only with PQ and BT.2020; always encoded by rav1e directly.
+    #[arg(long="colorspace", value_enum, default_value_t = ColorSpaceArg::Ycbcr, conflicts_with = "sdr_passthrough")]
+    colorspace: ColorSpaceArg,
+    /// Also write an image of the encoding error of the HDR AVIF to this file, as a 16-bit TIFF: The absolute differences
+    /// between the PQ-encoded R'G'B' of the HDR pixels and of the decoded AVIF, amplified so that 16 10-bit code values are white.
+    /// For tuning `--qp` and `--bit-depth`. The AVIF is then always encoded by rav1e directly.
+    #[arg(long="diff-image", conflicts_with_all = ["sdr_passthrough", "summary_only"])]
+    diff_image_file_path: Option<String>,
+    /// What to do if the AVIF would be wider or taller than the 65535 pixels of an AV1 frame, e.g. for a huge panorama.
+    #[arg(long="oversize", value_enum, default_value_t = OversizeArg::Fail)]
+    oversize: OversizeArg,
+    /// Make the output reproducible, by seeding `--rounding stochastic` with a fixed value instead of the current time.
+    #[arg(long="deterministic", default_value_t = false)]
+    deterministic: bool,
+    /// Merge `--sdr` and `--gainmap`, or a gain map computed from `--sdr` and `--hdr`, into an Ultra HDR JPEG written
+    /// to the output, instead of converting an input.
+    #[arg(long="mux", default_value_t = false, requires = "sdr_file_path", conflicts_with_all = ["input_file_path", "stdin"])]
+    mux: bool,
+    /// The SDR JPEG to become the primary image of `--mux`.
+    #[arg(long="sdr", requires = "mux")]
+    sdr_file_path: Option<String>,
+    /// The gain map JPEG for `--mux`.
+    #[arg(long="gainmap", requires = "mux")]
+    gain_map_file_path: Option<String>,
+    /// An XMP file with the `hdrgm` gain map metadata for `--mux`.
+    /// If not specified, the gain map JPEG must already contain it.
+    #[arg(long="gainmap-metadata", requires = "mux")]
+    gain_map_metadata_file_path: Option<String>,
+    /// A Radiance `.hdr` to compute the gain map of `--mux` from instead of `--gainmap`, so that the Ultra HDR JPEG
+    /// reconstructs it from `--sdr`. Linear with 1 being SDR white, e.g. as written by `--format hdr`, and of the extent
+    /// of `--sdr` as stored. Requires building with `--features hdr`.
+    #[arg(long="hdr", requires = "mux", conflicts_with_all = ["gain_map_file_path", "gain_map_metadata_file_path"])]
+    hdr_file_path: Option<String>,
+    /// Ignore ICC profiles, assuming sRGB with the sRGB EOTF instead.
+    /// For diagnosing whether an ICC profile is causing wrong colors.
+    #[arg(long="ignore-icc", default_value_t = false)]
+    ignore_icc: bool,
+    /// Reject the input if the primary image or a gain map has more pixels than this, as declared by its JPEG headers
+    /// before anything is decoded. Guards against inputs declaring huge dimensions.
+    #[arg(long="max-pixels")]
+    max_pixels: Option<usize>,
+    /// Accept truncated or otherwise damaged JPEGs, keeping whatever could be decoded and filling the rest with gray.
+    /// Such input is rejected by default.
+    #[arg(long="allow-partial", default_value_t = false)]
+    allow_partial: bool,
+    /// Request RGB output from the JPEG decoder for every JPEG, so that it converts YCbCr, grayscale and CMYK itself.
+    /// By default, grayscale JPEGs are decoded to a single channel, and become monochrome AVIFs unless the gain map adds color.
+    #[arg(long="force-rgb-decode", default_value_t = false)]
+    force_rgb_decode: bool,
+    /// Warn if the width or height of a gain map is less than this fraction of the primary image's, e.g. `0.25`,
+    /// which is the default. `0` disables the warning.
+    #[arg(long="min-gain-map-scale")]
+    min_gain_map_scale: Option<f32>,
+    /// Store the intermediate HDR pixels as 16-bit floats instead of 32-bit ones, reducing memory use for huge images
+    /// at a slight loss of precision.
+    #[arg(long="low-memory", default_value_t = false)]
+    low_memory: bool,
+    /// Convert the HDR AVIF in bands of this many rows, e.g. `256`, so that the intermediate HDR pixels of huge images
+    /// are never all in memory at once. `--bit-depth auto` is then 10 bits.
+    #[arg(long="band-rows", conflicts_with_all = ["sdr_passthrough", "diff_image_file_path"])]
+    band_rows: Option<usize>,
+    /// Write a JSON report of the input and the conversion to stdout instead of informational log lines.
+    /// Warnings and errors are still logged to stderr.
+    #[cfg(feature = "json")]
+    #[arg(long="json", default_value_t = false, conflicts_with_all = ["stdout", "mux"])]
+    json: bool,
+    /// Compute the luminance statistics of the HDR rendition, such as MaxCLL, and print them without encoding or writing
+    /// any output. With `--json`, they are part of the report instead.
+    #[arg(long="summary-only", default_value_t = false, conflicts_with_all = ["output_file_path", "stdout", "sdr_passthrough"])]
+    summary_only: bool,
 }
 
-fn main() -> Result<(), String> {
-    logging::LoggingConfig::default().apply();
+impl Args {
+    /// Whether `--json` is specified, which it can't be without the `json` feature.
+    fn json(&self) -> bool {
+        #[cfg(feature = "json")]
+        return self.json;
+        #[cfg(not(feature = "json"))]
+        false
+    }
+}
+
+fn parse_max_display_boost(s: &str) -> Result<MaxDisplayBoostArg, String> {
+    if s == "auto" {
+        return Ok(MaxDisplayBoostArg::Auto);
+    }
+    s.trim().parse::<f32>()
+        .map(MaxDisplayBoostArg::Value)
+        .map_err(|e| format!("Expected a boost or `auto` but got '{}': {}", s, e))
+}
 
+fn parse_crop(s: &str) -> Result<CropRect, String> {
+    let values = s.split(',')
+        .map(|value| value.trim().parse::<usize>().map_err(|e| format!("Invalid crop value '{}': {}", value, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [x, y, width, height] => Ok(CropRect { x, y, width, height }),
+        _ => Err(format!("Expected 4 comma-separated values `x,y,w,h` but got {}", values.len())),
+    }
+}
+
+/// The names of the formats of `EncoderRegistry::default()`, with their descriptions for `--help`.
+/// `OPTIONAL_FORMATS` that weren't built are accepted but hidden, so that `resolve_encoder` can tell which feature they need.
+fn format_parser() -> PossibleValuesParser {
+    let registry = EncoderRegistry::default();
+    let missing_formats = OPTIONAL_FORMATS.iter().filter(|&&format| registry.by_name(format).is_none());
+    PossibleValuesParser::new(
+        registry.encoders().map(|encoder| PossibleValue::new(encoder.name()).help(encoder.description()))
+            .chain(missing_formats.map(|&format| PossibleValue::new(format).hide(true))),
+    )
+}
+
+/// The encoder selected by `--format`, or else by the output file extension, or else the `DEFAULT_FORMAT` one.
+fn resolve_encoder<'a>(args: &Args, registry: &'a EncoderRegistry) -> Result<&'a dyn HdrEncoder, String> {
+    if let Some(format) = &args.format {
+        return registry.by_name(format).ok_or_else(|| {
+            if OPTIONAL_FORMATS.contains(&format.as_str()) {
+                format!("The '{}' format requires building with `--features {}`", format, format)
+            } else {
+                format!("Unknown format '{}'", format)
+            }
+        });
+    }
+    args.output_file_path.as_ref()
+        .and_then(|output_file_path| registry.by_path(std::path::Path::new(output_file_path)))
+        .or_else(|| registry.by_name(DEFAULT_FORMAT))
+        .ok_or_else(|| format!("The default format '{}' is not available", DEFAULT_FORMAT))
+}
+
+fn main() -> Result<(), String> {
     let args = Args::parse();
-    
-    let mut reader : Box<dyn Read> = if let Some(input_file_path) = args.input_file_path {
+
+    if args.json() {
+        logging::LoggingConfig::default().quiet().apply();
+    } else {
+        logging::LoggingConfig::default().apply();
+    }
+
+    if args.mux {
+        return mux(&args);
+    }
+
+    if args.input_dir_path.is_some() {
+        return batch::convert_dir(&args);
+    }
+    if args.output_dir_path.is_some() {
+        return batch::convert_glob(&args);
+    }
+    if let Some(input_file_path) = &args.input_file_path && batch::is_glob(input_file_path) {
+        return Err(format!("The input {:?} doesn't exist; to convert the files a pattern matches, also specify `--output-dir`", input_file_path));
+    }
+
+    let mut report = new_report(&args);
+    let result = convert(&args, &mut report);
+
+    #[cfg(feature = "json")]
+    if args.json {
+        report.error = result.as_ref().err().cloned();
+        println!("{}", report.to_json());
+    }
+
+    result
+}
+
+/// The report of converting as specified by `args`, before anything is known about the input.
+fn new_report(args: &Args) -> ConversionReport {
+    ConversionReport {
+        input: args.input_file_path.clone(),
+        output: args.output_file_path.clone(),
+        settings: ConversionSettings {
+            max_display_boost: args.max_display_boost.initial_value(),
+            auto_max_display_boost: args.max_display_boost == MaxDisplayBoostArg::Auto,
+            target_sdr_white_level: args.target_sdr_white_level,
+            auto_white: args.auto_white,
+            ev: args.ev,
+            crop: args.crop,
+            primary_index: args.primary_index,
+            gain_map_index: args.gain_map_index,
+            sdr_passthrough: args.sdr_passthrough,
+            emit_sdr_also: args.emit_sdr_also_file_path.clone(),
+            tone_map: args.tone_map.map(Into::into),
+            qp: args.qp,
+            quality: args.quality,
+            speed: args.speed,
+            range: args.range.into(),
+            chroma: args.chroma.into(),
+            lut: args.lut_file_path.clone(),
+            // Set by `convert`, which seeds it.
+            rounding: Rounding::Round,
+            bit_depth: args.bit_depth.into(),
+            derive_peak: args.derive_peak,
+            strip_metadata: args.strip_metadata,
+            transfer: args.transfer.into(),
+            primaries: args.primaries.into(),
+            color_space: args.colorspace.into(),
+            diff_image: args.diff_image_file_path.clone(),
+            oversize: args.oversize.into(),
+            ignore_icc: args.ignore_icc,
+            max_pixels: args.max_pixels,
+            allow_partial: args.allow_partial,
+            force_rgb_decode: args.force_rgb_decode,
+            min_gain_map_scale: args.min_gain_map_scale,
+            low_memory: args.low_memory,
+            band_rows: args.band_rows,
+        },
+        ..Default::default()
+    }
+}
+
+/// Converts as specified by `args`, filling in `report` along the way.
+fn convert(args: &Args, report: &mut ConversionReport) -> Result<(), String> {
+    let mut reader : Box<dyn Read> = if let Some(input_file_path) = &args.input_file_path {
         trace!("Reading input from file: {}", input_file_path);
         Box::new(File::open(input_file_path).map_err(|e| format!("Failed to open input file: {}", e))?)
     } else if args.stdin {
@@ -60,25 +683,448 @@ fn main() -> Result<(), String> {
         return Err("No input file specified and stdin not enabled".to_string());
     };
 
-    let max_display_boost = args.max_display_boost;
+    let registry = EncoderRegistry::default();
+    let encoder = resolve_encoder(args, &registry)?;
+    report.format = encoder.name().to_string();
 
-    let uhdr_converter = UhdrConverter::new(&mut reader, max_display_boost)
+    let is_avif = encoder.name() == "avif";
+    if args.sdr_passthrough && !is_avif {
+        return Err("`--sdr-passthrough` is only supported for AVIF output".to_string());
+    }
+    if args.tone_map.is_some() && !is_avif {
+        return Err("`--tone-map` is only supported for AVIF output".to_string());
+    }
+    if args.qp.is_some() && !is_avif {
+        return Err("`--qp` is only supported for AVIF output".to_string());
+    }
+    let encode_options = AvifEncodeOptions { quality: args.quality, speed: args.speed, range: args.range.into(), chroma: args.chroma.into() };
+    if encode_options != AvifEncodeOptions::default() && !is_avif {
+        return Err("`--quality`, `--speed`, `--range` and `--chroma` are only supported for AVIF output".to_string());
+    }
+    encode_options.validate().map_err(|e| e.to_string())?;
+    if args.lut_file_path.is_some() && !is_avif {
+        return Err("`--lut` is only supported for AVIF output".to_string());
+    }
+    if args.rounding != RoundingArg::Round && !is_avif {
+        return Err("`--rounding` is only supported for AVIF output".to_string());
+    }
+    if args.bit_depth != BitDepthArg::Ten && !is_avif {
+        return Err("`--bit-depth` is only supported for AVIF output".to_string());
+    }
+    if args.strip_metadata && !is_avif {
+        return Err("`--strip-metadata` is only supported for AVIF output".to_string());
+    }
+    if args.derive_peak && !is_avif {
+        return Err("`--derive-peak` is only supported for AVIF output".to_string());
+    }
+    if args.transfer != TransferArg::Pq && !is_avif {
+        return Err("`--transfer` is only supported for AVIF output".to_string());
+    }
+    if args.primaries != PrimariesArg::Bt2020 && !is_avif {
+        return Err("`--primaries` is only supported for AVIF output".to_string());
+    }
+    if args.colorspace != ColorSpaceArg::Ycbcr && !is_avif {
+        return Err("`--colorspace` is only supported for AVIF output".to_string());
+    }
+    if args.lut_file_path.is_some() && args.primaries != PrimariesArg::Bt2020 {
+        return Err("`--lut` is only supported with `--primaries bt2020`, the primaries it is graded for".to_string());
+    }
+    if args.colorspace == ColorSpaceArg::Ictcp && (args.transfer != TransferArg::Pq || args.primaries != PrimariesArg::Bt2020) {
+        return Err("`--colorspace ictcp` is only supported with `--transfer pq` and `--primaries bt2020`".to_string());
+    }
+    if args.emit_sdr_also_file_path.is_some() && !is_avif {
+        return Err("`--emit-sdr-also` is only supported for AVIF output".to_string());
+    }
+    if args.oversize != OversizeArg::Fail && !is_avif {
+        return Err("`--oversize` is only supported for AVIF output".to_string());
+    }
+    if args.diff_image_file_path.is_some() && !is_avif {
+        return Err("`--diff-image` is only supported for AVIF output".to_string());
+    }
+    if args.band_rows.is_some() && !is_avif {
+        return Err("`--band-rows` is only supported for AVIF output".to_string());
+    }
+
+    let max_display_boost = args.max_display_boost.initial_value();
+
+    let decode_options = DecodeOptions {
+        primary_index: args.primary_index,
+        gain_map_index: args.gain_map_index,
+        ignore_icc: args.ignore_icc,
+        max_pixels: args.max_pixels,
+        allow_partial: args.allow_partial,
+        jpeg: JpegDecodeOptions { force_rgb: args.force_rgb_decode, ..Default::default() },
+        min_gain_map_scale: args.min_gain_map_scale,
+    };
+
+    let mut uhdr_converter = UhdrConverter::new_with_options(&mut reader, max_display_boost, &decode_options)
         .map_err(|e| format!("Failed to create UHDR converter: {}", e))?;
 
-    let mut writer: Box<dyn Write> = if let Some(output_file_path) = args.output_file_path {
+    if args.max_display_boost == MaxDisplayBoostArg::Auto {
+        match uhdr_converter.authored_max_display_boost() {
+            Some(max_display_boost) => {
+                info!("Using the authored max display boost of {:.2}", max_display_boost);
+                report.settings.max_display_boost = max_display_boost;
+                uhdr_converter = uhdr_converter.with_max_display_boost(max_display_boost);
+            },
+            None => trace!("No gain map to take the max display boost from, using {}", max_display_boost),
+        }
+    }
+
+    if let Some(crop) = args.crop {
+        uhdr_converter = uhdr_converter.with_crop(crop)
+            .map_err(|e| format!("Invalid crop: {}", e))?;
+    }
+
+    if args.ev != 0.0 {
+        uhdr_converter = uhdr_converter.with_exposure_ev(args.ev);
+    }
+
+    if let Some(gain_map_filter) = args.gain_map_filter {
+        uhdr_converter = uhdr_converter.with_gain_map_filter(gain_map_filter.into());
+    }
+
+    uhdr_converter = uhdr_converter.with_low_memory(args.low_memory);
+    uhdr_converter = uhdr_converter.with_avif_band_rows(args.band_rows);
+
+    if let Some(qp) = args.qp {
+        uhdr_converter = uhdr_converter.with_avif_quantizer(qp);
+    }
+
+    uhdr_converter = uhdr_converter.with_derived_peak(args.derive_peak);
+    uhdr_converter = uhdr_converter.with_strip_metadata(args.strip_metadata);
+
+    let rounding = args.rounding.to_rounding(args.deterministic);
+    report.settings.rounding = rounding;
+    uhdr_converter = uhdr_converter.with_rounding(rounding);
+    uhdr_converter = uhdr_converter.with_avif_bit_depth(args.bit_depth.into());
+    uhdr_converter = uhdr_converter.with_avif_oversize(args.oversize.into());
+    uhdr_converter = uhdr_converter.with_avif_encode_options(encode_options);
+    uhdr_converter = uhdr_converter.with_avif_transfer(args.transfer.into());
+    uhdr_converter = uhdr_converter.with_avif_color_primaries(args.primaries.into());
+    uhdr_converter = uhdr_converter.with_avif_color_space(args.colorspace.into());
+
+    if let Some(lut_file_path) = &args.lut_file_path {
+        trace!("Reading LUT from file: {}", lut_file_path);
+        let text = std::fs::read_to_string(lut_file_path).map_err(|e| format!("Failed to read LUT file: {}", e))?;
+        let lut = CubeLut::from_cube_str(&text).map_err(|e| format!("Failed to parse LUT file: {}", e))?;
+        uhdr_converter = uhdr_converter.with_lut(lut);
+    }
+
+    report.info = Some(uhdr_converter.info());
+    let (output_width, output_height) = uhdr_converter.output_extent();
+    report.output_width = Some(output_width);
+    report.output_height = Some(output_height);
+
+    let target_sdr_white_level = if args.auto_white {
+        let target_sdr_white_level = uhdr_converter.suggest_target_sdr_white_level();
+        info!("Using the suggested target SDR white level of {:.0} nits", target_sdr_white_level);
+        report.settings.target_sdr_white_level = target_sdr_white_level;
+        target_sdr_white_level
+    } else {
+        args.target_sdr_white_level
+    };
+
+    if args.summary_only {
+        let stats = uhdr_converter.compute_luminance_stats(target_sdr_white_level);
+        if !args.json() {
+            print_summary(&uhdr_converter.info(), &stats, target_sdr_white_level);
+        }
+        report.stats = Some(stats);
+        return Ok(());
+    }
+
+    let mut writer = open_output(args)?;
+
+    if args.sdr_passthrough {
+        uhdr_converter.convert_to_sdr_avif(&mut writer)
+            .map_err(|e| format!("Failed to convert UHDR JPEG to SDR AVIF: {}", e))?;
+        return Ok(());
+    }
+    if let Some(tone_map) = args.tone_map {
+        uhdr_converter.convert_to_tone_mapped_avif(&mut writer, tone_map.into())
+            .map_err(|e| format!("Failed to convert UHDR JPEG to tone-mapped AVIF: {}", e))?;
+        return Ok(());
+    }
+
+    let encode_options = EncodeOptions {
+        target_sdr_white_level,
+        tiff_transfer: args.tiff_transfer.into(),
+        png_transfer: args.png_transfer.into(),
+    };
+    if let Some(diff_image_file_path) = &args.diff_image_file_path {
+        trace!("Writing diff image to file: {}", diff_image_file_path);
+        let mut diff_writer = File::create(diff_image_file_path).map_err(|e| format!("Failed to create diff image file: {}", e))?;
+        let (timings, error) = uhdr_converter.convert_to_avif_with_diff_image(&mut writer, target_sdr_white_level, &mut diff_writer)
+            .map_err(|e| format!("Failed to convert UHDR JPEG to avif: {}", e))?;
+        let transfer = match error.transfer {
+            AvifTransfer::Pq => "PQ",
+            AvifTransfer::Hlg => "HLG",
+        };
+        let max_code_value = ((1u32 << error.bit_depth) - 1) as f32;
+        info!(
+            "Encoding error in {}-bit {} code values: Max {:.2}, mean {:.3}",
+            error.bit_depth, transfer, error.max() * max_code_value, error.mean() * max_code_value,
+        );
+        report.timings = Some(timings);
+    } else {
+        report.timings = encoder.encode(&uhdr_converter, &mut writer, &encode_options)
+            .map_err(|e| format!("Failed to convert UHDR JPEG to {}: {}", encoder.name(), e))?;
+    }
+
+    if let Some(sdr_file_path) = &args.emit_sdr_also_file_path {
+        trace!("Writing SDR output to file: {}", sdr_file_path);
+        let mut sdr_writer = File::create(sdr_file_path).map_err(|e| format!("Failed to create SDR output file: {}", e))?;
+        uhdr_converter.convert_to_sdr_avif(&mut sdr_writer)
+            .map_err(|e| format!("Failed to convert UHDR JPEG to SDR AVIF: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Prints the HDR characteristics of the input for `--summary-only`.
+fn print_summary(info: &UhdrInfo, stats: &LuminanceStats, target_sdr_white_level: f32) {
+    println!("Dimensions: {}x{}", info.width, info.height);
+    for (index, gain_map) in info.gain_maps.iter().enumerate() {
+        println!(
+            "Gain map {}: {}x{}, HDR capacity {:.2}x to {:.2}x",
+            index,
+            gain_map.width,
+            gain_map.height,
+            gain_map.metadata.hdr_capacity_min.exp2(),
+            gain_map.metadata.hdr_capacity_max.exp2(),
+        );
+    }
+    println!("MaxCLL: {:.1} nits", stats.max_cll);
+    println!("MaxFALL: {:.1} nits", stats.max_fall);
+    println!("Peak boost: {:.2}x over SDR white at {} nits", stats.peak_boost, target_sdr_white_level);
+}
+
+/// Merges the `--sdr` and `--gainmap` JPEGs into an Ultra HDR JPEG.
+fn mux(args: &Args) -> Result<(), String> {
+    let read = |path: &Option<String>, what: &str| {
+        let path = path.as_ref().ok_or_else(|| format!("No {} file specified", what))?;
+        trace!("Reading {} from file: {}", what, path);
+        std::fs::read(path).map_err(|e| format!("Failed to read {} file: {}", what, e))
+    };
+
+    let sdr_jpeg_bytes = read(&args.sdr_file_path, "SDR JPEG")?;
+    if args.hdr_file_path.is_some() {
+        return mux_computed_gain_map(args, &sdr_jpeg_bytes, read(&args.hdr_file_path, "HDR image")?);
+    }
+    let gain_map_jpeg_bytes = read(&args.gain_map_file_path, "gain map JPEG")?;
+    let metadata = args.gain_map_metadata_file_path.is_some()
+        .then(|| {
+            let xmp_bytes = read(&args.gain_map_metadata_file_path, "gain map metadata")?;
+            GainMapMetadata::new_from_xmp_bytes(&xmp_bytes)
+                .map_err(|e| format!("Failed to parse gain map metadata from XMP: {}", e))
+        })
+        .transpose()?;
+
+    let mut writer = open_output(args)?;
+
+    libuhdr::write_uhdr_jpeg(&mut writer, &sdr_jpeg_bytes, &gain_map_jpeg_bytes, metadata.as_ref())
+        .map_err(|e| format!("Failed to write Ultra HDR JPEG: {}", e))
+}
+
+/// Writes an Ultra HDR JPEG with a gain map computed from `sdr_jpeg_bytes` and the Radiance `.hdr` of `hdr_bytes`.
+#[cfg(feature = "hdr")]
+fn mux_computed_gain_map(args: &Args, sdr_jpeg_bytes: &[u8], hdr_bytes: Vec<u8>) -> Result<(), String> {
+    let hdr = libuhdr::read_hdr(&hdr_bytes).map_err(|e| format!("Failed to read HDR image: {}", e))?;
+    let hdr_color_gamut = hdr.primaries
+        .map(|[red, green, blue, white_point]| {
+            libuhdr::ColorGamut::from_xy([red, green, blue], white_point).ok_or_else(|| "The primaries of the HDR image span no gamut".to_string())
+        })
+        .transpose()?;
+
+    let mut writer = open_output(args)?;
+
+    libuhdr::write_uhdr_jpeg_from_hdr(&mut writer, sdr_jpeg_bytes, &hdr.content, hdr_color_gamut.as_ref())
+        .map_err(|e| format!("Failed to write Ultra HDR JPEG: {}", e))
+}
+
+#[cfg(not(feature = "hdr"))]
+fn mux_computed_gain_map(_args: &Args, _sdr_jpeg_bytes: &[u8], _hdr_bytes: Vec<u8>) -> Result<(), String> {
+    Err("`--hdr` requires building with `--features hdr`".to_string())
+}
+
+fn open_output(args: &Args) -> Result<Box<dyn Write>, String> {
+    if let Some(output_file_path) = &args.output_file_path {
         trace!("Writing output to file: {}", output_file_path);
-        Box::new(File::create(output_file_path).map_err(|e| format!("Failed to create output file: {}", e))?)
+        Ok(Box::new(File::create(output_file_path).map_err(|e| format!("Failed to create output file: {}", e))?))
     } else if args.stdout {
         trace!("Writing output to stdout");
-        Box::new(std::io::stdout())
+        Ok(Box::new(std::io::stdout()))
     } else {
-        return Err("No output file specified and stdout not enabled".to_string());
-    };
+        Err("No output file specified and stdout not enabled".to_string())
+    }
+}
 
-    let target_sdr_white_level = args.target_sdr_white_level;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    uhdr_converter.convert_to_avif(&mut writer, target_sdr_white_level)
-        .map_err(|e| format!("Failed to convert UHDR JPEG to AVIF: {}", e))?;
-    
-    Ok(())
+    /// A white 8x8 Ultra HDR JPEG with a 4x4 gain map boosting it by the full 4x.
+    pub(crate) fn uhdr_jpeg_bytes() -> Vec<u8> {
+        let encode_jpeg = |width: u16, height: u16, value: u8| {
+            let mut bytes = Vec::new();
+            let rgb = vec![value; width as usize * height as usize * 3];
+            jpeg_encoder::Encoder::new(&mut bytes, 100)
+                .encode(&rgb, width, height, jpeg_encoder::ColorType::Rgb)
+                .unwrap();
+            bytes
+        };
+        let mut uhdr_jpeg_bytes = Vec::new();
+        libuhdr::write_uhdr_jpeg(&mut uhdr_jpeg_bytes, &encode_jpeg(8, 8, 255), &encode_jpeg(4, 4, 255), Some(&full_boost_metadata())).unwrap();
+        uhdr_jpeg_bytes
+    }
+
+    /// Gain map metadata boosting by up to 4x.
+    fn full_boost_metadata() -> GainMapMetadata {
+        GainMapMetadata {
+            base_rendition_is_hdr: false,
+            gain_map_min: [0.0; 3],
+            gain_map_max: [2.0; 3],
+            gamma: [1.0; 3],
+            offset_sdr: [0.015625; 3],
+            offset_hdr: [0.015625; 3],
+            hdr_capacity_min: 0.0,
+            hdr_capacity_max: 2.0,
+            formulation: libuhdr::GainMapFormulation::Adobe,
+        }
+    }
+
+    #[test]
+    fn summary_only_computes_stats_without_writing_output() {
+        let uhdr_jpeg_bytes = uhdr_jpeg_bytes();
+
+        let directory = std::env::temp_dir().join(format!("uhdr2avif-summary-only-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let input_path = directory.join("input.jpg");
+        std::fs::write(&input_path, &uhdr_jpeg_bytes).unwrap();
+
+        let args = Args::parse_from(["uhdr2avif", "-i", input_path.to_str().unwrap(), "--summary-only"]);
+        let mut report = ConversionReport::default();
+        let result = convert(&args, &mut report);
+
+        let entries: Vec<_> = std::fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        result.unwrap();
+        let stats = report.stats.unwrap();
+        // SDR white boosted by the full 4x at the default maximum display boost of 10.
+        assert!((stats.peak_boost - 4.0).abs() < 0.05, "{:?}", stats);
+        assert!(stats.max_fall <= stats.max_cll);
+        assert!(report.timings.is_none());
+        assert_eq!(entries, ["input.jpg"]);
+
+        // An output can't be combined with it.
+        assert!(Args::try_parse_from(["uhdr2avif", "-i", "input.jpg", "-o", "output.avif", "--summary-only"]).is_err());
+    }
+
+    #[test]
+    fn format_is_resolved_from_the_registry() {
+        let registry = EncoderRegistry::default();
+        let resolve = |args: &[&str]| {
+            let args = Args::parse_from(["uhdr2avif", "-i", "input.jpg"].iter().chain(args));
+            resolve_encoder(&args, &registry).unwrap().name()
+        };
+
+        assert_eq!(resolve(&["-o", "output.avif"]), "avif");
+        assert_eq!(resolve(&["-o", "output.TIF"]), "tiff");
+        assert_eq!(resolve(&["-o", "output.jpg"]), "uhdr-jpeg");
+        assert_eq!(resolve(&["-o", "output.jpg", "--format", "avif"]), "avif");
+        assert_eq!(resolve(&["-o", "output.unknown"]), "avif");
+        assert_eq!(resolve(&["--stdout", "--format", "tiff"]), "tiff");
+
+        assert!(Args::try_parse_from(["uhdr2avif", "-i", "input.jpg", "--format", "bmp"]).is_err());
+
+        // A format that wasn't built is parsed, but fails to resolve with the feature it needs.
+        let args = Args::parse_from(["uhdr2avif", "-i", "input.jpg", "--format", "exr"]);
+        let registry = EncoderRegistry::empty();
+        assert_eq!(
+            resolve_encoder(&args, &registry).err(),
+            Some("The 'exr' format requires building with `--features exr`".to_string()),
+        );
+    }
+
+    #[test]
+    fn force_rgb_decode_keeps_a_grayscale_jpeg_from_becoming_a_monochrome_avif() {
+        let encode_gray_jpeg = |extent: u16| {
+            let mut bytes = Vec::new();
+            let gray = vec![200u8; extent as usize * extent as usize];
+            jpeg_encoder::Encoder::new(&mut bytes, 100)
+                .encode(&gray, extent, extent, jpeg_encoder::ColorType::Luma)
+                .unwrap();
+            bytes
+        };
+        let mut uhdr_jpeg_bytes = Vec::new();
+        libuhdr::write_uhdr_jpeg(&mut uhdr_jpeg_bytes, &encode_gray_jpeg(8), &encode_gray_jpeg(4), Some(&full_boost_metadata())).unwrap();
+
+        let directory = std::env::temp_dir().join(format!("uhdr2avif-force-rgb-decode-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let input_path = directory.join("input.jpg");
+        std::fs::write(&input_path, &uhdr_jpeg_bytes).unwrap();
+        let convert_to = |name: &str, extra_args: &[&str]| {
+            let output_path = directory.join(name);
+            let args = Args::parse_from(
+                ["uhdr2avif", "-i", input_path.to_str().unwrap(), "-o", output_path.to_str().unwrap()].iter().chain(extra_args),
+            );
+            convert(&args, &mut ConversionReport::default()).map(|()| std::fs::read(&output_path).unwrap())
+        };
+        let (gray_avif_bytes, rgb_avif_bytes) = (convert_to("gray.avif", &[]), convert_to("rgb.avif", &["--force-rgb-decode"]));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        // The `mono_chrome` flag of `av1C`.
+        let is_monochrome = |avif_bytes: &[u8]| {
+            let av1c = avif_bytes.windows(4).position(|window| window == b"av1C").unwrap() + 4;
+            avif_bytes[av1c + 2] & 0x10 != 0
+        };
+        assert!(is_monochrome(&gray_avif_bytes.unwrap()));
+        assert!(!is_monochrome(&rgb_avif_bytes.unwrap()));
+    }
+
+    #[test]
+    fn tone_map_rejects_the_options_of_hdr_avif() {
+        assert!(Args::try_parse_from(["uhdr2avif", "-i", "input.jpg", "-o", "output.avif", "--tone-map", "hable"]).is_ok());
+        for option in [
+            &["--transfer", "hlg"][..], &["--bit-depth", "12"], &["--derive-peak"], &["--range", "limited"], &["--chroma", "420"],
+            &["--primaries", "display-p3"], &["--colorspace", "ictcp"], &["--quality", "50"], &["--speed", "8"], &["--qp", "40"],
+            &["--rounding", "floor"], &["--strip-metadata"], &["--band-rows", "256"], &["--auto-white"], &["--lut", "grade.cube"],
+        ] {
+            let args = ["uhdr2avif", "-i", "input.jpg", "-o", "output.avif"].iter().chain(option);
+            assert!(Args::try_parse_from(args).is_ok(), "{:?}", option);
+            let args = ["uhdr2avif", "-i", "input.jpg", "-o", "output.avif", "--tone-map", "hable"].iter().chain(option);
+            assert!(Args::try_parse_from(args).is_err(), "{:?}", option);
+        }
+    }
+
+    #[test]
+    fn emit_sdr_also_writes_8_bit_sdr_alongside_10_bit_hdr() {
+        let directory = std::env::temp_dir().join(format!("uhdr2avif-emit-sdr-also-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let input_path = directory.join("input.jpg");
+        let hdr_path = directory.join("hdr.avif");
+        let sdr_path = directory.join("sdr.avif");
+        std::fs::write(&input_path, uhdr_jpeg_bytes()).unwrap();
+
+        let args = Args::parse_from([
+            "uhdr2avif",
+            "-i", input_path.to_str().unwrap(),
+            "-o", hdr_path.to_str().unwrap(),
+            "--emit-sdr-also", sdr_path.to_str().unwrap(),
+        ]);
+        let result = convert(&args, &mut ConversionReport::default());
+        let (hdr_bytes, sdr_bytes) = (std::fs::read(&hdr_path), std::fs::read(&sdr_path));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        result.unwrap();
+        // The `high_bitdepth` flag of `av1C`, set for 10 bits.
+        let high_bitdepth = |avif_bytes: &[u8]| {
+            let av1c = avif_bytes.windows(4).position(|window| window == b"av1C").unwrap() + 4;
+            avif_bytes[av1c + 2] & 0x40 != 0
+        };
+        assert!(high_bitdepth(&hdr_bytes.unwrap()));
+        assert!(!high_bitdepth(&sdr_bytes.unwrap()));
+    }
 }
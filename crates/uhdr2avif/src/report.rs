@@ -0,0 +1,199 @@
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use libuhdr::{AvifBitDepth, AvifChroma, AvifColorPrimaries, AvifColorSpace, AvifOversize, AvifRange, AvifTransfer, ConversionTimings, CropRect, LuminanceStats, Rounding, ToneMapOperator, UhdrInfo};
+
+/// The machine-readable report written to stdout by `--json`, which the batch summary is also drawn from.
+///
+/// Fields that could not be determined because the conversion failed early are `null`, and `error` is set instead.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+// Without `--json`, only what the log lines and the batch summary need is read.
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+pub struct ConversionReport {
+    /// The input file path, or `null` for stdin.
+    pub input: Option<String>,
+    /// The output file path.
+    pub output: Option<String>,
+    /// The output format, e.g. `"avif"`.
+    pub format: String,
+    pub settings: ConversionSettings,
+    /// What was found in the input.
+    pub info: Option<UhdrInfo>,
+    pub output_width: Option<usize>,
+    pub output_height: Option<usize>,
+    /// How long each phase of the conversion took in seconds. Only available for HDR AVIF output.
+    pub timings: Option<ConversionTimings>,
+    /// The luminance statistics of the HDR rendition. Only available with `--summary-only`.
+    pub stats: Option<LuminanceStats>,
+    /// The error message if the conversion failed.
+    pub error: Option<String>,
+    /// Whether the input wasn't converted because its output already exists, with `--no-clobber`.
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+// Without `--json`, only what the log lines and the batch summary need is read.
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+pub struct ConversionSettings {
+    /// The authored one with `--max-display-boost auto`.
+    pub max_display_boost: f32,
+    pub auto_max_display_boost: bool,
+    /// The suggested one with `--auto-white`.
+    pub target_sdr_white_level: f32,
+    pub auto_white: bool,
+    pub ev: f32,
+    pub crop: Option<CropRect>,
+    pub primary_index: Option<usize>,
+    pub gain_map_index: Option<usize>,
+    pub sdr_passthrough: bool,
+    /// The `--emit-sdr-also` file path.
+    pub emit_sdr_also: Option<String>,
+    pub tone_map: Option<ToneMapOperator>,
+    pub qp: Option<u8>,
+    pub quality: f32,
+    pub speed: u8,
+    pub range: AvifRange,
+    pub chroma: AvifChroma,
+    /// The `.cube` file path.
+    pub lut: Option<String>,
+    /// The seed of stochastic rounding is included, so that the output can be reproduced.
+    pub rounding: Rounding,
+    pub bit_depth: AvifBitDepth,
+    pub derive_peak: bool,
+    pub strip_metadata: bool,
+    pub transfer: AvifTransfer,
+    pub primaries: AvifColorPrimaries,
+    pub color_space: AvifColorSpace,
+    /// The `--diff-image` file path.
+    pub diff_image: Option<String>,
+    pub oversize: AvifOversize,
+    pub ignore_icc: bool,
+    pub max_pixels: Option<usize>,
+    pub allow_partial: bool,
+    pub force_rgb_decode: bool,
+    /// `None` if the default was used.
+    pub min_gain_map_scale: Option<f32>,
+    pub low_memory: bool,
+    /// `None` if the whole image was converted at once.
+    pub band_rows: Option<usize>,
+}
+
+#[cfg(feature = "json")]
+impl ConversionReport {
+    pub fn to_json(&self) -> String {
+        // Only fails for maps with non-string keys, which the report doesn't have.
+        serde_json::to_string_pretty(self).expect("Failed to serialize the conversion report")
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    use libuhdr::{GainMapFilter, GainMapFormulation, GainMapInfo, GainMapMetadata};
+
+    #[test]
+    fn json_contains_expected_keys() {
+        let metadata = GainMapMetadata {
+            base_rendition_is_hdr: false,
+            gain_map_min: [0.0; 3],
+            gain_map_max: [2.0; 3],
+            gamma: [1.0; 3],
+            offset_sdr: [0.015625; 3],
+            offset_hdr: [0.015625; 3],
+            hdr_capacity_min: 0.0,
+            hdr_capacity_max: 2.0,
+            formulation: GainMapFormulation::Adobe,
+        };
+        let report = ConversionReport {
+            input: Some("input.jpg".to_string()),
+            output: Some("output.avif".to_string()),
+            format: "avif".to_string(),
+            settings: ConversionSettings {
+                max_display_boost: 10.0,
+                auto_max_display_boost: false,
+                target_sdr_white_level: 80.0,
+                auto_white: false,
+                ev: 0.0,
+                crop: Some(CropRect { x: 1, y: 2, width: 3, height: 4 }),
+                primary_index: None,
+                gain_map_index: None,
+                sdr_passthrough: false,
+                emit_sdr_also: None,
+                tone_map: None,
+                qp: None,
+                quality: 100.0,
+                speed: 4,
+                range: AvifRange::Full,
+                chroma: AvifChroma::Chroma444,
+                lut: None,
+                rounding: Rounding::Stochastic { seed: 0 },
+                bit_depth: AvifBitDepth::Auto,
+                derive_peak: false,
+                strip_metadata: false,
+                transfer: AvifTransfer::Pq,
+                primaries: AvifColorPrimaries::Bt2020,
+                color_space: AvifColorSpace::Ycbcr,
+                diff_image: None,
+                oversize: AvifOversize::Fail,
+                ignore_icc: false,
+                max_pixels: None,
+                allow_partial: false,
+                force_rgb_decode: false,
+                min_gain_map_scale: None,
+                low_memory: false,
+                band_rows: None,
+            },
+            info: Some(UhdrInfo {
+                width: 16,
+                height: 8,
+                icc_description: None,
+                has_icc_profile: false,
+                gain_maps: vec![GainMapInfo { width: 4, height: 2, metadata, filter: GainMapFilter::Bilinear }],
+            }),
+            output_width: Some(3),
+            output_height: Some(4),
+            timings: Some(ConversionTimings {
+                encode: std::time::Duration::from_millis(1500),
+                ..Default::default()
+            }),
+            stats: None,
+            error: None,
+            skipped: false,
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+
+        assert_eq!(json["input"], "input.jpg");
+        assert_eq!(json["format"], "avif");
+        assert_eq!(json["settings"]["max_display_boost"], 10.0);
+        assert_eq!(json["settings"]["crop"]["width"], 3);
+        assert_eq!(json["settings"]["rounding"]["stochastic"]["seed"], 0);
+        assert_eq!(json["info"]["width"], 16);
+        assert_eq!(json["info"]["gain_maps"][0]["filter"], "bilinear");
+        assert_eq!(json["info"]["gain_maps"][0]["metadata"]["gain_map_max"][1], 2.0);
+        assert_eq!(json["info"]["gain_maps"][0]["metadata"]["hdr_capacity_max"], 2.0);
+        assert_eq!(json["info"]["gain_maps"][0]["metadata"]["formulation"], "adobe");
+        assert_eq!(json["output_width"], 3);
+        assert_eq!(json["timings"]["encode"], 1.5);
+        assert_eq!(json["timings"]["jpeg_decode"], 0.0);
+        assert!(json["error"].is_null());
+        assert_eq!(json["skipped"], false);
+    }
+
+    #[test]
+    fn failed_conversion_reports_error() {
+        let report = ConversionReport {
+            error: Some("Failed to parse JPEG".to_string()),
+            ..Default::default()
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+
+        assert_eq!(json["error"], "Failed to parse JPEG");
+        assert!(json["info"].is_null());
+        assert!(json.get("settings").is_some());
+    }
+}
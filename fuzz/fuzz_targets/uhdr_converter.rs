@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use libuhdr::{CropRect, DecodeOptions, TiffTransfer, UhdrConverter};
+
+// Malformed input must be rejected with `Err`; libFuzzer reports any panic or abort as a crash.
+fuzz_target!(|data: &[u8]| {
+    // Decoding huge images is slow without finding anything new.
+    let options = DecodeOptions { max_pixels: Some(4096 * 4096), ..Default::default() };
+    let Ok(converter) = UhdrConverter::new_with_options(&mut &data[..], 4.0, &options) else {
+        return;
+    };
+    let _ = converter.info();
+
+    // A single pixel is enough to sample the primary image and the gain maps, without spending time on huge images.
+    if let Ok(converter) = converter.with_crop(CropRect { x: 0, y: 0, width: 1, height: 1 }) {
+        let _ = converter.convert_to_tiff(&mut Vec::new(), 80.0, TiffTransfer::Linear);
+    }
+});